@@ -0,0 +1,87 @@
+//! Compares building a fresh RTP packet for every frame against reusing one
+//! packet per player, for a second of audio from 100 players.
+//!
+//! Run with `cargo bench --bench rtp`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use swc::voice::constants::{AUDIO_FRAME_RATE, VOICE_PACKET_MAX};
+use swc::voice::rtp::{EncryptionMode, Encryptor, Packet};
+
+/// How many players are sending audio at once.
+const PLAYERS: usize = 100;
+
+/// How many times each run is repeated.
+const ROUNDS: u32 = 50;
+
+/// About the size of a 128kbps Opus frame.
+const PAYLOAD: &[u8] = &[0xAB; 320];
+
+fn new_encryptors() -> Vec<Encryptor> {
+    (0..PLAYERS)
+        .map(|_| Encryptor::new(EncryptionMode::Lite, [7; 32]))
+        .collect()
+}
+
+fn fill(packet: &mut Packet<[u8; VOICE_PACKET_MAX]>) {
+    packet.payload_mut()[..PAYLOAD.len()].copy_from_slice(PAYLOAD);
+    packet.set_payload_len(PAYLOAD.len());
+}
+
+/// A new packet for every frame, like the streamer used to do.
+fn fresh(encryptors: &mut [Encryptor]) {
+    for frame in 0..AUDIO_FRAME_RATE {
+        for encryptor in encryptors.iter_mut() {
+            let mut packet = Packet::default();
+            fill(&mut packet);
+            packet.set_sequence(frame as u16);
+            encryptor.encrypt(&mut packet).unwrap();
+            black_box(packet.as_ref());
+        }
+    }
+}
+
+/// One packet per player, reused for every frame.
+fn reused(encryptors: &mut [Encryptor], packets: &mut [Packet<[u8; VOICE_PACKET_MAX]>]) {
+    for frame in 0..AUDIO_FRAME_RATE {
+        for (encryptor, packet) in encryptors.iter_mut().zip(packets.iter_mut()) {
+            fill(packet);
+            packet.set_sequence(frame as u16);
+            encryptor.encrypt(packet).unwrap();
+            black_box(packet.as_ref());
+        }
+    }
+}
+
+fn time(name: &str, mut f: impl FnMut()) -> Duration {
+    // warm up
+    f();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+
+    println!(
+        "{:>6}: {:?} per second of audio from {} players",
+        name, elapsed, PLAYERS
+    );
+
+    elapsed
+}
+
+fn main() {
+    let mut encryptors = new_encryptors();
+    let fresh = time("fresh", || fresh(&mut encryptors));
+
+    let mut encryptors = new_encryptors();
+    let mut packets = (0..PLAYERS).map(|_| Packet::default()).collect::<Vec<_>>();
+    let reused = time("reused", || reused(&mut encryptors, &mut packets));
+
+    println!(
+        "reusing packets takes {:.1}% of the time",
+        reused.as_secs_f64() / fresh.as_secs_f64() * 100.0
+    );
+}
@@ -0,0 +1,23 @@
+//! Serves swc as an audio node for other bots.
+//!
+//! See [`swc::node`] for the protocol.
+//!
+//! Run with:
+//! ```sh
+//! NODE_ADDR=127.0.0.1:2334 NODE_PASSWORD=... cargo run --example node
+//! ```
+
+use std::error::Error;
+
+use swc::node::{self, NodeConfig};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    dotenv::dotenv().ok();
+    tracing_subscriber::fmt::init();
+
+    let config = NodeConfig::from_env().ok_or("NODE_ADDR or NODE_PASSWORD isn't set")?;
+    node::serve(config).await?;
+
+    Ok(())
+}
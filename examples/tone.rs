@@ -0,0 +1,177 @@
+//! Plays a tone in a voice channel, using the voice client directly.
+//!
+//! This skips [`swc::voice::Player`] and the music layer entirely: it joins
+//! the channel through the main gateway, opens a [`Connection`] with the
+//! session the gateway hands back, and streams a generated tone over it with
+//! a [`PacketStreamer`], which paces the frames and says when to speak.
+//! Anything that makes its own audio, like text-to-speech or a soundboard,
+//! can be built the same way, by implementing [`AudioSource`].
+//!
+//! Run with:
+//! ```sh
+//! DISCORD_TOKEN=... GUILD_ID=... CHANNEL_ID=... cargo run --example tone
+//! ```
+
+use std::env;
+use std::error::Error;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use std::time::Duration;
+
+use opus::{Application, Channels};
+use twilight_gateway::{Event, Intents, Shard, ShardId};
+use twilight_model::gateway::payload::outgoing::UpdateVoiceState;
+use twilight_model::id::Id;
+
+use swc::voice::constants::{MONO_FRAME_SIZE, SAMPLE_RATE, STEREO_FRAME_SIZE};
+use swc::voice::encoder::{CodecError, Encoder};
+use swc::voice::source::Error as SourceError;
+use swc::voice::ws::payload::{Speaking, SpeakingFlags};
+use swc::voice::ws::{Connection, Session};
+use swc::voice::{AudioSource, PacketStreamer, Status};
+
+/// The pitch of the tone, in Hz.
+const PITCH: f32 = 440.;
+
+/// How long to play the tone for, in frames.
+const FRAMES: usize = 5 * 50;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let token = env::var("DISCORD_TOKEN")?;
+    let guild_id = Id::new(env::var("GUILD_ID")?.parse()?);
+    let channel_id = Id::new(env::var("CHANNEL_ID")?.parse()?);
+
+    let mut shard = Shard::new(
+        ShardId::ONE,
+        token,
+        Intents::GUILDS | Intents::GUILD_VOICE_STATES,
+    );
+
+    // join the channel, and wait for the gateway to say where the voice
+    // server is
+    let mut user_id = None;
+    let mut session_id = None;
+    let mut server = None;
+
+    let session = loop {
+        match shard.next_event().await? {
+            Event::Ready(ready) => {
+                user_id = Some(ready.user.id);
+                shard
+                    .command(&UpdateVoiceState::new(
+                        guild_id,
+                        Some(channel_id),
+                        false,
+                        false,
+                    ))
+                    .await?;
+            }
+            Event::VoiceStateUpdate(ev) if Some(ev.0.user_id) == user_id => {
+                session_id = Some(ev.0.session_id);
+            }
+            Event::VoiceServerUpdate(ev) => {
+                server = Some((ev.endpoint.ok_or("no voice server")?, ev.token));
+            }
+            _ => (),
+        }
+
+        if let (Some(user_id), Some(session_id), Some((endpoint, token))) =
+            (user_id, &session_id, &server)
+        {
+            break Session {
+                endpoint: endpoint.clone(),
+                guild_id,
+                user_id,
+                session_id: session_id.clone(),
+                token: token.clone(),
+            };
+        }
+    };
+
+    let (mut conn, mut rtp) = Connection::connect(session, None).await?;
+
+    let mut streamer = PacketStreamer::new(Duration::from_millis(200), Arc::default());
+    streamer.source(Tone::new()?);
+
+    // the connection has to be polled for heartbeats to be sent, so stream
+    // the tone while polling it
+    loop {
+        tokio::select! {
+            status = streamer.stream(&mut rtp) => match status? {
+                Status::Started(ssrc) => speak(&mut conn, SpeakingFlags::MICROPHONE, ssrc).await?,
+                Status::Stopped(ssrc) => {
+                    speak(&mut conn, SpeakingFlags::empty(), ssrc).await?;
+                    break;
+                }
+                Status::SourceStopped => (),
+            },
+            ev = conn.recv() => match ev {
+                Some(Ok(_)) => (),
+                Some(Err(err)) => return Err(err.into()),
+                None => return Err("voice connection closed".into()),
+            },
+        }
+    }
+
+    conn.disconnect().await;
+
+    Ok(())
+}
+
+async fn speak(
+    conn: &mut Connection,
+    speaking: SpeakingFlags,
+    ssrc: u32,
+) -> Result<(), Box<dyn Error>> {
+    conn.send(Speaking {
+        speaking,
+        delay: Some(0),
+        ssrc,
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// [`FRAMES`] frames of a sine wave.
+struct Tone {
+    encoder: Encoder,
+    frame: usize,
+}
+
+impl Tone {
+    fn new() -> Result<Tone, CodecError> {
+        Ok(Tone {
+            encoder: Encoder::new(SAMPLE_RATE as u32, Channels::Stereo, Application::Audio)?,
+            frame: 0,
+        })
+    }
+}
+
+impl AudioSource for Tone {
+    async fn read_opus(&mut self, buf: &mut [u8]) -> Result<usize, SourceError> {
+        if self.frame == FRAMES {
+            return Ok(0);
+        }
+
+        let mut pcm = [0f32; STEREO_FRAME_SIZE];
+
+        for i in 0..MONO_FRAME_SIZE {
+            let t = (self.frame * MONO_FRAME_SIZE + i) as f32 / SAMPLE_RATE as f32;
+            let sample = (t * PITCH * TAU).sin() * 0.2;
+            pcm[i * 2] = sample;
+            pcm[i * 2 + 1] = sample;
+        }
+
+        self.frame += 1;
+
+        let packet = self
+            .encoder
+            .encode_vec_float(&pcm, buf.len())
+            .map_err(SourceError::Codec)?;
+        buf[..packet.len()].copy_from_slice(&packet);
+
+        Ok(packet.len())
+    }
+}
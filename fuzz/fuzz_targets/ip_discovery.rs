@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use swc::voice::rtp::parse_ip_discovery;
+
+fuzz_target!(|data: &[u8]| {
+    // the first four bytes pick the ssrc, so valid responses can be found
+    if let Some((ssrc, buf)) = data.split_first_chunk::<4>() {
+        let _ = parse_ip_discovery(buf, u32::from_be_bytes(*ssrc));
+    }
+});
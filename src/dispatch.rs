@@ -0,0 +1,828 @@
+//! Handing interactions to the queues.
+//!
+//! A [`CommandRouter`] maps the name of each of swc's [`commands`] to how its
+//! options are parsed into an [`Action`], and sends the action to the guild's
+//! queue. Buttons on the [panel](crate::music::panel) and the `/bulkplay`
+//! modal are handled too, so a bot only has to hand it the interactions it
+//! gets.
+//!
+//! A bot can have other commands besides swc's, all coming in on the same
+//! interaction route. Interactions swc doesn't know, like a command it
+//! doesn't have or a button it didn't make, go to a [`Fallback`] instead of
+//! being dropped, so the bot can handle them itself.
+//!
+//! [`commands`]: crate::commands
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use twilight_http::Client as HttpClient;
+use twilight_model::application::interaction::{
+    application_command::CommandData, message_component::MessageComponentInteractionData,
+    modal::ModalInteractionData, Interaction, InteractionData,
+};
+use twilight_model::channel::message::component::{TextInput, TextInputStyle};
+use twilight_model::guild::Permissions;
+use twilight_model::id::{
+    marker::{AttachmentMarker, GuildMarker, RoleMarker},
+    Id,
+};
+
+use crate::command_options;
+use crate::i18n;
+use crate::interaction::{ext::*, modal, modal_value, subcommand, text_input, SchemaError};
+use crate::music::{self, respond::InteractionResponder, Action, QueueServer};
+use crate::scrobble::Service;
+use crate::store::{AgePolicy, BlockKind, Feature, NowPlayingStatus};
+
+/// The custom id of the `/bulkplay` modal.
+pub const BULKPLAY_MODAL: &str = "bulkplay";
+
+/// The custom id of the queries input in the `/bulkplay` modal.
+pub const BULKPLAY_QUERIES: &str = "queries";
+
+/// Parses the options of a command into an [`Action`].
+pub type Parser = fn(&CommandData) -> Result<Action, SchemaError>;
+
+/// Handles the interactions swc doesn't know.
+///
+/// Every method logs the interaction and drops it by default.
+pub trait Fallback: Send + Sync {
+    /// Handles a command that isn't one of swc's.
+    fn command(&self, _interaction: Interaction, data: Box<CommandData>) -> BoxFuture<'_, ()> {
+        tracing::warn!(command = data.name, "got unknown command");
+        Box::pin(async {})
+    }
+
+    /// Handles a button or select menu that isn't one of swc's.
+    fn component(
+        &self,
+        _interaction: Interaction,
+        data: MessageComponentInteractionData,
+    ) -> BoxFuture<'_, ()> {
+        tracing::warn!(custom_id = data.custom_id, "got unknown component");
+        Box::pin(async {})
+    }
+
+    /// Handles a modal that isn't one of swc's.
+    fn modal(&self, _interaction: Interaction, data: ModalInteractionData) -> BoxFuture<'_, ()> {
+        tracing::warn!(custom_id = data.custom_id, "got unknown modal");
+        Box::pin(async {})
+    }
+}
+
+/// A [`Fallback`] that logs unknown interactions and drops them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IgnoreUnknown;
+
+impl Fallback for IgnoreUnknown {}
+
+/// Sends the interactions of swc's commands to the queues.
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use swc::dispatch::CommandRouter;
+/// # use swc::music::QueueServer;
+/// # use twilight_http::Client;
+/// # use twilight_model::application::interaction::Interaction;
+/// # async fn handle(queue_server: &Arc<QueueServer>, http: &Arc<Client>, interaction: Interaction) {
+/// let router = CommandRouter::new();
+/// router.handle(queue_server, http, interaction).await;
+/// # }
+/// ```
+pub struct CommandRouter {
+    routes: HashMap<String, Parser>,
+    fallback: Box<dyn Fallback>,
+}
+
+impl CommandRouter {
+    /// Creates a `CommandRouter` for every one of swc's commands, which drops
+    /// anything else.
+    pub fn new() -> CommandRouter {
+        CommandRouter {
+            routes: ROUTES
+                .iter()
+                .map(|&(name, parser)| (name.to_owned(), parser))
+                .collect(),
+            fallback: Box::new(IgnoreUnknown),
+        }
+    }
+
+    /// Hands the interactions swc doesn't know to `fallback`.
+    pub fn with_fallback(self, fallback: impl Fallback + 'static) -> CommandRouter {
+        CommandRouter {
+            fallback: Box::new(fallback),
+            ..self
+        }
+    }
+
+    /// Parses a command with `parser`, replacing how it was parsed if it was
+    /// already routed.
+    pub fn with_route(mut self, name: impl Into<String>, parser: Parser) -> CommandRouter {
+        self.routes.insert(name.into(), parser);
+        self
+    }
+
+    /// Gets the action of a command from its options.
+    ///
+    /// Returns `Ok(None)` if the command isn't routed.
+    pub fn parse(&self, data: &CommandData) -> Result<Option<Action>, SchemaError> {
+        self.routes
+            .get(&data.name)
+            .map(|parser| parser(data))
+            .transpose()
+    }
+
+    /// Handles an interaction, sending what it asks for to its guild's
+    /// queue.
+    ///
+    /// **This is run on the main thread! Do not block!**
+    pub async fn handle(
+        &self,
+        queue_server: &Arc<QueueServer>,
+        http_client: &Arc<HttpClient>,
+        mut interaction: Interaction,
+    ) {
+        match interaction.data.take() {
+            Some(InteractionData::ApplicationCommand(data)) => {
+                self.handle_command(queue_server, http_client, interaction, data)
+                    .await;
+            }
+            Some(InteractionData::MessageComponent(data)) => {
+                self.handle_component(queue_server, interaction, data).await;
+            }
+            Some(InteractionData::ModalSubmit(data)) => {
+                self.handle_modal(queue_server, interaction, data).await;
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles a command.
+    async fn handle_command(
+        &self,
+        queue_server: &Arc<QueueServer>,
+        http_client: &Arc<HttpClient>,
+        interaction: Interaction,
+        data: Box<CommandData>,
+    ) {
+        // the queries are asked for in a modal, which has to be the response
+        if data.name == "bulkplay" {
+            let locale = interaction.locale.as_deref();
+            let input = TextInput {
+                placeholder: Some(String::from("https://www.youtube.com/watch?v=...")),
+                max_length: Some(4000),
+                ..text_input(
+                    BULKPLAY_QUERIES,
+                    i18n::tr(locale, "urls or queries, one per line"),
+                    TextInputStyle::Paragraph,
+                )
+            };
+
+            let _ = http_client
+                .interaction(interaction.application_id)
+                .create_response(
+                    interaction.id,
+                    &interaction.token,
+                    &modal(BULKPLAY_MODAL, i18n::tr(locale, "bulk play"), vec![input]),
+                )
+                .await;
+            return;
+        }
+
+        let action = match self.parse(&data) {
+            Ok(Some(action)) => Ok(action),
+            // someone else's command
+            Ok(None) => return self.fallback.command(interaction, data).await,
+            Err(err) => Err(err),
+        };
+
+        let Some(command_data) = command_data(interaction, false) else {
+            return;
+        };
+
+        let action = match action {
+            Ok(action) => action,
+            Err(err) => {
+                tracing::warn!(%err, command = data.name, "command doesn't match schema");
+
+                command_data
+                    .respond(http_client)
+                    .error(command_data.trf(
+                        "this command is out of date, try again later ({error})",
+                        &[("error", &err)],
+                    ))
+                    .respond()
+                    .await;
+                return;
+            }
+        };
+
+        // send to the queue
+        queue_server
+            .command(
+                command_data.guild_id,
+                music::Command {
+                    data: command_data,
+                    action,
+                },
+            )
+            .await;
+    }
+
+    /// Handles a button press.
+    async fn handle_component(
+        &self,
+        queue_server: &Arc<QueueServer>,
+        interaction: Interaction,
+        data: MessageComponentInteractionData,
+    ) {
+        let Some(action) = music::panel::action(&data.custom_id) else {
+            return self.fallback.component(interaction, data).await;
+        };
+
+        let Some(command_data) = command_data(interaction, true) else {
+            return;
+        };
+
+        queue_server
+            .command(
+                command_data.guild_id,
+                music::Command {
+                    data: command_data,
+                    action,
+                },
+            )
+            .await;
+    }
+
+    /// Handles a submitted modal.
+    async fn handle_modal(
+        &self,
+        queue_server: &Arc<QueueServer>,
+        interaction: Interaction,
+        data: ModalInteractionData,
+    ) {
+        if data.custom_id != BULKPLAY_MODAL {
+            return self.fallback.modal(interaction, data).await;
+        }
+
+        let queries = modal_value(&data, BULKPLAY_QUERIES)
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .take(music::MAX_BATCH_QUERIES)
+            .map(String::from)
+            .collect();
+
+        let Some(command_data) = command_data(interaction, false) else {
+            return;
+        };
+
+        queue_server
+            .command(
+                command_data.guild_id,
+                music::Command {
+                    data: command_data,
+                    action: Action::BulkPlay(queries, Default::default()),
+                },
+            )
+            .await;
+    }
+}
+
+impl Default for CommandRouter {
+    fn default() -> CommandRouter {
+        CommandRouter::new()
+    }
+}
+
+/// Gets what the queue needs to know about an interaction.
+///
+/// Returns `None` if the interaction isn't from a guild.
+pub fn command_data(interaction: Interaction, from_component: bool) -> Option<music::CommandData> {
+    let guild_id = interaction.guild_id?;
+    let user = interaction.member.as_ref().and_then(|m| m.user.as_ref())?;
+    let channel_id = interaction.channel.as_ref().map(|c| c.id)?;
+
+    let responder = InteractionResponder {
+        application_id: interaction.application_id,
+        interaction_id: interaction.id,
+        token: interaction.token,
+        channel_id,
+        received_at: Instant::now(),
+        from_component,
+        lost: Default::default(),
+    };
+
+    Some(music::CommandData {
+        responder: Arc::new(responder),
+        outbox: Default::default(),
+        guild_id,
+        channel_id,
+        user_id: user.id,
+        roles: interaction
+            .member
+            .as_ref()
+            .map(|member| member.roles.clone())
+            .unwrap_or_default(),
+        permissions: interaction
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .unwrap_or_else(Permissions::empty),
+        locale: interaction.locale,
+    })
+}
+
+command_options! {
+    /// The options of `/play` and `/playnow`.
+    struct PlayArgs {
+        query: String,
+        limit: Option<i64>,
+        start: Option<i64>,
+        shuffle: Option<bool>,
+        reverse: Option<bool>,
+        position: Option<i64>,
+        interrupt: Option<bool>,
+        park: Option<bool>,
+    }
+}
+
+command_options! {
+    /// The options of `/shuffle`.
+    struct ShuffleArgs {
+        seed: Option<i64>,
+    }
+}
+
+command_options! {
+    /// The options of `/autodisconnect` and `/autoplay`.
+    struct ToggleArgs {
+        setting: Option<bool>,
+    }
+}
+
+command_options! {
+    /// The options of `/bookmark`.
+    struct BookmarkArgs {
+        name: Option<String>,
+    }
+}
+
+command_options! {
+    /// The options of `/jump`.
+    struct JumpArgs {
+        bookmark: String,
+    }
+}
+
+command_options! {
+    /// The options of the `/playlist` subcommands.
+    struct NameArgs {
+        name: String,
+    }
+}
+
+command_options! {
+    /// The options of `/settings`.
+    struct SettingsArgs {
+        queuemode: Option<music::QueueMode>,
+        presence: Option<bool>,
+        sponsorblock: Option<music::SponsorBlockMode>,
+        djrole: Option<Id<RoleMarker>>,
+        maxduration: Option<i64>,
+        livestreams: Option<bool>,
+        agerestricted: Option<AgePolicy>,
+        textcommands: Option<bool>,
+        largethumbnails: Option<bool>,
+        highquality: Option<bool>,
+        farewell: Option<bool>,
+        status: Option<NowPlayingStatus>,
+    }
+}
+
+command_options! {
+    /// The options of `/tts`.
+    struct TtsArgs {
+        text: String,
+        mode: Option<music::TtsMode>,
+    }
+}
+
+command_options! {
+    /// The options of `/announce`.
+    struct AnnounceArgs {
+        text: Option<String>,
+        clip: Option<String>,
+    }
+}
+
+command_options! {
+    /// The options of `/export`.
+    struct ExportArgs {
+        format: Option<music::ExportFormat>,
+    }
+}
+
+command_options! {
+    /// The options of `/loop`.
+    struct LoopArgs {
+        mode: Option<music::LoopMode>,
+    }
+}
+
+command_options! {
+    /// The options of the `/block` subcommands that change the blocklist.
+    struct BlockArgs {
+        value: String,
+    }
+}
+
+command_options! {
+    /// The options of `/scrobble listenbrainz`.
+    struct ListenBrainzArgs {
+        token: String,
+    }
+}
+
+command_options! {
+    /// The options of `/scrobble lastfm`.
+    struct LastFmArgs {
+        apikey: String,
+        apisecret: String,
+        sessionkey: String,
+    }
+}
+
+command_options! {
+    /// The options of `/mynext`.
+    struct MyNextArgs {
+        position: i64,
+    }
+}
+
+command_options! {
+    /// The options of `/lock`.
+    struct LockArgs {
+        minutes: Option<i64>,
+    }
+}
+
+command_options! {
+    /// The options of `/debug dump`.
+    struct DebugArgs {
+        guild: Option<Id<GuildMarker>>,
+    }
+}
+
+command_options! {
+    /// The options of `/stats`.
+    struct StatsArgs {
+        period: Option<music::StatsPeriod>,
+    }
+}
+
+command_options! {
+    /// The options of `/chapter`.
+    struct ChapterArgs {
+        number: i64,
+    }
+}
+
+command_options! {
+    /// The options of `/player`.
+    struct PlayerArgs {
+        progress: Option<bool>,
+    }
+}
+
+command_options! {
+    /// The options of `/import`.
+    struct ImportArgs {
+        file: Option<Id<AttachmentMarker>>,
+        urls: Option<String>,
+    }
+}
+
+command_options! {
+    /// The options of the `/operator` subcommands for a server.
+    struct GuildArgs {
+        guild: Id<GuildMarker>,
+    }
+}
+
+command_options! {
+    /// The options of `/operator allowlist`.
+    struct AllowlistArgs {
+        enabled: bool,
+    }
+}
+
+command_options! {
+    /// The options of `/operator feature`.
+    struct FeatureArgs {
+        guild: Id<GuildMarker>,
+        feature: Feature,
+        enabled: bool,
+    }
+}
+
+/// How each of swc's commands is parsed, by name.
+///
+/// `/bulkplay` isn't here, since it answers with a modal instead.
+const ROUTES: &[(&str, Parser)] = &[
+    ("play", play),
+    ("playnow", play),
+    ("skip", |_| Ok(Action::Skip)),
+    ("pause", |_| Ok(Action::Pause)),
+    ("stop", |_| Ok(Action::Stop)),
+    ("loop", |data| {
+        Ok(Action::Loop(LoopArgs::from_options(&data.options)?.mode))
+    }),
+    ("player", |data| {
+        let args = PlayerArgs::from_options(&data.options)?;
+        Ok(Action::Player(args.progress.unwrap_or_default()))
+    }),
+    ("queue", |_| Ok(Action::Queue)),
+    ("mynext", |data| {
+        let args = MyNextArgs::from_options(&data.options)?;
+        Ok(Action::MyNext(args.position as usize))
+    }),
+    ("chapters", |_| Ok(Action::Chapters)),
+    ("ping", |_| Ok(Action::Ping)),
+    ("stats", |data| {
+        let args = StatsArgs::from_options(&data.options)?;
+        Ok(Action::Stats(args.period.unwrap_or_default()))
+    }),
+    ("chapter", |data| {
+        let args = ChapterArgs::from_options(&data.options)?;
+        Ok(Action::Chapter(args.number as usize))
+    }),
+    ("shuffle", |data| {
+        let args = ShuffleArgs::from_options(&data.options)?;
+        Ok(Action::Shuffle(args.seed.map(|seed| seed as u64)))
+    }),
+    ("unshuffle", |_| Ok(Action::Unshuffle)),
+    ("disconnect", |_| Ok(Action::Disconnect)),
+    ("autodisconnect", |data| {
+        let args = ToggleArgs::from_options(&data.options)?;
+        Ok(Action::AutoDisconnect(args.setting))
+    }),
+    ("autoplay", |data| {
+        let args = ToggleArgs::from_options(&data.options)?;
+        Ok(Action::Autoplay(args.setting))
+    }),
+    ("bookmark", |data| {
+        let args = BookmarkArgs::from_options(&data.options)?;
+        Ok(Action::Bookmark(args.name))
+    }),
+    ("jump", |data| {
+        let args = JumpArgs::from_options(&data.options)?;
+        Ok(Action::Jump(args.bookmark))
+    }),
+    ("settings", settings),
+    ("tts", |data| {
+        let args = TtsArgs::from_options(&data.options)?;
+        Ok(Action::Tts(args.text, args.mode.unwrap_or_default()))
+    }),
+    ("announce", |data| {
+        let args = AnnounceArgs::from_options(&data.options)?;
+
+        Ok(Action::Announce(music::Announcement {
+            text: args.text,
+            clip: args.clip,
+        }))
+    }),
+    ("export", |data| {
+        let args = ExportArgs::from_options(&data.options)?;
+        Ok(Action::Export(args.format.unwrap_or_default()))
+    }),
+    ("import", import),
+    ("playlist", playlist),
+    ("operator", operator),
+    ("debug", |data| {
+        let subcommand = subcommand(&data.options)?;
+
+        match subcommand.path()[..] {
+            ["dump"] => Ok(Action::Debug(
+                DebugArgs::from_options(subcommand.options)?.guild,
+            )),
+            _ => Err(subcommand.unknown()),
+        }
+    }),
+    ("admin", |data| {
+        let subcommand = subcommand(&data.options)?;
+
+        match subcommand.path()[..] {
+            ["update-ytdl"] => Ok(Action::UpdateYtdl),
+            _ => Err(subcommand.unknown()),
+        }
+    }),
+    ("block", block),
+    ("quarantine", |data| {
+        let subcommand = subcommand(&data.options)?;
+
+        let action = match subcommand.path()[..] {
+            ["list"] => music::QuarantineAction::List,
+            ["clear"] => music::QuarantineAction::Clear,
+            _ => return Err(subcommand.unknown()),
+        };
+
+        Ok(Action::Quarantine(action))
+    }),
+    ("lock", |data| {
+        let args = LockArgs::from_options(&data.options)?;
+
+        Ok(Action::Lock(
+            args.minutes
+                .map(|mins| Duration::from_secs(mins as u64 * 60)),
+        ))
+    }),
+    ("unlock", |_| Ok(Action::Unlock)),
+    ("scrobble", scrobble),
+    (crate::PLAY_LINKS, play_links),
+];
+
+fn play(data: &CommandData) -> Result<Action, SchemaError> {
+    let args = PlayArgs::from_options(&data.options)?;
+
+    let options = music::PlayOptions {
+        playnow: data.name == "playnow",
+        position: args.position.map(|position| position as usize),
+        limit: args.limit.map(|limit| limit as usize),
+        start: args.start.map(|start| start as usize),
+        shuffle: args.shuffle.unwrap_or_default(),
+        reverse: args.reverse.unwrap_or_default(),
+        interrupt: args.interrupt.unwrap_or_default(),
+        park: args.park.unwrap_or_default(),
+        ..Default::default()
+    };
+
+    Ok(Action::Play(args.query, options))
+}
+
+/// Plays the links in the message the command was used on.
+fn play_links(data: &CommandData) -> Result<Action, SchemaError> {
+    let message = data
+        .target_id
+        .and_then(|target_id| data.resolved.as_ref()?.messages.get(&target_id.cast()))
+        .ok_or(SchemaError::Invalid("message"))?;
+
+    Ok(Action::BulkPlay(
+        music::find_urls(&message.content),
+        Default::default(),
+    ))
+}
+
+fn settings(data: &CommandData) -> Result<Action, SchemaError> {
+    let args = SettingsArgs::from_options(&data.options)?;
+
+    Ok(Action::Settings(music::SettingsUpdate {
+        queue_mode: args.queuemode,
+        presence: args.presence,
+        sponsorblock: args.sponsorblock,
+        dj_role: args.djrole,
+        max_duration: args
+            .maxduration
+            .map(|mins| Duration::from_secs(mins as u64 * 60)),
+        livestreams: args.livestreams,
+        age_policy: args.agerestricted,
+        text_commands: args.textcommands,
+        large_thumbnails: args.largethumbnails,
+        high_quality: args.highquality,
+        farewell: args.farewell,
+        now_playing_status: args.status,
+    }))
+}
+
+fn import(data: &CommandData) -> Result<Action, SchemaError> {
+    let args = ImportArgs::from_options(&data.options)?;
+    let file = args
+        .file
+        .map(|id| {
+            data.resolved
+                .as_ref()
+                .and_then(|resolved| resolved.attachments.get(&id))
+                .ok_or(SchemaError::Invalid("file"))
+        })
+        .transpose()?;
+
+    // an empty list fails with nothing to import
+    let source = match file {
+        Some(file) => music::ImportSource::Attachment {
+            url: file.url.clone(),
+            filename: file.filename.clone(),
+            size: file.size,
+        },
+        None => music::ImportSource::Text(args.urls.unwrap_or_default()),
+    };
+
+    Ok(Action::Import(source))
+}
+
+fn playlist(data: &CommandData) -> Result<Action, SchemaError> {
+    let subcommand = subcommand(&data.options)?;
+    let name_arg = || NameArgs::from_options(subcommand.options).map(|args| args.name);
+
+    let action = match subcommand.path()[..] {
+        ["save"] => music::PlaylistAction::Save(name_arg()?),
+        ["play"] => music::PlaylistAction::Play(name_arg()?),
+        ["list"] => music::PlaylistAction::List,
+        ["delete"] => music::PlaylistAction::Delete(name_arg()?),
+        _ => return Err(subcommand.unknown()),
+    };
+
+    Ok(Action::Playlist(action))
+}
+
+fn operator(data: &CommandData) -> Result<Action, SchemaError> {
+    let subcommand = subcommand(&data.options)?;
+    let options = subcommand.options;
+    let guild_arg = || GuildArgs::from_options(options).map(|args| args.guild);
+
+    let action = match subcommand.path()[..] {
+        ["allow"] => music::OperatorAction::Allow(guild_arg()?),
+        ["deny"] => music::OperatorAction::Deny(guild_arg()?),
+        ["reset"] => music::OperatorAction::Reset(guild_arg()?),
+        ["show"] => music::OperatorAction::Show(guild_arg()?),
+        ["allowlist"] => {
+            music::OperatorAction::Allowlist(AllowlistArgs::from_options(options)?.enabled)
+        }
+        ["feature"] => {
+            let args = FeatureArgs::from_options(options)?;
+            music::OperatorAction::Feature(args.guild, args.feature, args.enabled)
+        }
+        _ => return Err(subcommand.unknown()),
+    };
+
+    Ok(Action::Operator(action))
+}
+
+fn block(data: &CommandData) -> Result<Action, SchemaError> {
+    let subcommand = subcommand(&data.options)?;
+    let entry = |kind| {
+        let kind = BlockKind::from_name(kind).ok_or_else(|| subcommand.unknown())?;
+        let args = BlockArgs::from_options(subcommand.options)?;
+        Ok::<_, SchemaError>((kind, args.value))
+    };
+
+    let action = match subcommand.path()[..] {
+        ["add", kind] => {
+            let (kind, value) = entry(kind)?;
+            music::BlockAction::Add(kind, value)
+        }
+        ["remove", kind] => {
+            let (kind, value) = entry(kind)?;
+            music::BlockAction::Remove(kind, value)
+        }
+        ["list"] => music::BlockAction::List,
+        _ => return Err(subcommand.unknown()),
+    };
+
+    Ok(Action::Block(action))
+}
+
+fn scrobble(data: &CommandData) -> Result<Action, SchemaError> {
+    let subcommand = subcommand(&data.options)?;
+
+    let service = match subcommand.path()[..] {
+        ["listenbrainz"] => {
+            let args = ListenBrainzArgs::from_options(subcommand.options)?;
+            Some(Service::ListenBrainz { token: args.token })
+        }
+        ["lastfm"] => {
+            let args = LastFmArgs::from_options(subcommand.options)?;
+            Some(Service::LastFm {
+                api_key: args.apikey,
+                api_secret: args.apisecret,
+                session_key: args.sessionkey,
+            })
+        }
+        ["off"] => None,
+        _ => return Err(subcommand.unknown()),
+    };
+
+    Ok(Action::Scrobble(service))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_every_command() {
+        let router = CommandRouter::new();
+
+        for command in crate::commands().iter().chain(&crate::dev_commands()) {
+            assert!(
+                command.name == "bulkplay" || router.routes.contains_key(&command.name),
+                "/{} isn't routed",
+                command.name
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Errors for the whole crate.
+//!
+//! Each module has its own error type for the details, and [`Error`] sorts
+//! them into a few categories so embedders can decide how to react without
+//! knowing about every module.
+
+#[cfg(feature = "music")]
+use crate::music::UserError;
+use crate::voice;
+use crate::ytdl::QueryError;
+
+use thiserror::Error;
+
+/// Any error from `swc`.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The user did something they can't do, like controlling the bot from
+    /// another channel. The message is safe to show to them.
+    #[cfg(feature = "music")]
+    #[error(transparent)]
+    User(#[from] UserError),
+    /// The voice connection or audio source failed.
+    ///
+    /// Boxed since voice errors are much bigger than the rest.
+    #[error(transparent)]
+    Voice(Box<voice::Error>),
+    /// A `youtube-dl` query failed.
+    #[error(transparent)]
+    Query(#[from] QueryError),
+    /// Something failed that the user can't do anything about.
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Wraps any other error as an [`Error::Internal`].
+    pub fn internal(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+        Error::Internal(err.into())
+    }
+}
+
+impl From<voice::Error> for Error {
+    fn from(err: voice::Error) -> Error {
+        Error::Voice(Box::new(err))
+    }
+}
+
+impl From<voice::source::Error> for Error {
+    fn from(err: voice::source::Error) -> Error {
+        voice::Error::from(err).into()
+    }
+}
+
+#[cfg(feature = "music")]
+impl From<crate::store::Error> for Error {
+    fn from(err: crate::store::Error) -> Error {
+        Error::internal(err)
+    }
+}
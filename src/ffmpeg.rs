@@ -0,0 +1,155 @@
+//! `ffmpeg` configuration.
+//!
+//! Every [`Source`](crate::voice::Source) runs `ffmpeg` to decode its audio.
+//! Like `youtube-dl`, the executable and the options for each process are set
+//! once at startup.
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+
+use std::env;
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+static FFMPEG_EXECUTABLE: OnceLock<String> = OnceLock::new();
+
+/// The `ffmpeg` executable.
+///
+/// If the executable was never initialized, `ffmpeg` is used.
+pub fn ffmpeg_executable() -> &'static str {
+    FFMPEG_EXECUTABLE.get_or_init(|| String::from("ffmpeg"))
+}
+
+pub fn init_ffmpeg_executable<F>(f: F) -> &'static str
+where
+    F: FnOnce() -> String,
+{
+    FFMPEG_EXECUTABLE.get_or_init(f)
+}
+
+/// Gets the version of the `ffmpeg` executable.
+pub async fn ffmpeg_version() -> Result<String, std::io::Error> {
+    let out = Command::new(ffmpeg_executable())
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+
+    if !out.status.success() {
+        return Err(std::io::Error::other(format!(
+            "ffmpeg exited with {}",
+            out.status
+        )));
+    }
+
+    // like "ffmpeg version 6.1.1 Copyright (c) ..."
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let version = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(2))
+        .unwrap_or("unknown");
+
+    Ok(version.to_owned())
+}
+
+static FFMPEG_OPTIONS: OnceLock<FfmpegOptions> = OnceLock::new();
+
+/// The options for every `ffmpeg` process.
+///
+/// If the options were never initialized, the defaults are used.
+pub fn ffmpeg_options() -> &'static FfmpegOptions {
+    FFMPEG_OPTIONS.get_or_init(FfmpegOptions::default)
+}
+
+pub fn init_ffmpeg_options<F>(f: F) -> &'static FfmpegOptions
+where
+    F: FnOnce() -> FfmpegOptions,
+{
+    FFMPEG_OPTIONS.get_or_init(f)
+}
+
+/// Options for `ffmpeg` processes.
+#[derive(Clone, Debug)]
+pub struct FfmpegOptions {
+    /// The niceness to run `ffmpeg` with, through `nice`.
+    ///
+    /// Higher values give `ffmpeg` less priority, so a busy host doesn't
+    /// starve the rest of the bot.
+    pub niceness: Option<i32>,
+    /// How many threads `ffmpeg` may use.
+    pub threads: Option<usize>,
+    /// The `-loglevel` of `ffmpeg`. Its output is logged as warnings.
+    pub loglevel: String,
+}
+
+impl Default for FfmpegOptions {
+    fn default() -> FfmpegOptions {
+        FfmpegOptions {
+            niceness: None,
+            threads: None,
+            loglevel: String::from("warning"),
+        }
+    }
+}
+
+impl FfmpegOptions {
+    /// Reads the options from the environment.
+    ///
+    /// | Variable           | Option         |
+    /// |--------------------|----------------|
+    /// | `FFMPEG_NICENESS`  | [`niceness`]   |
+    /// | `FFMPEG_THREADS`   | [`threads`]    |
+    /// | `FFMPEG_LOGLEVEL`  | [`loglevel`]   |
+    ///
+    /// [`niceness`]: FfmpegOptions::niceness
+    /// [`threads`]: FfmpegOptions::threads
+    /// [`loglevel`]: FfmpegOptions::loglevel
+    pub fn from_env() -> FfmpegOptions {
+        let default = FfmpegOptions::default();
+
+        FfmpegOptions {
+            niceness: env::var("FFMPEG_NICENESS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            threads: env::var("FFMPEG_THREADS").ok().and_then(|v| v.parse().ok()),
+            loglevel: env::var("FFMPEG_LOGLEVEL").unwrap_or(default.loglevel),
+        }
+    }
+
+    /// Creates a command running `executable` with these options.
+    ///
+    /// Arguments added to the command are passed to `ffmpeg`.
+    pub fn command(&self, executable: &str) -> Command {
+        let mut command = match self.niceness {
+            Some(niceness) => {
+                let mut command = Command::new("nice");
+                command.args(["-n", &niceness.to_string(), executable]);
+                command
+            }
+            None => Command::new(executable),
+        };
+
+        command.args(["-nostdin", "-loglevel", &self.loglevel]);
+
+        if let Some(threads) = self.threads {
+            command.args(["-threads", &threads.to_string()]);
+        }
+
+        command
+    }
+}
+
+/// Logs everything `ffmpeg` prints to `stderr`.
+///
+/// Resolves once `stderr` is closed.
+pub async fn log_stderr(stderr: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(stderr).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        warn!(%line, "ffmpeg");
+    }
+}
@@ -0,0 +1,37 @@
+//! The HTTPS client shared by everything that talks to a web API.
+//!
+//! Building a client loads the native root certificates, so it's done once
+//! and the client is cloned where it's needed. Clones share a connection
+//! pool.
+
+use std::sync::OnceLock;
+
+use hyper::client::HttpConnector;
+use hyper::Client;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+
+/// An HTTPS client.
+pub type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+static CLIENT: OnceLock<HttpsClient> = OnceLock::new();
+
+/// The shared client, which only makes requests over HTTPS.
+pub fn https_client() -> &'static HttpsClient {
+    CLIENT.get_or_init(|| build(true))
+}
+
+/// Builds a new client.
+///
+/// If `https_only` is `false`, the client makes plain HTTP requests too, for
+/// services that might be running on the same host.
+pub fn build(https_only: bool) -> HttpsClient {
+    let builder = HttpsConnectorBuilder::new().with_native_roots();
+
+    let connector = if https_only {
+        builder.https_only().enable_http1().build()
+    } else {
+        builder.https_or_http().enable_http1().build()
+    };
+
+    Client::builder().build(connector)
+}
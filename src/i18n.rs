@@ -0,0 +1,152 @@
+//! Localization.
+//!
+//! English strings double as the keys for every other language, so English
+//! never needs a table: anything that isn't translated falls back to the
+//! English string it was looked up with.
+//!
+//! To add a language, add a [`Language`] to [`LANGUAGES`] with the
+//! [Discord locale][1] it is for. Command descriptions are looked up by the
+//! command's name (`play`) and option descriptions by the command and option
+//! name (`play.query`), with subcommands in between (`playlist.save.name`);
+//! everything else is looked up by the English string.
+//!
+//! [1]: https://discord.com/developers/docs/reference#locales
+
+mod de;
+
+use twilight_model::application::command::{Command, CommandOption};
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// A table of translations for a locale.
+pub struct Language {
+    /// The Discord locale, like `es-ES` or `de`.
+    pub locale: &'static str,
+    /// Pairs of English strings (or command keys) and their translations.
+    pub strings: &'static [(&'static str, &'static str)],
+}
+
+impl Language {
+    /// Gets the translation of `key`.
+    pub fn get(&self, key: &str) -> Option<&'static str> {
+        self.strings
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, translation)| *translation)
+    }
+}
+
+/// Every language other than English.
+pub static LANGUAGES: &[Language] = &[de::GERMAN];
+
+/// Finds the language for a Discord locale.
+///
+/// A locale with a region (`es-419`) falls back to a language without one
+/// (`es`).
+pub fn language(locale: &str) -> Option<&'static Language> {
+    LANGUAGES
+        .iter()
+        .find(|language| language.locale == locale)
+        .or_else(|| {
+            let (lang, _region) = locale.split_once('-')?;
+            LANGUAGES.iter().find(|language| language.locale == lang)
+        })
+}
+
+/// Translates an English string to a locale.
+///
+/// Returns `text` if there is no translation.
+pub fn tr<'a>(locale: Option<&str>, text: &'a str) -> &'a str {
+    locale
+        .and_then(language)
+        .and_then(|language| language.get(text))
+        .unwrap_or(text)
+}
+
+/// Translates an English string to a locale, then fills in its `{name}`
+/// placeholders with `args`.
+pub fn trf(locale: Option<&str>, text: &str, args: &[(&str, &(dyn Display + Sync))]) -> String {
+    let mut text = tr(locale, text).to_owned();
+
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+
+    text
+}
+
+/// Fills in the description localizations of commands and their options.
+pub fn localize_commands(commands: &mut [Command]) {
+    for command in commands {
+        command.description_localizations = localizations(&command.name);
+
+        localize_options(&command.name, &mut command.options);
+    }
+}
+
+/// Fills in the description localizations of options, and the options of
+/// subcommands.
+fn localize_options(prefix: &str, options: &mut [CommandOption]) {
+    for option in options {
+        let key = format!("{}.{}", prefix, option.name);
+        option.description_localizations = localizations(&key);
+
+        if let Some(options) = option.options.as_mut() {
+            localize_options(&key, options);
+        }
+    }
+}
+
+/// Gets every translation of `key`, or `None` if there are none.
+fn localizations(key: &str) -> Option<HashMap<String, String>> {
+    let localizations = LANGUAGES
+        .iter()
+        .filter_map(|language| {
+            language
+                .get(key)
+                .map(|translation| (language.locale.to_owned(), translation.to_owned()))
+        })
+        .collect::<HashMap<_, _>>();
+
+    if localizations.is_empty() {
+        None
+    } else {
+        Some(localizations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translations_keep_placeholders() {
+        let placeholders = |text: &str| {
+            let mut names = text
+                .split('{')
+                .skip(1)
+                .filter_map(|rest| rest.split_once('}').map(|(name, _)| name.to_owned()))
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        for language in LANGUAGES {
+            for (key, translation) in language.strings {
+                assert_eq!(
+                    placeholders(key),
+                    placeholders(translation),
+                    "{}: {:?}",
+                    language.locale,
+                    key
+                );
+            }
+        }
+
+        assert_eq!(
+            trf(Some("de-AT"), "missing {name}", &[("name", &"query")]),
+            "query fehlt"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! German.
+
+use super::Language;
+
+pub const GERMAN: Language = Language {
+    locale: "de",
+    strings: &[
+        // commands
+        ("play", "spielt einen Musiktitel ab"),
+        (
+            "playnow",
+            "spielt einen Musiktitel ab und setzt ihn an den Anfang der Warteschlange",
+        ),
+        ("skip", "überspringt den aktuellen Titel"),
+        ("pause", "pausiert den aktuellen Titel oder setzt ihn fort"),
+        ("stop", "hört auf zu spielen und leert die Warteschlange"),
+        ("queue", "zeigt die aktuelle Warteschlange"),
+        ("shuffle", "mischt die Warteschlange"),
+        (
+            "unshuffle",
+            "stellt die Reihenfolge der Warteschlange vor dem Mischen wieder her",
+        ),
+        ("disconnect", "trennt den Musikbot"),
+        (
+            "loop",
+            "wiederholt den aktuellen Titel oder die Warteschlange",
+        ),
+        // errors
+        (
+            "you must be in the same voice channel as the bot to use this!",
+            "dafür musst du im selben Sprachkanal wie der Bot sein!",
+        ),
+        (
+            "you must be in a voice channel to use this!",
+            "dafür musst du in einem Sprachkanal sein!",
+        ),
+        (
+            "the bot must be in a voice channel to use this!",
+            "dafür muss der Bot in einem Sprachkanal sein!",
+        ),
+        (
+            "you must have the DJ role to use this!",
+            "dafür brauchst du die DJ-Rolle!",
+        ),
+        (
+            "you must be able to manage the server to use this!",
+            "dafür musst du den Server verwalten können!",
+        ),
+        (
+            "only the bot's operators can use this!",
+            "das können nur die Betreiber des Bots!",
+        ),
+        (
+            "you're using commands too quickly, slow down!",
+            "du benutzt Befehle zu schnell, mach langsamer!",
+        ),
+        (
+            "the bot isn't available in this server",
+            "der Bot ist auf diesem Server nicht verfügbar",
+        ),
+        (
+            "`{feature}` is turned off in this server",
+            "`{feature}` ist auf diesem Server ausgeschaltet",
+        ),
+        ("audio backend unavailable", "Audio-Backend nicht verfügbar"),
+        (
+            "<@{user}> locked the queue for now",
+            "<@{user}> hat die Warteschlange vorerst gesperrt",
+        ),
+        (
+            "the bot is playing in as many servers as it can right now, so this server is \
+                #{place} in line. I'll say so here when there's room!",
+            "der Bot spielt gerade auf so vielen Servern, wie er kann, also ist dieser Server \
+                #{place} in der Schlange. Ich sage hier Bescheid, sobald Platz ist!",
+        ),
+        ("missing {name}", "{name} fehlt"),
+        ("invalid {name}", "ungültige Angabe für {name}"),
+        (
+            "failed to query: {error}",
+            "Abfrage fehlgeschlagen: {error}",
+        ),
+        (
+            "couldn't read the message you replied to",
+            "die Nachricht, auf die du geantwortet hast, konnte nicht gelesen werden",
+        ),
+        (
+            "there are no links to play",
+            "es gibt keine Links zum Abspielen",
+        ),
+        (
+            "there's no track at that position",
+            "an dieser Stelle ist kein Titel",
+        ),
+        (
+            "the queue only has {count} tracks, so the position can be at most {max}",
+            "die Warteschlange hat nur {count} Titel, also darf die Position höchstens {max} \
+                sein",
+        ),
+        // responses
+        ("enqueued track", "Titel eingereiht"),
+        ("playing track now", "Titel wird jetzt gespielt"),
+        (
+            "playing your track next",
+            "dein Titel wird als Nächstes gespielt",
+        ),
+        ("skipped track", "Titel übersprungen"),
+        (
+            "skipped track, now playing nothing :(",
+            "Titel übersprungen, jetzt läuft nichts :(",
+        ),
+        ("paused track", "Titel pausiert"),
+        ("resumed track", "Titel fortgesetzt"),
+        (
+            "stopped playing and cleared the queue",
+            "Wiedergabe gestoppt und Warteschlange geleert",
+        ),
+        ("disconnected!", "getrennt!"),
+        ("the queue is empty", "die Warteschlange ist leer"),
+        ("nothing is playing", "es läuft nichts"),
+        ("nothing currently playing", "gerade läuft nichts"),
+        (
+            "shuffled music queue (seed {seed})",
+            "Warteschlange gemischt (Seed {seed})",
+        ),
+        (
+            "unshuffled music queue",
+            "Mischen der Warteschlange rückgängig gemacht",
+        ),
+        (
+            "the queue isn't shuffled",
+            "die Warteschlange ist nicht gemischt",
+        ),
+        ("now playing", "läuft gerade"),
+        ("position", "Position"),
+        ("plays in", "spielt in"),
+        ("enabled", "eingeschaltet"),
+        ("disabled", "ausgeschaltet"),
+        (
+            "there's room to play music again, use /play to start",
+            "es ist wieder Platz für Musik, starte mit /play",
+        ),
+    ],
+};
@@ -3,10 +3,24 @@
 use twilight_model::application::interaction::application_command::{
     CommandDataOption, CommandOptionValue,
 };
+use twilight_model::application::interaction::modal::ModalInteractionData;
+use twilight_model::channel::message::component::{
+    ActionRow, Component, TextInput, TextInputStyle,
+};
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::{
+    marker::{AttachmentMarker, GuildMarker, RoleMarker},
+    Id,
+};
+
+use std::fmt::{self, Display, Formatter};
 
 pub mod ext {
     pub use super::CommandOptionValueCastExt;
     pub use super::CommandOptionValueListCastExt;
+    pub use super::FromCommandData;
 }
 
 /// Cast extension methods for interaction options.
@@ -43,6 +57,14 @@ pub trait CommandOptionValueListCastExt: Sized {
     fn cast<'a, T>(&'a self, idx: usize) -> Result<T, CastError>
     where
         T: CommandOptionType<'a>;
+
+    /// Casts the value of the option named `name` to a type.
+    ///
+    /// Returns `Ok(None)` if there is no option with that name, which is the
+    /// case for optional options the user did not fill out.
+    fn cast_named<'a, T>(&'a self, name: &str) -> Result<Option<T>, CastError>
+    where
+        T: CommandOptionType<'a>;
 }
 
 impl CommandOptionValueListCastExt for Vec<CommandDataOption> {
@@ -52,6 +74,16 @@ impl CommandOptionValueListCastExt for Vec<CommandDataOption> {
     {
         self.get(idx).ok_or(CastError).and_then(|s| s.cast())
     }
+
+    fn cast_named<'a, T>(&'a self, name: &str) -> Result<Option<T>, CastError>
+    where
+        T: CommandOptionType<'a>,
+    {
+        self.iter()
+            .find(|s| s.name == name)
+            .map(|s| s.cast())
+            .transpose()
+    }
 }
 
 /// A type that a [`CommandOptionValue`] can be.
@@ -86,5 +118,359 @@ impl<'a> CommandOptionType<'a> for bool {
     }
 }
 
+impl<'a> CommandOptionType<'a> for i64 {
+    fn cast_from(value: &'a CommandOptionValue) -> Result<i64, CastError> {
+        match value {
+            CommandOptionValue::Integer(data) => Ok(*data),
+            _ => Err(CastError),
+        }
+    }
+}
+
+impl<'a> CommandOptionType<'a> for &'a Vec<CommandDataOption> {
+    fn cast_from(value: &'a CommandOptionValue) -> Result<&'a Vec<CommandDataOption>, CastError> {
+        match value {
+            CommandOptionValue::SubCommand(options) => Ok(options),
+            _ => Err(CastError),
+        }
+    }
+}
+
+impl<'a> CommandOptionType<'a> for Id<AttachmentMarker> {
+    fn cast_from(value: &'a CommandOptionValue) -> Result<Id<AttachmentMarker>, CastError> {
+        match value {
+            CommandOptionValue::Attachment(data) => Ok(*data),
+            _ => Err(CastError),
+        }
+    }
+}
+
+impl<'a> CommandOptionType<'a> for Id<RoleMarker> {
+    fn cast_from(value: &'a CommandOptionValue) -> Result<Id<RoleMarker>, CastError> {
+        match value {
+            CommandOptionValue::Role(id) => Ok(*id),
+            _ => Err(CastError),
+        }
+    }
+}
+
+/// Guild ids are given as text, since there is no guild option.
+impl<'a> CommandOptionType<'a> for Id<GuildMarker> {
+    fn cast_from(value: &'a CommandOptionValue) -> Result<Id<GuildMarker>, CastError> {
+        <&str>::cast_from(value)?
+            .trim()
+            .parse()
+            .ok()
+            .and_then(Id::new_checked)
+            .ok_or(CastError)
+    }
+}
+
 #[derive(Debug)]
 pub struct CastError;
+
+/// A type that is built from the options of a command.
+///
+/// Implement this with [`command_options!`](crate::command_options), which
+/// looks up each field by its name.
+pub trait FromCommandData: Sized {
+    /// Builds the type from the options of a command.
+    fn from_options(options: &[CommandDataOption]) -> Result<Self, SchemaError>;
+}
+
+/// A field of a [`FromCommandData`] type.
+///
+/// Any [`CommandOptionType`] is a required option, and an `Option` of one is
+/// an optional option.
+pub trait CommandOptionField: Sized {
+    /// Gets the option named `name`.
+    fn from_option(options: &[CommandDataOption], name: &'static str) -> Result<Self, SchemaError>;
+}
+
+impl<T> CommandOptionField for T
+where
+    T: for<'a> CommandOptionType<'a>,
+{
+    fn from_option(options: &[CommandDataOption], name: &'static str) -> Result<T, SchemaError> {
+        Option::<T>::from_option(options, name)?.ok_or(SchemaError::Missing(name))
+    }
+}
+
+impl<T> CommandOptionField for Option<T>
+where
+    T: for<'a> CommandOptionType<'a>,
+{
+    fn from_option(
+        options: &[CommandDataOption],
+        name: &'static str,
+    ) -> Result<Option<T>, SchemaError> {
+        options
+            .iter()
+            .find(|option| option.name == name)
+            .map(|option| option.cast().map_err(|_| SchemaError::Invalid(name)))
+            .transpose()
+    }
+}
+
+/// A subcommand that was used, and its options.
+#[derive(Clone, Copy, Debug)]
+pub struct Subcommand<'a> {
+    /// The group the subcommand is in, if it is in one.
+    pub group: Option<&'a str>,
+    /// The name of the subcommand.
+    pub name: &'a str,
+    /// The options of the subcommand.
+    pub options: &'a [CommandDataOption],
+}
+
+impl<'a> Subcommand<'a> {
+    /// The path to the subcommand, like `["queue", "save"]` for a subcommand
+    /// in a group or `["save"]` for one that isn't.
+    ///
+    /// This is meant to be matched on to route each subcommand.
+    pub fn path(&self) -> Vec<&'a str> {
+        self.group.into_iter().chain([self.name]).collect()
+    }
+
+    /// The error for a subcommand that isn't known.
+    pub fn unknown(&self) -> SchemaError {
+        SchemaError::UnknownSubcommand(self.path().join(" "))
+    }
+}
+
+/// Gets the subcommand of a command, looking through its group if it is in
+/// one.
+pub fn subcommand(options: &[CommandDataOption]) -> Result<Subcommand<'_>, SchemaError> {
+    let option = options.first().ok_or(SchemaError::MissingSubcommand)?;
+
+    match &option.value {
+        CommandOptionValue::SubCommand(options) => Ok(Subcommand {
+            group: None,
+            name: &option.name,
+            options,
+        }),
+        CommandOptionValue::SubCommandGroup(options) => {
+            let subcommand = subcommand(options)?;
+
+            match subcommand.group {
+                // groups can't be nested
+                None => Ok(Subcommand {
+                    group: Some(&option.name),
+                    ..subcommand
+                }),
+                Some(_) => Err(SchemaError::MissingSubcommand),
+            }
+        }
+        _ => Err(SchemaError::MissingSubcommand),
+    }
+}
+
+/// Creates a response that opens a modal with text inputs.
+///
+/// Each input gets its own row. The submitted values are read with
+/// [`modal_value`].
+pub fn modal(
+    custom_id: impl Into<String>,
+    title: impl Into<String>,
+    inputs: Vec<TextInput>,
+) -> InteractionResponse {
+    let components = inputs
+        .into_iter()
+        .map(|input| {
+            Component::ActionRow(ActionRow {
+                components: vec![Component::TextInput(input)],
+            })
+        })
+        .collect();
+
+    InteractionResponse {
+        kind: InteractionResponseType::Modal,
+        data: Some(InteractionResponseData {
+            custom_id: Some(custom_id.into()),
+            title: Some(title.into()),
+            components: Some(components),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Creates a **required** text input for a [`modal`].
+pub fn text_input(
+    custom_id: impl Into<String>,
+    label: impl Into<String>,
+    style: TextInputStyle,
+) -> TextInput {
+    TextInput {
+        custom_id: custom_id.into(),
+        label: label.into(),
+        max_length: None,
+        min_length: None,
+        placeholder: None,
+        required: Some(true),
+        style,
+        value: None,
+    }
+}
+
+/// Gets the value of the text input `custom_id` in a submitted modal.
+///
+/// Returns `None` if there is no such input, or it was left empty.
+pub fn modal_value<'a>(data: &'a ModalInteractionData, custom_id: &str) -> Option<&'a str> {
+    data.components
+        .iter()
+        .flat_map(|row| &row.components)
+        .find(|component| component.custom_id == custom_id)
+        .and_then(|component| component.value.as_deref())
+        .filter(|value| !value.is_empty())
+}
+
+/// Declares a struct of command options, implementing [`FromCommandData`]
+/// for it.
+///
+/// Fields are looked up by their name, so each field must be named after its
+/// option.
+///
+/// ```
+/// # use swc::command_options;
+/// use swc::interaction::FromCommandData;
+///
+/// command_options! {
+///     /// The options of `/shuffle`.
+///     struct ShuffleOptions {
+///         seed: Option<i64>,
+///     }
+/// }
+///
+/// let options = ShuffleOptions::from_options(&[]).unwrap();
+/// assert_eq!(options.seed, None);
+/// ```
+#[macro_export]
+macro_rules! command_options {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::interaction::FromCommandData for $name {
+            fn from_options(
+                options: &[::twilight_model::application::interaction::application_command::CommandDataOption],
+            ) -> ::std::result::Result<Self, $crate::interaction::SchemaError> {
+                ::std::result::Result::Ok($name {
+                    $(
+                        $field: $crate::interaction::CommandOptionField::from_option(
+                            options,
+                            stringify!($field),
+                        )?,
+                    )*
+                })
+            }
+        }
+    };
+}
+
+/// An error building a [`FromCommandData`] type, from the command's options
+/// not matching what the bot expects.
+///
+/// This happens when the commands registered with Discord are out of date.
+#[derive(Debug)]
+pub enum SchemaError {
+    /// A required option is missing.
+    Missing(&'static str),
+    /// An option is the wrong type or an unknown choice.
+    Invalid(&'static str),
+    /// The command has no subcommand.
+    MissingSubcommand,
+    /// The subcommand is unknown.
+    UnknownSubcommand(String),
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Missing(name) => write!(f, "missing option `{}`", name),
+            SchemaError::Invalid(name) => write!(f, "invalid option `{}`", name),
+            SchemaError::MissingSubcommand => f.write_str("missing subcommand"),
+            SchemaError::UnknownSubcommand(name) => write!(f, "unknown subcommand `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    command_options! {
+        struct Options {
+            query: String,
+            limit: Option<i64>,
+            shuffle: Option<bool>,
+        }
+    }
+
+    fn option(name: &str, value: CommandOptionValue) -> CommandDataOption {
+        CommandDataOption {
+            name: name.to_owned(),
+            value,
+        }
+    }
+
+    #[test]
+    fn options_by_name() {
+        let options = Options::from_options(&[
+            option("limit", CommandOptionValue::Integer(5)),
+            option("query", CommandOptionValue::String(String::from("a"))),
+        ])
+        .unwrap();
+
+        assert_eq!(options.query, "a");
+        assert_eq!(options.limit, Some(5));
+        assert_eq!(options.shuffle, None);
+    }
+
+    #[test]
+    fn subcommands_in_groups() {
+        let options = [option(
+            "queue",
+            CommandOptionValue::SubCommandGroup(vec![option(
+                "save",
+                CommandOptionValue::SubCommand(vec![option(
+                    "name",
+                    CommandOptionValue::String(String::from("a")),
+                )]),
+            )]),
+        )];
+
+        let subcommand = subcommand(&options).unwrap();
+        assert_eq!(subcommand.path(), ["queue", "save"]);
+        assert_eq!(subcommand.options.len(), 1);
+
+        let options = [option("list", CommandOptionValue::SubCommand(Vec::new()))];
+        assert_eq!(super::subcommand(&options).unwrap().path(), ["list"]);
+    }
+
+    #[test]
+    fn schema_errors() {
+        assert!(matches!(
+            Options::from_options(&[]),
+            Err(SchemaError::Missing("query"))
+        ));
+        assert!(matches!(
+            Options::from_options(&[option("query", CommandOptionValue::Integer(5))]),
+            Err(SchemaError::Invalid("query"))
+        ));
+    }
+}
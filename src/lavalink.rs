@@ -0,0 +1,726 @@
+//! Playing audio on a [Lavalink][1] node.
+//!
+//! Lavalink is an audio server that plays tracks to Discord voice channels
+//! for bots. [`Node`] is a client for the v4 websocket and REST API of a
+//! node, and [`LavalinkPlayer`] plays a guild's queue on it, so the audio
+//! work happens in another process.
+//!
+//! Lavalink looks tracks up itself, so only tracks with a url can be played.
+//! Text-to-speech and announcements need the native voice client.
+//!
+//! [1]: https://lavalink.dev
+
+use async_tungstenite::tokio::{connect_async, ConnectStream};
+use async_tungstenite::WebSocketStream;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use hyper::{Body, Method, Request, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{RwLock, RwLockReadGuard};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::HeaderValue;
+use tungstenite::Message;
+use twilight_model::gateway::payload::incoming::{VoiceServerUpdate, VoiceStateUpdate};
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
+use twilight_model::voice::VoiceState;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use crate::https::{self, HttpsClient};
+use crate::music::backend::PlaybackBackend;
+use crate::voice::{self, Event, EventType, Overlay, Source};
+use crate::ytdl::Track;
+
+/// How long to wait before reconnecting to a node that closed the
+/// websocket, at first.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest to wait between attempts to reconnect to a node.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Where a Lavalink node is and how to log in to it.
+#[derive(Clone, Debug)]
+pub struct LavalinkConfig {
+    /// The base url of the node, like `http://localhost:2333`.
+    pub url: String,
+    /// The password of the node.
+    pub password: String,
+}
+
+impl LavalinkConfig {
+    /// Reads the config from `LAVALINK_URL` and `LAVALINK_PASSWORD`.
+    ///
+    /// Returns `None` if `LAVALINK_URL` isn't set, so the native voice client
+    /// is used.
+    pub fn from_env() -> Option<LavalinkConfig> {
+        Some(LavalinkConfig {
+            url: env::var("LAVALINK_URL").ok()?,
+            password: env::var("LAVALINK_PASSWORD").unwrap_or_default(),
+        })
+    }
+}
+
+/// A connection to a Lavalink node.
+///
+/// If the websocket closes, every player on the node is told it was
+/// disconnected, and the node is reconnected to in the background, backing
+/// off from [`RECONNECT_DELAY`] up to [`MAX_RECONNECT_DELAY`]. The node starts
+/// a new session, so players made after that rejoin their channel on it.
+pub struct Node {
+    config: LavalinkConfig,
+    user_id: Id<UserMarker>,
+    session_id: Mutex<String>,
+    client: HttpsClient,
+    players: Mutex<HashMap<Id<GuildMarker>, Arc<Shared>>>,
+}
+
+impl Node {
+    /// Connects to a node, waiting for it to be ready.
+    pub async fn connect(
+        config: LavalinkConfig,
+        user_id: Id<UserMarker>,
+    ) -> Result<Arc<Node>, Error> {
+        let (wss, session_id) = open(&config, user_id).await?;
+
+        let node = Arc::new(Node {
+            config,
+            user_id,
+            session_id: Mutex::new(session_id),
+            client: https::build(false),
+            players: Mutex::default(),
+        });
+
+        tokio::spawn(run(Arc::downgrade(&node), wss));
+
+        Ok(node)
+    }
+
+    /// Creates a player for a guild, sending its events to `event_tx`.
+    ///
+    /// This replaces the guild's old player, if it has one.
+    pub fn player(
+        self: &Arc<Node>,
+        user_id: Id<UserMarker>,
+        guild_id: Id<GuildMarker>,
+        event_tx: UnboundedSender<Event>,
+    ) -> LavalinkPlayer {
+        let shared = Arc::new(Shared {
+            guild_id,
+            event_tx,
+            voice_state: RwLock::new(initial_voice_state(user_id, guild_id)),
+            playing: AtomicBool::default(),
+            position: Mutex::new(Position::default()),
+        });
+
+        self.players
+            .lock()
+            .unwrap()
+            .insert(guild_id, shared.clone());
+
+        let (request_tx, request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(player_run(self.clone(), shared.clone(), request_rx));
+
+        LavalinkPlayer { shared, request_tx }
+    }
+
+    /// Sends a request to the REST API.
+    async fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<(), Error> {
+        let uri = format!("{}/v4{}", self.config.url.trim_end_matches('/'), path);
+        let body = match body {
+            Some(body) => Body::from(body.to_string()),
+            None => Body::empty(),
+        };
+
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", &self.config.password)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .map_err(|_| Error::InvalidUrl)?;
+
+        let res = self.client.request(request).await.map_err(Error::Http)?;
+
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(Error::Status(status)),
+        }
+    }
+
+    fn player_path(&self, guild_id: Id<GuildMarker>) -> String {
+        let session_id = self.session_id.lock().unwrap();
+        format!("/sessions/{}/players/{}", session_id, guild_id)
+    }
+
+    fn shared(&self, guild_id: Id<GuildMarker>) -> Option<Arc<Shared>> {
+        self.players.lock().unwrap().get(&guild_id).cloned()
+    }
+}
+
+/// Plays a guild's audio on a Lavalink [`Node`].
+pub struct LavalinkPlayer {
+    shared: Arc<Shared>,
+    request_tx: UnboundedSender<PlayerRequest>,
+}
+
+impl LavalinkPlayer {
+    fn send(&self, request: PlayerRequest) -> Result<(), crate::Error> {
+        self.request_tx
+            .send(request)
+            .map_err(|_| voice::Error::Closed.into())
+    }
+}
+
+impl PlaybackBackend for LavalinkPlayer {
+    fn play(&self, track: &Track, start: Duration) -> Result<(), crate::Error> {
+        if track.speech.is_some() || track.url.is_empty() {
+            // fail like a source would, so the queue moves on
+            let reason = String::from("lavalink can only play tracks with a url");
+            self.shared.send(EventType::Error(
+                voice::source::Error::Remote(reason).into(),
+            ));
+            self.shared.send(EventType::Stopped);
+            return Ok(());
+        }
+
+        self.shared.playing.store(true, Ordering::Release);
+        self.shared.position.lock().unwrap().set(start);
+
+        self.send(PlayerRequest::Update(json!({
+            "track": { "identifier": track.url },
+            "position": start.as_millis() as u64,
+            "paused": false,
+        })))
+    }
+
+    fn interject(&self, _source: Source) -> Result<(), crate::Error> {
+        Err(crate::Error::internal(Error::Unsupported))
+    }
+
+    fn announce(&self, _overlay: Overlay, _gain: f32) -> Result<(), crate::Error> {
+        Err(crate::Error::internal(Error::Unsupported))
+    }
+
+    fn pause(&self) -> Result<(), crate::Error> {
+        self.shared.position.lock().unwrap().pause();
+        self.send(PlayerRequest::Update(json!({ "paused": true })))
+    }
+
+    fn resume(&self) -> Result<(), crate::Error> {
+        self.shared.position.lock().unwrap().resume();
+        self.send(PlayerRequest::Update(json!({ "paused": false })))
+    }
+
+    fn stop(&self) -> Result<(), crate::Error> {
+        self.send(PlayerRequest::Update(
+            json!({ "track": { "encoded": null } }),
+        ))
+    }
+
+    fn disconnect(&self) -> Result<(), crate::Error> {
+        self.send(PlayerRequest::Destroy)
+    }
+
+    fn playing(&self) -> bool {
+        self.shared.playing.load(Ordering::Acquire)
+    }
+
+    fn position(&self) -> Duration {
+        self.shared.position.lock().unwrap().get()
+    }
+
+    fn voice_state(&self) -> BoxFuture<'_, Result<RwLockReadGuard<'_, VoiceState>, crate::Error>> {
+        Box::pin(async move { Ok(self.shared.voice_state.read().await) })
+    }
+
+    fn voice_state_update(&self, ev: Box<VoiceStateUpdate>) -> Result<(), crate::Error> {
+        self.send(PlayerRequest::VoiceState(ev))
+    }
+
+    fn voice_server_update(&self, ev: VoiceServerUpdate) -> Result<(), crate::Error> {
+        self.send(PlayerRequest::VoiceServer(ev))
+    }
+}
+
+impl Drop for LavalinkPlayer {
+    fn drop(&mut self) {
+        let _ = self.request_tx.send(PlayerRequest::Destroy);
+    }
+}
+
+/// The state of a player, shared with the node's websocket task.
+struct Shared {
+    guild_id: Id<GuildMarker>,
+    event_tx: UnboundedSender<Event>,
+    voice_state: RwLock<VoiceState>,
+    playing: AtomicBool,
+    position: Mutex<Position>,
+}
+
+impl Shared {
+    fn send(&self, kind: EventType) {
+        let _ = self.event_tx.send(Event {
+            guild_id: self.guild_id,
+            kind,
+        });
+    }
+}
+
+/// The position of the playing track, counted locally between the node's
+/// updates.
+#[derive(Debug)]
+struct Position {
+    at: Duration,
+    since: Instant,
+    paused: bool,
+}
+
+impl Position {
+    fn get(&self) -> Duration {
+        if self.paused {
+            self.at
+        } else {
+            self.at + self.since.elapsed()
+        }
+    }
+
+    fn set(&mut self, at: Duration) {
+        self.at = at;
+        self.since = Instant::now();
+    }
+
+    fn pause(&mut self) {
+        self.at = self.get();
+        self.paused = true;
+    }
+
+    fn resume(&mut self) {
+        self.since = Instant::now();
+        self.paused = false;
+    }
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position {
+            at: Duration::ZERO,
+            since: Instant::now(),
+            paused: false,
+        }
+    }
+}
+
+/// A request to a player, sent to its task so they run in order.
+enum PlayerRequest {
+    Update(Value),
+    VoiceState(Box<VoiceStateUpdate>),
+    VoiceServer(VoiceServerUpdate),
+    Destroy,
+}
+
+/// Sends a player's requests to the node one by one.
+async fn player_run(
+    node: Arc<Node>,
+    shared: Arc<Shared>,
+    mut request_rx: UnboundedReceiver<PlayerRequest>,
+) {
+    let guild_id = shared.guild_id;
+
+    while let Some(request) = request_rx.recv().await {
+        // the session changes when the node is reconnected to
+        let path = node.player_path(guild_id);
+
+        let res = match request {
+            PlayerRequest::Update(body) => node.request(Method::PATCH, &path, Some(body)).await,
+            PlayerRequest::VoiceState(ev) => {
+                let mut voice_state = shared.voice_state.write().await;
+                let left = voice_state.channel_id.is_some() && ev.channel_id.is_none();
+                *voice_state = ev.0;
+                drop(voice_state);
+
+                if left {
+                    shared.send(EventType::Error(voice::Error::Disconnected));
+                }
+
+                Ok(())
+            }
+            PlayerRequest::VoiceServer(ev) => {
+                let session_id = shared.voice_state.read().await.session_id.clone();
+
+                match ev.endpoint {
+                    Some(endpoint) => {
+                        let body = json!({
+                            "voice": {
+                                "token": ev.token,
+                                "endpoint": endpoint,
+                                "sessionId": session_id,
+                            },
+                        });
+
+                        node.request(Method::PATCH, &path, Some(body)).await
+                    }
+                    None => Ok(()),
+                }
+            }
+            PlayerRequest::Destroy => {
+                // a new player may have taken the guild already, and the
+                // node only has one player per guild
+                let current = {
+                    let mut players = node.players.lock().unwrap();
+                    let current = players
+                        .get(&guild_id)
+                        .is_some_and(|other| Arc::ptr_eq(other, &shared));
+
+                    if current {
+                        players.remove(&guild_id);
+                    }
+
+                    current
+                };
+
+                if current {
+                    if let Err(err) = node.request(Method::DELETE, &path, None).await {
+                        error!(%err, %guild_id, "failed to destroy lavalink player");
+                    }
+                }
+
+                break;
+            }
+        };
+
+        if let Err(err) = res {
+            error!(%err, %guild_id, "lavalink request failed");
+        }
+    }
+}
+
+/// Reads the node's websocket, sending events to the players, and
+/// reconnects to the node if it closes.
+async fn run(node: Weak<Node>, mut wss: WebSocketStream<ConnectStream>) {
+    loop {
+        let payload = recv(&mut wss).await;
+
+        let Some(node) = node.upgrade() else {
+            break;
+        };
+
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!(%err, "lavalink websocket closed");
+
+                // the players are gone with the old session
+                let players = std::mem::take(&mut *node.players.lock().unwrap());
+                for shared in players.values() {
+                    shared.send(EventType::Error(voice::Error::Disconnected));
+                }
+
+                let config = node.config.clone();
+                let user_id = node.user_id;
+                let node = Arc::downgrade(&node);
+
+                match reconnect(&node, &config, user_id).await {
+                    Some(reconnected) => {
+                        wss = reconnected;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+        };
+
+        match payload {
+            Payload::PlayerUpdate { guild_id, state } => {
+                if let Some(shared) = node.shared(guild_id) {
+                    shared
+                        .position
+                        .lock()
+                        .unwrap()
+                        .set(Duration::from_millis(state.position));
+                }
+            }
+            Payload::Event { guild_id, event } => {
+                if let Some(shared) = node.shared(guild_id) {
+                    handle_event(&shared, event);
+                }
+            }
+            Payload::Ready { .. } | Payload::Other => (),
+        }
+    }
+}
+
+/// Reconnects to a node until it works, returning the new websocket, or
+/// `None` if the node was dropped in the meantime.
+async fn reconnect(
+    node: &Weak<Node>,
+    config: &LavalinkConfig,
+    user_id: Id<UserMarker>,
+) -> Option<WebSocketStream<ConnectStream>> {
+    let mut delay = RECONNECT_DELAY;
+
+    loop {
+        tokio::time::sleep(delay).await;
+
+        if node.strong_count() == 0 {
+            return None;
+        }
+
+        match open(config, user_id).await {
+            Ok((wss, session_id)) => {
+                *node.upgrade()?.session_id.lock().unwrap() = session_id;
+                return Some(wss);
+            }
+            Err(err) => {
+                warn!(%err, ?delay, "failed to reconnect to lavalink");
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+fn handle_event(shared: &Shared, event: NodeEvent) {
+    match event {
+        NodeEvent::TrackStart => shared.send(EventType::Playing),
+        // a new track was played over it
+        NodeEvent::TrackEnd { reason } if reason == "replaced" => (),
+        NodeEvent::TrackEnd { .. } => {
+            shared.playing.store(false, Ordering::Release);
+            shared.send(EventType::Stopped);
+        }
+        NodeEvent::TrackException { exception } => {
+            let reason = exception.message.unwrap_or_default();
+            shared.send(EventType::Error(
+                voice::source::Error::Remote(reason).into(),
+            ));
+        }
+        NodeEvent::TrackStuck => {
+            shared.send(EventType::Error(voice::source::Error::Stalled.into()));
+        }
+        NodeEvent::WebSocketClosed { code, reason } => {
+            warn!(code, reason, guild_id = %shared.guild_id, "lavalink voice closed");
+            shared.send(EventType::Error(voice::Error::Disconnected));
+        }
+        NodeEvent::Other => (),
+    }
+}
+
+/// Opens the node's websocket, returning it once the node is ready with the
+/// id of the new session.
+async fn open(
+    config: &LavalinkConfig,
+    user_id: Id<UserMarker>,
+) -> Result<(WebSocketStream<ConnectStream>, String), Error> {
+    let url = config.url.trim_end_matches('/');
+    let ws_url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}/v4/websocket", rest),
+        Some((_, rest)) => format!("ws://{}/v4/websocket", rest),
+        None => format!("ws://{}/v4/websocket", url),
+    };
+
+    let mut request = ws_url.into_client_request()?;
+    let headers = request.headers_mut();
+    headers.insert("Authorization", header(&config.password)?);
+    headers.insert("User-Id", header(&user_id.to_string())?);
+    headers.insert("Client-Name", HeaderValue::from_static("swc"));
+
+    let (mut wss, _response) = connect_async(request).await?;
+
+    let session_id = loop {
+        match recv(&mut wss).await? {
+            Payload::Ready { session_id } => break session_id,
+            payload => debug!(?payload, "lavalink payload before ready"),
+        }
+    };
+
+    info!(session_id, "connected to lavalink");
+
+    Ok((wss, session_id))
+}
+
+/// Receives the next payload the node sends.
+async fn recv(wss: &mut WebSocketStream<ConnectStream>) -> Result<Payload, Error> {
+    loop {
+        match wss.next().await {
+            Some(Ok(Message::Text(text))) => {
+                return serde_json::from_str(&text).map_err(Error::Json);
+            }
+            Some(Ok(Message::Close(_))) | None => return Err(Error::Closed),
+            Some(Ok(_)) => (),
+            Some(Err(err)) => return Err(err.into()),
+        }
+    }
+}
+
+/// A payload from the node's websocket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum Payload {
+    #[serde(rename_all = "camelCase")]
+    Ready { session_id: String },
+    #[serde(rename_all = "camelCase")]
+    PlayerUpdate {
+        guild_id: Id<GuildMarker>,
+        state: PlayerUpdateState,
+    },
+    #[serde(rename_all = "camelCase")]
+    Event {
+        guild_id: Id<GuildMarker>,
+        #[serde(flatten)]
+        event: NodeEvent,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerUpdateState {
+    /// The position of the track, in milliseconds.
+    #[serde(default)]
+    position: u64,
+}
+
+/// An event of a player on the node.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum NodeEvent {
+    #[serde(rename = "TrackStartEvent")]
+    TrackStart,
+    #[serde(rename = "TrackEndEvent")]
+    TrackEnd { reason: String },
+    #[serde(rename = "TrackExceptionEvent")]
+    TrackException { exception: Exception },
+    #[serde(rename = "TrackStuckEvent")]
+    TrackStuck,
+    #[serde(rename = "WebSocketClosedEvent")]
+    WebSocketClosed { code: u16, reason: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct Exception {
+    message: Option<String>,
+}
+
+fn header(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value).map_err(|_| Error::InvalidUrl)
+}
+
+fn initial_voice_state(user_id: Id<UserMarker>, guild_id: Id<GuildMarker>) -> VoiceState {
+    VoiceState {
+        channel_id: None,
+        guild_id: Some(guild_id),
+        user_id,
+        deaf: false,
+        mute: false,
+        self_deaf: false,
+        self_mute: false,
+        self_stream: false,
+        self_video: false,
+        suppress: false,
+        session_id: String::new(),
+        member: None,
+        request_to_speak_timestamp: None,
+    }
+}
+
+/// An error from a Lavalink node.
+#[derive(Debug)]
+pub enum Error {
+    /// The url or password can't be put in a request.
+    InvalidUrl,
+    /// The websocket failed.
+    ///
+    /// Boxed since websocket errors are much bigger than the rest.
+    Ws(Box<tungstenite::Error>),
+    /// The websocket closed.
+    Closed,
+    /// A request failed.
+    Http(hyper::Error),
+    /// The node responded with an error.
+    Status(StatusCode),
+    /// A payload couldn't be deserialized.
+    Json(serde_json::Error),
+    /// Lavalink can't play local audio, like text-to-speech.
+    Unsupported,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidUrl => f.write_str("invalid lavalink url or password"),
+            Error::Ws(err) => Display::fmt(err, f),
+            Error::Closed => f.write_str("lavalink closed the connection"),
+            Error::Http(err) => Display::fmt(err, f),
+            Error::Status(status) => write!(f, "lavalink responded with {}", status),
+            Error::Json(err) => Display::fmt(err, f),
+            Error::Unsupported => f.write_str("lavalink can't play local audio"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Ws(err) => Some(err.as_ref()),
+            Error::Http(err) => Some(err),
+            Error::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Error {
+        Error::Ws(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_payloads() {
+        let payload: Payload = serde_json::from_str(
+            r#"{"op":"event","type":"TrackEndEvent","guildId":"1","track":{},"reason":"finished"}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            payload,
+            Payload::Event {
+                event: NodeEvent::TrackEnd { ref reason },
+                ..
+            } if reason == "finished"
+        ));
+
+        let payload: Payload = serde_json::from_str(
+            r#"{"op":"playerUpdate","guildId":"1","state":{"time":0,"position":1500,"connected":true,"ping":0}}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            payload,
+            Payload::PlayerUpdate {
+                state: PlayerUpdateState { position: 1500 },
+                ..
+            }
+        ));
+
+        let payload: Payload = serde_json::from_str(r#"{"op":"stats","players":0}"#).unwrap();
+        assert!(matches!(payload, Payload::Other));
+    }
+}
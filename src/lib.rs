@@ -1,16 +1,80 @@
 //! Soundwave command library.
+//!
+//! The music bot lives behind the default `music` feature. Without it, this
+//! is just the voice client in [`voice`], for building other kinds of voice
+//! applications on top of twilight.
 
 //pub mod player;
+#[cfg(feature = "music")]
+pub mod dispatch;
+pub mod error;
+pub mod ffmpeg;
+#[cfg(feature = "music")]
+pub mod https;
+#[cfg(feature = "music")]
+pub mod i18n;
 pub mod interaction;
+#[cfg(feature = "music")]
+pub mod lavalink;
+#[cfg(feature = "music")]
 pub mod music;
+pub mod node;
+#[cfg(feature = "music")]
+pub mod scrobble;
+#[cfg(feature = "music")]
+pub mod setup;
+#[cfg(feature = "music")]
+pub mod sponsorblock;
+#[cfg(feature = "music")]
+pub mod store;
+#[cfg(feature = "music")]
+pub mod sync;
+#[cfg(feature = "text-commands")]
+pub mod text;
+#[cfg(feature = "music")]
+pub mod tts;
 pub mod voice;
 pub mod ytdl;
 
+pub use error::Error;
+#[cfg(feature = "music")]
+pub use setup::{validate_setup, SetupError};
+
 use twilight_model::application::command::{
-    Command, CommandOption, CommandOptionType, CommandType,
+    Command, CommandOption, CommandOptionChoice, CommandOptionChoiceValue, CommandOptionType,
+    CommandOptionValue, CommandType,
 };
+use twilight_model::channel::message::Embed;
 use twilight_model::id::Id;
 
+/// The name of the message command that plays the links in a message.
+#[cfg(feature = "music")]
+pub const PLAY_LINKS: &str = "Play links";
+
+/// The color of the bot's embeds.
+pub const EMBED_COLOR: u32 = 0xEE1428;
+
+/// Returns an empty embed in the bot's color.
+///
+/// Like [`command`], it's meant to be filled in with struct update syntax.
+pub fn embed() -> Embed {
+    Embed {
+        author: None,
+        color: Some(EMBED_COLOR),
+        description: None,
+        fields: Vec::new(),
+        footer: None,
+        image: None,
+        kind: String::from("rich"),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: None,
+        url: None,
+        video: None,
+    }
+}
+
 /// Returns a chat command with a name and description.
 ///
 /// This makes for easy autocompletion with struct flattening:
@@ -32,7 +96,8 @@ pub fn command(name: impl Into<String>, description: impl Into<String>) -> Comma
     Command {
         application_id: None,
         default_member_permissions: None,
-        dm_permission: None,
+        // everything the bot does is for a guild's voice channel
+        dm_permission: Some(false),
         description: description.into(),
         description_localizations: None,
         guild_id: None,
@@ -70,31 +135,262 @@ pub fn command_option(
     }
 }
 
+/// Creates a new, **required** command option that can only be one of
+/// `choices`.
+pub fn command_option_with_choices(
+    kind: CommandOptionType,
+    name: impl Into<String>,
+    description: impl Into<String>,
+    choices: Vec<CommandOptionChoice>,
+) -> CommandOption {
+    command_option(kind, name, description).choices(choices)
+}
+
+/// Builder methods for [`CommandOption`]s.
+///
+/// The constraints are checked by Discord before the command is sent, so the
+/// bot doesn't have to.
+/// ```
+/// # use twilight_model::application::command::CommandOptionType;
+/// # use swc::{command_option, CommandOptionExt};
+/// let volume = command_option(CommandOptionType::Integer, "volume", "the volume")
+///     .optional()
+///     .min_value(0)
+///     .max_value(200);
+/// ```
+pub trait CommandOptionExt {
+    /// Makes the option optional.
+    fn optional(self) -> Self;
+
+    /// Limits the option to `choices`.
+    fn choices(self, choices: Vec<CommandOptionChoice>) -> Self;
+
+    /// Sets the smallest value of an integer option.
+    fn min_value(self, min: i64) -> Self;
+
+    /// Sets the largest value of an integer option.
+    fn max_value(self, max: i64) -> Self;
+
+    /// Sets the shortest length of a string option.
+    fn min_length(self, min: u16) -> Self;
+
+    /// Sets the longest length of a string option.
+    fn max_length(self, max: u16) -> Self;
+}
+
+impl CommandOptionExt for CommandOption {
+    fn optional(self) -> CommandOption {
+        CommandOption {
+            required: Some(false),
+            ..self
+        }
+    }
+
+    fn choices(self, choices: Vec<CommandOptionChoice>) -> CommandOption {
+        CommandOption {
+            choices: Some(choices),
+            ..self
+        }
+    }
+
+    fn min_value(self, min: i64) -> CommandOption {
+        CommandOption {
+            min_value: Some(CommandOptionValue::Integer(min)),
+            ..self
+        }
+    }
+
+    fn max_value(self, max: i64) -> CommandOption {
+        CommandOption {
+            max_value: Some(CommandOptionValue::Integer(max)),
+            ..self
+        }
+    }
+
+    fn min_length(self, min: u16) -> CommandOption {
+        CommandOption {
+            min_length: Some(min),
+            ..self
+        }
+    }
+
+    fn max_length(self, max: u16) -> CommandOption {
+        CommandOption {
+            max_length: Some(max),
+            ..self
+        }
+    }
+}
+
+/// Creates a subcommand with options.
+///
+/// Subcommands go in the options of a command or a [`group`]. A command with
+/// subcommands can't have any other options.
+/// ```
+/// # use twilight_model::application::command::{Command, CommandOptionType};
+/// # use swc::{command, command_option, subcommand};
+/// let command = Command {
+///     options: vec![
+///         subcommand(
+///             "save",
+///             "saves the queue",
+///             vec![command_option(CommandOptionType::String, "name", "the name")],
+///         ),
+///         subcommand("list", "lists the saved queues", Vec::new()),
+///     ],
+///     ..command("playlist", "manages saved queues")
+/// };
+/// ```
+pub fn subcommand(
+    name: impl Into<String>,
+    description: impl Into<String>,
+    options: Vec<CommandOption>,
+) -> CommandOption {
+    CommandOption {
+        required: None,
+        options: Some(options),
+        ..command_option(CommandOptionType::SubCommand, name, description)
+    }
+}
+
+/// Creates a group of [`subcommand`]s.
+pub fn group(
+    name: impl Into<String>,
+    description: impl Into<String>,
+    subcommands: Vec<CommandOption>,
+) -> CommandOption {
+    CommandOption {
+        required: None,
+        options: Some(subcommands),
+        ..command_option(CommandOptionType::SubCommandGroup, name, description)
+    }
+}
+
+/// Creates a string choice for a command option.
+pub fn choice(name: impl Into<String>, value: impl Into<String>) -> CommandOptionChoice {
+    CommandOptionChoice {
+        name: name.into(),
+        name_localizations: None,
+        value: CommandOptionChoiceValue::String(value.into()),
+    }
+}
+
 /// Creates a list of commands the bot supports.
+///
+/// Descriptions are translated with [`i18n::localize_commands`].
+#[cfg(feature = "music")]
 pub fn commands() -> Vec<Command> {
-    vec![
+    let mut commands = vec![
         Command {
-            options: vec![command_option(
-                CommandOptionType::String,
-                "query",
-                "the url or query of the track",
-            )],
+            options: play_options()
+                .into_iter()
+                .chain([command_option(
+                    CommandOptionType::Integer,
+                    "position",
+                    "where in the queue to put the track, instead of the end",
+                )
+                .optional()
+                .min_value(1)])
+                .collect(),
             ..command("play", "play a music track")
         },
         Command {
-            options: vec![command_option(
-                CommandOptionType::String,
-                "query",
-                "the url or query of the track",
-            )],
+            options: play_options()
+                .into_iter()
+                .chain([
+                    command_option(
+                        CommandOptionType::Boolean,
+                        "interrupt",
+                        "whether to skip the playing track so this plays right away",
+                    )
+                    .optional(),
+                    command_option(
+                        CommandOptionType::Boolean,
+                        "park",
+                        "whether the skipped track plays again after, from where it was",
+                    )
+                    .optional(),
+                ])
+                .collect(),
             ..command(
                 "playnow",
                 "play a music track and moves it to the top of the queue",
             )
         },
+        command("bulkplay", "plays a list of urls or queries, one per line"),
+        // message commands have no description
+        Command {
+            kind: CommandType::Message,
+            ..command(PLAY_LINKS, "")
+        },
         command("skip", "skips the currently playing song"),
+        command("pause", "pauses or resumes the currently playing song"),
+        command("stop", "stops playing and clears the queue"),
+        command(
+            "chapters",
+            "lists the chapters of the currently playing song",
+        ),
+        Command {
+            options: vec![command_option(
+                CommandOptionType::Integer,
+                "number",
+                "the number of the chapter, from /chapters",
+            )
+            .min_value(1)],
+            ..command(
+                "chapter",
+                "skips to a chapter of the currently playing song",
+            )
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::String,
+                "mode",
+                "what to loop; omit to go to the next mode",
+            )
+            .optional()
+            .choices(vec![
+                choice("off", "off"),
+                choice("track", "track"),
+                choice("queue", "queue"),
+            ])],
+            ..command("loop", "loops the playing track or the queue")
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::Boolean,
+                "progress",
+                "whether to show how far into the track it is, updated every 15 seconds",
+            )
+            .optional()],
+            ..command("player", "posts buttons to control the player")
+        },
         command("queue", "lists the current music queue"),
-        command("shuffle", "shuffles the music queue"),
+        Command {
+            options: vec![command_option(
+                CommandOptionType::Integer,
+                "position",
+                "the position of your track in the queue",
+            )
+            .min_value(1)],
+            ..command(
+                "mynext",
+                "plays one of your queued tracks after the current one",
+            )
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::Integer,
+                "seed",
+                "the seed to shuffle with, to get the same order again",
+            )
+            .optional()],
+            ..command("shuffle", "shuffles the music queue")
+        },
+        command(
+            "unshuffle",
+            "restores the queue to its order before shuffling",
+        ),
         command("disconnect", "disconnects the music bot"),
         Command {
             options: vec![command_option(
@@ -107,5 +403,491 @@ pub fn commands() -> Vec<Command> {
                 "sets the autodisconnect setting; omit setting to toggle",
             )
         },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::Boolean,
+                "setting",
+                "whether to autoplay or not",
+            )
+            .optional()],
+            ..command(
+                "autoplay",
+                "plays related tracks when the queue runs out; omit setting to toggle",
+            )
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::String,
+                "name",
+                "the name of the bookmark; defaults to the track title",
+            )
+            .optional()],
+            ..command("bookmark", "bookmarks the current position in the track")
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::String,
+                "bookmark",
+                "the name of the bookmark",
+            )],
+            ..command("jump", "plays a bookmark next, from where it was saved")
+        },
+        Command {
+            options: vec![
+                command_option(
+                    CommandOptionType::String,
+                    "queuemode",
+                    "how new tracks are placed in the queue",
+                )
+                .optional()
+                .choices(vec![
+                    choice("fifo", "fifo"),
+                    choice("fair (alternate between requesters)", "fair"),
+                ]),
+                command_option(
+                    CommandOptionType::Boolean,
+                    "presence",
+                    "whether the playing track is shown in the bot's status",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::String,
+                    "sponsorblock",
+                    "which parts of YouTube videos are skipped with SponsorBlock",
+                )
+                .optional()
+                .choices(vec![
+                    choice("off", "off"),
+                    choice("sponsors", "sponsors"),
+                    choice("sponsors, promotions, intros and outros", "all"),
+                ]),
+                command_option(
+                    CommandOptionType::Role,
+                    "djrole",
+                    "the role needed to control the queue; @everyone to remove it",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::Integer,
+                    "maxduration",
+                    "the longest track, in minutes, that users without the dj role can enqueue; \
+                        0 to remove it",
+                )
+                .optional()
+                .min_value(0),
+                command_option(
+                    CommandOptionType::Boolean,
+                    "livestreams",
+                    "whether users without the dj role can enqueue livestreams",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::String,
+                    "agerestricted",
+                    "where age-restricted tracks can play",
+                )
+                .optional()
+                .choices(vec![
+                    choice("nowhere", "deny"),
+                    choice("only in nsfw voice channels", "nsfw"),
+                    choice("anywhere", "allow"),
+                ]),
+                command_option(
+                    CommandOptionType::Boolean,
+                    "largethumbnails",
+                    "whether track thumbnails are shown full-size",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::Boolean,
+                    "highquality",
+                    "whether tracks play at the voice channel's bitrate",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::Boolean,
+                    "farewell",
+                    "whether a summary is announced when the bot leaves on its own",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::String,
+                    "status",
+                    "where the playing track is shown outside of commands",
+                )
+                .optional()
+                .choices(vec![
+                    choice("nowhere", "off"),
+                    choice("the status of the voice channel", "voice"),
+                    choice(
+                        "the topic of the channel tracks are requested from",
+                        "topic",
+                    ),
+                ]),
+            ],
+            ..command(
+                "settings",
+                "changes the queue settings; omit options to show them",
+            )
+        },
+        Command {
+            options: vec![
+                command_option(CommandOptionType::String, "text", "the text to say")
+                    .max_length(tts::MAX_TEXT_LEN as u16),
+                command_option(
+                    CommandOptionType::String,
+                    "mode",
+                    "when to say it; defaults to interject",
+                )
+                .optional()
+                .choices(vec![
+                    choice("interject (over the playing track)", "interject"),
+                    choice("queue (as a track)", "queue"),
+                ]),
+            ],
+            ..command("tts", "says something in the voice channel")
+        },
+        Command {
+            options: vec![
+                command_option(CommandOptionType::String, "text", "the text to say")
+                    .optional()
+                    .max_length(tts::MAX_TEXT_LEN as u16),
+                command_option(
+                    CommandOptionType::String,
+                    "clip",
+                    "the url or query of a clip to play",
+                )
+                .optional(),
+            ],
+            ..command(
+                "announce",
+                "plays an announcement over the music, turning it down meanwhile",
+            )
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::String,
+                "format",
+                "the format of the file; defaults to json",
+            )
+            .optional()
+            .choices(vec![
+                choice("json (keeps track details)", "json"),
+                choice("urls (one per line)", "urls"),
+            ])],
+            ..command("export", "exports the queue as a file")
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::String,
+                "period",
+                "how far back to look; defaults to a week",
+            )
+            .optional()
+            .choices(vec![choice("week", "week"), choice("month", "month")])],
+            ..command("stats", "shows what the server has listened to the most")
+        },
+        command(
+            "ping",
+            "shows the bot's latency and checks that it can play",
+        ),
+        Command {
+            options: vec![
+                command_option(
+                    CommandOptionType::Attachment,
+                    "file",
+                    "an exported queue or a list of urls",
+                )
+                .optional(),
+                command_option(
+                    CommandOptionType::String,
+                    "urls",
+                    "urls to enqueue, separated by spaces",
+                )
+                .optional(),
+            ],
+            ..command("import", "enqueues an exported queue or a list of urls")
+        },
+        Command {
+            options: vec![
+                subcommand(
+                    "save",
+                    "saves the queue as a playlist",
+                    vec![command_option(
+                        CommandOptionType::String,
+                        "name",
+                        "the name of the playlist; replaces a playlist with the same name",
+                    )],
+                ),
+                subcommand(
+                    "play",
+                    "enqueues a saved playlist",
+                    vec![command_option(
+                        CommandOptionType::String,
+                        "name",
+                        "the name of the playlist",
+                    )],
+                ),
+                subcommand("list", "lists the saved playlists", Vec::new()),
+                subcommand(
+                    "delete",
+                    "deletes a saved playlist",
+                    vec![command_option(
+                        CommandOptionType::String,
+                        "name",
+                        "the name of the playlist",
+                    )],
+                ),
+            ],
+            ..command("playlist", "manages the server's saved playlists")
+        },
+        Command {
+            default_member_permissions: Some(twilight_model::guild::Permissions::MANAGE_GUILD),
+            options: vec![
+                group(
+                    "add",
+                    "stops something from being played in the server",
+                    block_subcommands("the entry to block"),
+                ),
+                group(
+                    "remove",
+                    "lets something be played in the server again",
+                    block_subcommands("the entry to unblock"),
+                ),
+                subcommand("list", "lists what is blocked in the server", Vec::new()),
+            ],
+            ..command("block", "manages what can't be played in the server")
+        },
+        Command {
+            default_member_permissions: Some(twilight_model::guild::Permissions::MANAGE_GUILD),
+            options: vec![
+                subcommand(
+                    "list",
+                    "lists the tracks that kept failing to play",
+                    Vec::new(),
+                ),
+                subcommand(
+                    "clear",
+                    "lets every track that kept failing be enqueued again",
+                    Vec::new(),
+                ),
+            ],
+            ..command("quarantine", "manages tracks that keep failing to play")
+        },
+        Command {
+            default_member_permissions: Some(twilight_model::guild::Permissions::MANAGE_GUILD),
+            options: vec![
+                subcommand(
+                    "listenbrainz",
+                    "scrobbles played tracks to ListenBrainz",
+                    vec![command_option(
+                        CommandOptionType::String,
+                        "token",
+                        "the ListenBrainz user token",
+                    )],
+                ),
+                subcommand(
+                    "lastfm",
+                    "scrobbles played tracks to Last.fm",
+                    vec![
+                        command_option(CommandOptionType::String, "apikey", "the Last.fm API key"),
+                        command_option(
+                            CommandOptionType::String,
+                            "apisecret",
+                            "the Last.fm API secret",
+                        ),
+                        command_option(
+                            CommandOptionType::String,
+                            "sessionkey",
+                            "the session key of the Last.fm account",
+                        ),
+                    ],
+                ),
+                subcommand(
+                    "off",
+                    "stops scrobbling to the server's own service",
+                    Vec::new(),
+                ),
+            ],
+            ..command(
+                "scrobble",
+                "sets where the server's played tracks are scrobbled",
+            )
+        },
+        Command {
+            options: vec![command_option(
+                CommandOptionType::Integer,
+                "minutes",
+                "how long to lock the queue for, an hour by default",
+            )
+            .optional()
+            .min_value(1)
+            .max_value(24 * 60)],
+            ..command(
+                "lock",
+                "keeps everyone but you and DJs from changing the queue for a while",
+            )
+        },
+        command("unlock", "lets anyone change the queue again"),
+    ];
+
+    #[cfg(feature = "text-commands")]
+    if let Some(settings) = commands
+        .iter_mut()
+        .find(|command| command.name == "settings")
+    {
+        settings.options.push(
+            command_option(
+                CommandOptionType::Boolean,
+                "textcommands",
+                "whether commands can also be typed in messages, like !play",
+            )
+            .optional(),
+        );
+    }
+
+    i18n::localize_commands(&mut commands);
+
+    commands
+}
+
+/// Creates a list of commands only registered in the developer's guild.
+///
+/// These are for debugging and running the bot, and are never registered
+/// globally.
+#[cfg(feature = "music")]
+pub fn dev_commands() -> Vec<Command> {
+    use twilight_model::guild::Permissions;
+
+    let guild = || command_option(CommandOptionType::String, "guild", "the id of the server");
+
+    vec![
+        Command {
+            default_member_permissions: Some(Permissions::ADMINISTRATOR),
+            options: vec![
+                subcommand("allow", "adds a server to the allowlist", vec![guild()]),
+                subcommand("deny", "stops a server from using the bot", vec![guild()]),
+                subcommand(
+                    "reset",
+                    "removes a server from both lists and turns its features back on",
+                    vec![guild()],
+                ),
+                subcommand(
+                    "allowlist",
+                    "turns the allowlist on or off",
+                    vec![command_option(
+                        CommandOptionType::Boolean,
+                        "enabled",
+                        "whether only allowed servers can use the bot",
+                    )],
+                ),
+                subcommand(
+                    "feature",
+                    "turns a feature on or off for a server",
+                    vec![
+                        guild(),
+                        command_option(CommandOptionType::String, "feature", "the feature")
+                            .choices(
+                                store::Feature::ALL
+                                    .into_iter()
+                                    .map(|feature| choice(feature.name(), feature.name()))
+                                    .collect(),
+                            ),
+                        command_option(
+                            CommandOptionType::Boolean,
+                            "enabled",
+                            "whether the server can use it",
+                        ),
+                    ],
+                ),
+                subcommand(
+                    "show",
+                    "shows the access settings of a server",
+                    vec![guild()],
+                ),
+            ],
+            ..command("operator", "controls which servers can use the bot")
+        },
+        Command {
+            default_member_permissions: Some(Permissions::ADMINISTRATOR),
+            options: vec![subcommand(
+                "dump",
+                "attaches the state of a server's queue as json",
+                vec![command_option(
+                    CommandOptionType::String,
+                    "guild",
+                    "the id of the server; defaults to this one",
+                )
+                .optional()],
+            )],
+            ..command("debug", "looks inside the bot")
+        },
+        Command {
+            default_member_permissions: Some(Permissions::ADMINISTRATOR),
+            options: vec![subcommand(
+                "update-ytdl",
+                "updates yt-dlp and swaps it in",
+                vec![],
+            )],
+            ..command("admin", "maintains the bot")
+        },
+    ]
+}
+
+/// The subcommands of the `/block` groups, one for each kind of entry.
+#[cfg(feature = "music")]
+fn block_subcommands(description: &str) -> Vec<CommandOption> {
+    let value = |name| vec![command_option(CommandOptionType::String, "value", name)];
+
+    vec![
+        subcommand("url", "a single track, by its url", value(description)),
+        subcommand(
+            "channel",
+            "every track from a channel, by its url or name",
+            value(description),
+        ),
+        subcommand(
+            "keyword",
+            "every track with a word in its title",
+            value(description),
+        ),
+    ]
+}
+
+/// The options shared by `/play` and `/playnow`.
+#[cfg(feature = "music")]
+fn play_options() -> Vec<CommandOption> {
+    vec![
+        command_option(
+            CommandOptionType::String,
+            "query",
+            "the url or query of the track, or several urls",
+        ),
+        command_option(
+            CommandOptionType::Integer,
+            "limit",
+            "the maximum number of playlist items to enqueue",
+        )
+        .optional()
+        .min_value(1),
+        command_option(
+            CommandOptionType::Integer,
+            "start",
+            "the playlist item to start enqueueing from",
+        )
+        .optional()
+        .min_value(1),
+        command_option(
+            CommandOptionType::Boolean,
+            "shuffle",
+            "whether to shuffle the playlist before enqueueing",
+        )
+        .optional(),
+        command_option(
+            CommandOptionType::Boolean,
+            "reverse",
+            "whether to enqueue the playlist in reverse",
+        )
+        .optional(),
     ]
 }
@@ -1,16 +1,21 @@
 use std::{env, sync::Arc};
 
-use swc::interaction::ext::*;
-use swc::music::{self, QueueServer};
+use swc::dispatch::CommandRouter;
+use swc::music::{self, QuarantineConfig, QueueServer};
+use swc::scrobble::{self, Scrobbler};
+use swc::store::Store;
+use swc::sync::CommandSync;
+use swc::voice::PlayerConfig;
 
 use tracing_subscriber::EnvFilter;
 use twilight_cache_inmemory::InMemoryCache;
 use twilight_gateway::{Config, Intents, Shard, ShardId};
 use twilight_http::client::Client;
 use twilight_model::{
-    application::interaction::{application_command::CommandData, Interaction, InteractionData},
+    application::interaction::Interaction,
+    channel::message::MessageFlags,
     gateway::event::Event,
-    id::Id,
+    http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
 };
 
 use tracing::instrument;
@@ -24,25 +29,59 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
         //.with_ansi(false)
         .init();
 
-    // init ytdl executable
-    swc::ytdl::init_ytdl_executable(|| {
-        env::var("YTDL_EXECUTABLE").unwrap_or_else(|_| String::from("youtube-dl"))
+    // init ffmpeg executable
+    swc::ffmpeg::init_ffmpeg_executable(|| {
+        env::var("FFMPEG_EXECUTABLE").unwrap_or_else(|_| String::from("ffmpeg"))
     });
+    swc::ffmpeg::init_ffmpeg_options(swc::ffmpeg::FfmpegOptions::from_env);
+    swc::voice::guardrail::init_guardrail(swc::voice::guardrail::GuardrailConfig::from_env);
+
+    // init text-to-speech
+    swc::tts::init_tts_backend(swc::tts::TtsBackend::from_env);
+    music::init_query_slots(music::QuerySlots::from_env);
+    music::cache::init_audio_cache(|| {
+        let config = music::cache::AudioCacheConfig::from_env()?;
+
+        music::cache::AudioCache::new(config)
+            .map_err(|err| tracing::error!(%err, "failed to open audio cache"))
+            .ok()
+    });
+
+    // check that audio can be played before connecting, instead of at the
+    // first track; lavalink nodes bring their own ffmpeg
+    let lavalink = swc::lavalink::LavalinkConfig::from_env();
+    let ytdl = swc::ytdl::YtdlConfig::from_env();
+    let audio_unavailable =
+        match music::health::check_audio_backend(&ytdl, lavalink.is_none()).await {
+            Ok(()) => None,
+            Err(problem) if env::var("AUDIO_REQUIRED").is_ok_and(|v| v == "1" || v == "true") => {
+                return Err(format!("audio backend unavailable: {}", problem).into());
+            }
+            Err(problem) => {
+                tracing::error!(problem, "audio backend unavailable, nothing can be played");
+                Some(problem)
+            }
+        };
 
     // initialize discord shard
     // we only need one shard, but our infrastructure can be scaled up
     // relatively easily.
-    let shard_config = Config::builder(
-        env::var("DISCORD_TOKEN")?,
-        Intents::GUILDS | Intents::GUILD_VOICE_STATES,
-    )
-    /*
-    .event_types(EventTypeFlags::READY
-        | EventTypeFlags::INTERACTION_CREATE
-        | EventTypeFlags::VOICE_STATE_UPDATE
-        | EventTypeFlags::VOICE_SERVER_UPDATE)*/
-    .build();
-    let mut shard = Shard::with_config(ShardId::ONE, shard_config);
+    let mut intents = Intents::GUILDS | Intents::GUILD_VOICE_STATES;
+
+    if cfg!(feature = "text-commands") {
+        intents |= Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT;
+    }
+
+    let shard_config = Config::builder(env::var("DISCORD_TOKEN")?, intents)
+        /*
+        .event_types(EventTypeFlags::READY
+            | EventTypeFlags::INTERACTION_CREATE
+            | EventTypeFlags::VOICE_STATE_UPDATE
+            | EventTypeFlags::VOICE_SERVER_UPDATE)*/
+        .build();
+    // each process runs the shard of the guilds it claims
+    let claim = music::GuildClaim::from_env();
+    let mut shard = Shard::with_config(ShardId::new(claim.index, claim.count), shard_config);
 
     // create http client
     let http_client = Arc::new(Client::new(env::var("DISCORD_TOKEN")?));
@@ -50,7 +89,52 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     // create cache
     let cache = Arc::new(InMemoryCache::builder().message_cache_size(10).build());
 
-    let queue_server = wait_for_ready(&mut shard, &cache, &http_client).await?;
+    // open persistent store
+    let store = Arc::new(
+        Store::open(env::var("STORE_PATH").unwrap_or_else(|_| String::from("store.json"))).await?,
+    );
+
+    let queue_server = wait_for_ready(
+        &mut shard,
+        &cache,
+        &http_client,
+        &store,
+        claim,
+        lavalink,
+        audio_unavailable,
+    )
+    .await?;
+
+    queue_server.rejoin_sessions().await;
+
+    // start scrobbling
+    Scrobbler::new(store.clone(), scrobble::Service::from_env()).start(queue_server.subscribe());
+    music::StatsRecorder::new(store.clone()).start(queue_server.subscribe());
+
+    // keep yt-dlp working
+    let ytdl_updates = music::update::init_ytdl_updates(music::update::YtdlUpdateConfig::from_env);
+    music::update::YtdlUpdater::new(ytdl_updates.clone(), queue_server.clone()).start();
+
+    // serve health checks, if there's somewhere to
+    #[cfg(feature = "rest")]
+    if let Some(addr) = env::var("HEALTH_ADDR")
+        .ok()
+        .and_then(|addr| addr.parse().ok())
+    {
+        let queue_server = queue_server.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = queue_server.serve_health(addr).await {
+                tracing::error!(%err, "health check server stopped");
+            }
+        });
+    }
+
+    #[cfg(feature = "text-commands")]
+    let text_prefix = swc::text::prefix_from_env();
+
+    // this bot only has swc's commands
+    let router = CommandRouter::new();
 
     loop {
         let ev = match shard.next_event().await {
@@ -66,15 +150,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
         };
 
         cache.update(&ev);
+        queue_server.update_shard(&shard);
         //log::debug!("{:?}", ev);
 
         match ev {
             //Event::Ready(ready) => { }
-            Event::InteractionCreate(mut interaction) => {
-                if let Some(InteractionData::ApplicationCommand(data)) = interaction.data.take() {
-                    handle_command(&queue_server, interaction.0, data).await;
+            Event::InteractionCreate(interaction) => {
+                if !check_guild(&http_client, &cache, &interaction).await {
+                    continue;
                 }
-            },
+
+                router
+                    .handle(&queue_server, &http_client, interaction.0)
+                    .await;
+            }
+            #[cfg(feature = "text-commands")]
+            Event::MessageCreate(message) => {
+                handle_message(
+                    &queue_server,
+                    &http_client,
+                    &cache,
+                    &store,
+                    &text_prefix,
+                    &message,
+                )
+                .await;
+            }
             Event::VoiceStateUpdate(ev) => {
                 queue_server.voice_state_update(ev).await;
             }
@@ -86,132 +187,109 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     }
 }
 
-/// Handles a command.
+/// Checks that an interaction is from a guild the bot is in, telling the
+/// user why nothing happens if it isn't.
+///
+/// Commands can't be used in DMs, but can still show up outside of a guild
+/// if a user installed the bot to their account.
+async fn check_guild(
+    http_client: &Client,
+    cache: &InMemoryCache,
+    interaction: &Interaction,
+) -> bool {
+    let error = match interaction.guild_id {
+        Some(guild_id) if cache.guild(guild_id).is_some() => return true,
+        Some(_) => "add the bot to this server to use it here",
+        None => "this only works in servers",
+    };
+
+    tracing::debug!(guild_id = ?interaction.guild_id, "interaction outside of a guild");
+
+    let response = InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(InteractionResponseData {
+            content: Some(swc::i18n::tr(interaction.locale.as_deref(), error).to_owned()),
+            flags: Some(MessageFlags::EPHEMERAL),
+            ..Default::default()
+        }),
+    };
+
+    let _ = http_client
+        .interaction(interaction.application_id)
+        .create_response(interaction.id, &interaction.token, &response)
+        .await;
+
+    false
+}
+
+/// Handles a message, which might be a text command.
 ///
 /// **This is run on the main thread! Do not block!**
-async fn handle_command(
+#[cfg(feature = "text-commands")]
+async fn handle_message(
     queue_server: &Arc<QueueServer>,
-    interaction: Interaction,
-    data: Box<CommandData>,
+    http_client: &Arc<Client>,
+    cache: &InMemoryCache,
+    store: &Store,
+    prefix: &str,
+    message: &twilight_model::channel::Message,
 ) {
-    let Some(guild_id) = data.guild_id else {
+    if message.author.bot {
+        return;
+    }
+
+    let Some(guild_id) = message.guild_id else {
         return;
     };
 
-    let Some(user) = interaction.member.as_ref().and_then(|m| m.user.as_ref()) else {
+    let reply = swc::text::reply(message);
+    let Some(action) = swc::text::parse(prefix, &message.content, reply) else {
         return;
     };
 
-    let command_data = music::CommandData {
-        application_id: interaction.application_id,
-        interaction_id: interaction.id,
-        interaction_token: interaction.token,
-        guild_id,
-        user_id: user.id,
+    let enabled = store
+        .read(|store| store.text_commands.contains(&guild_id))
+        .await;
+
+    if !enabled {
+        return;
+    }
+
+    let Some(command_data) = swc::text::command_data(cache, message) else {
+        return;
     };
 
-    match &*data.name {
-        "play" | "playnow" => {
-            // first argument is the query
-            let query = data
-                .options
-                .cast::<String>(0)
-                .expect("invalid command schema");
-
-            let playnow = matches!(&*data.name, "playnow");
-
-            // send to the queue
-            queue_server
-                .command(
-                    guild_id,
-                    music::Command {
-                        data: command_data,
-                        action: music::Action::Play(query, playnow),
-                    },
-                )
-                .await;
-        }
-        "skip" => {
-            // send to the queue
-            queue_server
-                .command(
-                    guild_id,
-                    music::Command {
-                        data: command_data,
-                        action: music::Action::Skip,
-                    },
-                )
-                .await;
-        }
-        "queue" => {
-            // send to the queue
-            queue_server
-                .command(
-                    guild_id,
-                    music::Command {
-                        data: command_data,
-                        action: music::Action::Queue,
-                    },
-                )
-                .await;
-        }
-        "shuffle" => {
-            // send to the queue
-            queue_server
-                .command(
-                    guild_id,
-                    music::Command {
-                        data: command_data,
-                        action: music::Action::Shuffle,
-                    },
-                )
-                .await;
-        }
-        "disconnect" => {
-            // send to the queue
-            queue_server
-                .command(
-                    guild_id,
-                    music::Command {
-                        data: command_data,
-                        action: music::Action::Disconnect,
-                    },
-                )
+    let action = match action {
+        Ok(action) => action,
+        Err(err) => {
+            command_data
+                .respond(http_client)
+                .error(err.localize(command_data.locale.as_deref()))
+                .respond()
                 .await;
+            return;
         }
-        "autodisconnect" => {
-            let option = if !data.options.is_empty() {
-                Some(
-                    data.options
-                        .cast::<bool>(0)
-                        .expect("invalid command schema"),
-                )
-            } else {
-                None
-            };
+    };
 
-            // send to the queue
-            queue_server
-                .command(
-                    guild_id,
-                    music::Command {
-                        data: command_data,
-                        action: music::Action::AutoDisconnect(option),
-                    },
-                )
-                .await;
-        }
-        // ignore missing commands
-        name => {
-            log::warn!("got missing or invalid command: /{}", name)
-        }
-    }
+    queue_server
+        .command(
+            command_data.guild_id,
+            music::Command {
+                data: command_data,
+                action,
+            },
+        )
+        .await;
 }
 
 async fn wait_for_ready(
     shard: &mut Shard,
     cache: &Arc<InMemoryCache>,
     http_client: &Arc<Client>,
+    store: &Arc<Store>,
+    claim: music::GuildClaim,
+    lavalink: Option<swc::lavalink::LavalinkConfig>,
+    audio_unavailable: Option<String>,
 ) -> Result<Arc<QueueServer>, Box<dyn std::error::Error + 'static>> {
     loop {
         let ev = match shard.next_event().await {
@@ -229,21 +307,85 @@ async fn wait_for_ready(
 
             log::info!("got ready, initializing on {}", user_id);
 
-            // setup commands
-            http_client
-                .interaction(ready.application.id)
-                //.set_guild_commands(Id::new(683483117473759249), &swc::commands())
-                .set_global_commands(&swc::commands())
-                .await
-                .unwrap();
+            // setup commands; only the first process does it
+            if claim.index == 0 {
+                let mut sync = CommandSync::new(swc::commands());
+
+                if let Some(guild_id) = env::var("DEV_GUILD_ID").ok().and_then(|id| id.parse().ok())
+                {
+                    sync = sync.guild(guild_id, swc::dev_commands());
+                }
+
+                let changed = sync
+                    .run(&http_client.interaction(ready.application.id))
+                    .await?;
+                log::info!("synced commands, {} changed", changed);
+            }
 
             // initialize music queues
-            let queue_server = Arc::new(QueueServer::new(
+            let mut queue_server = QueueServer::new(
                 shard.sender(),
                 cache.clone(),
                 http_client.clone(),
+                store.clone(),
                 user_id,
-            ));
+            )
+            .with_player_config(PlayerConfig::from_env())
+            .with_quarantine_config(QuarantineConfig::from_env())
+            .with_claim(claim)
+            .validate_setup(shard.config().intents())?;
+
+            // play on a lavalink node, if there is one
+            if let Some(config) = lavalink.clone() {
+                let node = swc::lavalink::Node::connect(config, user_id).await?;
+                queue_server = queue_server.with_lavalink(node);
+            }
+
+            if let Some(problem) = audio_unavailable.clone() {
+                queue_server = queue_server.with_audio_unavailable(problem);
+            }
+
+            // show one guild's music in the bot's presence: the configured
+            // guild, or the only guild if the bot is just in one
+            let presence_guild = match env::var("PRESENCE_GUILD_ID") {
+                Ok(id) => Some(id.parse()?),
+                Err(_) if ready.guilds.len() == 1 => Some(ready.guilds[0].id),
+                Err(_) => None,
+            };
+
+            if let Some(guild_id) = presence_guild {
+                queue_server = queue_server.with_presence(guild_id);
+            }
+
+            // pick sessions back up after a restart
+            if env::var("REJOIN_SESSIONS").is_ok_and(|v| v == "1" || v == "true") {
+                queue_server = queue_server.with_rejoin();
+            }
+
+            // waitlist guilds once this many are playing
+            let max_players = env::var("MAX_PLAYERS")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|max| *max > 0);
+
+            if let Some(max_players) = max_players {
+                queue_server = queue_server.with_max_players(max_players);
+            }
+
+            // the users who can use the developer commands
+            let operators = env::var("OPERATOR_IDS").unwrap_or_default();
+            let operators = operators
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::parse)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            queue_server = queue_server.with_operators(operators);
+
+            let queue_server = Arc::new(queue_server);
+            queue_server.start_sweeper();
+            queue_server.start_guardrail();
 
             return Ok(queue_server);
         }
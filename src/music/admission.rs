@@ -0,0 +1,315 @@
+//! Limiting how many guilds play at once.
+//!
+//! Small hosts can only stream to so many voice channels before all of them
+//! stutter. With [`QueueServer::with_max_players`], a guild that wants to
+//! join a channel while every [`Slot`] is taken is told the bot is at
+//! capacity and put on a waitlist instead. When a slot frees up, it's held
+//! for the first guild on the waitlist for [`RESERVE_TIME`], and whoever
+//! asked is told in the channel they asked from. If the guild doesn't use it
+//! in time, it's offered to the next guild in line.
+//!
+//! Queues that join without a command, like when rejoining a session after a
+//! restart, always get a slot, even past the limit.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{error, info};
+use twilight_http::Client as HttpClient;
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker},
+    Id,
+};
+
+use super::commands::CommandData;
+use super::{QueueServer, QueueState, UserError};
+use crate::i18n;
+
+/// How long a freed slot is held for the guild it was offered to.
+pub const RESERVE_TIME: Duration = Duration::from_secs(2 * 60);
+
+/// Keeps track of how many players are running.
+#[derive(Debug)]
+pub struct Admission {
+    max_players: Option<usize>,
+    state: Mutex<AdmissionState>,
+    http_client: Arc<HttpClient>,
+}
+
+#[derive(Debug, Default)]
+struct AdmissionState {
+    active: usize,
+    /// Slots held for guilds that were on the waitlist, until they expire.
+    reserved: HashMap<Id<GuildMarker>, Instant>,
+    waitlist: VecDeque<Waiter>,
+}
+
+/// A guild waiting for a slot.
+#[derive(Clone, Debug)]
+struct Waiter {
+    guild_id: Id<GuildMarker>,
+    /// Where to say a slot freed up.
+    channel_id: Id<ChannelMarker>,
+    locale: Option<String>,
+}
+
+/// A running player, counted until it's dropped.
+#[derive(Debug)]
+pub struct Slot {
+    admission: Arc<Admission>,
+}
+
+impl Admission {
+    /// Creates an `Admission` that lets `max_players` players run at once,
+    /// or any number if `None`.
+    pub fn new(max_players: Option<usize>, http_client: Arc<HttpClient>) -> Admission {
+        Admission {
+            max_players,
+            state: Mutex::default(),
+            http_client,
+        }
+    }
+
+    /// Takes a slot for a guild if there is one, or puts the guild on the
+    /// waitlist.
+    ///
+    /// Returns the guild's place on the waitlist, counting from 1, if there
+    /// wasn't a slot.
+    fn admit(self: &Arc<Self>, waiter: Waiter) -> Result<Slot, usize> {
+        self.expire();
+
+        let mut state = self.state.lock().unwrap();
+
+        let free = match self.max_players {
+            Some(max) => state.active + state.reserved.len() < max,
+            None => true,
+        };
+
+        if state.reserved.remove(&waiter.guild_id).is_some() || free {
+            state.waitlist.retain(|w| w.guild_id != waiter.guild_id);
+            state.active += 1;
+
+            return Ok(Slot {
+                admission: self.clone(),
+            });
+        }
+
+        let place = match state
+            .waitlist
+            .iter()
+            .position(|w| w.guild_id == waiter.guild_id)
+        {
+            Some(i) => i,
+            None => {
+                info!(guild_id = %waiter.guild_id, "at capacity, waitlisting guild");
+                state.waitlist.push_back(waiter);
+                state.waitlist.len() - 1
+            }
+        };
+
+        Err(place + 1)
+    }
+
+    /// Takes a slot, even if there isn't one free.
+    fn acquire(self: &Arc<Self>, guild_id: Id<GuildMarker>) -> Slot {
+        let mut state = self.state.lock().unwrap();
+
+        state.reserved.remove(&guild_id);
+        state.waitlist.retain(|w| w.guild_id != guild_id);
+        state.active += 1;
+
+        Slot {
+            admission: self.clone(),
+        }
+    }
+
+    /// How many players are running and how many guilds are waiting, as
+    /// JSON.
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+
+        json!({
+            "active": state.active,
+            "max": self.max_players,
+            "reserved": state.reserved.len(),
+            "waitlist": state.waitlist.len(),
+        })
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        state.active = state.active.saturating_sub(1);
+
+        let waiter = state.reserve_next();
+        drop(state);
+
+        if let Some(waiter) = waiter {
+            self.offer(waiter);
+        }
+    }
+
+    /// Frees the slots held for guilds that didn't take them in time,
+    /// offering them to the next guilds on the waitlist.
+    fn expire(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+
+        let now = Instant::now();
+        let held = state.reserved.len();
+        state.reserved.retain(|_, until| *until > now);
+        let expired = held - state.reserved.len();
+
+        let offered = (0..expired)
+            .map_while(|_| state.reserve_next())
+            .collect::<Vec<_>>();
+        drop(state);
+
+        for waiter in offered {
+            self.offer(waiter);
+        }
+    }
+
+    /// Tells a guild a slot is held for it, and offers the slot to the next
+    /// guild if it isn't taken in time.
+    fn offer(self: &Arc<Self>, waiter: Waiter) {
+        info!(guild_id = %waiter.guild_id, "offering slot to waitlisted guild");
+
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let admission = Arc::downgrade(self);
+        runtime.spawn(async move {
+            tokio::time::sleep(RESERVE_TIME).await;
+
+            if let Some(admission) = admission.upgrade() {
+                admission.expire();
+            }
+        });
+
+        let http_client = self.http_client.clone();
+        runtime.spawn(async move {
+            let content = i18n::tr(
+                waiter.locale.as_deref(),
+                "there's room to play music again, use /play to start",
+            );
+
+            let res = match http_client
+                .create_message(waiter.channel_id)
+                .content(content)
+            {
+                Ok(req) => req.await.map(|_| ()),
+                Err(err) => {
+                    error!(%err, "invalid waitlist message");
+                    return;
+                }
+            };
+
+            if let Err(err) = res {
+                error!(%err, "failed to tell waitlisted guild");
+            }
+        });
+    }
+}
+
+impl AdmissionState {
+    /// Holds a slot for the first guild on the waitlist, if there is one.
+    fn reserve_next(&mut self) -> Option<Waiter> {
+        let waiter = self.waitlist.pop_front()?;
+
+        self.reserved
+            .insert(waiter.guild_id, Instant::now() + RESERVE_TIME);
+
+        Some(waiter)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.admission.release();
+    }
+}
+
+impl QueueServer {
+    /// Lets at most `max_players` players run at once, waitlisting guilds
+    /// that want to play past that.
+    pub fn with_max_players(self, max_players: usize) -> QueueServer {
+        QueueServer {
+            admission: Arc::new(Admission::new(Some(max_players), self.http_client.clone())),
+            ..self
+        }
+    }
+}
+
+impl QueueState {
+    /// Takes a slot for a player before joining the user's channel.
+    pub(super) fn admit(&mut self, command: &CommandData) -> Result<(), UserError> {
+        if self.slot.is_some() {
+            return Ok(());
+        }
+
+        let waiter = Waiter {
+            guild_id: self.guild_id,
+            channel_id: command.channel_id,
+            locale: command.locale.clone(),
+        };
+
+        self.slot = Some(
+            self.queue_server
+                .admission
+                .admit(waiter)
+                .map_err(UserError::AtCapacity)?,
+        );
+
+        Ok(())
+    }
+
+    /// Takes a slot for a player, if the queue doesn't have one yet.
+    pub(super) fn acquire_slot(&mut self) {
+        if self.slot.is_none() {
+            self.slot = Some(self.queue_server.admission.acquire(self.guild_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waitlists_past_capacity() {
+        let admission = Arc::new(Admission::new(
+            Some(1),
+            Arc::new(HttpClient::new(String::new())),
+        ));
+        let waiter = |guild_id| Waiter {
+            guild_id: Id::new(guild_id),
+            channel_id: Id::new(1),
+            locale: None,
+        };
+
+        let slot = admission.admit(waiter(1)).unwrap();
+        assert_eq!(admission.admit(waiter(2)).unwrap_err(), 1);
+        assert_eq!(admission.admit(waiter(3)).unwrap_err(), 2);
+        assert_eq!(admission.admit(waiter(2)).unwrap_err(), 1);
+
+        // the freed slot is held for the first guild in line
+        drop(slot);
+        assert_eq!(admission.to_json()["active"], 0);
+        assert_eq!(admission.admit(waiter(3)).unwrap_err(), 1);
+        let slot = admission.admit(waiter(2)).unwrap();
+        assert_eq!(admission.to_json()["active"], 1);
+
+        // a slot that isn't taken in time goes to the next guild in line
+        assert_eq!(admission.admit(waiter(4)).unwrap_err(), 2);
+        drop(slot);
+        for until in admission.state.lock().unwrap().reserved.values_mut() {
+            *until = Instant::now();
+        }
+
+        assert_eq!(admission.admit(waiter(5)).unwrap_err(), 1);
+        let _slot = admission.admit(waiter(4)).unwrap();
+        assert_eq!(admission.to_json()["reserved"], 0);
+    }
+}
@@ -0,0 +1,190 @@
+//! Where the audio of a queue is played.
+//!
+//! Queues play through a [`PlaybackBackend`]. The native backend is the
+//! voice client in [`voice`](crate::voice), which does the audio work in this
+//! process; a [Lavalink](crate::lavalink) node can do it somewhere else
+//! instead.
+
+use futures_util::future::BoxFuture;
+use opus::Bitrate;
+use tokio::sync::RwLockReadGuard;
+use twilight_model::gateway::payload::incoming::{VoiceServerUpdate, VoiceStateUpdate};
+use twilight_model::id::{marker::GuildMarker, Id};
+use twilight_model::voice::VoiceState;
+
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::tts;
+use crate::voice::encoder::Signal;
+use crate::voice::{Overlay, Player, Source, SourceBuilder, StreamTimings};
+use crate::ytdl::{Track, YtdlConfig};
+
+/// Something that plays a guild's audio.
+///
+/// A backend reports what happens to the audio as [`voice::Event`]s on the
+/// channel it was created with.
+///
+/// [`voice::Event`]: crate::voice::Event
+pub trait PlaybackBackend: Send + Sync {
+    /// Plays a track from `start`, replacing what is playing.
+    fn play(&self, track: &Track, start: Duration) -> Result<(), crate::Error>;
+
+    /// Sets the bitrate tracks are played at, from the next track on.
+    ///
+    /// Backends that don't encode the audio themselves ignore this.
+    fn set_bitrate(&self, _bitrate: Bitrate) {}
+
+    /// Plays a source over the playing track, which continues after.
+    fn interject(&self, source: Source) -> Result<(), crate::Error>;
+
+    /// Plays an overlay over the playing track, turned down to `gain`.
+    fn announce(&self, overlay: Overlay, gain: f32) -> Result<(), crate::Error>;
+
+    /// Pauses the playing track.
+    fn pause(&self) -> Result<(), crate::Error>;
+
+    /// Resumes the paused track.
+    fn resume(&self) -> Result<(), crate::Error>;
+
+    /// Stops the playing track.
+    fn stop(&self) -> Result<(), crate::Error>;
+
+    /// Leaves the voice channel for good.
+    fn disconnect(&self) -> Result<(), crate::Error>;
+
+    /// Whether a track is playing.
+    fn playing(&self) -> bool;
+
+    /// The position in the playing track.
+    fn position(&self) -> Duration;
+
+    /// The round trip time to the voice server, if the backend knows it.
+    fn latency(&self) -> Option<Duration> {
+        None
+    }
+
+    /// How long streaming packets takes, if the backend streams them.
+    fn timings(&self) -> Option<&StreamTimings> {
+        None
+    }
+
+    /// The bot's voice state in the guild.
+    fn voice_state(&self) -> BoxFuture<'_, Result<RwLockReadGuard<'_, VoiceState>, crate::Error>>;
+
+    /// Sends a voice state update event to the backend.
+    fn voice_state_update(&self, ev: Box<VoiceStateUpdate>) -> Result<(), crate::Error>;
+
+    /// Sends a voice server update event to the backend.
+    fn voice_server_update(&self, ev: VoiceServerUpdate) -> Result<(), crate::Error>;
+}
+
+impl PlaybackBackend for Player {
+    fn play(&self, track: &Track, start: Duration) -> Result<(), crate::Error> {
+        // the tracks of an album flow into each other, so they don't fade
+        let gapless = track.album.is_some();
+        let fade_in = if gapless {
+            Duration::ZERO
+        } else {
+            self.fade_in()
+        };
+
+        let builder = track_source(track, start, self.guild_id(), self.ytdl())?
+            .fade_in(fade_in)
+            .bitrate(self.bitrate())
+            .timings(self.timings().clone());
+        info!(
+            url = track.url,
+            format = track.format.as_ref().map(|format| format.id.as_str()),
+            pipeline = %builder.pipeline(),
+            "playing track",
+        );
+
+        let source = builder.build()?;
+        Player::set_gapless(self, gapless)?;
+        Player::play(self, source)
+    }
+
+    fn set_bitrate(&self, bitrate: Bitrate) {
+        Player::set_bitrate(self, bitrate)
+    }
+
+    fn interject(&self, source: Source) -> Result<(), crate::Error> {
+        Player::interject(self, source)
+    }
+
+    fn announce(&self, overlay: Overlay, gain: f32) -> Result<(), crate::Error> {
+        Player::announce(self, overlay, gain)
+    }
+
+    fn pause(&self) -> Result<(), crate::Error> {
+        Player::pause(self)
+    }
+
+    fn resume(&self) -> Result<(), crate::Error> {
+        Player::resume(self)
+    }
+
+    fn stop(&self) -> Result<(), crate::Error> {
+        Player::stop(self)
+    }
+
+    fn disconnect(&self) -> Result<(), crate::Error> {
+        Player::disconnect(self)
+    }
+
+    fn playing(&self) -> bool {
+        Player::playing(self)
+    }
+
+    fn position(&self) -> Duration {
+        Player::position(self)
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        Player::latency(self)
+    }
+
+    fn timings(&self) -> Option<&StreamTimings> {
+        Some(Player::timings(self))
+    }
+
+    fn voice_state(&self) -> BoxFuture<'_, Result<RwLockReadGuard<'_, VoiceState>, crate::Error>> {
+        Box::pin(Player::voice_state(self))
+    }
+
+    fn voice_state_update(&self, ev: Box<VoiceStateUpdate>) -> Result<(), crate::Error> {
+        Player::voice_state_update(self, ev)
+    }
+
+    fn voice_server_update(&self, ev: VoiceServerUpdate) -> Result<(), crate::Error> {
+        Player::voice_server_update(self, ev)
+    }
+}
+
+/// Starts playing a track from `offset`.
+///
+/// Speech is always said from the start.
+fn track_source(
+    track: &Track,
+    offset: Duration,
+    guild_id: Id<GuildMarker>,
+    ytdl: &YtdlConfig,
+) -> Result<SourceBuilder, crate::voice::source::Error> {
+    match &track.speech {
+        Some(text) => tts::tts_backend()
+            .expect("speech is only enqueued with a backend")
+            .builder(text)
+            .map(|builder| builder.signal(Signal::Voice)),
+        None => Ok(super::cache::audio_cache()
+            .and_then(|cache| cache.source(track, guild_id, ytdl))
+            .unwrap_or_else(|| match &track.format {
+                Some(format) => SourceBuilder::format(&track.url, format),
+                None => SourceBuilder::ytdl(&track.url),
+            })
+            .ytdl_config(ytdl.clone())
+            .offset(offset)
+            .signal(Signal::Music)),
+    }
+}
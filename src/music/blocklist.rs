@@ -0,0 +1,282 @@
+//! Content that moderators don't let anyone play.
+//!
+//! Each guild keeps a [`Blocklist`] in the store, changed with `/block`.
+//! Tracks are checked against it by the guild's
+//! [`Policy`](super::policy::Policy) before they're enqueued, so single
+//! tracks, playlist items and autoplayed tracks are all caught.
+
+use tracing::error;
+use twilight_model::guild::Permissions;
+
+use super::commands::{BlockAction, CommandData};
+use super::{youtube_id, QueueState, UserError};
+use crate::store::{BlockKind, Blocklist};
+use crate::ytdl::Track;
+
+/// How long the list of blocked entries can get before the rest are left
+/// out, under Discord's limit of 2000 characters.
+const MAX_SUMMARY_LEN: usize = 1800;
+
+impl Blocklist {
+    /// Checks if a track is blocked, returning the kind of entry that
+    /// blocks it.
+    pub fn blocks(&self, track: &Track) -> Option<BlockKind> {
+        if !track.url.is_empty() {
+            let url = url_key(&track.url);
+
+            if self.urls.iter().any(|entry| url_key(entry) == url) {
+                return Some(BlockKind::Url);
+            }
+        }
+
+        let author_url = track.author.url.as_deref().map(url_key);
+        let blocked_channel = self.channels.iter().any(|entry| {
+            author_url == Some(url_key(entry)) || entry.eq_ignore_ascii_case(&track.author.name)
+        });
+
+        if blocked_channel {
+            return Some(BlockKind::Channel);
+        }
+
+        let title = track.title.to_lowercase();
+
+        if self.keywords.iter().any(|keyword| title.contains(keyword)) {
+            return Some(BlockKind::Keyword);
+        }
+
+        None
+    }
+}
+
+/// Gets the part of a url that identifies what it points to, so the same
+/// video with different timestamps or links matches.
+pub(super) fn url_key(url: &str) -> &str {
+    youtube_id(url).unwrap_or_else(|| url.trim_end_matches('/'))
+}
+
+/// Cleans up an entry before it is added or removed.
+fn normalize(kind: BlockKind, value: &str) -> String {
+    let value = value.trim();
+
+    match kind {
+        BlockKind::Keyword => value.to_lowercase(),
+        BlockKind::Url | BlockKind::Channel => value.to_owned(),
+    }
+}
+
+impl QueueState {
+    /// Gets the guild's blocklist.
+    pub(super) async fn blocklist(&self) -> Blocklist {
+        self.queue_server
+            .store
+            .read(|store| {
+                store
+                    .blocklists
+                    .get(&self.guild_id)
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .await
+    }
+
+    pub(super) async fn block(
+        &self,
+        command: &CommandData,
+        action: BlockAction,
+    ) -> Result<(), UserError> {
+        if !command.permissions.contains(Permissions::MANAGE_GUILD) {
+            return Err(UserError::NotManager);
+        }
+
+        let (kind, value, add) = match action {
+            BlockAction::Add(kind, value) => (kind, normalize(kind, &value), true),
+            BlockAction::Remove(kind, value) => (kind, normalize(kind, &value), false),
+            BlockAction::List => {
+                let msg = blocklist_summary(command, &self.blocklist().await);
+
+                command
+                    .respond(&self.queue_server.http_client)
+                    .content(msg)
+                    .ephemeral()
+                    .respond()
+                    .await;
+
+                return Ok(());
+            }
+        };
+
+        if value.is_empty() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("there's nothing to block"))
+                .ephemeral()
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        let guild_id = self.guild_id;
+        let res = self
+            .queue_server
+            .store
+            .update(|store| {
+                let blocklist = store.blocklists.entry(guild_id).or_default();
+                let changed = if add {
+                    blocklist.entries_mut(kind).insert(value.clone())
+                } else {
+                    blocklist.entries_mut(kind).remove(&value)
+                };
+
+                if blocklist.is_empty() {
+                    store.blocklists.remove(&guild_id);
+                }
+
+                changed
+            })
+            .await;
+
+        let msg = match res {
+            Ok(true) if add => command.trf(
+                "blocked {kind} `{value}`",
+                &[("kind", &kind.name()), ("value", &value)],
+            ),
+            Ok(true) => command.trf(
+                "unblocked {kind} `{value}`",
+                &[("kind", &kind.name()), ("value", &value)],
+            ),
+            Ok(false) if add => command.trf(
+                "{kind} `{value}` is already blocked",
+                &[("kind", &kind.name()), ("value", &value)],
+            ),
+            Ok(false) => command.trf(
+                "{kind} `{value}` isn't blocked",
+                &[("kind", &kind.name()), ("value", &value)],
+            ),
+            Err(err) => {
+                error!(%err, "failed to save blocklist");
+
+                command
+                    .respond(&self.queue_server.http_client)
+                    .error(command.tr("failed to save the change"))
+                    .ephemeral()
+                    .respond()
+                    .await;
+
+                return Ok(());
+            }
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .ephemeral()
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Lists everything on a blocklist, leaving out what doesn't fit in a
+/// message.
+fn blocklist_summary(command: &CommandData, blocklist: &Blocklist) -> String {
+    if blocklist.is_empty() {
+        return command.tr("nothing is blocked in this server").to_owned();
+    }
+
+    let mut msg = String::new();
+    let mut left_out = 0;
+
+    for kind in BlockKind::ALL {
+        let mut entries = blocklist.entries(kind).iter().collect::<Vec<_>>();
+
+        if entries.is_empty() {
+            continue;
+        }
+
+        entries.sort();
+
+        let heading = format!("**{}**", kind.name());
+
+        if left_out > 0 || msg.len() + heading.len() > MAX_SUMMARY_LEN {
+            left_out += entries.len();
+            continue;
+        }
+
+        if !msg.is_empty() {
+            msg.push('\n');
+        }
+
+        msg.push_str(&heading);
+
+        for entry in entries {
+            let line = format!("\n- `{}`", entry);
+
+            if left_out > 0 || msg.len() + line.len() > MAX_SUMMARY_LEN {
+                left_out += 1;
+            } else {
+                msg.push_str(&line);
+            }
+        }
+    }
+
+    if left_out > 0 {
+        msg.push('\n');
+        msg.push_str(&command.trf("...and {count} more", &[("count", &left_out)]));
+    }
+
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ytdl::Author;
+
+    fn track(url: &str, title: &str, author: &str) -> Track {
+        Track {
+            url: url.to_owned(),
+            title: title.to_owned(),
+            author: Author {
+                name: author.to_owned(),
+                url: Some(format!("https://www.youtube.com/@{}", author)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn blocks_tracks() {
+        let mut blocklist = Blocklist::default();
+        blocklist
+            .urls
+            .insert(String::from("https://youtu.be/dQw4w9WgXcQ?t=5"));
+        blocklist.channels.insert(String::from("spammer"));
+        blocklist
+            .keywords
+            .insert(normalize(BlockKind::Keyword, "Earrape "));
+
+        let blocked = track(
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "a song",
+            "someone",
+        );
+        assert_eq!(blocklist.blocks(&blocked), Some(BlockKind::Url));
+
+        let blocked = track("https://www.youtube.com/watch?v=a", "a song", "Spammer");
+        assert_eq!(blocklist.blocks(&blocked), Some(BlockKind::Channel));
+
+        let blocked = track(
+            "https://www.youtube.com/watch?v=b",
+            "EARRAPE remix",
+            "someone",
+        );
+        assert_eq!(blocklist.blocks(&blocked), Some(BlockKind::Keyword));
+
+        let allowed = track("https://www.youtube.com/watch?v=c", "a song", "someone");
+        assert_eq!(blocklist.blocks(&allowed), None);
+    }
+}
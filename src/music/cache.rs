@@ -0,0 +1,392 @@
+//! Caching the audio of short tracks on disk.
+//!
+//! Short tracks, like memes and soundtracks, tend to be played over and over.
+//! With a cache, the first play downloads the track's audio in the
+//! background, and later plays read it from disk instead of starting
+//! `youtube-dl` again, which saves bandwidth and starts much faster. Audio is
+//! downloaded again after [`AudioCacheConfig::ttl`], and the least recently
+//! played audio is removed when the cache gets bigger than
+//! [`AudioCacheConfig::max_size`].
+
+use tokio::time::Instant;
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::voice::SourceBuilder;
+use crate::ytdl::{Track, YtdlConfig};
+
+/// The longest track that is cached, if not set with
+/// `AUDIO_CACHE_MAX_DURATION`.
+pub const DEFAULT_MAX_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// How long audio is kept, if not set with `AUDIO_CACHE_TTL`.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How big the cache can get in bytes, if not set with
+/// `AUDIO_CACHE_MAX_SIZE`.
+pub const DEFAULT_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The extension of cached audio.
+const AUDIO_EXTENSION: &str = "audio";
+
+/// The extension of audio that is still downloading.
+const DOWNLOAD_EXTENSION: &str = "download";
+
+static AUDIO_CACHE: OnceLock<Option<AudioCache>> = OnceLock::new();
+
+/// The audio cache.
+///
+/// If the cache was never initialized, there is none, and every track is
+/// played with `youtube-dl`.
+pub fn audio_cache() -> Option<&'static AudioCache> {
+    AUDIO_CACHE.get_or_init(|| None).as_ref()
+}
+
+pub fn init_audio_cache<F>(f: F) -> Option<&'static AudioCache>
+where
+    F: FnOnce() -> Option<AudioCache>,
+{
+    AUDIO_CACHE.get_or_init(f).as_ref()
+}
+
+/// Where audio is cached and for how long.
+#[derive(Clone, Debug)]
+pub struct AudioCacheConfig {
+    /// The directory the audio is kept in.
+    pub dir: PathBuf,
+    /// The longest track that is cached.
+    pub max_duration: Duration,
+    /// How long audio is played from disk before it is downloaded again.
+    pub ttl: Duration,
+    /// How big the cache can get, in bytes.
+    pub max_size: u64,
+}
+
+impl AudioCacheConfig {
+    /// Reads the config from the environment.
+    ///
+    /// Returns `None` if `AUDIO_CACHE_DIR` isn't set, so nothing is cached.
+    /// `AUDIO_CACHE_MAX_DURATION` and `AUDIO_CACHE_TTL` are in seconds, and
+    /// `AUDIO_CACHE_MAX_SIZE` is in megabytes.
+    pub fn from_env() -> Option<AudioCacheConfig> {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+
+        Some(AudioCacheConfig {
+            dir: env::var("AUDIO_CACHE_DIR").ok()?.into(),
+            max_duration: var("AUDIO_CACHE_MAX_DURATION")
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_MAX_DURATION),
+            ttl: var("AUDIO_CACHE_TTL")
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TTL),
+            max_size: var("AUDIO_CACHE_MAX_SIZE")
+                .map(|mb| mb * 1024 * 1024)
+                .unwrap_or(DEFAULT_MAX_SIZE),
+        })
+    }
+}
+
+/// Audio of short tracks, kept on disk.
+#[derive(Debug)]
+pub struct AudioCache {
+    config: AudioCacheConfig,
+    index: Mutex<Index>,
+}
+
+impl AudioCache {
+    /// Opens the cache, creating its directory.
+    ///
+    /// Audio left over from before is removed, since the cache doesn't know
+    /// how old it is.
+    pub fn new(config: AudioCacheConfig) -> Result<AudioCache, std::io::Error> {
+        std::fs::create_dir_all(&config.dir)?;
+
+        for entry in std::fs::read_dir(&config.dir)? {
+            let path = entry?.path();
+            let ext = path.extension().and_then(|ext| ext.to_str());
+
+            if matches!(ext, Some(AUDIO_EXTENSION | DOWNLOAD_EXTENSION)) {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+
+        Ok(AudioCache {
+            config,
+            index: Mutex::default(),
+        })
+    }
+
+    /// Gets a source for the cached audio of a track.
+    ///
+    /// If the track isn't cached but is short enough, its audio starts
+    /// downloading once the guild gets one of the
+    /// [`query_slots`](super::query_slots), and `None` is returned so it is
+    /// played with `youtube-dl` this time.
+    pub fn source(
+        &'static self,
+        track: &Track,
+        guild_id: Id<GuildMarker>,
+        ytdl: &YtdlConfig,
+    ) -> Option<SourceBuilder> {
+        let key = key(&track.url);
+        let now = Instant::now();
+
+        let mut index = self.index.lock().unwrap();
+
+        if let Some(path) = index.get(&key, now, self.config.ttl) {
+            debug!(url = track.url, "playing cached audio");
+            return Some(SourceBuilder::url(path.to_string_lossy()));
+        }
+
+        let short = track
+            .duration
+            .is_some_and(|duration| duration <= self.config.max_duration);
+
+        if short && index.start_download(&key) {
+            let url = track.url.clone();
+            let ytdl = ytdl.clone();
+            tokio::spawn(async move {
+                let _permit = super::query_slots().acquire(guild_id).await;
+                self.download(&ytdl, key, url).await
+            });
+        }
+
+        None
+    }
+
+    async fn download(&self, ytdl: &YtdlConfig, key: String, url: String) {
+        let path = self.path(&key, AUDIO_EXTENSION);
+        let download_path = self.path(&key, DOWNLOAD_EXTENSION);
+
+        let res = ytdl
+            .command()
+            .args(["-f", "bestaudio/best", "-q", "--no-playlist", "-o"])
+            .arg(&download_path)
+            .arg("--")
+            .arg(&url)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await;
+
+        let size = match res {
+            Ok(status) if status.success() => {
+                match tokio::fs::rename(&download_path, &path).await {
+                    Ok(()) => tokio::fs::metadata(&path).await.map(|m| m.len()).ok(),
+                    Err(err) => {
+                        warn!(%err, url, "failed to move cached audio");
+                        None
+                    }
+                }
+            }
+            Ok(status) => {
+                warn!(%status, url, "failed to download audio to cache");
+                None
+            }
+            Err(err) => {
+                warn!(%err, url, "failed to download audio to cache");
+                None
+            }
+        };
+
+        let mut index = self.index.lock().unwrap();
+
+        let Some(size) = size else {
+            let _ = std::fs::remove_file(&download_path);
+            index.cancel_download(&key);
+            return;
+        };
+
+        debug!(url, size, "cached audio");
+
+        for path in index.insert(key, path, size, Instant::now(), self.config.max_size) {
+            debug!(?path, "evicting cached audio");
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.config.dir.join(key).with_extension(extension)
+    }
+}
+
+/// Gets the name a track's audio is cached under.
+///
+/// YouTube videos are cached by their id, so different links to the same
+/// video share the audio.
+fn key(url: &str) -> String {
+    match super::youtube_id(url) {
+        Some(id) => format!("yt-{}", id),
+        None => {
+            let mut hasher = DefaultHasher::new();
+            url.hash(&mut hasher);
+            format!("url-{:016x}", hasher.finish())
+        }
+    }
+}
+
+/// What is in the cache.
+#[derive(Debug, Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    /// The size of all cached audio, in bytes.
+    size: u64,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Downloading,
+    Cached {
+        path: PathBuf,
+        size: u64,
+        cached_at: Instant,
+        used_at: Instant,
+    },
+}
+
+impl Index {
+    /// Gets the path of fresh cached audio, marking it as used.
+    ///
+    /// Stale audio is removed.
+    fn get(&mut self, key: &str, now: Instant, ttl: Duration) -> Option<&Path> {
+        match self.entries.get(key) {
+            Some(Entry::Cached { cached_at, .. }) if now - *cached_at > ttl => {
+                if let Some(path) = self.remove(key) {
+                    let _ = std::fs::remove_file(path);
+                }
+
+                None
+            }
+            Some(Entry::Cached { .. }) => match self.entries.get_mut(key) {
+                Some(Entry::Cached { path, used_at, .. }) => {
+                    *used_at = now;
+                    Some(path)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Marks audio as downloading, returning `false` if it already is.
+    fn start_download(&mut self, key: &str) -> bool {
+        if self.entries.contains_key(key) {
+            return false;
+        }
+
+        self.entries.insert(key.to_owned(), Entry::Downloading);
+        true
+    }
+
+    fn cancel_download(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    /// Adds downloaded audio, returning the paths of audio evicted to keep
+    /// the cache under `max_size`.
+    fn insert(
+        &mut self,
+        key: String,
+        path: PathBuf,
+        size: u64,
+        now: Instant,
+        max_size: u64,
+    ) -> Vec<PathBuf> {
+        self.size += size;
+        self.entries.insert(
+            key,
+            Entry::Cached {
+                path,
+                size,
+                cached_at: now,
+                used_at: now,
+            },
+        );
+
+        let mut evicted = Vec::new();
+
+        while self.size > max_size {
+            let oldest = self
+                .entries
+                .iter()
+                .filter_map(|(key, entry)| match entry {
+                    Entry::Cached { used_at, .. } => Some((key, *used_at)),
+                    Entry::Downloading => None,
+                })
+                .min_by_key(|(_, used_at)| *used_at)
+                .map(|(key, _)| key.clone());
+
+            match oldest.and_then(|key| self.remove(&key)) {
+                Some(path) => evicted.push(path),
+                None => break,
+            }
+        }
+
+        evicted
+    }
+
+    fn remove(&mut self, key: &str) -> Option<PathBuf> {
+        match self.entries.remove(key)? {
+            Entry::Cached { path, size, .. } => {
+                self.size -= size;
+                Some(path)
+            }
+            Entry::Downloading => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut index = Index::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+
+        assert!(index.start_download("a"));
+        assert!(!index.start_download("a"));
+        assert!(index
+            .insert("a".into(), "a".into(), 40, now, 100)
+            .is_empty());
+        assert!(index
+            .insert(
+                "b".into(),
+                "b".into(),
+                40,
+                now + Duration::from_secs(1),
+                100
+            )
+            .is_empty());
+
+        // a was played more recently than b
+        assert!(index.get("a", now + Duration::from_secs(2), ttl).is_some());
+
+        let evicted = index.insert(
+            "c".into(),
+            "c".into(),
+            40,
+            now + Duration::from_secs(3),
+            100,
+        );
+        assert_eq!(evicted, [PathBuf::from("b")]);
+        assert_eq!(index.size, 80);
+
+        // a is stale
+        assert!(index.get("a", now + Duration::from_secs(61), ttl).is_none());
+        assert_eq!(index.size, 40);
+    }
+}
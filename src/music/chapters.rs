@@ -0,0 +1,182 @@
+//! Moving around in tracks that the uploader split into chapters.
+//!
+//! `youtube-dl` gives the chapters of a track with its metadata, so they
+//! are only known for tracks it found. `/chapters` lists them, and
+//! `/chapter` seeks the playing track to the start of one.
+
+use std::time::Duration;
+
+use tracing::error;
+
+use super::commands::CommandData;
+use super::{format_duration, PlayerState, QueueState, UserError};
+use crate::ytdl::{Chapter, Track};
+
+impl Track {
+    /// Gets the index of the chapter at `position`, if the track has one
+    /// there.
+    pub fn chapter_at(&self, position: Duration) -> Option<usize> {
+        self.chapters
+            .iter()
+            .rposition(|chapter| chapter.start <= position && position < chapter.end)
+    }
+}
+
+impl QueueState {
+    pub(super) async fn chapters(&self, command: &CommandData) -> Result<(), UserError> {
+        let (Some(track), Some(PlayerState { player, .. })) =
+            (self.playing.as_ref(), self.player.as_ref())
+        else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("nothing is playing"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        if track.chapters.is_empty() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("this track doesn't have chapters"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        let current = track.chapter_at(player.position());
+        let mut embed = track.as_embed(self.large_thumbnails);
+        embed.description = Some(chapter_list(&track.chapters, current));
+
+        command
+            .respond(&self.queue_server.http_client)
+            .embed(embed)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    pub(super) async fn chapter(
+        &mut self,
+        command: &CommandData,
+        number: usize,
+    ) -> Result<(), UserError> {
+        let (Some(track), Some(PlayerState { player, .. })) =
+            (self.playing.as_ref(), self.player.as_ref())
+        else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("nothing is playing"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        let Some(chapter) = number
+            .checked_sub(1)
+            .and_then(|index| track.chapters.get(index))
+        else {
+            let msg = if track.chapters.is_empty() {
+                command.tr("this track doesn't have chapters").to_owned()
+            } else {
+                command.trf(
+                    "there is no chapter {number}, this track has {count}",
+                    &[
+                        ("number", &number.to_string()),
+                        ("count", &track.chapters.len().to_string()),
+                    ],
+                )
+            };
+
+            command
+                .respond(&self.queue_server.http_client)
+                .error(msg)
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        if let Err(err) = player.play(track, chapter.start) {
+            error!(%err, "failed to seek to chapter");
+
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("failed to seek to the chapter"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        let msg = command.trf(
+            "skipped to chapter {number}, \"{title}\" at {position}",
+            &[
+                ("number", &number.to_string()),
+                ("title", &chapter.title),
+                ("position", &format_duration(chapter.start)),
+            ],
+        );
+
+        // the track starts playing again if it was paused
+        self.paused = false;
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Lists chapters, one per line, with `current` in bold.
+fn chapter_list(chapters: &[Chapter], current: Option<usize>) -> String {
+    chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            let line = format!(
+                "{}. `{}` {}",
+                i + 1,
+                format_duration(chapter.start),
+                chapter.title
+            );
+
+            if Some(i) == current {
+                format!("**{}**", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(title: &str, start: u64, end: u64) -> Chapter {
+        Chapter {
+            title: title.to_owned(),
+            start: Duration::from_secs(start),
+            end: Duration::from_secs(end),
+        }
+    }
+
+    #[test]
+    fn lists_chapters() {
+        let chapters = [chapter("intro", 0, 30), chapter("verse", 30, 95)];
+
+        assert_eq!(
+            chapter_list(&chapters, Some(1)),
+            "1. `0:00` intro\n**2. `0:30` verse**"
+        );
+    }
+}
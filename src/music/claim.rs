@@ -0,0 +1,76 @@
+//! Splitting guilds between bot processes.
+//!
+//! Playing audio is the heaviest thing the bot does, so a big bot can run
+//! several processes that each take a share of the guilds. Guilds are split
+//! the same way Discord splits them between shards, so a process that runs
+//! the matching shard gets the events of exactly the guilds it claims.
+
+use std::env;
+
+use twilight_model::id::{marker::GuildMarker, Id};
+
+/// The share of guilds a process handles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuildClaim {
+    /// The index of this process, starting at zero.
+    pub index: u64,
+    /// How many processes the guilds are split between.
+    pub count: u64,
+}
+
+impl GuildClaim {
+    /// Creates a claim on every guild, for a bot with a single process.
+    pub const ALL: GuildClaim = GuildClaim { index: 0, count: 1 };
+
+    /// Creates a claim on the `index`th share of `count`.
+    ///
+    /// Returns `None` if `index` isn't less than `count`.
+    pub fn new(index: u64, count: u64) -> Option<GuildClaim> {
+        (index < count).then_some(GuildClaim { index, count })
+    }
+
+    /// Reads the claim from the environment.
+    ///
+    /// `CLAIM_INDEX` and `CLAIM_COUNT` set the share of this process. If
+    /// they aren't set or are invalid, every guild is claimed.
+    pub fn from_env() -> GuildClaim {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse().ok());
+
+        var("CLAIM_INDEX")
+            .zip(var("CLAIM_COUNT"))
+            .and_then(|(index, count)| GuildClaim::new(index, count))
+            .unwrap_or(GuildClaim::ALL)
+    }
+
+    /// Checks if this process handles a guild.
+    pub fn claims(&self, guild_id: Id<GuildMarker>) -> bool {
+        // Discord's shard formula
+        (guild_id.get() >> 22) % self.count == self.index
+    }
+}
+
+impl Default for GuildClaim {
+    fn default() -> GuildClaim {
+        GuildClaim::ALL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_guilds() {
+        let guilds = (1..100u64).map(|n| Id::new(n << 22));
+        let claims = (0..3)
+            .map(|index| GuildClaim::new(index, 3).unwrap())
+            .collect::<Vec<_>>();
+
+        for guild_id in guilds {
+            assert_eq!(claims.iter().filter(|c| c.claims(guild_id)).count(), 1);
+            assert!(GuildClaim::ALL.claims(guild_id));
+        }
+
+        assert_eq!(GuildClaim::new(3, 3), None);
+    }
+}
@@ -2,20 +2,25 @@
 
 use std::fmt::Display;
 use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::i18n;
+use crate::interaction::{CastError, CommandOptionType};
+use crate::scrobble::Service;
+use crate::sponsorblock::Category;
+use crate::store::{AgePolicy, BlockKind, Feature, NowPlayingStatus};
+
+use super::respond::{Outbox, Responder};
 
-use twilight_http::{
-    client::{Client as HttpClient, InteractionClient},
-    response::{marker::EmptyBody, Response},
-    Error as HttpError,
-};
 use twilight_model::{
-    channel::{
-        message::{Embed, MessageFlags},
-        Message,
-    },
-    http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
+    application::interaction::application_command::CommandOptionValue,
+    guild::Permissions,
     id::{
-        marker::{ApplicationMarker, GuildMarker, InteractionMarker, UserMarker},
+        marker::{ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker},
         Id,
     },
 };
@@ -32,133 +37,601 @@ pub struct Command {
 /// The actual command data.
 #[derive(Clone, Debug)]
 pub struct CommandData {
-    pub interaction_id: Id<InteractionMarker>,
-    pub interaction_token: String,
+    /// Where responses to the command go.
+    pub responder: Arc<dyn Responder>,
+    /// Sends the responses to the responder.
+    pub outbox: Outbox,
 
-    pub application_id: Id<ApplicationMarker>,
     pub guild_id: Id<GuildMarker>,
+    pub channel_id: Id<ChannelMarker>,
     pub user_id: Id<UserMarker>,
+
+    /// The roles of the user.
+    pub roles: Vec<Id<RoleMarker>>,
+    /// The permissions of the user in the channel.
+    pub permissions: Permissions,
+
+    /// The locale of the user, used to translate responses.
+    pub locale: Option<String>,
 }
 
 /// The action that a commands wants completed.
 #[derive(Debug)]
 pub enum Action {
     /// Plays a track, with a URL to query YTDL with.
-    Play(String, bool),
+    Play(String, PlayOptions),
     /// Skips the currently playing track.
     Skip,
     /// Lists all of the tracks in a queue.
     Queue,
-    /// Shuffles the tracks in a queue.
-    Shuffle,
+    /// Shuffles the tracks in a queue, optionally with a seed.
+    Shuffle(Option<u64>),
+    /// Restores the order of a queue before it was shuffled.
+    Unshuffle,
     /// Disconnects the bot.
     Disconnect,
     /// Sets the autodisconnect flag.
     AutoDisconnect(Option<bool>),
+    /// Bookmarks the current position of the playing track, with an
+    /// optional name.
+    Bookmark(Option<String>),
+    /// Plays a bookmark next.
+    Jump(String),
+    /// Changes the settings of the queue, or shows them if nothing is
+    /// changed.
+    Settings(SettingsUpdate),
+    /// Says some text with text-to-speech.
+    Tts(String, TtsMode),
+    /// Plays an announcement over the playing track, ducking it.
+    Announce(Announcement),
+    /// Exports the queue as a file.
+    Export(ExportFormat),
+    /// Enqueues the tracks of an exported queue or a list of urls.
+    Import(ImportSource),
+    /// Manages the guild's saved playlists.
+    Playlist(PlaylistAction),
+    /// Sets the autoplay flag.
+    Autoplay(Option<bool>),
+    /// Pauses the playing track, or resumes it if it is paused.
+    Pause,
+    /// Stops playing and clears the queue.
+    Stop,
+    /// Sets what is looped, or moves to the next loop mode.
+    Loop(Option<LoopMode>),
+    /// Posts a control panel for the player, which shows the progress of
+    /// the playing track if set.
+    Player(bool),
+    /// Plays a list of urls or search queries, in order.
+    BulkPlay(Vec<String>, PlayOptions),
+    /// Plays the links in the message a text command replied to.
+    PlayReply(Reply, PlayOptions),
+    /// Changes which guilds can use the bot and what they can use.
+    Operator(OperatorAction),
+    /// Plays one of the user's queued tracks, by its position in the queue,
+    /// after the playing track.
+    MyNext(usize),
+    /// Changes what the guild doesn't let anyone play.
+    Block(BlockAction),
+    /// Lists the chapters of the playing track.
+    Chapters,
+    /// Seeks the playing track to the start of a chapter, by its number.
+    Chapter(usize),
+    /// Shows what the guild has listened to the most.
+    Stats(StatsPeriod),
+    /// Dumps the state of a guild's queue, or this guild's if none is
+    /// given.
+    Debug(Option<Id<GuildMarker>>),
+    /// Shows the latency of the bot and checks that yt-dlp works.
+    Ping,
+    /// Updates yt-dlp.
+    UpdateYtdl,
+    /// Shows or empties the tracks that keep failing to play.
+    Quarantine(QuarantineAction),
+    /// Keeps everyone but the user and DJs from changing the queue, for a
+    /// while or the default lock time.
+    Lock(Option<Duration>),
+    /// Lets anyone change the queue again.
+    Unlock,
+    /// Sets where the guild scrobbles to, or goes back to the operator's
+    /// service if `None`.
+    Scrobble(Option<Service>),
 }
 
-impl CommandData {
-    /// Begins a command response.
-    pub fn respond<'a>(&'a self, client: &'a HttpClient) -> CommandResponse<'a> {
-        CommandResponse {
-            command: self,
-            client: client.interaction(self.application_id),
+impl Action {
+    /// The name of the action, for logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Play(..) => "play",
+            Action::Skip => "skip",
+            Action::Queue => "queue",
+            Action::Shuffle(_) => "shuffle",
+            Action::Unshuffle => "unshuffle",
+            Action::Disconnect => "disconnect",
+            Action::AutoDisconnect(_) => "autodisconnect",
+            Action::Bookmark(_) => "bookmark",
+            Action::Jump(_) => "jump",
+            Action::Settings(_) => "settings",
+            Action::Tts(..) => "tts",
+            Action::Announce(_) => "announce",
+            Action::Export(_) => "export",
+            Action::Import(_) => "import",
+            Action::Playlist(_) => "playlist",
+            Action::Autoplay(_) => "autoplay",
+            Action::Pause => "pause",
+            Action::Stop => "stop",
+            Action::Loop(_) => "loop",
+            Action::Player(_) => "player",
+            Action::BulkPlay(..) => "bulkplay",
+            Action::PlayReply(..) => "play",
+            Action::Operator(_) => "operator",
+            Action::MyNext(_) => "mynext",
+            Action::Block(_) => "block",
+            Action::Chapters => "chapters",
+            Action::Chapter(_) => "chapter",
+            Action::Stats(_) => "stats",
+            Action::Debug(_) => "debug",
+            Action::Ping => "ping",
+            Action::UpdateYtdl => "admin",
+            Action::Lock(_) => "lock",
+            Action::Unlock => "unlock",
+            Action::Quarantine(_) => "quarantine",
+            Action::Scrobble(_) => "scrobble",
+        }
+    }
+}
+
+/// What [`Action::Playlist`] does.
+#[derive(Clone, Debug)]
+pub enum PlaylistAction {
+    /// Saves the playing track and the queue under a name.
+    Save(String),
+    /// Enqueues a saved playlist.
+    Play(String),
+    /// Lists the saved playlists.
+    List,
+    /// Deletes a saved playlist.
+    Delete(String),
+}
 
-            content: None,
-            embeds: None,
-            flags: MessageFlags::empty(),
+/// What [`Action::Operator`] does.
+#[derive(Clone, Debug)]
+pub enum OperatorAction {
+    /// Adds a guild to the allowlist.
+    Allow(Id<GuildMarker>),
+    /// Stops a guild from using the bot.
+    Deny(Id<GuildMarker>),
+    /// Removes a guild from both lists and turns its features back on.
+    Reset(Id<GuildMarker>),
+    /// Turns the allowlist on or off.
+    Allowlist(bool),
+    /// Turns a feature on or off for a guild.
+    Feature(Id<GuildMarker>, Feature, bool),
+    /// Shows the access settings of a guild.
+    Show(Id<GuildMarker>),
+}
+
+/// What [`Action::Block`] does.
+#[derive(Clone, Debug)]
+pub enum BlockAction {
+    /// Adds an entry to the blocklist.
+    Add(BlockKind, String),
+    /// Removes an entry from the blocklist.
+    Remove(BlockKind, String),
+    /// Lists the blocklist.
+    List,
+}
+
+/// What [`Action::Quarantine`] does.
+#[derive(Clone, Copy, Debug)]
+pub enum QuarantineAction {
+    /// Lists the quarantined tracks.
+    List,
+    /// Lets every track out of quarantine.
+    Clear,
+}
+
+/// The format of [`Action::Export`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// JSON that keeps the details of every track.
+    #[default]
+    Json,
+    /// A url per line.
+    Urls,
+}
+
+impl ExportFormat {
+    /// Gets an export format from its name.
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name {
+            "json" => Some(ExportFormat::Json),
+            "urls" => Some(ExportFormat::Urls),
+            _ => None,
+        }
+    }
+
+    /// The extension of files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Urls => "txt",
         }
     }
 }
 
-impl Deref for Command {
-    type Target = CommandData;
+/// How far back [`Action::Stats`] looks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatsPeriod {
+    /// The last seven days.
+    #[default]
+    Week,
+    /// The last thirty days.
+    Month,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.data
+impl StatsPeriod {
+    /// Gets a period from its name.
+    pub fn from_name(name: &str) -> Option<StatsPeriod> {
+        match name {
+            "week" => Some(StatsPeriod::Week),
+            "month" => Some(StatsPeriod::Month),
+            _ => None,
+        }
+    }
+
+    /// How long the period is.
+    pub fn duration(&self) -> Duration {
+        match self {
+            StatsPeriod::Week => Duration::from_secs(7 * 24 * 60 * 60),
+            StatsPeriod::Month => Duration::from_secs(30 * 24 * 60 * 60),
+        }
     }
 }
 
-/// A builder for a response to a command.
-pub struct CommandResponse<'a> {
-    command: &'a CommandData,
-    client: InteractionClient<'a>,
+/// Where [`Action::Import`] reads tracks from.
+#[derive(Clone, Debug)]
+pub enum ImportSource {
+    /// A file attached to the command.
+    Attachment {
+        url: String,
+        filename: String,
+        /// The size of the file, in bytes.
+        size: u64,
+    },
+    /// Text pasted into the command.
+    Text(String),
+}
 
-    content: Option<String>,
-    embeds: Option<Vec<Embed>>,
-    flags: MessageFlags,
+/// What [`Action::Announce`] plays.
+///
+/// Exactly one of these should be set.
+#[derive(Clone, Debug, Default)]
+pub struct Announcement {
+    /// Text to say with text-to-speech.
+    pub text: Option<String>,
+    /// The url or query of a clip to play.
+    pub clip: Option<String>,
 }
 
-impl<'a> CommandResponse<'a> {
-    /// Sets the response as a quick, user friendly error.
-    pub fn error(&mut self, error: impl Display) -> &mut Self {
-        self.content = Some(error.to_string());
-        self.flags |= MessageFlags::EPHEMERAL;
+/// Changes for [`Action::Settings`].
+///
+/// Settings that are `None` are left alone.
+#[derive(Clone, Debug, Default)]
+pub struct SettingsUpdate {
+    /// How tracks are ordered in the queue.
+    pub queue_mode: Option<QueueMode>,
+    /// Whether the playing track is shown in the bot's presence.
+    pub presence: Option<bool>,
+    /// Which segments of YouTube videos are skipped.
+    pub sponsorblock: Option<SponsorBlockMode>,
+    /// The role needed to control the queue. The `@everyone` role, which
+    /// shares the guild's id, removes it.
+    pub dj_role: Option<Id<RoleMarker>>,
+    /// The longest track users without the DJ role can enqueue. Zero
+    /// removes the limit.
+    pub max_duration: Option<Duration>,
+    /// Whether users without the DJ role can enqueue livestreams.
+    pub livestreams: Option<bool>,
+    /// Where age-restricted tracks can play.
+    pub age_policy: Option<AgePolicy>,
+    /// Whether text commands can be used in the guild.
+    pub text_commands: Option<bool>,
+    /// Whether track thumbnails are shown full-size in embeds.
+    pub large_thumbnails: Option<bool>,
+    /// Whether tracks play at the bitrate of the voice channel, when it's
+    /// higher than the default.
+    pub high_quality: Option<bool>,
+    /// Whether a summary of the session is announced when the bot leaves on
+    /// its own.
+    pub farewell: Option<bool>,
+    /// Where the playing track is shown outside of commands.
+    pub now_playing_status: Option<NowPlayingStatus>,
+}
 
-        self
+impl SettingsUpdate {
+    /// Checks if the update doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.queue_mode.is_none()
+            && self.presence.is_none()
+            && self.sponsorblock.is_none()
+            && self.dj_role.is_none()
+            && self.max_duration.is_none()
+            && self.livestreams.is_none()
+            && self.age_policy.is_none()
+            && self.text_commands.is_none()
+            && self.large_thumbnails.is_none()
+            && self.high_quality.is_none()
+            && self.farewell.is_none()
+            && self.now_playing_status.is_none()
     }
 
-    /// Sets the content of the message.
-    pub fn content(&mut self, content: impl Display) -> &mut Self {
-        self.content = Some(content.to_string());
+    /// Checks if the update changes a setting only server managers can
+    /// change.
+    pub fn needs_manager(&self) -> bool {
+        self.dj_role.is_some()
+            || self.max_duration.is_some()
+            || self.livestreams.is_some()
+            || self.age_policy.is_some()
+            || self.text_commands.is_some()
+            || self.now_playing_status.is_some()
+    }
+}
+
+/// Which segments of YouTube videos are skipped with SponsorBlock.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SponsorBlockMode {
+    /// Nothing is skipped.
+    #[default]
+    Off,
+    /// Paid promotions are skipped.
+    Sponsors,
+    /// Promotions of any kind, reminders to subscribe, intros and outros are
+    /// skipped.
+    All,
+}
 
-        self
+impl SponsorBlockMode {
+    /// Gets a SponsorBlock mode from its name.
+    pub fn from_name(name: &str) -> Option<SponsorBlockMode> {
+        match name {
+            "off" => Some(SponsorBlockMode::Off),
+            "sponsors" => Some(SponsorBlockMode::Sponsors),
+            "all" => Some(SponsorBlockMode::All),
+            _ => None,
+        }
     }
 
-    /// Adds an embed to the response.
-    pub fn embed(&mut self, embed: Embed) -> &mut Self {
-        if self.embeds.is_none() {
-            self.embeds = Some(Vec::new());
+    /// The name of the SponsorBlock mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SponsorBlockMode::Off => "off",
+            SponsorBlockMode::Sponsors => "sponsors",
+            SponsorBlockMode::All => "all",
         }
+    }
 
-        self.embeds.as_mut().unwrap().push(embed);
+    /// The categories of segments that are skipped.
+    pub fn categories(&self) -> &'static [Category] {
+        match self {
+            SponsorBlockMode::Off => &[],
+            SponsorBlockMode::Sponsors => &[Category::Sponsor],
+            SponsorBlockMode::All => &[
+                Category::Sponsor,
+                Category::SelfPromo,
+                Category::Interaction,
+                Category::Intro,
+                Category::Outro,
+            ],
+        }
+    }
+}
+
+/// How new tracks are placed in the queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueMode {
+    /// Tracks are played in the order they were enqueued.
+    #[default]
+    Fifo,
+    /// Tracks are interleaved by requester, so one user enqueueing a large
+    /// playlist doesn't starve everyone else.
+    Fair,
+}
 
-        self
+impl QueueMode {
+    /// Gets a queue mode from its name.
+    pub fn from_name(name: &str) -> Option<QueueMode> {
+        match name {
+            "fifo" => Some(QueueMode::Fifo),
+            "fair" => Some(QueueMode::Fair),
+            _ => None,
+        }
     }
 
-    /// Acks the response.
+    /// The name of the queue mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueueMode::Fifo => "fifo",
+            QueueMode::Fair => "fair",
+        }
+    }
+}
+
+/// When [`Action::Tts`] speech is played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TtsMode {
+    /// Over the playing track, which picks up again after.
+    #[default]
+    Interject,
+    /// As a track at the end of the queue.
+    Queue,
+}
+
+impl TtsMode {
+    /// Gets a tts mode from its name.
+    pub fn from_name(name: &str) -> Option<TtsMode> {
+        match name {
+            "interject" => Some(TtsMode::Interject),
+            "queue" => Some(TtsMode::Queue),
+            _ => None,
+        }
+    }
+}
+
+/// Implements [`CommandOptionType`] for choices, from their names.
+macro_rules! choice_option {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> CommandOptionType<'a> for $ty {
+                fn cast_from(value: &'a CommandOptionValue) -> Result<$ty, CastError> {
+                    <&str>::cast_from(value).and_then(|name| <$ty>::from_name(name).ok_or(CastError))
+                }
+            }
+        )*
+    };
+}
+
+/// What is played again once it ends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Nothing loops.
+    #[default]
+    Off,
+    /// The playing track plays again, until it is skipped.
+    Track,
+    /// Tracks go back to the end of the queue once they are played.
+    Queue,
+}
+
+impl LoopMode {
+    /// Gets a loop mode from its name.
+    pub fn from_name(name: &str) -> Option<LoopMode> {
+        match name {
+            "off" => Some(LoopMode::Off),
+            "track" => Some(LoopMode::Track),
+            "queue" => Some(LoopMode::Queue),
+            _ => None,
+        }
+    }
+
+    /// The name of the loop mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LoopMode::Off => "off",
+            LoopMode::Track => "track",
+            LoopMode::Queue => "queue",
+        }
+    }
+
+    /// The loop mode after this one, for cycling through them.
+    pub fn next(&self) -> LoopMode {
+        match self {
+            LoopMode::Off => LoopMode::Track,
+            LoopMode::Track => LoopMode::Queue,
+            LoopMode::Queue => LoopMode::Off,
+        }
+    }
+}
+
+choice_option!(
+    ExportFormat,
+    SponsorBlockMode,
+    QueueMode,
+    TtsMode,
+    LoopMode,
+    Feature,
+    AgePolicy,
+    NowPlayingStatus,
+    StatsPeriod
+);
+
+/// A message a text command replied to, for [`Action::PlayReply`].
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub channel_id: Id<ChannelMarker>,
+    pub message_id: Id<MessageMarker>,
+    /// The content of the message, if the gateway sent it along.
+    pub content: Option<String>,
+}
+
+/// Options for [`Action::Play`].
+#[derive(Clone, Debug, Default)]
+pub struct PlayOptions {
+    /// Whether to move the track(s) to the top of the queue.
+    pub playnow: bool,
+    /// The 1-based position in the queue to insert the track(s) at, instead
+    /// of the end. Ignored with `playnow`.
+    pub position: Option<usize>,
+    /// Whether to also skip the playing track, so the track(s) play right
+    /// away. Only used with `playnow`.
+    pub interrupt: bool,
+    /// Whether the track skipped by `interrupt` plays again after, from
+    /// where it was skipped.
+    pub park: bool,
+    /// The maximum number of playlist items to enqueue.
+    pub limit: Option<usize>,
+    /// The 1-based index of the first playlist item to enqueue.
+    pub start: Option<usize>,
+    /// Whether to shuffle the playlist items before enqueueing.
+    pub shuffle: bool,
+    /// Whether to enqueue the playlist items in reverse.
     ///
-    /// The final message must be updated with [`CommandResponse::update`].
-    pub async fn ack(&mut self) -> Result<Response<EmptyBody>, HttpError> {
-        self.client
-            .create_response(
-                self.command.interaction_id,
-                &self.command.interaction_token,
-                &InteractionResponse {
-                    kind: InteractionResponseType::DeferredChannelMessageWithSource,
-                    data: None,
-                },
-            )
-            .await
-    }
-
-    /// Updates the previous message (mostly an ACK).
-    pub async fn update(&mut self) -> Result<Response<Message>, HttpError> {
-        self.client
-            .update_response(&self.command.interaction_token)
-            .content(self.content.as_deref())
-            .unwrap()
-            .embeds(self.embeds.as_deref())
-            .unwrap()
-            .await
-    }
-
-    /// Responds with a new message.
-    pub async fn respond(&mut self) -> Result<Response<EmptyBody>, HttpError> {
-        self.client
-            .create_response(
-                self.command.interaction_id,
-                &self.command.interaction_token,
-                &InteractionResponse {
-                    kind: InteractionResponseType::ChannelMessageWithSource,
-                    data: Some(InteractionResponseData {
-                        flags: Some(self.flags),
-                        embeds: self.embeds.take(),
-                        content: self.content.take(),
-                        ..Default::default()
-                    }),
-                },
-            )
-            .await
+    /// A play command can't set this and `shuffle` both. If they are, the
+    /// items are only shuffled.
+    pub reverse: bool,
+    /// Where to start playing the track from.
+    ///
+    /// This is ignored for playlists.
+    pub offset: Duration,
+}
+
+impl PlayOptions {
+    /// Slices and reorders the tracks of a playlist according to the options.
+    ///
+    /// The playlist is sliced first, so `start` and `limit` always refer to
+    /// the playlist's original order.
+    pub fn apply_playlist<T, R>(&self, tracks: &mut Vec<T>, rng: &mut R)
+    where
+        R: Rng + ?Sized,
+    {
+        if let Some(start) = self.start {
+            tracks.drain(..start.saturating_sub(1).min(tracks.len()));
+        }
+
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+
+        if self.shuffle {
+            tracks.shuffle(rng);
+        } else if self.reverse {
+            tracks.reverse();
+        }
+    }
+}
+
+impl CommandData {
+    /// Translates an English string to the user's locale.
+    ///
+    /// See [`i18n::tr`].
+    pub fn tr<'a>(&self, text: &'a str) -> &'a str {
+        i18n::tr(self.locale.as_deref(), text)
+    }
+
+    /// Translates an English string to the user's locale and fills in its
+    /// placeholders.
+    ///
+    /// See [`i18n::trf`].
+    pub fn trf(&self, text: &str, args: &[(&str, &(dyn Display + Sync))]) -> String {
+        i18n::trf(self.locale.as_deref(), text, args)
+    }
+}
+
+impl Deref for Command {
+    type Target = CommandData;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
     }
 }
@@ -0,0 +1,159 @@
+//! Looking inside queues, for whoever runs the bot.
+//!
+//! `/debug dump` is registered with `/operator` in the developer's guild. It
+//! asks a guild's queue task for a snapshot of its state over the queue's
+//! inspect channel, and attaches it as JSON. Queues that aren't running
+//! aren't started just to be dumped.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use super::commands::CommandData;
+use super::{PlayerState, QueueServer, QueueState, UserError};
+use crate::voice::StreamTimings;
+use crate::ytdl::Track;
+
+/// How long a queue has to answer before the dump gives up on it.
+const DUMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request for a snapshot of a queue's state.
+pub(super) type Inspect = oneshot::Sender<Value>;
+
+impl QueueServer {
+    /// Gets a snapshot of the state of a guild's queue, if it is running.
+    pub async fn dump(&self, guild_id: Id<GuildMarker>) -> Option<Value> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        {
+            let queues = self.queues.read().await;
+            let queue = queues
+                .get(&guild_id)
+                .filter(|queue| !queue.task.is_finished())?;
+
+            queue.inspect_tx.send(reply_tx).ok()?;
+        }
+
+        tokio::time::timeout(DUMP_TIMEOUT, reply_rx)
+            .await
+            .ok()?
+            .ok()
+    }
+}
+
+impl QueueState {
+    /// Takes a snapshot of the state of the queue.
+    pub(super) async fn dump(&self) -> Value {
+        let player = match self.player.as_ref() {
+            Some(PlayerState {
+                player, listeners, ..
+            }) => {
+                let channel_id = match player.voice_state().await {
+                    Ok(voice_state) => voice_state.channel_id.map(|id| id.to_string()),
+                    Err(_) => None,
+                };
+
+                json!({
+                    "channel_id": channel_id,
+                    "playing": player.playing(),
+                    "position_ms": player.position().as_millis() as u64,
+                    "latency_ms": player.latency().map(|latency| latency.as_millis() as u64),
+                    "listeners": listeners.len(),
+                })
+            }
+            None => Value::Null,
+        };
+
+        let disconnects_in = self
+            .autodisconnect
+            .disconnect_at
+            .map(|at| at.saturating_duration_since(Instant::now()).as_secs());
+
+        json!({
+            "guild_id": self.guild_id.to_string(),
+            "playing": self.playing.as_ref().map(dump_track),
+            "paused": self.paused,
+            "queue": self.track_queue.iter().map(dump_track).collect::<Vec<_>>(),
+            "loop_mode": self.loop_mode.name(),
+            "queue_mode": self.queue_mode.name(),
+            "autoplay": self.autoplay,
+            "shuffle_seed": self.shuffle.as_ref().map(|shuffle| shuffle.seed),
+            "autodisconnect": {
+                "enabled": self.autodisconnect.enabled,
+                "disconnects_in_secs": disconnects_in,
+            },
+            "locked_by": self.lock.as_ref().map(|lock| lock.user_id.to_string()),
+            "player": player,
+            "pending_queries": self.query_queue.len(),
+            "checking_track": self.track_check.is_some(),
+            "source_retry": self.source_retry,
+            "last_error": self.last_error,
+            "idle_secs": self.last_active.elapsed().as_secs(),
+            "timings": {
+                "commands": self.command_timings.to_json(),
+                "stream": self
+                    .player
+                    .as_ref()
+                    .and_then(|PlayerState { player, .. }| player.timings())
+                    .map(StreamTimings::to_json),
+            },
+        })
+    }
+
+    pub(super) async fn debug_dump(
+        &self,
+        command: &CommandData,
+        guild_id: Option<Id<GuildMarker>>,
+    ) -> Result<(), UserError> {
+        if !self.is_operator(command) {
+            return Err(UserError::NotOperator);
+        }
+
+        let guild_id = guild_id.unwrap_or(self.guild_id);
+
+        // this queue can't wait on its own task
+        let dump = if guild_id == self.guild_id {
+            Some(self.dump().await)
+        } else {
+            self.queue_server.dump(guild_id).await
+        };
+
+        let Some(dump) = dump else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("that server doesn't have a queue running"))
+                .ephemeral()
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        let file = serde_json::to_vec_pretty(&dump).unwrap_or_default();
+
+        command
+            .respond(&self.queue_server.http_client)
+            .attachment(format!("queue-{}.json", guild_id), file)
+            .ephemeral()
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
+
+fn dump_track(track: &Track) -> Value {
+    json!({
+        "url": track.url,
+        "title": track.title,
+        "requester": track.requester.map(|id| id.to_string()),
+        "start_ms": track.start.as_millis() as u64,
+        "duration_ms": track.duration.map(|duration| duration.as_millis() as u64),
+        "live": track.live,
+        "speech": track.speech.is_some(),
+        "format": track.format.as_ref().map(|format| &format.id),
+    })
+}
@@ -0,0 +1,41 @@
+//! Queue events.
+//!
+//! Subscribe to these with [`QueueServer::subscribe`][1] to react to what the
+//! queues are doing without touching the queues themselves.
+//!
+//! [1]: super::QueueServer::subscribe
+
+use crate::voice::guardrail::GuardrailEvent;
+use crate::ytdl::Track;
+
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use std::time::Duration;
+
+/// An event from a queue.
+#[derive(Clone, Debug)]
+pub enum QueueEvent {
+    /// A track started playing.
+    TrackStarted {
+        guild_id: Id<GuildMarker>,
+        track: Track,
+    },
+    /// A track stopped playing, whether it finished or not.
+    TrackEnded {
+        guild_id: Id<GuildMarker>,
+        track: Track,
+        /// How much of the track was played.
+        played: Duration,
+    },
+    /// A track was skipped because it can't be played anymore, like when it
+    /// was taken down while it sat in the queue.
+    TrackUnavailable {
+        guild_id: Id<GuildMarker>,
+        track: Track,
+        /// Why the track can't be played.
+        reason: String,
+    },
+    /// Encoding got close to using all of the CPU, or came back down from
+    /// it. This isn't about any one guild.
+    Guardrail(GuardrailEvent),
+}
@@ -0,0 +1,378 @@
+//! Exporting and importing queues.
+//!
+//! Queues are exported either as JSON, which keeps everything needed to show
+//! the tracks so importing them doesn't have to query `youtube-dl` again, or
+//! as a plain list of urls, one per line. Both can be imported again, along
+//! with any other list of urls.
+
+use hyper::StatusCode;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use super::commands::{ExportFormat, ImportSource};
+use super::query;
+use super::summary::Failure;
+use crate::https::https_client;
+use crate::ytdl::{Author, Playlist, Track, YtdlConfig};
+
+/// The most tracks that can be imported at once.
+pub const MAX_IMPORT_TRACKS: usize = 500;
+
+/// The most urls that can be imported at once, since each has to be queried.
+pub const MAX_IMPORT_URLS: usize = 50;
+
+/// The largest attachment that can be imported, in bytes.
+pub const MAX_IMPORT_SIZE: u64 = 256 * 1024;
+
+/// An exported queue.
+#[derive(Debug, Deserialize, Serialize)]
+struct ExportedQueue {
+    tracks: Vec<ExportedTrack>,
+}
+
+/// An exported track.
+#[derive(Debug, Deserialize, Serialize)]
+struct ExportedTrack {
+    url: String,
+    title: String,
+    author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thumbnail_url: Option<String>,
+    /// How long the track is, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<u64>,
+    /// Where to start playing the track from, in milliseconds.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    start: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl From<&Track> for ExportedTrack {
+    fn from(track: &Track) -> ExportedTrack {
+        ExportedTrack {
+            url: track.url.clone(),
+            title: track.title.clone(),
+            author: track.author.name.clone(),
+            author_url: track.author.url.clone(),
+            thumbnail_url: track.thumbnail_url.clone(),
+            duration: track.duration.map(|duration| duration.as_millis() as u64),
+            start: track.start.as_millis() as u64,
+        }
+    }
+}
+
+impl From<ExportedTrack> for Track {
+    fn from(track: ExportedTrack) -> Track {
+        Track {
+            url: track.url,
+            title: track.title,
+            author: Author {
+                name: track.author,
+                url: track.author_url,
+                avatar_url: None,
+            },
+            thumbnail_url: track.thumbnail_url,
+            requester: None,
+            start: Duration::from_millis(track.start),
+            duration: track.duration.map(Duration::from_millis),
+            speech: None,
+            resolved_at: None,
+            picked: false,
+            live: false,
+            age_limit: 0,
+            chapters: Vec::new(),
+            album: None,
+            format: None,
+        }
+    }
+}
+
+/// Exports tracks as a file.
+///
+/// Speech can't be exported, so it is left out.
+pub fn export<'a>(tracks: impl IntoIterator<Item = &'a Track>, format: ExportFormat) -> Vec<u8> {
+    let tracks = tracks.into_iter().filter(|track| track.speech.is_none());
+
+    match format {
+        ExportFormat::Json => {
+            let queue = ExportedQueue {
+                tracks: tracks.map(ExportedTrack::from).collect(),
+            };
+
+            serde_json::to_vec_pretty(&queue).expect("queue is always valid json")
+        }
+        ExportFormat::Urls => tracks.fold(Vec::new(), |mut out, track| {
+            out.extend_from_slice(track.url.as_bytes());
+            out.push(b'\n');
+            out
+        }),
+    }
+}
+
+/// What an import file turned out to be.
+#[derive(Debug)]
+enum Parsed {
+    /// Tracks from an exported queue, ready to enqueue.
+    Tracks(Vec<Track>),
+    /// Urls that still have to be queried.
+    Urls(Vec<String>),
+}
+
+/// Parses an exported queue or a list of urls.
+///
+/// Url lists can be separated by lines or spaces. Lines starting with `#`
+/// are skipped.
+fn parse(text: &str) -> Result<Parsed, ImportError> {
+    if text.trim_start().starts_with('{') {
+        let queue: ExportedQueue = serde_json::from_str(text).map_err(ImportError::Json)?;
+
+        if queue.tracks.len() > MAX_IMPORT_TRACKS {
+            return Err(ImportError::TooLong(MAX_IMPORT_TRACKS));
+        }
+
+        // the urls are handed to `youtube-dl` as they are
+        if let Some(i) = queue.tracks.iter().position(|track| !is_url(&track.url)) {
+            return Err(ImportError::InvalidUrl(i + 1));
+        }
+
+        return Ok(Parsed::Tracks(
+            queue.tracks.into_iter().map(Track::from).collect(),
+        ));
+    }
+
+    let urls = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .enumerate()
+        .map(|(i, line)| {
+            if is_url(line) {
+                Ok(line.to_owned())
+            } else {
+                Err(ImportError::InvalidUrl(i + 1))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if urls.len() > MAX_IMPORT_URLS {
+        return Err(ImportError::TooLong(MAX_IMPORT_URLS));
+    }
+
+    Ok(Parsed::Urls(urls))
+}
+
+fn is_url(s: &str) -> bool {
+    s.starts_with("https://") || s.starts_with("http://")
+}
+
+/// Imports tracks, as a playlist to enqueue.
+///
+/// This can take a while: attachments have to be downloaded, and url lists
+/// are queried as a batch. Urls that fail to query are skipped, and returned
+/// with the playlist.
+pub async fn import(
+    ytdl: &YtdlConfig,
+    guild_id: Id<GuildMarker>,
+    source: ImportSource,
+) -> Result<(Playlist, Vec<Failure>), crate::Error> {
+    let (title, text) = match source {
+        ImportSource::Attachment {
+            url,
+            filename,
+            size,
+        } => (filename, fetch(&url, size).await?),
+        ImportSource::Text(text) => (String::from("imported queue"), text),
+    };
+
+    let (mut tracks, failures) = match parse(&text)? {
+        Parsed::Tracks(tracks) => (tracks, Vec::new()),
+        Parsed::Urls(urls) => {
+            let (playlist, failures) = query::query_batch(ytdl, guild_id, urls, None).await?;
+            (playlist.tracks, failures)
+        }
+    };
+
+    tracks.truncate(MAX_IMPORT_TRACKS);
+
+    if tracks.is_empty() {
+        return Err(ImportError::Empty.into());
+    }
+
+    let playlist = Playlist {
+        url: String::new(),
+        title,
+        author: Author {
+            name: String::from("import"),
+            url: None,
+            avatar_url: None,
+        },
+        thumbnail_url: None,
+        tracks,
+        album: None,
+    };
+
+    Ok((playlist, failures))
+}
+
+/// Downloads an attachment.
+async fn fetch(url: &str, size: u64) -> Result<String, ImportError> {
+    if size > MAX_IMPORT_SIZE {
+        return Err(ImportError::TooLarge);
+    }
+
+    let uri = url.parse().map_err(|_| ImportError::Download(None))?;
+    let res = https_client()
+        .get(uri)
+        .await
+        .map_err(|err| ImportError::Download(Some(err)))?;
+
+    if res.status() != StatusCode::OK {
+        return Err(ImportError::Download(None));
+    }
+
+    let body = hyper::body::to_bytes(res.into_body())
+        .await
+        .map_err(|err| ImportError::Download(Some(err)))?;
+
+    if body.len() as u64 > MAX_IMPORT_SIZE {
+        return Err(ImportError::TooLarge);
+    }
+
+    String::from_utf8(body.to_vec()).map_err(|_| ImportError::NotText)
+}
+
+/// An error importing a queue.
+#[derive(Debug)]
+pub enum ImportError {
+    /// The attachment couldn't be downloaded.
+    Download(Option<hyper::Error>),
+    /// The attachment is over [`MAX_IMPORT_SIZE`].
+    TooLarge,
+    /// The attachment isn't UTF-8 text.
+    NotText,
+    /// The exported queue is malformed.
+    Json(serde_json::Error),
+    /// The entry of a url list, counting from 1, isn't a url.
+    InvalidUrl(usize),
+    /// There are more tracks than the limit.
+    TooLong(usize),
+    /// Nothing could be imported.
+    Empty,
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ImportError::Download(_) => f.write_str("failed to download the attachment"),
+            ImportError::TooLarge => {
+                write!(f, "the attachment is over {} KiB", MAX_IMPORT_SIZE / 1024)
+            }
+            ImportError::NotText => f.write_str("the attachment isn't text"),
+            ImportError::Json(err) => write!(f, "invalid exported queue: {}", err),
+            ImportError::InvalidUrl(line) => write!(f, "entry {} isn't a url", line),
+            ImportError::TooLong(max) => write!(f, "can't import more than {} tracks", max),
+            ImportError::Empty => f.write_str("nothing to import"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Download(Some(err)) => Some(err),
+            ImportError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ImportError> for crate::Error {
+    fn from(err: ImportError) -> crate::Error {
+        crate::Error::internal(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(url: &str) -> Track {
+        Track {
+            url: url.to_owned(),
+            title: String::from("title"),
+            author: Author {
+                name: String::from("author"),
+                ..Default::default()
+            },
+            start: Duration::from_secs(5),
+            duration: Some(Duration::from_secs(60)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let speech = Track {
+            speech: Some(String::from("hello")),
+            ..track("")
+        };
+        let tracks = [track("https://a"), speech, track("https://b")];
+
+        let out = export(&tracks, ExportFormat::Json);
+        let Parsed::Tracks(imported) = parse(std::str::from_utf8(&out).unwrap()).unwrap() else {
+            panic!("expected tracks");
+        };
+
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].url, "https://a");
+        assert_eq!(imported[1].start, Duration::from_secs(5));
+        assert_eq!(imported[1].duration, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn url_round_trip() {
+        let tracks = [track("https://a"), track("https://b")];
+
+        let out = export(&tracks, ExportFormat::Urls);
+        let Parsed::Urls(urls) = parse(std::str::from_utf8(&out).unwrap()).unwrap() else {
+            panic!("expected urls");
+        };
+
+        assert_eq!(urls, ["https://a", "https://b"]);
+    }
+
+    #[test]
+    fn url_list_errors() {
+        let Parsed::Urls(urls) =
+            parse("# my queue\n\nhttps://a\n  https://b https://c \n").unwrap()
+        else {
+            panic!("expected urls");
+        };
+        assert_eq!(urls, ["https://a", "https://b", "https://c"]);
+
+        assert!(matches!(
+            parse("https://a\nnot a url"),
+            Err(ImportError::InvalidUrl(2))
+        ));
+
+        let out = export(&[track("https://a"), track("--exec")], ExportFormat::Json);
+        assert!(matches!(
+            parse(std::str::from_utf8(&out).unwrap()),
+            Err(ImportError::InvalidUrl(2))
+        ));
+
+        let long = "https://a\n".repeat(MAX_IMPORT_URLS + 1);
+        assert!(matches!(parse(&long), Err(ImportError::TooLong(_))));
+    }
+}
@@ -0,0 +1,123 @@
+//! Saying goodbye.
+//!
+//! While the bot is in a voice channel, the queue keeps a [`Session`] of what
+//! it played. When the bot leaves on its own, because nobody was listening,
+//! guilds that turned on the `farewell` setting get a summary of the session
+//! in the announce channel: how many tracks played, for how long, and who
+//! requested the most of them.
+
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use twilight_model::channel::message::Embed;
+use twilight_model::id::{marker::UserMarker, Id};
+
+use super::stats::counts_as_played;
+use super::{format_duration, QueueState};
+use crate::i18n;
+use crate::ytdl::Track;
+
+/// What the bot played since it joined a channel.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// When the first track of the session played.
+    started: Option<Instant>,
+    plays: usize,
+    listened: Duration,
+    requests: HashMap<Id<UserMarker>, usize>,
+}
+
+impl Session {
+    /// Records a track that ended after playing for `played`.
+    pub fn record(&mut self, track: &Track, played: Duration) {
+        if !counts_as_played(track, played) {
+            return;
+        }
+
+        if let Some(requester) = track.requester {
+            *self.requests.entry(requester).or_default() += 1;
+        }
+
+        self.plays += 1;
+        self.listened += played;
+    }
+
+    /// Notes that a track started playing.
+    pub fn start(&mut self) {
+        self.started.get_or_insert_with(Instant::now);
+    }
+
+    /// Checks if nothing was played.
+    pub fn is_empty(&self) -> bool {
+        self.plays == 0
+    }
+
+    /// The user whose requests were played most, with how many were played.
+    pub fn top_requester(&self) -> Option<(Id<UserMarker>, usize)> {
+        self.requests
+            .iter()
+            .map(|(user_id, count)| (*user_id, *count))
+            .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+    }
+}
+
+impl QueueState {
+    /// Leaves the voice channel since nobody was listening, announcing a
+    /// summary of the session if the guild wants one.
+    pub(super) async fn leave_unheard(&mut self) {
+        // the playing track counts too
+        self.set_playing(None);
+        let session = mem::take(&mut self.session);
+
+        self.disconnect().await;
+
+        if !self.farewell || session.is_empty() {
+            return;
+        }
+
+        let embed = self.farewell_embed(&session);
+        self.announce(embed).await;
+    }
+
+    fn farewell_embed(&self, session: &Session) -> Embed {
+        let locale = self.guild_locale();
+        let locale = locale.as_deref();
+
+        let mut lines = vec![
+            i18n::tr(locale, "nobody was listening, so I left the channel").to_owned(),
+            i18n::trf(
+                locale,
+                "played {plays} tracks for {time} in {length}",
+                &[
+                    ("plays", &session.plays),
+                    ("time", &format_duration(session.listened)),
+                    (
+                        "length",
+                        &format_duration(
+                            session
+                                .started
+                                .map(|started| started.elapsed())
+                                .unwrap_or_default(),
+                        ),
+                    ),
+                ],
+            ),
+        ];
+
+        if let Some((user_id, count)) = session.top_requester() {
+            lines.push(i18n::trf(
+                locale,
+                "most requested by {user} ({count} tracks)",
+                &[("user", &format!("<@{}>", user_id)), ("count", &count)],
+            ));
+        }
+
+        Embed {
+            description: Some(lines.join("\n")),
+            title: Some(i18n::tr(locale, "see you next time").to_owned()),
+            ..crate::embed()
+        }
+    }
+}
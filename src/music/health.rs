@@ -0,0 +1,248 @@
+//! Whether the bot is working.
+//!
+//! `/ping` shows the latency of the gateway and the voice connection, and
+//! checks that yt-dlp runs. With the `rest` feature, the same is served over
+//! HTTP at `/healthz` for container orchestrators, which only consider the
+//! bot ready once its shard is connected.
+//!
+//! yt-dlp and ffmpeg are also checked at startup with
+//! [`check_audio_backend`]. If either doesn't run, the bot can refuse to
+//! start, or start anyway with [`QueueServer::with_audio_unavailable`], where
+//! everything but playing works until yt-dlp is updated and they're checked
+//! again.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::time::timeout;
+use tracing::{info, warn};
+use twilight_gateway::{ConnectionStatus, Shard};
+
+use super::commands::CommandData;
+use super::{PlayerState, QueueServer, QueueState, UserError};
+use crate::ffmpeg::ffmpeg_version;
+use crate::ytdl::YtdlConfig;
+
+/// How long yt-dlp and ffmpeg have to print their versions.
+const YTDL_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The last known state of the gateway shard.
+#[derive(Clone, Debug, Default)]
+pub struct ShardHealth {
+    /// Whether the shard is connected.
+    pub connected: bool,
+    /// What the shard is doing.
+    pub status: &'static str,
+    /// The average latency of the shard's heartbeats.
+    pub latency: Option<Duration>,
+}
+
+/// Checks that the programs audio is played with run, logging their
+/// versions.
+///
+/// `ffmpeg` is only needed if the audio is played here, rather than on a
+/// Lavalink node. Returns why the backend is unavailable if it is.
+pub async fn check_audio_backend(ytdl: &YtdlConfig, ffmpeg: bool) -> Result<(), String> {
+    let mut problems = Vec::new();
+
+    match timeout(YTDL_CHECK_TIMEOUT, ytdl.version()).await {
+        Ok(Ok(version)) => info!(version, executable = %ytdl.executable(), "found yt-dlp"),
+        Ok(Err(err)) => problems.push(format!(
+            "yt-dlp ({}) doesn't run: {}",
+            ytdl.executable(),
+            err
+        )),
+        Err(_) => problems.push(format!("yt-dlp ({}) timed out", ytdl.executable())),
+    }
+
+    if ffmpeg {
+        match timeout(YTDL_CHECK_TIMEOUT, ffmpeg_version()).await {
+            Ok(Ok(version)) => info!(version, "found ffmpeg"),
+            Ok(Err(err)) => problems.push(format!("ffmpeg doesn't run: {}", err)),
+            Err(_) => problems.push(String::from("ffmpeg timed out")),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join(", "))
+    }
+}
+
+impl QueueServer {
+    /// Updates the state of the gateway shard shown by `/ping` and
+    /// `/healthz`.
+    ///
+    /// The shard belongs to the event loop, so this should be called after
+    /// every event.
+    pub fn update_shard(&self, shard: &Shard) {
+        let status = match shard.status() {
+            ConnectionStatus::Connected => "connected",
+            ConnectionStatus::Disconnected { .. } => "disconnected",
+            ConnectionStatus::FatallyClosed { .. } => "fatally closed",
+            ConnectionStatus::Identifying => "identifying",
+            ConnectionStatus::Resuming => "resuming",
+        };
+
+        *self.shard_health.lock().unwrap() = ShardHealth {
+            connected: shard.status().is_connected(),
+            status,
+            latency: shard.latency().average(),
+        };
+    }
+
+    /// The last known state of the gateway shard.
+    pub fn shard_health(&self) -> ShardHealth {
+        self.shard_health.lock().unwrap().clone()
+    }
+
+    /// Why nothing can be played, if yt-dlp or ffmpeg didn't run when they
+    /// were last checked.
+    pub fn audio_unavailable(&self) -> Option<String> {
+        self.audio_unavailable.lock().unwrap().clone()
+    }
+
+    /// Checks yt-dlp and ffmpeg again, like after yt-dlp was updated, so
+    /// tracks can be played again if they run now.
+    pub async fn recheck_audio_backend(&self) {
+        let res = check_audio_backend(self.ytdl(), self.lavalink.is_none()).await;
+        let mut audio_unavailable = self.audio_unavailable.lock().unwrap();
+
+        match &res {
+            Ok(()) if audio_unavailable.is_some() => info!("audio backend is available again"),
+            Ok(()) => (),
+            Err(problem) => warn!(problem, "audio backend unavailable, nothing can be played"),
+        }
+
+        *audio_unavailable = res.err();
+    }
+
+    /// How many voice channels the bot is in.
+    pub fn active_players(&self) -> usize {
+        self.cache
+            .iter()
+            .voice_states()
+            .filter(|voice_state| voice_state.user_id() == self.user_id)
+            .count()
+    }
+
+    /// The health of the bot, as JSON.
+    pub async fn health(&self) -> Value {
+        let shard = self.shard_health();
+        let queues = self.queues.read().await;
+        let running = queues.values().filter(|queue| !queue.task.is_finished());
+
+        json!({
+            "ready": shard.connected,
+            "shard": {
+                "index": self.claim.index,
+                "count": self.claim.count,
+                "status": shard.status,
+                "latency_ms": shard.latency.map(|latency| latency.as_millis() as u64),
+            },
+            "audio": self.audio_unavailable().as_deref().unwrap_or("ok"),
+            "players": self.active_players(),
+            "queues": running.count(),
+            "admission": self.admission.to_json(),
+            "queries": super::query_slots().stats().to_json(),
+            "timings": {
+                "commands": self.command_timings.to_json(),
+                "stream": self.player_config.timings.to_json(),
+            },
+        })
+    }
+
+    /// Serves `/healthz` on `addr`.
+    ///
+    /// It answers with [`QueueServer::health`], and a `503` if the shard
+    /// isn't connected.
+    #[cfg(feature = "rest")]
+    pub async fn serve_health(
+        self: std::sync::Arc<Self>,
+        addr: std::net::SocketAddr,
+    ) -> Result<(), hyper::Error> {
+        use std::convert::Infallible;
+
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+        let make_service = make_service_fn(move |_| {
+            let queue_server = self.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let queue_server = queue_server.clone();
+
+                    async move {
+                        let mut res = Response::new(Body::empty());
+
+                        if req.method() != Method::GET || req.uri().path() != "/healthz" {
+                            *res.status_mut() = StatusCode::NOT_FOUND;
+                            return Ok::<_, Infallible>(res);
+                        }
+
+                        let health = queue_server.health().await;
+
+                        if health["ready"] != true {
+                            *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        }
+
+                        res.headers_mut().insert(
+                            hyper::header::CONTENT_TYPE,
+                            hyper::header::HeaderValue::from_static("application/json"),
+                        );
+                        *res.body_mut() = Body::from(health.to_string());
+
+                        Ok(res)
+                    }
+                }))
+            }
+        });
+
+        Server::try_bind(&addr)?.serve(make_service).await
+    }
+}
+
+impl QueueState {
+    pub(super) async fn ping(&self, command: &CommandData) -> Result<(), UserError> {
+        let shard = self.queue_server.shard_health();
+
+        let mut lines = vec![match shard.latency {
+            Some(latency) => command.trf("gateway: {latency}", &[("latency", &ms(latency))]),
+            None => command.trf("gateway: {status}", &[("status", &shard.status)]),
+        }];
+
+        if let Some(PlayerState { player, .. }) = self.player.as_ref() {
+            if let Some(latency) = player.latency() {
+                lines.push(command.trf("voice: {latency}", &[("latency", &ms(latency))]));
+            }
+        }
+
+        if self.queue_server.audio_unavailable().is_some() {
+            lines.push(command.tr("audio backend unavailable").to_owned());
+        }
+
+        let version =
+            tokio::time::timeout(YTDL_CHECK_TIMEOUT, self.queue_server.ytdl().version()).await;
+
+        lines.push(match version {
+            Ok(Ok(version)) => command.trf("yt-dlp: {version}", &[("version", &version)]),
+            Ok(Err(err)) => command.trf("yt-dlp: not working ({err})", &[("err", &err)]),
+            Err(_) => command.tr("yt-dlp: not responding").to_owned(),
+        });
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(lines.join("\n"))
+            .ephemeral()
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
+
+fn ms(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
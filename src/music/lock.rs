@@ -0,0 +1,100 @@
+//! Locking the queue for a while.
+//!
+//! `/lock` keeps everyone but the user who locked the queue, DJs and users
+//! that can manage the server from changing it, which helps at events.
+//! [`Layer::Lock`](super::middleware::Layer::Lock) turns everyone else away
+//! until the lock expires or is let go with `/unlock`.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::debug;
+use twilight_model::id::{marker::UserMarker, Id};
+
+use super::commands::CommandData;
+use super::{format_duration, QueueState, UserError};
+
+/// How long the queue is locked for, by default.
+pub const DEFAULT_LOCK_TIME: Duration = Duration::from_secs(60 * 60);
+
+/// The longest the queue can be locked for.
+pub const MAX_LOCK_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A lock on the queue.
+#[derive(Clone, Debug)]
+pub struct QueueLock {
+    /// The user who locked the queue.
+    pub user_id: Id<UserMarker>,
+    /// When the lock expires.
+    pub until: Instant,
+}
+
+impl QueueState {
+    /// Checks if a user can change the queue while it's locked.
+    pub(super) async fn check_lock(&self, command: &CommandData) -> Result<(), UserError> {
+        let Some(lock) = self.lock.as_ref() else {
+            return Ok(());
+        };
+
+        if lock.user_id == command.user_id || lock.until <= Instant::now() {
+            return Ok(());
+        }
+
+        if self.is_dj(command).await {
+            Ok(())
+        } else {
+            Err(UserError::Locked(lock.user_id))
+        }
+    }
+
+    /// Lets the lock go once it expires.
+    pub(super) fn expire_lock(&mut self) {
+        if let Some(lock) = self.lock.take() {
+            debug!(user_id = %lock.user_id, "queue lock expired");
+        }
+    }
+
+    pub(super) async fn lock(
+        &mut self,
+        command: &CommandData,
+        duration: Option<Duration>,
+    ) -> Result<(), UserError> {
+        let duration = duration.unwrap_or(DEFAULT_LOCK_TIME).min(MAX_LOCK_TIME);
+
+        self.lock = Some(QueueLock {
+            user_id: command.user_id,
+            until: Instant::now() + duration,
+        });
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.trf(
+                "locked the queue for {time}, so only you and DJs can change it",
+                &[("time", &format_duration(duration))],
+            ))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    pub(super) async fn unlock(&mut self, command: &CommandData) -> Result<(), UserError> {
+        if self.lock.take().is_none() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("the queue isn't locked"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.tr("unlocked the queue"))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
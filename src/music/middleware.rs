@@ -0,0 +1,372 @@
+//! Checks that wrap around commands.
+//!
+//! Every [`Action`] goes through a stack of [`Layer`]s before its handler
+//! runs. Each layer can stop the command with a [`UserError`], which is shown
+//! to the user, and some also do something once the handler is done.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, instrument, warn};
+use twilight_model::guild::Permissions;
+use twilight_model::id::{
+    marker::{RoleMarker, UserMarker},
+    Id,
+};
+
+use super::commands::{Action, CommandData, PlaylistAction};
+use super::{QueueState, UserError};
+
+/// How many commands a user can use in [`RATE_LIMIT_WINDOW`].
+pub const RATE_LIMIT_COMMANDS: usize = 5;
+
+/// The window that [`RATE_LIMIT_COMMANDS`] is counted over.
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// How long a command can take before it is logged as slow.
+///
+/// Discord waits 3 seconds for a response, so a command slower than this is
+/// close to failing.
+const SLOW_COMMAND: Duration = Duration::from_secs(2);
+
+/// A check around a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    /// Logs how long the command took.
+    Timing,
+    /// Stops guilds the operator denied, and features the operator turned
+    /// off for the guild.
+    Access,
+    /// Stops users that use too many commands at once.
+    RateLimit,
+    /// Stops commands that play audio if yt-dlp or ffmpeg don't run.
+    Audio,
+    /// Only lets users with the guild's DJ role through, if it has one.
+    /// Users that can manage the guild always get through.
+    Dj,
+    /// Only lets the user who locked the queue through while it's locked,
+    /// along with DJs and users that can manage the guild.
+    Lock,
+    /// Only lets users in the bot's voice channel through.
+    InChannel,
+    /// Like [`Layer::InChannel`], but has the bot join the user's channel if
+    /// it isn't in one.
+    JoinChannel,
+}
+
+/// Commands that only look at the queue.
+const VIEW: &[Layer] = &[Layer::Timing, Layer::Access, Layer::RateLimit];
+
+/// Commands that add to the queue.
+const ENQUEUE: &[Layer] = &[
+    Layer::Timing,
+    Layer::Access,
+    Layer::RateLimit,
+    Layer::Audio,
+    Layer::Lock,
+    Layer::JoinChannel,
+];
+
+/// Commands that change what everyone is listening to.
+const CONTROL: &[Layer] = &[
+    Layer::Timing,
+    Layer::Access,
+    Layer::RateLimit,
+    Layer::Dj,
+    Layer::Lock,
+    Layer::InChannel,
+];
+
+/// Commands that talk over what everyone is listening to.
+const ANNOUNCE: &[Layer] = &[
+    Layer::Timing,
+    Layer::Access,
+    Layer::RateLimit,
+    Layer::Audio,
+    Layer::Dj,
+    Layer::Lock,
+    Layer::JoinChannel,
+];
+
+/// Commands that only change the user's own tracks.
+const PERSONAL: &[Layer] = &[
+    Layer::Timing,
+    Layer::Access,
+    Layer::RateLimit,
+    Layer::Lock,
+    Layer::InChannel,
+];
+
+/// Commands for moderators, which check their permissions themselves.
+const MODERATE: &[Layer] = &[Layer::Timing, Layer::Access, Layer::RateLimit];
+
+/// Commands for the operator, which work even where the bot is denied.
+const OPERATOR: &[Layer] = &[Layer::Timing];
+
+impl Action {
+    /// The layers the action goes through, outermost first.
+    pub fn layers(&self) -> &'static [Layer] {
+        match self {
+            Action::Play(..)
+            | Action::BulkPlay(..)
+            | Action::PlayReply(..)
+            | Action::Jump(_)
+            | Action::Tts(..)
+            | Action::Import(_) => ENQUEUE,
+            Action::Playlist(PlaylistAction::Play(_)) => ENQUEUE,
+            Action::Queue
+            | Action::Bookmark(_)
+            | Action::Export(_)
+            | Action::Player(_)
+            | Action::Chapters
+            | Action::Stats(_)
+            | Action::Ping => VIEW,
+            Action::Playlist(PlaylistAction::Save(_) | PlaylistAction::List) => VIEW,
+            Action::Settings(update) if update.is_empty() => VIEW,
+            Action::Operator(_) | Action::Debug(_) | Action::UpdateYtdl => OPERATOR,
+            Action::MyNext(_) => PERSONAL,
+            Action::Block(_) | Action::Quarantine(_) | Action::Scrobble(_) => MODERATE,
+            Action::Announce(_) => ANNOUNCE,
+            Action::Skip
+            | Action::Shuffle(_)
+            | Action::Unshuffle
+            | Action::Disconnect
+            | Action::AutoDisconnect(_)
+            | Action::Autoplay(_)
+            | Action::Pause
+            | Action::Stop
+            | Action::Loop(_)
+            | Action::Chapter(_)
+            | Action::Settings(_)
+            | Action::Lock(_)
+            | Action::Unlock
+            | Action::Playlist(PlaylistAction::Delete(_)) => CONTROL,
+        }
+    }
+}
+
+/// Limits how many commands each user can use in a window of time.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    uses: HashMap<Id<UserMarker>, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    /// Creates a new `RateLimiter` letting `max` commands through every
+    /// `window`.
+    pub fn new(max: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            max,
+            window,
+            uses: HashMap::new(),
+        }
+    }
+
+    /// Counts a use of a command, returning `false` if the user is over the
+    /// limit.
+    ///
+    /// Uses over the limit don't count.
+    pub fn check(&mut self, user_id: Id<UserMarker>, now: Instant) -> bool {
+        // forget users that have been quiet
+        let window = self.window;
+        self.uses
+            .retain(|_, uses| uses.back().is_some_and(|&at| now - at < window));
+
+        let uses = self.uses.entry(user_id).or_default();
+
+        while uses.front().is_some_and(|&at| now - at >= window) {
+            uses.pop_front();
+        }
+
+        if uses.len() >= self.max {
+            return false;
+        }
+
+        uses.push_back(now);
+        true
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new(RATE_LIMIT_COMMANDS, RATE_LIMIT_WINDOW)
+    }
+}
+
+impl QueueState {
+    /// Runs a command through its layers and handler.
+    pub(super) async fn dispatch(
+        &mut self,
+        command: &CommandData,
+        action: Action,
+    ) -> Result<(), UserError> {
+        let layers = action.layers();
+        let name = action.name();
+        let started = Instant::now();
+
+        // layers that stop the command still let the ones around them finish
+        let mut entered = 0;
+        let mut res = Ok(());
+
+        for layer in layers {
+            res = self.enter_layer(*layer, command, &action).await;
+
+            if res.is_err() {
+                break;
+            }
+
+            entered += 1;
+        }
+
+        if res.is_ok() {
+            res = self.handle_action(command, action).await;
+        }
+
+        for layer in layers[..entered].iter().rev() {
+            self.exit_layer(*layer, name, started, &res);
+        }
+
+        res
+    }
+
+    /// Checks a command against a layer before it runs.
+    async fn enter_layer(
+        &mut self,
+        layer: Layer,
+        command: &CommandData,
+        action: &Action,
+    ) -> Result<(), UserError> {
+        match layer {
+            Layer::Timing => Ok(()),
+            Layer::Access => self.check_access(action).await,
+            Layer::RateLimit => {
+                if self.rate_limit.check(command.user_id, Instant::now()) {
+                    Ok(())
+                } else {
+                    Err(UserError::RateLimited)
+                }
+            }
+            Layer::Audio => match self.queue_server.audio_unavailable() {
+                Some(_) => Err(UserError::AudioUnavailable),
+                None => Ok(()),
+            },
+            Layer::Dj => self.check_dj(command).await,
+            Layer::Lock => self.check_lock(command).await,
+            Layer::InChannel => self.check_user_in_channel(command.user_id).await,
+            Layer::JoinChannel => match self.check_user_in_channel(command.user_id).await {
+                // join user's channel
+                Err(UserError::BotNotInChannel(channel_id)) => {
+                    self.admit(command)?;
+                    self.join(channel_id).await;
+                    Ok(())
+                }
+                res => res,
+            },
+        }
+    }
+
+    /// Finishes a layer after the command has run.
+    fn exit_layer(
+        &self,
+        layer: Layer,
+        name: &'static str,
+        started: Instant,
+        res: &Result<(), UserError>,
+    ) {
+        if layer == Layer::Timing {
+            let elapsed = started.elapsed();
+            self.command_timings.record(elapsed);
+            self.queue_server.command_timings.record(elapsed);
+
+            if elapsed > SLOW_COMMAND {
+                warn!(command = name, ?elapsed, ok = res.is_ok(), "slow command");
+            } else {
+                debug!(
+                    command = name,
+                    ?elapsed,
+                    ok = res.is_ok(),
+                    "handled command"
+                );
+            }
+        }
+    }
+
+    /// Checks if a user has the DJ role, if the guild has one.
+    async fn check_dj(&self, command: &CommandData) -> Result<(), UserError> {
+        match self.dj_role().await {
+            Some(_) if !self.is_dj(command).await => Err(UserError::NotDj),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if a user is one of the guild's DJs.
+    ///
+    /// Users that can manage the server always are. Everyone else needs the
+    /// DJ role, so without one, nobody else is.
+    pub(super) async fn is_dj(&self, command: &CommandData) -> bool {
+        if command.permissions.contains(Permissions::MANAGE_GUILD) {
+            return true;
+        }
+
+        self.dj_role()
+            .await
+            .is_some_and(|role_id| command.roles.contains(&role_id))
+    }
+
+    /// The role needed to control the queue, if the guild has one.
+    pub(super) async fn dj_role(&self) -> Option<Id<RoleMarker>> {
+        self.queue_server
+            .store
+            .read(|store| store.dj_roles.get(&self.guild_id).copied())
+            .await
+    }
+
+    /// Checks if a user can use a music control command.
+    ///
+    /// A user can use a music control command if the user is in the same
+    /// channel as the bot.
+    #[instrument(name = "check_user_in_channel", skip(self))]
+    pub(super) async fn check_user_in_channel(
+        &self,
+        user_id: Id<UserMarker>,
+    ) -> Result<(), UserError> {
+        let user_channel_id = self.queue_server.user_channel(self.guild_id, user_id).await;
+
+        let voice_state = self.voice_state().await;
+        if let Some(voice_state) = voice_state {
+            if voice_state.channel_id == user_channel_id {
+                Ok(())
+            } else {
+                Err(UserError::UserInDifferentChannel)
+            }
+        } else if let Some(channel_id) = user_channel_id {
+            Err(UserError::BotNotInChannel(channel_id))
+        } else {
+            Err(UserError::UserNotInChannel)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limits_per_user() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let (alice, bob) = (Id::new(1), Id::new(2));
+        let now = Instant::now();
+
+        assert!(limiter.check(alice, now));
+        assert!(limiter.check(alice, now + Duration::from_secs(1)));
+        assert!(!limiter.check(alice, now + Duration::from_secs(2)));
+        assert!(limiter.check(bob, now + Duration::from_secs(2)));
+
+        // the first use falls out of the window
+        assert!(limiter.check(alice, now + Duration::from_secs(10)));
+        assert!(!limiter.check(alice, now + Duration::from_secs(10)));
+    }
+}
@@ -5,19 +5,64 @@
 //! up, and commands are simply sent to each task, where the side-effect-doing
 //! happens on the task. See [`Queue`] for more info.
 
+mod admission;
+pub mod backend;
+mod blocklist;
+pub mod cache;
+mod chapters;
+mod claim;
 mod commands;
+mod debug;
+pub mod event;
+mod export;
+mod farewell;
+pub mod health;
+mod lock;
+pub mod middleware;
+mod operator;
+pub mod panel;
+mod policy;
+mod quarantine;
 mod query;
+mod rejoin;
+pub mod respond;
+mod stats;
+mod status;
+mod summary;
+pub mod update;
+mod voice_states;
+
+pub use claim::GuildClaim;
+pub use commands::{
+    Action, Announcement, BlockAction, Command, CommandData, ExportFormat, ImportSource, LoopMode,
+    OperatorAction, PlayOptions, PlaylistAction, QuarantineAction, QueueMode, Reply,
+    SettingsUpdate, SponsorBlockMode, StatsPeriod, TtsMode,
+};
+pub use event::QueueEvent;
+pub use health::ShardHealth;
+pub use quarantine::QuarantineConfig;
+pub use query::{
+    find_urls, init_query_slots, query_slots, QuerySlots, QueryStats, DEFAULT_QUERY_CONCURRENCY,
+    MAX_BATCH_QUERIES,
+};
+pub use stats::StatsRecorder;
 
-pub use commands::{Action, Command, CommandData};
-
+use backend::PlaybackBackend;
+use middleware::RateLimiter;
+use opus::Bitrate;
+use panel::Panel;
 use query::{QueryQueue, QueryResult as QueryMessage};
-use rand::SeedableRng;
-use tokio::time::{sleep_until, Instant};
-use tracing::{debug, error, instrument};
-use twilight_model::channel::message::embed::EmbedThumbnail;
+use rand::{Rng, SeedableRng};
+use status::ChannelStatus;
+use summary::{Failure, PlaylistSummary, ProgressReporter};
+use tokio::time::{interval, sleep_until, Instant};
+use tracing::{debug, error, info, instrument, warn};
+use twilight_model::channel::message::embed::{
+    EmbedAuthor, EmbedField, EmbedFooter, EmbedThumbnail,
+};
 use twilight_model::channel::message::Embed;
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 use std::fmt::{self, Display, Formatter, Write as _};
 use std::iter::once;
 use std::sync::Arc;
@@ -26,25 +71,42 @@ use std::time::Duration;
 use rand::{rngs::SmallRng, seq::SliceRandom};
 
 use tokio::sync::{
+    broadcast,
     mpsc::{self, UnboundedReceiver, UnboundedSender},
     RwLockReadGuard,
 };
 use tokio::task::JoinHandle;
 
-use super::voice::{self, Player, Source};
+use super::voice::{
+    self,
+    constants::{DEFAULT_BITRATE, DUCK_GAIN},
+    guardrail::{self, Guardrail},
+    ErrorKind, Histogram, Player, PlayerConfig, SourceBuilder,
+};
 
-use crate::ytdl::{Query as YtdlQuery, QueryError, Track};
+use crate::i18n;
+use crate::lavalink;
+use crate::scrobble::Service;
+use crate::sponsorblock::{self, Segment, SponsorBlock};
+use crate::store::{
+    AgePolicy, Bookmark, Feature, NowPlayingStatus, SavedPlaylist, SavedSession, SavedTrack, Store,
+};
+use crate::tts;
+use crate::ytdl::{Author, Playlist, Query as YtdlQuery, Track, YtdlConfig};
 
 use twilight_cache_inmemory::InMemoryCache;
-use twilight_gateway::MessageSender as GatewayMessageSender;
+use twilight_gateway::{Intents, MessageSender as GatewayMessageSender};
 use twilight_http::Client as HttpClient;
 use twilight_model::{
     gateway::payload::{
         incoming::{VoiceServerUpdate, VoiceStateUpdate},
-        outgoing::UpdateVoiceState,
+        outgoing::{update_presence::UpdatePresencePayload, UpdatePresence, UpdateVoiceState},
     },
+    gateway::presence::{ActivityType, MinimalActivity, Status},
+    gateway::OpCode,
+    guild::Permissions,
     id::{
-        marker::{ChannelMarker, GuildMarker, UserMarker},
+        marker::{ChannelMarker, GuildMarker, MessageMarker, UserMarker},
         Id,
     },
     voice::VoiceState,
@@ -55,14 +117,78 @@ use tokio::sync::RwLock;
 /// How long the bot will wait in an empty voice channel until disconnecting.
 pub const AUTODISCONNECT_TIME: Duration = Duration::from_secs(900);
 
+/// How long a queue without a player or any commands lives before it stops.
+pub const QUEUE_IDLE_TIME: Duration = Duration::from_secs(600);
+
+/// How often the [`QueueServer`] forgets about stopped queues.
+const QUEUE_SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How many events are buffered for each subscriber of a [`QueueServer`].
+const EVENT_CAPACITY: usize = 64;
+
+/// How many bookmarks a user can have before the oldest are forgotten.
+const MAX_BOOKMARKS: usize = 25;
+
+/// How far before a segment the queue can be and still skip it, since the
+/// skip timer and the player don't quite agree on time.
+const SEGMENT_TOLERANCE: Duration = Duration::from_millis(250);
+
+/// How many of the last played tracks autoplay avoids repeating.
+const AUTOPLAY_HISTORY: usize = 50;
+
+/// How long after a track was found it is checked again before playing.
+const STALE_TRACK_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The most playlists a guild can save.
+const MAX_PLAYLISTS: usize = 25;
+
+/// The most tracks a saved playlist can have.
+const MAX_PLAYLIST_TRACKS: usize = 500;
+
+/// The longest an announced clip can play for.
+const MAX_ANNOUNCEMENT_LEN: Duration = Duration::from_secs(30);
+
+/// How long after rebuilding a crashed player another crash clears the queue
+/// instead.
+const PLAYER_REBUILD_COOLDOWN: Duration = Duration::from_secs(30);
+
 /// A music server is a shardable server for music queues.
 pub struct QueueServer {
     gateway: GatewayMessageSender,
     cache: Arc<InMemoryCache>,
     http_client: Arc<HttpClient>,
+    store: Arc<Store>,
 
     user_id: Id<UserMarker>,
     queues: RwLock<HashMap<Id<GuildMarker>, Queue>>,
+    events: broadcast::Sender<QueueEvent>,
+
+    /// The guild whose playing track is shown in the bot's presence.
+    presence_guild: Option<Id<GuildMarker>>,
+    /// The config of new players.
+    player_config: PlayerConfig,
+    /// The SponsorBlock client every queue looks segments up with.
+    sponsorblock: Arc<SponsorBlock>,
+    /// The guilds this process handles.
+    claim: GuildClaim,
+    /// The Lavalink node to play on, instead of the native voice client.
+    lavalink: Option<Arc<lavalink::Node>>,
+    /// The last known state of the gateway shard.
+    shard_health: std::sync::Mutex<ShardHealth>,
+    /// Why nothing can be played, if yt-dlp or ffmpeg don't run.
+    audio_unavailable: std::sync::Mutex<Option<String>>,
+    /// When tracks that keep failing are quarantined.
+    quarantine_config: QuarantineConfig,
+    /// The tracks of each guild that keep failing.
+    quarantines: std::sync::Mutex<HashMap<Id<GuildMarker>, quarantine::Quarantine>>,
+    /// Whether queues save their sessions to rejoin them after a restart.
+    rejoin: bool,
+    /// How long every queue takes to handle commands.
+    command_timings: Histogram,
+    /// How many players can run at once.
+    admission: Arc<admission::Admission>,
+    /// The users who run the bot.
+    operators: HashSet<Id<UserMarker>>,
 }
 
 impl QueueServer {
@@ -71,19 +197,120 @@ impl QueueServer {
         gateway: GatewayMessageSender,
         cache: Arc<InMemoryCache>,
         http_client: Arc<HttpClient>,
+        store: Arc<Store>,
 
         user_id: Id<UserMarker>,
     ) -> QueueServer {
         QueueServer {
             gateway,
+            admission: Arc::new(admission::Admission::new(None, http_client.clone())),
             http_client,
             cache,
+            store,
 
             user_id,
             queues: RwLock::new(HashMap::new()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+
+            presence_guild: None,
+            player_config: PlayerConfig::default(),
+            sponsorblock: Arc::default(),
+            claim: GuildClaim::ALL,
+            lavalink: None,
+            shard_health: Default::default(),
+            audio_unavailable: Default::default(),
+            quarantine_config: QuarantineConfig::default(),
+            quarantines: Default::default(),
+            rejoin: false,
+            command_timings: Histogram::new(),
+            operators: HashSet::new(),
+        }
+    }
+
+    /// Checks that the shard's `intents` and the cache are enough for the
+    /// queues, with [`validate_setup`](crate::validate_setup).
+    pub fn validate_setup(self, intents: Intents) -> Result<QueueServer, crate::SetupError> {
+        crate::validate_setup(intents, self.cache.config())?;
+        Ok(self)
+    }
+
+    /// Refuses to play anything, because of `reason`.
+    ///
+    /// Commands that would play audio fail with
+    /// [`UserError::AudioUnavailable`], and everything else works.
+    pub fn with_audio_unavailable(self, reason: impl Into<String>) -> QueueServer {
+        QueueServer {
+            audio_unavailable: std::sync::Mutex::new(Some(reason.into())),
+            ..self
+        }
+    }
+
+    /// Quarantines tracks that keep failing with a config.
+    pub fn with_quarantine_config(self, quarantine_config: QuarantineConfig) -> QueueServer {
+        QueueServer {
+            quarantine_config,
+            ..self
+        }
+    }
+
+    /// Runs `ytdl` with a config, instead of the one in the player config.
+    ///
+    /// Set this after [`QueueServer::with_player_config`], which replaces it.
+    pub fn with_ytdl(mut self, ytdl: YtdlConfig) -> QueueServer {
+        self.player_config.ytdl = ytdl;
+        self
+    }
+
+    /// How `ytdl` is run for queries and new players.
+    pub fn ytdl(&self) -> &YtdlConfig {
+        &self.player_config.ytdl
+    }
+
+    /// Shows the track playing in a guild in the bot's presence.
+    ///
+    /// The presence is shared by every guild, so only one guild can have it.
+    /// The guild can turn it off and on again with its settings.
+    pub fn with_presence(self, guild_id: Id<GuildMarker>) -> QueueServer {
+        QueueServer {
+            presence_guild: Some(guild_id),
+            ..self
+        }
+    }
+
+    /// Sets the config new players are created with.
+    pub fn with_player_config(self, config: PlayerConfig) -> QueueServer {
+        QueueServer {
+            player_config: config,
+            ..self
+        }
+    }
+
+    /// Only handles the guilds in a claim, ignoring the events of the rest.
+    ///
+    /// See [`GuildClaim`].
+    pub fn with_claim(self, claim: GuildClaim) -> QueueServer {
+        QueueServer { claim, ..self }
+    }
+
+    /// Plays every queue on a Lavalink node instead of the native voice
+    /// client.
+    ///
+    /// See [`lavalink`].
+    pub fn with_lavalink(self, node: Arc<lavalink::Node>) -> QueueServer {
+        QueueServer {
+            lavalink: Some(node),
+            ..self
         }
     }
 
+    /// Subscribes to the events of every queue.
+    ///
+    /// Subscribers that fall more than a few events behind miss the oldest
+    /// events.
+    pub fn subscribe(&self) -> broadcast::Receiver<QueueEvent> {
+        self.events.subscribe()
+    }
+
     /// Sends a command to a queue in a guild.
     pub async fn command(
         self: &Arc<QueueServer>,
@@ -116,31 +343,107 @@ impl QueueServer {
         .await;
     }
 
+    /// Starts a task that periodically removes stopped queues.
+    ///
+    /// The task stops once the `QueueServer` is dropped.
+    pub fn start_sweeper(self: &Arc<QueueServer>) -> JoinHandle<()> {
+        let queue_server = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut interval = interval(QUEUE_SWEEP_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let Some(queue_server) = queue_server.upgrade() else {
+                    break;
+                };
+
+                queue_server.sweep().await;
+            }
+        })
+    }
+
+    /// Starts a task that watches how much of the CPU encoding uses, lowering
+    /// the quality of new tracks when it gets close to all of it and sending a
+    /// [`QueueEvent::Guardrail`] when it does.
+    ///
+    /// Returns `None` if the [guardrail](crate::voice::guardrail) is off. The
+    /// task stops once the `QueueServer` is dropped.
+    pub fn start_guardrail(self: &Arc<QueueServer>) -> Option<JoinHandle<()>> {
+        let mut guardrail = Guardrail::new(self.player_config.timings.clone())?;
+        let queue_server = Arc::downgrade(self);
+
+        Some(tokio::spawn(async move {
+            let mut interval = interval(guardrail::CHECK_INTERVAL);
+            // the first tick is immediate
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                let Some(queue_server) = queue_server.upgrade() else {
+                    break;
+                };
+
+                if let Some(event) = guardrail.check() {
+                    let _ = queue_server.events.send(QueueEvent::Guardrail(event));
+                }
+            }
+        }))
+    }
+
+    /// Removes queues whose tasks have stopped.
+    pub async fn sweep(&self) {
+        let mut queues = self.queues.write().await;
+        let before = queues.len();
+
+        queues.retain(|_, queue| !queue.task.is_finished());
+
+        debug!(removed = before - queues.len(), "swept queues");
+    }
+
     /// Gets a currently running queue or starts a new queue.
+    ///
+    /// Exactly one queue runs per guild; queues are only started while the
+    /// write lock is held.
     async fn with_queue<F>(self: &Arc<QueueServer>, guild_id: Id<GuildMarker>, f: F)
     where
         F: FnOnce(&Queue),
     {
-        let queues = self.queues.read().await;
+        // another process handles this guild
+        if !self.claim.claims(guild_id) {
+            return;
+        }
 
-        if let Some(queue) = queues.get(&guild_id) {
-            if !queue.task.is_finished() {
-                f(queue);
-                return;
+        // most of the time, the queue is already running
+        {
+            let queues = self.queues.read().await;
+
+            if let Some(queue) = queues.get(&guild_id) {
+                if !queue.task.is_finished() {
+                    f(queue);
+                    return;
+                }
             }
         }
 
-        // start a new queue
-        let new_queue = Queue::new(self.clone(), guild_id);
+        let mut queues = self.queues.write().await;
 
-        f(&new_queue);
+        let queue = match queues.entry(guild_id) {
+            Entry::Occupied(mut entry) => {
+                // another call may have started the queue while we were
+                // waiting for the lock
+                if entry.get().task.is_finished() {
+                    entry.insert(Queue::new(self.clone(), guild_id));
+                }
 
-        // DROP THE FUCKING RWLOCK GUARD, THIS CAUSES A DEADLOCK.
-        drop(queues);
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(Queue::new(self.clone(), guild_id)),
+        };
 
-        // add to map
-        let mut queues = self.queues.write().await;
-        queues.insert(guild_id, new_queue);
+        f(queue);
     }
 }
 
@@ -149,6 +452,10 @@ struct Queue {
     task: JoinHandle<()>,
     command_tx: UnboundedSender<Command>,
     gateway_tx: UnboundedSender<GatewayEvent>,
+    /// Requests for snapshots of the queue's state.
+    inspect_tx: UnboundedSender<debug::Inspect>,
+    /// Saved sessions to rejoin.
+    rejoin_tx: UnboundedSender<SavedSession>,
 }
 
 #[derive(Debug)]
@@ -160,32 +467,28 @@ enum GatewayEvent {
 impl Queue {
     /// Spins up a new queue task.
     pub fn new(queue_server: Arc<QueueServer>, guild_id: impl Into<Id<GuildMarker>>) -> Queue {
+        let guild_id = guild_id.into();
         let (command_tx, command_rx) = mpsc::unbounded_channel();
         let (gateway_tx, gateway_rx) = mpsc::unbounded_channel();
+        let (inspect_tx, inspect_rx) = mpsc::unbounded_channel();
+        let (rejoin_tx, rejoin_rx) = mpsc::unbounded_channel();
 
         // start task
-        let task = tokio::spawn(queue_run(QueueState {
-            query_queue: QueryQueue::new(queue_server.http_client.clone()),
-
+        let task = tokio::spawn(queue_run(QueueState::new(
             queue_server,
-            guild_id: guild_id.into(),
-
-            player: None,
+            guild_id,
             command_rx,
             gateway_rx,
-
-            autodisconnect: AutoDisconnect::default(),
-
-            track_queue: VecDeque::default(),
-            playing: None,
-
-            rng: SmallRng::from_entropy(),
-        }));
+            inspect_rx,
+            rejoin_rx,
+        )));
 
         Queue {
             task,
             command_tx,
             gateway_tx,
+            inspect_tx,
+            rejoin_tx,
         }
     }
 }
@@ -198,391 +501,2794 @@ struct QueueState {
     query_queue: QueryQueue<QueryResult>,
     command_rx: UnboundedReceiver<Command>,
     gateway_rx: UnboundedReceiver<GatewayEvent>,
+    inspect_rx: UnboundedReceiver<debug::Inspect>,
+    rejoin_rx: UnboundedReceiver<SavedSession>,
 
     autodisconnect: AutoDisconnect,
+    rate_limit: RateLimiter,
+    /// How long the queue takes to handle commands.
+    command_timings: Histogram,
+    /// The lock on the queue, if it's locked.
+    lock: Option<lock::QueueLock>,
+    /// The queue's share of the players that can run at once, while it has a
+    /// player.
+    slot: Option<admission::Slot>,
+    /// Whether a related track is enqueued when the queue runs out.
+    autoplay: bool,
+    /// The query for a track related to the last one, for autoplay.
+    related: Option<JoinHandle<Result<YtdlQuery, crate::Error>>>,
+    /// The urls of the last played tracks, most recent last.
+    history: VecDeque<String>,
+    /// What was played since the bot joined its channel.
+    session: farewell::Session,
+    /// Which segments of the playing track are skipped.
+    sponsorblock: SponsorBlockMode,
+    /// The segments of the playing track to skip, in order.
+    segments: Vec<Segment>,
+    /// The lookup of the segments of the playing track.
+    segment_fetch: Option<SegmentFetch>,
+    /// The check that a stale track can still be played, before it plays.
+    track_check: Option<TrackCheck>,
 
     track_queue: VecDeque<Track>,
     playing: Option<Track>,
+    /// Whether the playing track is paused.
+    paused: bool,
+    loop_mode: LoopMode,
+    /// Whether the playing track shouldn't loop when it ends, since it was
+    /// skipped or failed.
+    skip_loop: bool,
+    panel: Option<Panel>,
+
+    /// The channel the last track was requested from, where playback
+    /// problems are reported.
+    announce_channel: Option<Id<ChannelMarker>>,
+    /// The url of the track being retried after a source error.
+    source_retry: Option<String>,
+    /// The last error the player ran into.
+    last_error: Option<String>,
+    /// When the player was last rebuilt after a connection error.
+    last_rebuild: Option<Instant>,
+    /// When the queue last got a command or query result.
+    last_active: Instant,
+    /// When the session was last saved for rejoining, if it's saved.
+    last_session_save: Option<Instant>,
+    /// The last shuffle, if the queue is shuffled.
+    shuffle: Option<Shuffle>,
+    queue_mode: QueueMode,
+    /// Whether the playing track is shown in the bot's presence.
+    presence: bool,
+    /// Whether track thumbnails are shown full-size in embeds.
+    large_thumbnails: bool,
+    /// Whether tracks play at the bitrate of the voice channel.
+    high_quality: bool,
+    /// Whether a summary of the session is announced when the bot leaves
+    /// on its own.
+    farewell: bool,
+    /// The playing track, as shown on a channel.
+    channel_status: ChannelStatus,
 
     rng: SmallRng,
 }
 
+/// A shuffle of the queue, remembering how to undo it.
+struct Shuffle {
+    seed: u64,
+    /// The urls of the tracks in the queue before it was shuffled.
+    original: Vec<String>,
+}
+
+impl Shuffle {
+    /// Puts the tracks back in their original order.
+    ///
+    /// Tracks that were added after the shuffle keep their order and go to
+    /// the end of the queue.
+    fn restore(self, queue: &mut VecDeque<Track>) {
+        let mut remaining = queue.drain(..).map(Some).collect::<Vec<_>>();
+
+        for url in self.original {
+            let track = remaining
+                .iter_mut()
+                .find(|track| track.as_ref().is_some_and(|track| track.url == url))
+                .and_then(Option::take);
+
+            queue.extend(track);
+        }
+
+        queue.extend(remaining.into_iter().flatten());
+    }
+}
+
 #[derive(Debug)]
 struct QueryInfo {
     query: YtdlQuery,
-    playnow: bool,
+    options: PlayOptions,
+    /// The items of a batch that failed to query.
+    failures: Vec<Failure>,
 }
 
-type QueryResult = Result<QueryInfo, QueryError>;
+type QueryResult = Result<QueryInfo, crate::Error>;
+
+/// A lookup of the segments of a track, resolving to the track's url and its
+/// segments.
+type SegmentFetch = JoinHandle<(String, Result<Vec<Segment>, sponsorblock::Error>)>;
+
+/// A check that a track can still be played, resolving to the track and the
+/// result of querying it again.
+type TrackCheck = JoinHandle<(Track, Result<YtdlQuery, crate::Error>)>;
 
 impl QueueState {
+    /// Creates the state of a new queue, with nothing queued.
+    fn new(
+        queue_server: Arc<QueueServer>,
+        guild_id: Id<GuildMarker>,
+        command_rx: UnboundedReceiver<Command>,
+        gateway_rx: UnboundedReceiver<GatewayEvent>,
+        inspect_rx: UnboundedReceiver<debug::Inspect>,
+        rejoin_rx: UnboundedReceiver<SavedSession>,
+    ) -> QueueState {
+        QueueState {
+            query_queue: QueryQueue::new(queue_server.http_client.clone()),
+
+            player: None,
+            command_rx,
+            gateway_rx,
+            inspect_rx,
+            rejoin_rx,
+
+            autodisconnect: AutoDisconnect::default(),
+            rate_limit: RateLimiter::default(),
+            command_timings: Histogram::new(),
+            lock: None,
+            slot: None,
+            autoplay: false,
+            related: None,
+            history: VecDeque::default(),
+            session: farewell::Session::default(),
+            sponsorblock: SponsorBlockMode::default(),
+            segments: Vec::new(),
+            segment_fetch: None,
+            track_check: None,
+
+            track_queue: VecDeque::default(),
+            playing: None,
+            paused: false,
+            loop_mode: LoopMode::default(),
+            skip_loop: false,
+            panel: None,
+
+            announce_channel: None,
+            source_retry: None,
+            last_error: None,
+            last_rebuild: None,
+            last_active: Instant::now(),
+            last_session_save: None,
+            shuffle: None,
+            queue_mode: QueueMode::default(),
+            presence: queue_server.presence_guild == Some(guild_id),
+            large_thumbnails: false,
+            high_quality: false,
+            farewell: false,
+            channel_status: ChannelStatus::default(),
+
+            queue_server,
+            guild_id,
+
+            rng: SmallRng::from_entropy(),
+        }
+    }
+
     #[instrument(name = "queue_handle_command", skip(self))]
     pub async fn handle_command(&mut self, command: Command) {
         let Command { data, action } = command;
 
-        let res = match action {
-            Action::Play(track, playnow) => self.play(&data, track, playnow).await,
-            Action::Skip => self.skip(&data).await,
-            Action::Queue => self.queue(&data).await,
-            Action::Shuffle => self.shuffle(&data).await,
-            Action::Disconnect => self.command_disconnect(&data).await,
-            Action::AutoDisconnect(op) => self.autodisconnect(&data, op).await,
-        };
-
-        if let Err(err) = res {
-            let _ = data
-                .respond(&self.queue_server.http_client)
-                .error(err)
+        if let Err(err) = self.dispatch(&data, action).await {
+            data.respond(&self.queue_server.http_client)
+                .error(err.localize(data.locale.as_deref()))
                 .respond()
                 .await;
         }
     }
 
+    /// Runs the handler of an action.
+    ///
+    /// Commands go through [`QueueState::dispatch`] first, which checks them
+    /// against their [`Layer`](middleware::Layer)s.
+    async fn handle_action(&mut self, data: &CommandData, action: Action) -> Result<(), UserError> {
+        match action {
+            Action::Play(track, options) => self.play(data, track, options).await,
+            Action::Skip => self.skip(data).await,
+            Action::Queue => self.queue(data).await,
+            Action::Shuffle(seed) => self.shuffle(data, seed).await,
+            Action::Unshuffle => self.unshuffle(data).await,
+            Action::Disconnect => self.command_disconnect(data).await,
+            Action::AutoDisconnect(op) => self.autodisconnect(data, op).await,
+            Action::Bookmark(name) => self.bookmark(data, name).await,
+            Action::Jump(name) => self.jump(data, name).await,
+            Action::Settings(update) => self.settings(data, update).await,
+            Action::Tts(text, mode) => self.tts(data, text, mode).await,
+            Action::Announce(announcement) => self.command_announce(data, announcement).await,
+            Action::Export(format) => self.export(data, format).await,
+            Action::Import(source) => self.import(data, source).await,
+            Action::Playlist(action) => self.playlist(data, action).await,
+            Action::Autoplay(op) => self.autoplay(data, op).await,
+            Action::Pause => self.pause(data).await,
+            Action::Stop => self.stop(data).await,
+            Action::Loop(mode) => self.command_loop(data, mode).await,
+            Action::Player(progress) => self.player_panel(data, progress).await,
+            Action::BulkPlay(queries, options) => self.bulk_play(data, queries, options).await,
+            Action::PlayReply(reply, options) => self.play_reply(data, reply, options).await,
+            Action::Operator(action) => self.operator(data, action).await,
+            Action::MyNext(position) => self.my_next(data, position).await,
+            Action::Block(action) => self.block(data, action).await,
+            Action::Chapters => self.chapters(data).await,
+            Action::Chapter(number) => self.chapter(data, number).await,
+            Action::Stats(period) => self.stats(data, period).await,
+            Action::Debug(guild_id) => self.debug_dump(data, guild_id).await,
+            Action::Ping => self.ping(data).await,
+            Action::UpdateYtdl => self.update_ytdl(data).await,
+            Action::Quarantine(action) => self.command_quarantine(data, action).await,
+            Action::Lock(duration) => self.lock(data, duration).await,
+            Action::Unlock => self.unlock(data).await,
+            Action::Scrobble(service) => self.scrobble(data, service).await,
+        }
+    }
+
     async fn play(
         &mut self,
         command: &CommandData,
         query: String,
-        playnow: bool,
+        options: PlayOptions,
     ) -> Result<(), UserError> {
-        match self.check_user_in_channel(command.user_id).await {
-            // user is in the same channel
-            Ok(_) => (),
-            // join user's channel
-            Err(UserError::BotNotInChannel(channel_id)) => {
-                self.join(channel_id).await;
-            }
-            Err(err) => {
-                return Err(err);
-            }
+        if !self.check_play_options(command, &options).await {
+            return Ok(());
+        }
+
+        self.announce_channel = Some(command.channel_id);
+        let ytdl = self.queue_server.ytdl().clone();
+        let guild_id = self.guild_id;
+
+        // several urls are queried together, like a bulk play
+        if let Some(urls) = query::split_urls(&query) {
+            let reporter =
+                ProgressReporter::new(command.clone(), self.queue_server.http_client.clone());
+
+            self.query_queue
+                .enqueue(command.clone(), move |_| async move {
+                    query::query_batch(&ytdl, guild_id, urls, Some(reporter))
+                        .await
+                        .map(|(playlist, failures)| QueryInfo {
+                            query: YtdlQuery::Playlist(playlist),
+                            options,
+                            failures,
+                        })
+                })
+                .await;
+
+            return Ok(());
         }
 
         self.query_queue
             .enqueue(command.clone(), move |_| async move {
-                YtdlQuery::query(&query)
+                query::query(&ytdl, guild_id, &query)
                     .await
-                    .map(|query| QueryInfo { query, playnow })
+                    .map(|query| QueryInfo {
+                        query,
+                        options,
+                        failures: Vec::new(),
+                    })
             })
             .await;
 
         Ok(())
     }
 
-    async fn skip(&mut self, command: &CommandData) -> Result<(), UserError> {
-        self.check_user_in_channel(command.user_id).await?;
+    async fn bulk_play(
+        &mut self,
+        command: &CommandData,
+        queries: Vec<String>,
+        options: PlayOptions,
+    ) -> Result<(), UserError> {
+        if queries.is_empty() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("there are no links to play"))
+                .respond()
+                .await;
 
-        self.skip_track();
+            return Ok(());
+        }
 
-        if let Some(track) = self.track_queue.front() {
-            let _ = command
+        if !self.check_play_options(command, &options).await {
+            return Ok(());
+        }
+
+        self.announce_channel = Some(command.channel_id);
+        let ytdl = self.queue_server.ytdl().clone();
+        let guild_id = self.guild_id;
+        let reporter =
+            ProgressReporter::new(command.clone(), self.queue_server.http_client.clone());
+
+        self.query_queue
+            .enqueue(command.clone(), move |_| async move {
+                query::query_batch(&ytdl, guild_id, queries, Some(reporter))
+                    .await
+                    .map(|(playlist, failures)| QueryInfo {
+                        query: YtdlQuery::Playlist(playlist),
+                        options,
+                        failures,
+                    })
+            })
+            .await;
+
+        Ok(())
+    }
+
+    /// Checks the options of a play command before querying, telling the
+    /// user if they can't be used.
+    ///
+    /// If the queue shrinks while the query runs, tracks placed past its end
+    /// go at the end instead.
+    async fn check_play_options(&self, command: &CommandData, options: &PlayOptions) -> bool {
+        if options.shuffle && options.reverse {
+            command
                 .respond(&self.queue_server.http_client)
-                .embed(Embed {
-                    description: Some(String::from("skipped track")),
-                    ..track.as_embed()
-                })
+                .error(command.tr("a playlist can't be both shuffled and reversed"))
                 .respond()
                 .await;
-        } else {
-            let _ = command
+
+            return false;
+        }
+
+        let max_position = self.track_queue.len() + 1;
+
+        if options
+            .position
+            .is_some_and(|position| position > max_position)
+        {
+            command
                 .respond(&self.queue_server.http_client)
-                .content("skipped track, now playing nothing :(")
+                .error(command.trf(
+                    "the queue only has {count} tracks, so the position can be at most {max}",
+                    &[("count", &self.track_queue.len()), ("max", &max_position)],
+                ))
                 .respond()
                 .await;
+
+            return false;
         }
 
-        Ok(())
+        true
     }
 
-    async fn queue(&self, command: &CommandData) -> Result<(), UserError> {
-        let mut description = self
-            .playing
-            .as_ref()
-            .map(|track| format!("now playing [{}]({})", track.title, track.url))
-            .unwrap_or_else(|| String::from("nothing currently playing"));
+    /// Plays the links in the message a text command replied to.
+    ///
+    /// The gateway usually sends the replied to message along, but if it
+    /// doesn't, it's looked up in the cache or fetched.
+    async fn play_reply(
+        &mut self,
+        command: &CommandData,
+        reply: Reply,
+        options: PlayOptions,
+    ) -> Result<(), UserError> {
+        let content = match reply.content {
+            Some(content) => Some(content),
+            None => {
+                self.replied_content(reply.channel_id, reply.message_id)
+                    .await
+            }
+        };
 
-        // construct queue
-        for (i, track) in self.track_queue.iter().enumerate().take(10) {
-            write!(
-                &mut description,
-                "\n{}. [{}]({})",
-                i + 1,
-                track.title,
-                track.url
-            )
-            .unwrap();
+        let Some(content) = content else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("couldn't read the message you replied to"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        self.bulk_play(command, find_urls(&content), options).await
+    }
+
+    /// Gets the content of a message, from the cache if it's there.
+    async fn replied_content(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+    ) -> Option<String> {
+        if let Some(cached) = self.queue_server.cache.message(message_id) {
+            return Some(cached.content().to_owned());
         }
 
-        if self.track_queue.len() > 10 {
-            let rest = self.track_queue.len() - 10;
+        let res = self
+            .queue_server
+            .http_client
+            .message(channel_id, message_id)
+            .await;
 
-            write!(&mut description, "\nand {} more...", rest).unwrap();
+        match res {
+            Ok(res) => res.model().await.ok().map(|message| message.content),
+            Err(err) => {
+                warn!(%err, "failed to fetch replied to message");
+                None
+            }
         }
+    }
 
-        let embed = Embed {
-            author: None,
-            // TODO: color
-            color: Some(0xEE1428),
-            description: Some(description),
-            fields: Vec::new(),
-            footer: None,
-            image: None,
-            kind: String::from("rich"),
-            provider: None,
-            thumbnail: self
-                .playing
-                .as_ref()
-                .and_then(|playing| playing.thumbnail_url.clone())
-                .map(|url| EmbedThumbnail {
-                    url,
-                    height: None,
-                    width: None,
-                    proxy_url: None,
-                }),
-            timestamp: None,
-            title: None,
-            url: self.playing.as_ref().map(|playing| playing.url.clone()),
-            video: None,
+    async fn tts(
+        &mut self,
+        command: &CommandData,
+        text: String,
+        mode: TtsMode,
+    ) -> Result<(), UserError> {
+        let Some(backend) = tts::tts_backend() else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("text-to-speech isn't set up"))
+                .respond()
+                .await;
+
+            return Ok(());
         };
 
-        let _ = command
-            .respond(&self.queue_server.http_client)
-            .embed(embed)
-            .respond()
-            .await;
+        self.announce_channel = Some(command.channel_id);
 
-        Ok(())
-    }
+        match mode {
+            TtsMode::Interject => {
+                let res = backend
+                    .speak(&text)
+                    .map_err(crate::Error::from)
+                    .and_then(|source| self.unwrap_player().interject(source));
 
-    async fn shuffle(&mut self, command: &CommandData) -> Result<(), UserError> {
-        self.check_user_in_channel(command.user_id).await?;
+                if let Err(err) = res {
+                    error!(%err, "tts");
 
-        let queue_slice = self.track_queue.make_contiguous();
+                    command
+                        .respond(&self.queue_server.http_client)
+                        .error(command.trf("failed to speak: {error}", &[("error", &err)]))
+                        .respond()
+                        .await;
 
-        queue_slice.shuffle(&mut self.rng);
+                    return Ok(());
+                }
 
-        let _ = command
-            .respond(&self.queue_server.http_client)
-            .content("shuffled music queue")
-            .respond()
-            .await;
+                command
+                    .respond(&self.queue_server.http_client)
+                    .content(command.trf("saying \"{text}\"", &[("text", &text)]))
+                    .respond()
+                    .await;
+            }
+            TtsMode::Queue => {
+                let track = speech_track(command, text);
+                let embed = track.as_embed(self.large_thumbnails);
+                let position = match self.place_tracks(once(track)) {
+                    Ok(position) => position,
+                    Err(err) => {
+                        command
+                            .respond(&self.queue_server.http_client)
+                            .error(command.trf("failed to play: {error}", &[("error", &err)]))
+                            .respond()
+                            .await;
+
+                        return Ok(());
+                    }
+                };
+
+                command
+                    .respond(&self.queue_server.http_client)
+                    .embed(Embed {
+                        description: Some(command.tr("enqueued speech").to_owned()),
+                        fields: self.position_fields(command, position),
+                        ..embed
+                    })
+                    .respond()
+                    .await;
+            }
+        }
 
         Ok(())
     }
 
-    async fn command_disconnect(&mut self, command: &CommandData) -> Result<(), UserError> {
-        self.check_user_in_channel(command.user_id).await?;
+    async fn export(&self, command: &CommandData, format: ExportFormat) -> Result<(), UserError> {
+        // pick the playing track up where it is now
+        let playing = self.playing.as_ref().map(|track| Track {
+            start: self
+                .player
+                .as_ref()
+                .map(|state| state.player.position())
+                .unwrap_or(track.start),
+            ..track.clone()
+        });
+
+        let tracks = playing
+            .iter()
+            .chain(&self.track_queue)
+            .filter(|track| track.speech.is_none())
+            .collect::<Vec<_>>();
+
+        if tracks.is_empty() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("the queue is empty"))
+                .respond()
+                .await;
 
-        self.disconnect().await;
+            return Ok(());
+        }
 
-        let _ = command
+        let file = export::export(tracks.iter().copied(), format);
+
+        command
             .respond(&self.queue_server.http_client)
-            .content("disconnected!")
+            .content(command.trf("exported {count} tracks", &[("count", &tracks.len())]))
+            .attachment(format!("queue.{}", format.extension()), file)
             .respond()
             .await;
 
         Ok(())
     }
 
-    async fn autodisconnect(
+    async fn import(
         &mut self,
         command: &CommandData,
-        op: Option<bool>,
+        source: ImportSource,
     ) -> Result<(), UserError> {
-        self.check_user_in_channel(command.user_id).await?;
+        self.announce_channel = Some(command.channel_id);
+        let ytdl = self.queue_server.ytdl().clone();
+        let guild_id = self.guild_id;
 
-        let enabled = match op {
-            Some(enabled) => enabled,
-            None => !self.autodisconnect.enabled,
+        self.query_queue
+            .enqueue(command.clone(), move |_| async move {
+                export::import(&ytdl, guild_id, source)
+                    .await
+                    .map(|(playlist, failures)| QueryInfo {
+                        query: YtdlQuery::Playlist(playlist),
+                        options: PlayOptions::default(),
+                        failures,
+                    })
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn playlist(
+        &mut self,
+        command: &CommandData,
+        action: PlaylistAction,
+    ) -> Result<(), UserError> {
+        match action {
+            PlaylistAction::Save(name) => self.playlist_save(command, name).await,
+            PlaylistAction::Play(name) => self.playlist_play(command, name).await,
+            PlaylistAction::List => self.playlist_list(command).await,
+            PlaylistAction::Delete(name) => self.playlist_delete(command, name).await,
+        }
+    }
+
+    async fn playlist_save(
+        &mut self,
+        command: &CommandData,
+        name: String,
+    ) -> Result<(), UserError> {
+        let tracks = self
+            .playing
+            .iter()
+            .chain(&self.track_queue)
+            .filter(|track| track.speech.is_none())
+            .take(MAX_PLAYLIST_TRACKS)
+            .map(SavedTrack::from)
+            .collect::<Vec<_>>();
+
+        if tracks.is_empty() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("the queue is empty"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        let count = tracks.len();
+        let playlist = SavedPlaylist {
+            name: name.clone(),
+            tracks,
         };
 
-        self.autodisconnect.enabled = enabled;
+        let res = self
+            .queue_server
+            .store
+            .update(|store| {
+                let playlists = store.playlists.entry(command.guild_id).or_default();
+
+                // replace playlists with the same name
+                match playlists.iter().position(|p| p.name == playlist.name) {
+                    Some(i) => playlists[i] = playlist,
+                    None if playlists.len() >= MAX_PLAYLISTS => return false,
+                    None => playlists.push(playlist),
+                }
 
-        let msg = if enabled {
-            format!(
-                "autodisconnect has been enabled, \
-                will autodisconnect after {:?}",
-                AUTODISCONNECT_TIME
-            )
-        } else {
-            String::from("autodisconnect has been disabled")
+                true
+            })
+            .await;
+
+        let mut respond = command.respond(&self.queue_server.http_client);
+
+        match res {
+            Ok(true) => respond.content(command.trf(
+                "saved {count} tracks as \"{name}\"",
+                &[("count", &count), ("name", &name)],
+            )),
+            Ok(false) => respond.error(command.trf(
+                "this server can't save more than {max} playlists",
+                &[("max", &MAX_PLAYLISTS)],
+            )),
+            Err(err) => {
+                error!(%err, "failed to save playlist");
+                respond.error(command.tr("failed to save playlist"))
+            }
+        };
+
+        respond.respond().await;
+
+        Ok(())
+    }
+
+    async fn playlist_play(
+        &mut self,
+        command: &CommandData,
+        name: String,
+    ) -> Result<(), UserError> {
+        let playlist = self
+            .queue_server
+            .store
+            .read(|store| {
+                store
+                    .playlists
+                    .get(&command.guild_id)
+                    .and_then(|playlists| playlists.iter().find(|p| p.name == name))
+                    .cloned()
+            })
+            .await;
+
+        let Some(playlist) = playlist else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.trf("there's no playlist named \"{name}\"", &[("name", &name)]))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        self.announce_channel = Some(command.channel_id);
+
+        let mut playlist = Playlist {
+            url: String::new(),
+            title: playlist.name,
+            author: Author {
+                name: command.tr("saved playlist").to_owned(),
+                url: None,
+                avatar_url: None,
+            },
+            thumbnail_url: None,
+            tracks: playlist
+                .tracks
+                .into_iter()
+                .map(|track| Track {
+                    requester: Some(command.user_id),
+                    ..track.into()
+                })
+                .collect(),
+            album: None,
+        };
+
+        let mut summary = PlaylistSummary::new(playlist.as_embed());
+
+        // the guild's rules may have changed since the playlist was saved
+        for (item, refusal) in self
+            .refuse_tracks(Some(command), &mut playlist.tracks)
+            .await
+        {
+            summary.add_failure(Failure {
+                item,
+                reason: refusal.message(command),
+            });
+        }
+
+        if playlist.tracks.is_empty() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("none of the playlist items left are allowed in this server"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        summary.add_tracks(&playlist.tracks);
+        let position = match self.place_tracks(playlist.tracks) {
+            Ok(position) => position,
+            Err(err) => {
+                command
+                    .respond(&self.queue_server.http_client)
+                    .error(command.trf("failed to play: {error}", &[("error", &err)]))
+                    .respond()
+                    .await;
+
+                return Ok(());
+            }
         };
 
-        let _ = command
+        command
             .respond(&self.queue_server.http_client)
-            .content(msg)
+            .embed(summary.build(command, self.position_fields(command, position)))
             .respond()
             .await;
 
         Ok(())
     }
 
-    /// Checks if a user can use a music control command.
-    ///
-    /// A user can use a music control command if the user is in the same
-    /// channel as the bot.
-    #[instrument(name = "check_user_in_channel", skip(self))]
-    async fn check_user_in_channel(&self, user_id: Id<UserMarker>) -> Result<(), UserError> {
-        let user_channel_id = self
+    async fn playlist_list(&self, command: &CommandData) -> Result<(), UserError> {
+        let description = self
             .queue_server
-            .cache
-            .voice_state(user_id, self.guild_id)
-            .map(|s| s.channel_id());
+            .store
+            .read(|store| {
+                let playlists = store.playlists.get(&command.guild_id)?;
+
+                let mut description = String::new();
+                for (i, playlist) in playlists.iter().enumerate() {
+                    if i > 0 {
+                        description.push('\n');
+                    }
 
-        let voice_state = self.voice_state().await;
-        if let Some(voice_state) = voice_state {
-            if voice_state.channel_id == user_channel_id {
-                Ok(())
-            } else {
-                Err(UserError::UserInDifferentChannel)
+                    write!(
+                        &mut description,
+                        "{}. **{}** ({})",
+                        i + 1,
+                        playlist.name,
+                        command.trf("{count} tracks", &[("count", &playlist.tracks.len())]),
+                    )
+                    .unwrap();
+                }
+
+                Some(description).filter(|description| !description.is_empty())
+            })
+            .await;
+
+        let Some(description) = description else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("no playlists are saved in this server"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .embed(Embed {
+                description: Some(description),
+                title: Some(command.tr("saved playlists").to_owned()),
+                ..crate::embed()
+            })
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn playlist_delete(&self, command: &CommandData, name: String) -> Result<(), UserError> {
+        let res = self
+            .queue_server
+            .store
+            .update(|store| {
+                let Some(playlists) = store.playlists.get_mut(&command.guild_id) else {
+                    return false;
+                };
+
+                let Some(i) = playlists.iter().position(|p| p.name == name) else {
+                    return false;
+                };
+
+                playlists.remove(i);
+
+                if playlists.is_empty() {
+                    store.playlists.remove(&command.guild_id);
+                }
+
+                true
+            })
+            .await;
+
+        let mut respond = command.respond(&self.queue_server.http_client);
+
+        match res {
+            Ok(true) => {
+                respond.content(command.trf("deleted playlist \"{name}\"", &[("name", &name)]))
+            }
+            Ok(false) => respond
+                .error(command.trf("there's no playlist named \"{name}\"", &[("name", &name)])),
+            Err(err) => {
+                error!(%err, "failed to delete playlist");
+                respond.error(command.tr("failed to delete playlist"))
+            }
+        };
+
+        respond.respond().await;
+
+        Ok(())
+    }
+
+    async fn command_announce(
+        &mut self,
+        command: &CommandData,
+        announcement: Announcement,
+    ) -> Result<(), UserError> {
+        let (overlay, content) = match announcement {
+            Announcement {
+                text: Some(text),
+                clip: None,
+            } => {
+                let Some(backend) = tts::tts_backend() else {
+                    command
+                        .respond(&self.queue_server.http_client)
+                        .error(command.tr("text-to-speech isn't set up"))
+                        .respond()
+                        .await;
+
+                    return Ok(());
+                };
+
+                let content = command.trf("announcing \"{text}\"", &[("text", &text)]);
+                (backend.overlay(&text), content)
+            }
+            Announcement {
+                text: None,
+                clip: Some(clip),
+            } => {
+                let overlay = SourceBuilder::ytdl(&clip)
+                    .ytdl_config(self.queue_server.ytdl().clone())
+                    .duration(MAX_ANNOUNCEMENT_LEN)
+                    .build_overlay();
+
+                (
+                    overlay,
+                    command.trf("announcing {clip}", &[("clip", &clip)]),
+                )
+            }
+            _ => {
+                command
+                    .respond(&self.queue_server.http_client)
+                    .error(command.tr("give either text or a clip to announce"))
+                    .respond()
+                    .await;
+
+                return Ok(());
             }
-        } else if let Some(channel_id) = user_channel_id {
-            Err(UserError::BotNotInChannel(channel_id))
+        };
+
+        self.announce_channel = Some(command.channel_id);
+
+        let res = overlay
+            .map_err(crate::Error::from)
+            .and_then(|overlay| self.unwrap_player().announce(overlay, DUCK_GAIN));
+
+        if let Err(err) = res {
+            error!(%err, "announce");
+
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.trf("failed to announce: {error}", &[("error", &err)]))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(content)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn skip(&mut self, command: &CommandData) -> Result<(), UserError> {
+        self.skip_track();
+
+        if let Some(track) = self.track_queue.front() {
+            command
+                .respond(&self.queue_server.http_client)
+                .embed(Embed {
+                    description: Some(command.tr("skipped track").to_owned()),
+                    ..track.as_embed(self.large_thumbnails)
+                })
+                .respond()
+                .await;
+        } else {
+            command
+                .respond(&self.queue_server.http_client)
+                .content(command.tr("skipped track, now playing nothing :("))
+                .respond()
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn pause(&mut self, command: &CommandData) -> Result<(), UserError> {
+        let (Some(_), Some(PlayerState { player, .. })) = (&self.playing, self.player.as_ref())
+        else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("nothing is playing"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        let content = if self.paused {
+            let _ = player.resume();
+            command.tr("resumed track")
         } else {
-            Err(UserError::UserNotInChannel)
+            let _ = player.pause();
+            command.tr("paused track")
+        };
+
+        self.paused = !self.paused;
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(content)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn stop(&mut self, command: &CommandData) -> Result<(), UserError> {
+        self.track_queue.clear();
+        self.shuffle = None;
+        self.skip_loop = true;
+
+        if let Some(check) = self.track_check.take() {
+            check.abort();
+        }
+
+        if let Some(PlayerState { player, .. }) = self.player.as_ref() {
+            if player.playing() {
+                let _ = player.stop();
+            }
+        }
+
+        self.set_playing(None);
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.tr("stopped playing and cleared the queue"))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn command_loop(
+        &mut self,
+        command: &CommandData,
+        mode: Option<LoopMode>,
+    ) -> Result<(), UserError> {
+        self.loop_mode = mode.unwrap_or(self.loop_mode.next());
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.trf("loop mode: {mode}", &[("mode", &self.loop_mode.name())]))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn queue(&self, command: &CommandData) -> Result<(), UserError> {
+        let mut description = self
+            .playing
+            .as_ref()
+            .map(|track| command.trf("now playing {track}", &[("track", &link(track))]))
+            .unwrap_or_else(|| command.tr("nothing currently playing").to_owned());
+
+        // construct queue
+        for (i, track) in self.track_queue.iter().enumerate().take(10) {
+            write!(&mut description, "\n{}. {}", i + 1, link(track)).unwrap();
+        }
+
+        if self.track_queue.len() > 10 {
+            let rest = self.track_queue.len() - 10;
+
+            description.push('\n');
+            description.push_str(&command.trf("and {count} more...", &[("count", &rest)]));
+        }
+
+        let embed = Embed {
+            description: Some(description),
+            footer: self.shuffle.as_ref().map(|shuffle| EmbedFooter {
+                icon_url: None,
+                proxy_icon_url: None,
+                text: command.trf("shuffled (seed {seed})", &[("seed", &shuffle.seed)]),
+            }),
+            thumbnail: self
+                .playing
+                .as_ref()
+                .and_then(|playing| playing.thumbnail_url.clone())
+                .map(|url| EmbedThumbnail {
+                    url,
+                    height: None,
+                    width: None,
+                    proxy_url: None,
+                }),
+            url: self
+                .playing
+                .as_ref()
+                .map(|playing| playing.url.clone())
+                .filter(|url| !url.is_empty()),
+            ..crate::embed()
+        };
+
+        // tracks of an album play together, so it heads the queue
+        let embed = match self.playing.as_ref().and_then(|track| track.album.as_ref()) {
+            Some(album) => Embed {
+                author: Some(EmbedAuthor {
+                    name: album.artist.clone(),
+                    url: None,
+                    icon_url: None,
+                    proxy_icon_url: None,
+                }),
+                title: Some(command.trf(
+                    "{album} · {count} tracks",
+                    &[("album", album), ("count", &album.tracks)],
+                )),
+                ..embed
+            },
+            None => embed,
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .embed(embed)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn shuffle(&mut self, command: &CommandData, seed: Option<u64>) -> Result<(), UserError> {
+        // pick a seed anyway so the shuffle can be repeated
+        let seed = seed.unwrap_or_else(|| self.rng.gen());
+
+        // only remember the order from before the first shuffle
+        let original = match self.shuffle.take() {
+            Some(shuffle) => shuffle.original,
+            None => self
+                .track_queue
+                .iter()
+                .map(|track| track.url.clone())
+                .collect(),
+        };
+
+        let queue_slice = self.track_queue.make_contiguous();
+
+        queue_slice.shuffle(&mut SmallRng::seed_from_u64(seed));
+        self.clear_picks();
+
+        self.shuffle = Some(Shuffle { seed, original });
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.trf("shuffled music queue (seed {seed})", &[("seed", &seed)]))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn unshuffle(&mut self, command: &CommandData) -> Result<(), UserError> {
+        let Some(shuffle) = self.shuffle.take() else {
+            command
+                .respond(&self.queue_server.http_client)
+                .content(command.tr("the queue isn't shuffled"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        shuffle.restore(&mut self.track_queue);
+        self.clear_picks();
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.tr("unshuffled music queue"))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn my_next(&mut self, command: &CommandData, position: usize) -> Result<(), UserError> {
+        let index = match self.pick_next(command.user_id, position) {
+            Ok(index) => index,
+            Err(error) => {
+                command
+                    .respond(&self.queue_server.http_client)
+                    .error(command.tr(error))
+                    .respond()
+                    .await;
+
+                return Ok(());
+            }
+        };
+
+        let embed = self.track_queue[index].as_embed(self.large_thumbnails);
+
+        command
+            .respond(&self.queue_server.http_client)
+            .embed(Embed {
+                description: Some(command.tr("playing your track next").to_owned()),
+                fields: self.position_fields(command, Some(index)),
+                ..embed
+            })
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    /// Moves a user's track at the 1-based `position` up to play next,
+    /// after the tracks picked before it.
+    ///
+    /// Returns the index the track was moved to, or why it can't be picked.
+    fn pick_next(
+        &mut self,
+        user_id: Id<UserMarker>,
+        position: usize,
+    ) -> Result<usize, &'static str> {
+        match self.track_queue.get(position.wrapping_sub(1)) {
+            None => return Err("there's no track at that position"),
+            Some(track) if track.requester != Some(user_id) => {
+                return Err("you can only pick your own tracks")
+            }
+            Some(track) if track.picked => return Err("that track is already up next"),
+            Some(_) if self.picked_by(user_id) => {
+                return Err("you already picked a track to play next")
+            }
+            Some(_) => (),
+        }
+
+        let mut track = self.track_queue.remove(position - 1).unwrap();
+        track.picked = true;
+
+        // picks play in the order they were made, after the ones before
+        let index = self
+            .track_queue
+            .iter()
+            .rposition(|track| track.picked)
+            .map_or(0, |index| index + 1);
+
+        self.track_queue.insert(index, track);
+
+        Ok(index)
+    }
+
+    /// Forgets the tracks users picked to play next, once the queue is
+    /// reordered around them.
+    fn clear_picks(&mut self) {
+        for track in self.track_queue.iter_mut() {
+            track.picked = false;
+        }
+    }
+
+    /// Whether a user has picked a queued track to play next.
+    fn picked_by(&self, user_id: Id<UserMarker>) -> bool {
+        self.track_queue
+            .iter()
+            .any(|track| track.picked && track.requester == Some(user_id))
+    }
+
+    async fn command_disconnect(&mut self, command: &CommandData) -> Result<(), UserError> {
+        self.disconnect().await;
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(command.tr("disconnected!"))
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn autodisconnect(
+        &mut self,
+        command: &CommandData,
+        op: Option<bool>,
+    ) -> Result<(), UserError> {
+        let enabled = match op {
+            Some(enabled) => enabled,
+            None => !self.autodisconnect.enabled,
+        };
+
+        self.autodisconnect.enabled = enabled;
+
+        let msg = if enabled {
+            command.trf(
+                "autodisconnect has been enabled, will autodisconnect after {time}",
+                &[("time", &format!("{:?}", AUTODISCONNECT_TIME))],
+            )
+        } else {
+            command.tr("autodisconnect has been disabled").to_owned()
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn autoplay(&mut self, command: &CommandData, op: Option<bool>) -> Result<(), UserError> {
+        let enabled = op.unwrap_or(!self.autoplay);
+
+        self.autoplay = enabled;
+
+        let msg = if enabled {
+            command
+                .tr("autoplay has been enabled, related tracks will play when the queue runs out")
+        } else {
+            if let Some(related) = self.related.take() {
+                related.abort();
+            }
+
+            command.tr("autoplay has been disabled")
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn bookmark(
+        &mut self,
+        command: &CommandData,
+        name: Option<String>,
+    ) -> Result<(), UserError> {
+        let (Some(track), Some(PlayerState { player, .. })) =
+            (self.playing.as_ref(), self.player.as_ref())
+        else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("nothing is playing"))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        if track.speech.is_some() {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("speech can't be bookmarked"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        let bookmark = Bookmark {
+            name: name.unwrap_or_else(|| track.title.clone()),
+            url: track.url.clone(),
+            title: track.title.clone(),
+            position: player.position().as_millis() as u64,
+        };
+
+        let msg = command.trf(
+            "bookmarked [{title}]({url}) at {position} as \"{name}\"",
+            &[
+                ("title", &bookmark.title),
+                ("url", &bookmark.url),
+                ("position", &format_duration(bookmark.position())),
+                ("name", &bookmark.name),
+            ],
+        );
+
+        let res = self
+            .queue_server
+            .store
+            .update(|store| {
+                let bookmarks = store.bookmarks.entry(command.user_id).or_default();
+
+                // replace bookmarks with the same name
+                bookmarks.retain(|b| b.name != bookmark.name);
+                bookmarks.push(bookmark);
+
+                if bookmarks.len() > MAX_BOOKMARKS {
+                    bookmarks.remove(0);
+                }
+            })
+            .await;
+
+        let mut respond = command.respond(&self.queue_server.http_client);
+
+        match res {
+            Ok(()) => respond.content(msg),
+            Err(err) => {
+                error!(%err, "failed to save bookmark");
+                respond.error(command.tr("failed to save bookmark"))
+            }
+        };
+
+        respond.respond().await;
+
+        Ok(())
+    }
+
+    async fn jump(&mut self, command: &CommandData, name: String) -> Result<(), UserError> {
+        let bookmark = self
+            .queue_server
+            .store
+            .read(|store| {
+                store
+                    .bookmarks
+                    .get(&command.user_id)
+                    .and_then(|bookmarks| bookmarks.iter().find(|b| b.name == name))
+                    .cloned()
+            })
+            .await;
+
+        let Some(bookmark) = bookmark else {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.trf(
+                    "you don't have a bookmark named \"{name}\"",
+                    &[("name", &name)],
+                ))
+                .respond()
+                .await;
+
+            return Ok(());
+        };
+
+        let options = PlayOptions {
+            playnow: true,
+            offset: bookmark.position(),
+            ..Default::default()
+        };
+
+        let ytdl = self.queue_server.ytdl().clone();
+        let guild_id = self.guild_id;
+
+        self.query_queue
+            .enqueue(command.clone(), move |_| async move {
+                query::query(&ytdl, guild_id, &bookmark.url)
+                    .await
+                    .map(|query| QueryInfo {
+                        query,
+                        options,
+                        failures: Vec::new(),
+                    })
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn settings(
+        &mut self,
+        command: &CommandData,
+        update: SettingsUpdate,
+    ) -> Result<(), UserError> {
+        // nothing is changed unless everything can be
+        if update.needs_manager() && !command.permissions.contains(Permissions::MANAGE_GUILD) {
+            return Err(UserError::NotManager);
+        }
+
+        let presence_available = self.queue_server.presence_guild == Some(self.guild_id);
+
+        if update.presence.is_some() && !presence_available {
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("the presence isn't available in this server"))
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        if let Some(queue_mode) = update.queue_mode {
+            self.set_queue_mode(queue_mode);
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| match queue_mode {
+                    QueueMode::Fifo => store.queue_modes.remove(&guild_id),
+                    queue_mode => store.queue_modes.insert(guild_id, queue_mode),
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save queue mode");
+            }
+        }
+
+        if let Some(presence) = update.presence {
+            self.presence = presence;
+            self.update_presence(if presence {
+                self.playing.as_ref()
+            } else {
+                None
+            });
+        }
+
+        if let Some(sponsorblock) = update.sponsorblock {
+            self.sponsorblock = sponsorblock;
+            self.fetch_segments();
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| match sponsorblock {
+                    SponsorBlockMode::Off => store.sponsorblock.remove(&guild_id),
+                    mode => store.sponsorblock.insert(guild_id, mode),
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save sponsorblock mode");
+            }
+        }
+
+        if let Some(role_id) = update.dj_role {
+            // @everyone has the same id as the guild
+            let role_id = Some(role_id).filter(|id| id.cast() != self.guild_id);
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| match role_id {
+                    Some(role_id) => store.dj_roles.insert(guild_id, role_id),
+                    None => store.dj_roles.remove(&guild_id),
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save dj role");
+            }
+        }
+
+        if let Some(age_policy) = update.age_policy {
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| match age_policy {
+                    AgePolicy::Nsfw => store.age_policies.remove(&guild_id),
+                    age_policy => store.age_policies.insert(guild_id, age_policy),
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save age policy");
+            }
+        }
+
+        if let Some(now_playing_status) = update.now_playing_status {
+            self.channel_status.target = now_playing_status;
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| match now_playing_status {
+                    NowPlayingStatus::Off => store.now_playing_status.remove(&guild_id),
+                    status => store.now_playing_status.insert(guild_id, status),
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save now playing status");
+            }
+        }
+
+        if let Some(large_thumbnails) = update.large_thumbnails {
+            self.large_thumbnails = large_thumbnails;
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| {
+                    if large_thumbnails {
+                        store.large_thumbnails.insert(guild_id)
+                    } else {
+                        store.large_thumbnails.remove(&guild_id)
+                    }
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save thumbnail size");
+            }
+        }
+
+        if let Some(high_quality) = update.high_quality {
+            self.high_quality = high_quality;
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| {
+                    if high_quality {
+                        store.high_quality.insert(guild_id)
+                    } else {
+                        store.high_quality.remove(&guild_id)
+                    }
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save audio quality");
+            }
+        }
+
+        if let Some(farewell) = update.farewell {
+            self.farewell = farewell;
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| {
+                    if farewell {
+                        store.farewell.insert(guild_id)
+                    } else {
+                        store.farewell.remove(&guild_id)
+                    }
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save farewell");
+            }
+        }
+
+        if let Some(enabled) = update.text_commands {
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| {
+                    if enabled {
+                        store.text_commands.insert(guild_id)
+                    } else {
+                        store.text_commands.remove(&guild_id)
+                    }
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save text commands");
+            }
+        }
+
+        if update.max_duration.is_some() || update.livestreams.is_some() {
+            let guild_id = self.guild_id;
+
+            let res = self
+                .queue_server
+                .store
+                .update(|store| {
+                    let limits = store.track_limits.entry(guild_id).or_default();
+
+                    if let Some(max_duration) = update.max_duration {
+                        limits.max_duration = Some(max_duration.as_secs()).filter(|secs| *secs > 0);
+                    }
+
+                    if let Some(livestreams) = update.livestreams {
+                        limits.deny_livestreams = !livestreams;
+                    }
+
+                    if limits.is_empty() {
+                        store.track_limits.remove(&guild_id);
+                    }
+                })
+                .await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to save track limits");
+            }
+        }
+
+        let (dj_role, limits, age_policy, text_commands) = self
+            .queue_server
+            .store
+            .read(|store| {
+                (
+                    store.dj_roles.get(&self.guild_id).copied(),
+                    store
+                        .track_limits
+                        .get(&self.guild_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                    store
+                        .age_policies
+                        .get(&self.guild_id)
+                        .copied()
+                        .unwrap_or_default(),
+                    store.text_commands.contains(&self.guild_id),
+                )
+            })
+            .await;
+
+        let autodisconnect = if self.autodisconnect.enabled {
+            command.tr("enabled")
+        } else {
+            command.tr("disabled")
+        };
+
+        let mut msg = command.trf(
+            "queue mode: {mode}\nautodisconnect: {autodisconnect}",
+            &[
+                ("mode", &self.queue_mode.name()),
+                ("autodisconnect", &autodisconnect),
+            ],
+        );
+
+        if presence_available {
+            let presence = if self.presence {
+                command.tr("enabled")
+            } else {
+                command.tr("disabled")
+            };
+
+            msg.push('\n');
+            msg.push_str(&command.trf("presence: {presence}", &[("presence", &presence)]));
+        }
+
+        msg.push('\n');
+        msg.push_str(&command.trf(
+            "sponsorblock: {sponsorblock}",
+            &[("sponsorblock", &self.sponsorblock.name())],
+        ));
+
+        if let Some(role_id) = dj_role {
+            msg.push('\n');
+            msg.push_str(&command.trf("dj role: {role}", &[("role", &format!("<@&{}>", role_id))]));
+        }
+
+        if let Some(max_duration) = limits.max_duration() {
+            msg.push('\n');
+            msg.push_str(&command.trf(
+                "max track length: {max}",
+                &[("max", &format_duration(max_duration))],
+            ));
+        }
+
+        if limits.deny_livestreams {
+            msg.push('\n');
+            msg.push_str(command.tr("livestreams: disabled"));
+        }
+
+        let large_thumbnails = if self.large_thumbnails {
+            command.tr("enabled")
+        } else {
+            command.tr("disabled")
+        };
+
+        msg.push('\n');
+        msg.push_str(&command.trf(
+            "large thumbnails: {enabled}",
+            &[("enabled", &large_thumbnails)],
+        ));
+
+        let high_quality = if self.high_quality {
+            command.tr("enabled")
+        } else {
+            command.tr("disabled")
+        };
+
+        msg.push('\n');
+        msg.push_str(&command.trf("high quality: {enabled}", &[("enabled", &high_quality)]));
+
+        let farewell = if self.farewell {
+            command.tr("enabled")
+        } else {
+            command.tr("disabled")
+        };
+
+        msg.push('\n');
+        msg.push_str(&command.trf("farewell: {enabled}", &[("enabled", &farewell)]));
+
+        msg.push('\n');
+        msg.push_str(&command.trf(
+            "age-restricted tracks: {policy}",
+            &[("policy", &age_policy.name())],
+        ));
+
+        msg.push('\n');
+        msg.push_str(&command.trf(
+            "now playing status: {status}",
+            &[("status", &self.channel_status.target.name())],
+        ));
+
+        if cfg!(feature = "text-commands") {
+            let text_commands = if text_commands {
+                command.tr("enabled")
+            } else {
+                command.tr("disabled")
+            };
+
+            msg.push('\n');
+            msg.push_str(&command.trf("text commands: {enabled}", &[("enabled", &text_commands)]));
+        }
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    async fn scrobble(
+        &self,
+        command: &CommandData,
+        service: Option<Service>,
+    ) -> Result<(), UserError> {
+        if !command.permissions.contains(Permissions::MANAGE_GUILD) {
+            return Err(UserError::NotManager);
+        }
+
+        let msg = match &service {
+            Some(Service::LastFm { .. }) => command.tr("scrobbling played tracks to Last.fm"),
+            Some(Service::ListenBrainz { .. }) => {
+                command.tr("scrobbling played tracks to ListenBrainz")
+            }
+            None => command.tr("stopped scrobbling to this server's service"),
+        };
+
+        let guild_id = self.guild_id;
+        let res = self
+            .queue_server
+            .store
+            .update(|store| match service {
+                Some(service) => {
+                    store.scrobblers.insert(guild_id, service);
+                }
+                None => {
+                    store.scrobblers.remove(&guild_id);
+                }
+            })
+            .await;
+
+        if let Err(err) = res {
+            error!(%err, "failed to save scrobbler");
+
+            command
+                .respond(&self.queue_server.http_client)
+                .error(command.tr("failed to save the change"))
+                .ephemeral()
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        // the credentials stay between the user and the bot
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .ephemeral()
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    #[instrument(name = "handle_query", skip(self))]
+    pub async fn handle_query(&mut self, result: QueryMessage<QueryResult>) {
+        let QueryMessage {
+            data: command,
+            message,
+        } = result;
+
+        match message {
+            Ok(QueryInfo {
+                query,
+                options,
+                failures,
+            }) => {
+                self.play_after_query(&command, query, options, failures)
+                    .await
+            }
+            Err(err) => {
+                command
+                    .respond(&self.queue_server.http_client)
+                    .error(command.trf("failed to query: {error}", &[("error", &err)]))
+                    .update()
+                    .await;
+            }
+        }
+    }
+
+    /// Executes the final result of a play command and their query.
+    async fn play_after_query(
+        &mut self,
+        command: &CommandData,
+        query: YtdlQuery,
+        options: PlayOptions,
+        failures: Vec<Failure>,
+    ) {
+        match query {
+            YtdlQuery::Track(track) => {
+                let mut tracks = vec![track];
+
+                if let Some((_, refusal)) =
+                    self.refuse_tracks(Some(command), &mut tracks).await.pop()
+                {
+                    command
+                        .respond(&self.queue_server.http_client)
+                        .error(refusal.message(command))
+                        .update()
+                        .await;
+                    return;
+                }
+
+                let mut track = tracks.pop().unwrap();
+                track.requester = Some(command.user_id);
+                track.start = options.offset;
+
+                let embed = track.as_embed(self.large_thumbnails);
+
+                // enqueue track
+                let mut position = match self.enqueue_tracks(once(track), &options) {
+                    Ok(position) => position,
+                    Err(err) => {
+                        command
+                            .respond(&self.queue_server.http_client)
+                            .error(command.trf("failed to play: {error}", &[("error", &err)]))
+                            .update()
+                            .await;
+                        return;
+                    }
+                };
+
+                let mut description = command.tr("enqueued track");
+
+                if options.playnow && options.interrupt {
+                    if let Some(placed) = position.take() {
+                        self.interrupt_track(placed + 1, options.park);
+                        description = command.tr("playing track now");
+                    }
+                }
+
+                command
+                    .respond(&self.queue_server.http_client)
+                    .embed(Embed {
+                        description: Some(description.to_owned()),
+                        fields: self.position_fields(command, position),
+                        ..embed
+                    })
+                    .update()
+                    .await;
+            }
+            YtdlQuery::Playlist(mut playlist) => {
+                options.apply_playlist(&mut playlist.tracks, &mut self.rng);
+
+                let mut summary = PlaylistSummary::new(playlist.as_embed());
+                for failure in failures {
+                    summary.add_failure(failure);
+                }
+
+                let refused = self
+                    .refuse_tracks(Some(command), &mut playlist.tracks)
+                    .await;
+                for (item, refusal) in &refused {
+                    summary.add_failure(Failure {
+                        item: item.clone(),
+                        reason: refusal.message(command),
+                    });
+                }
+
+                for track in playlist.tracks.iter_mut() {
+                    track.requester = Some(command.user_id);
+                }
+
+                if playlist.tracks.is_empty() {
+                    let msg = if !refused.is_empty() {
+                        command.tr("none of the playlist items left are allowed in this server")
+                    } else {
+                        command.tr("no playlist items left to enqueue")
+                    };
+
+                    command
+                        .respond(&self.queue_server.http_client)
+                        .error(msg)
+                        .update()
+                        .await;
+                    return;
+                }
+
+                summary.add_tracks(&playlist.tracks);
+
+                if playlist.album.is_some() {
+                    summary = summary
+                        .with_note(command.tr("this album plays without gaps between tracks"));
+                }
+
+                // enqueue track
+                let mut position = match self.enqueue_tracks(playlist.tracks, &options) {
+                    Ok(position) => position,
+                    Err(err) => {
+                        command
+                            .respond(&self.queue_server.http_client)
+                            .error(command.trf("failed to play: {error}", &[("error", &err)]))
+                            .update()
+                            .await;
+                        return;
+                    }
+                };
+
+                if options.playnow && options.interrupt {
+                    if let Some(placed) = position.take() {
+                        self.interrupt_track(placed + 1, options.park);
+                    }
+                }
+
+                command
+                    .respond(&self.queue_server.http_client)
+                    .embed(summary.build(command, self.position_fields(command, position)))
+                    .update()
+                    .await;
+            }
+        }
+    }
+
+    /// Enqueues tracks where the options of a play command put them.
+    ///
+    /// Returns the position of the first track in the queue, or `None` if it
+    /// started playing immediately. Fails like [`QueueState::place_tracks`].
+    fn enqueue_tracks(
+        &mut self,
+        tracks: impl IntoIterator<Item = Track>,
+        options: &PlayOptions,
+    ) -> Result<Option<usize>, crate::Error> {
+        match options.position {
+            _ if options.playnow => self.place_tracks_front(tracks),
+            Some(position) => self.place_tracks_at(tracks, position.saturating_sub(1)),
+            None => self.place_tracks(tracks),
+        }
+    }
+
+    /// Creates embed fields showing where a track is in the queue and how
+    /// long until it plays.
+    ///
+    /// `position` is the track's index in the queue, or `None` if the track
+    /// is already playing.
+    fn position_fields(&self, command: &CommandData, position: Option<usize>) -> Vec<EmbedField> {
+        let Some(position) = position else {
+            return Vec::new();
+        };
+
+        let wait = self
+            .wait_time(position)
+            .map(format_duration)
+            .unwrap_or_else(|| command.tr("unknown").to_owned());
+
+        vec![
+            EmbedField {
+                inline: true,
+                name: command.tr("position").to_owned(),
+                value: format!("#{}", position + 1),
+            },
+            EmbedField {
+                inline: true,
+                name: command.tr("plays in").to_owned(),
+                value: wait,
+            },
+        ]
+    }
+
+    /// Estimates how long until the track at `position` in the queue starts
+    /// playing.
+    ///
+    /// Returns `None` if the duration of a track ahead of it is unknown.
+    fn wait_time(&self, position: usize) -> Option<Duration> {
+        let remaining = match (self.playing.as_ref(), self.player.as_ref()) {
+            (Some(track), Some(PlayerState { player, .. })) => {
+                track.duration?.saturating_sub(player.position())
+            }
+            _ => Duration::ZERO,
+        };
+
+        self.track_queue
+            .iter()
+            .take(position)
+            .try_fold(remaining, |wait, track| {
+                Some(wait + track.duration?.saturating_sub(track.start))
+            })
+    }
+
+    /// Enqueues a track onto the player.
+    ///
+    /// Starts playing the song immediately if there is no song playing.
+    /// Otherwise, enqueue the track on the queue.
+    ///
+    /// To enqueue one track, use [`std::iter::once`].
+    ///
+    /// Returns the position of the first track in the queue, or `None` if it
+    /// started playing immediately. If the first track can't be played, the
+    /// player is dropped, the rest of the tracks are discarded and the error
+    /// is returned.
+    pub fn place_tracks(
+        &mut self,
+        tracks: impl IntoIterator<Item = Track>,
+    ) -> Result<Option<usize>, crate::Error> {
+        let mut tracks = tracks.into_iter();
+
+        match self.pull_track_if_not_playing(&mut tracks) {
+            Pull::NotPulled => (),
+            Pull::Played => {
+                // place the rest anyway
+                self.place_tracks(tracks)?;
+                return Ok(None);
+            }
+            Pull::PlayerGone(err) => return Err(err),
+        }
+
+        // place other tracks on queue
+        Ok(match self.queue_mode {
+            QueueMode::Fifo => {
+                let position = self.track_queue.len();
+                self.track_queue.extend(tracks);
+                Some(position)
+            }
+            QueueMode::Fair => tracks
+                .map(|track| self.place_track_fair(track))
+                .reduce(|first, _| first),
+        })
+    }
+
+    /// Enqueues tracks onto the player at `index` in the queue, or the end if
+    /// the queue is shorter.
+    ///
+    /// Starts playing the song immediately if there is no song playing.
+    ///
+    /// Returns the position of the first track in the queue, or `None` if it
+    /// started playing immediately. Fails like [`QueueState::place_tracks`].
+    pub fn place_tracks_at(
+        &mut self,
+        tracks: impl IntoIterator<Item = Track>,
+        index: usize,
+    ) -> Result<Option<usize>, crate::Error> {
+        let mut tracks = tracks.into_iter();
+
+        match self.pull_track_if_not_playing(&mut tracks) {
+            Pull::NotPulled => (),
+            Pull::Played => {
+                // place the rest anyway
+                self.place_tracks_at(tracks, index)?;
+                return Ok(None);
+            }
+            Pull::PlayerGone(err) => return Err(err),
+        }
+
+        let index = index.min(self.track_queue.len());
+
+        // the tracks go ahead of any picks, so they play first
+        if self.track_queue.range(index..).any(|track| track.picked) {
+            self.clear_picks();
+        }
+
+        for (i, track) in tracks.enumerate() {
+            self.track_queue.insert(index + i, track);
+        }
+
+        Ok(Some(index))
+    }
+
+    /// Places a track in the queue so tracks alternate between requesters.
+    ///
+    /// The queue is thought of as rounds where every requester gets one
+    /// track. The track goes at the end of the first round its requester
+    /// doesn't have a track in yet.
+    ///
+    /// Returns the position the track was placed at.
+    fn place_track_fair(&mut self, track: Track) -> usize {
+        let round = self
+            .track_queue
+            .iter()
+            .filter(|queued| queued.requester == track.requester)
+            .count();
+
+        let mut rounds = HashMap::new();
+        let position = self.track_queue.iter().position(|queued| {
+            let queued_round = rounds.entry(queued.requester).or_insert(0usize);
+            *queued_round += 1;
+            *queued_round > round + 1
+        });
+
+        match position {
+            Some(position) => {
+                self.track_queue.insert(position, track);
+                position
+            }
+            None => {
+                self.track_queue.push_back(track);
+                self.track_queue.len() - 1
+            }
+        }
+    }
+
+    /// Changes the queue mode, reordering the queue to match.
+    fn set_queue_mode(&mut self, queue_mode: QueueMode) {
+        if self.queue_mode == queue_mode {
+            return;
+        }
+
+        self.queue_mode = queue_mode;
+
+        if queue_mode == QueueMode::Fair {
+            let tracks = std::mem::take(&mut self.track_queue);
+
+            for track in tracks {
+                self.place_track_fair(track);
+            }
+        }
+    }
+
+    /// Enqueues a track onto the player at the front.
+    ///
+    /// Starts playing the song immediately if there is no song playing.
+    /// Otherwise, enqueue the track on the queue.
+    ///
+    /// To enqueue one track, use [`std::iter::once`].
+    ///
+    /// Returns the position of the first track in the queue, or `None` if it
+    /// started playing immediately. Fails like [`QueueState::place_tracks`].
+    pub fn place_tracks_front(
+        &mut self,
+        tracks: impl IntoIterator<Item = Track>,
+    ) -> Result<Option<usize>, crate::Error> {
+        let mut tracks = tracks.into_iter();
+
+        let playing = match self.pull_track_if_not_playing(&mut tracks) {
+            Pull::NotPulled => false,
+            Pull::Played => true,
+            Pull::PlayerGone(err) => return Err(err),
+        };
+
+        // place other tracks on front (there is no ExtendFront)
+        let mut count = 0usize;
+        for track in tracks {
+            self.track_queue.push_front(track);
+            count += 1;
+        }
+
+        Ok(if playing {
+            None
+        } else {
+            // every track after the first was pushed in front of it
+            count.checked_sub(1)
+        })
+    }
+
+    /// Plays the first track if nothing is playing.
+    fn pull_track_if_not_playing<T>(&mut self, tracks: &mut T) -> Pull
+    where
+        T: Iterator<Item = Track>,
+    {
+        if self.playing.is_some() || self.track_check.is_some() {
+            return Pull::NotPulled;
+        }
+
+        let Some(track) = tracks.next() else {
+            return Pull::NotPulled;
+        };
+
+        if is_stale(&track) {
+            self.check_track(track);
+            return Pull::Played;
+        }
+
+        // get player
+        self.update_bitrate();
+        let Some(PlayerState { player, .. }) = self.player.as_ref() else {
+            return Pull::PlayerGone(crate::Error::internal("there is no audio player"));
+        };
+
+        // play track immediately
+        if let Err(err) = player.play(&track, track.start) {
+            error!(%err, "failed to play track");
+            // the queue goes with the player, so the rest of the tracks
+            // are left for the caller to drop
+            self.drop_player();
+            return Pull::PlayerGone(err);
+        }
+
+        self.set_playing(Some(track));
+        Pull::Played
+    }
+
+    /// Skips the current track by stopping the player.
+    pub fn skip_track(&mut self) {
+        let Some(PlayerState { player, .. }) = self.player.as_ref() else {
+            return;
+        };
+
+        self.skip_loop = true;
+
+        if player.playing() {
+            if let Err(err) = player.stop() {
+                error!(%err, "failed to stop track");
+                self.drop_player();
+            }
+        } else {
+            // do not wait for stop event and enqueue new song now
+            self.next_track();
+        }
+    }
+
+    /// Skips the current track so the `count` tracks at the front of the
+    /// queue play now.
+    ///
+    /// If `park`, the skipped track is put back after them, to pick up where
+    /// it was skipped. While the queue loops, it goes round to the back like
+    /// any other skipped track instead.
+    fn interrupt_track(&mut self, count: usize, park: bool) {
+        let parked = self
+            .playing
+            .as_ref()
+            .filter(|track| park && track.speech.is_none())
+            .filter(|_| self.loop_mode != LoopMode::Queue);
+
+        if let (Some(track), Some(PlayerState { player, .. })) = (parked, self.player.as_ref()) {
+            let track = Track {
+                start: player.position(),
+                picked: false,
+                ..track.clone()
+            };
+
+            let index = count.min(self.track_queue.len());
+            self.track_queue.insert(index, track);
+        }
+
+        self.skip_track();
+    }
+
+    /// Plays a new track onto the player.
+    pub fn next_track(&mut self) {
+        if self.player.is_none() {
+            return;
+        }
+
+        // skipping a track that is being checked skips it
+        if let Some(check) = self.track_check.take() {
+            check.abort();
+        }
+
+        let skipped = std::mem::take(&mut self.skip_loop);
+
+        if let Some(track) = self.playing.clone().filter(|track| track.speech.is_none()) {
+            let track = Track {
+                start: Duration::ZERO,
+                picked: false,
+                ..track
+            };
+
+            match self.loop_mode {
+                LoopMode::Track if !skipped => self.track_queue.push_front(track),
+                LoopMode::Queue => self.track_queue.push_back(track),
+                _ => (),
+            }
+        }
+
+        match self.pop_next() {
+            Some(track) if is_stale(&track) => {
+                // the old track is over, even if the new one isn't ready
+                self.set_playing(None);
+                self.check_track(track);
+            }
+            track => self.play_track(track),
+        }
+    }
+
+    /// Takes the track that plays next off of the queue.
+    ///
+    /// Tracks that users picked with `/mynext` go first, in the order they
+    /// are queued, so each user's pick jumps ahead of everyone else's
+    /// tracks at most once.
+    fn pop_next(&mut self) -> Option<Track> {
+        match self.track_queue.iter().position(|track| track.picked) {
+            Some(index) => self.track_queue.remove(index),
+            None => self.track_queue.pop_front(),
+        }
+    }
+
+    /// Plays a track onto the player, or stops playing if there is none.
+    fn play_track(&mut self, track: Option<Track>) {
+        if track.is_none() && self.autoplay {
+            self.find_related();
+        }
+
+        // the player's position still belongs to the old track until it gets
+        // the new source
+        self.set_playing(track);
+
+        if let Some(track) = self.playing.as_ref() {
+            self.update_bitrate();
+
+            if let Err(err) = self.unwrap_player().play(track, track.start) {
+                error!(%err, "failed to play track");
+                self.drop_player();
+            }
+        }
+    }
+
+    /// Starts checking that a track can still be played.
+    ///
+    /// `youtube-dl` can find a track and fail to play it hours later, if it
+    /// was taken down or made private in the meantime.
+    fn check_track(&mut self, track: Track) {
+        debug!(url = track.url, "checking stale track");
+
+        let guild_id = self.guild_id;
+        let ytdl = self.queue_server.ytdl().clone();
+        self.track_check = Some(tokio::spawn(async move {
+            let result = query::query(&ytdl, guild_id, &track.url).await;
+            (track, result)
+        }));
+    }
+
+    /// Plays a checked track, or skips it if it can't be played anymore.
+    async fn handle_track_check(
+        &mut self,
+        mut track: Track,
+        result: Result<YtdlQuery, crate::Error>,
+    ) {
+        if self.player.is_none() {
+            return;
+        }
+
+        match result {
+            Ok(result) => {
+                track.resolved_at = Some(std::time::Instant::now());
+
+                // the old format's url may have expired
+                if let YtdlQuery::Track(found) = result {
+                    track.format = found.format;
+                }
+
+                self.play_track(Some(track));
+            }
+            Err(crate::Error::Query(err)) if err.is_unavailable() => {
+                info!(%err, url = track.url, "track unavailable, skipping");
+
+                let _ = self.queue_server.events.send(QueueEvent::TrackUnavailable {
+                    guild_id: self.guild_id,
+                    track: track.clone(),
+                    reason: err.to_string(),
+                });
+
+                let embed = Embed {
+                    description: Some(i18n::trf(
+                        self.guild_locale().as_deref(),
+                        "track is no longer available: {error}",
+                        &[("error", &err)],
+                    )),
+                    ..track.as_embed(self.large_thumbnails)
+                };
+
+                self.announce(embed).await;
+                self.next_track();
+            }
+            Err(err) => {
+                // not the track's fault, so try to play it anyway
+                error!(%err, url = track.url, "failed to check track");
+                self.play_track(Some(track));
+            }
+        }
+    }
+
+    /// Starts looking for a track related to the one playing, for autoplay.
+    fn find_related(&mut self) {
+        let Some(track) = self.playing.as_ref().filter(|track| track.speech.is_none()) else {
+            return;
+        };
+
+        if let Some(related) = self.related.take() {
+            related.abort();
+        }
+
+        let query = related_query(track);
+        let guild_id = self.guild_id;
+        let ytdl = self.queue_server.ytdl().clone();
+        self.related = Some(tokio::spawn(async move {
+            query::query(&ytdl, guild_id, &query).await
+        }));
+    }
+
+    /// Enqueues the first related track that wasn't played recently, unless
+    /// something was enqueued in the meantime.
+    async fn handle_related(&mut self, result: Result<YtdlQuery, crate::Error>) {
+        let mut tracks = match result {
+            Ok(YtdlQuery::Track(track)) => vec![track],
+            Ok(YtdlQuery::Playlist(playlist)) => playlist.tracks,
+            Err(err) => {
+                error!(%err, "failed to find related track");
+                return;
+            }
+        };
+
+        if self.playing.is_some() || !self.track_queue.is_empty() || self.player.is_none() {
+            return;
+        }
+
+        for (title, refusal) in self.refuse_tracks(None, &mut tracks).await {
+            debug!(title, ?refusal, "not autoplaying refused track");
+        }
+
+        let track = tracks
+            .into_iter()
+            .find(|track| !self.history.contains(&track.url));
+
+        match track {
+            Some(track) => {
+                debug!(url = track.url, "autoplaying related track");
+                if let Err(err) = self.place_tracks(once(track)) {
+                    error!(%err, "failed to autoplay related track");
+                }
+            }
+            None => debug!("no related tracks left to autoplay"),
+        }
+    }
+
+    /// Changes the playing track, letting subscribers know.
+    fn set_playing(&mut self, track: Option<Track>) {
+        if let Some(ended) = self.playing.take() {
+            let played = self
+                .player
+                .as_ref()
+                .map(|PlayerState { player, .. }| player.position().saturating_sub(ended.start))
+                .unwrap_or_default();
+
+            self.session.record(&ended, played);
+
+            if played >= quarantine::PLAYED_THROUGH {
+                self.queue_server.quarantine(self.guild_id, |quarantine| {
+                    quarantine.record_success(&ended.url)
+                });
+            }
+
+            let _ = self.queue_server.events.send(QueueEvent::TrackEnded {
+                guild_id: self.guild_id,
+                track: ended,
+                played,
+            });
+        }
+
+        if let Some(track) = track.as_ref().filter(|track| track.speech.is_none()) {
+            self.session.start();
+            self.history.push_back(track.url.clone());
+
+            if self.history.len() > AUTOPLAY_HISTORY {
+                self.history.pop_front();
+            }
+        }
+
+        if let Some(track) = track.as_ref() {
+            let _ = self.queue_server.events.send(QueueEvent::TrackStarted {
+                guild_id: self.guild_id,
+                track: track.clone(),
+            });
+        }
+
+        if self.presence {
+            self.update_presence(track.as_ref());
+        }
+
+        self.playing = track;
+        self.paused = false;
+        self.fetch_segments();
+    }
+
+    /// Starts looking up the segments of the playing track to skip.
+    fn fetch_segments(&mut self) {
+        self.segments.clear();
+
+        if let Some(fetch) = self.segment_fetch.take() {
+            fetch.abort();
+        }
+
+        let categories = self.sponsorblock.categories();
+        if categories.is_empty() {
+            return;
+        }
+
+        let Some(track) = self.playing.as_ref() else {
+            return;
+        };
+
+        let Some(id) = youtube_id(&track.url) else {
+            return;
+        };
+
+        let sponsorblock = self.queue_server.sponsorblock.clone();
+        let url = track.url.clone();
+        let id = id.to_owned();
+
+        self.segment_fetch = Some(tokio::spawn(async move {
+            let segments = sponsorblock.segments(&id, categories).await;
+            (url, segments)
+        }));
+    }
+
+    /// When the next segment of the playing track starts, if there is one.
+    fn next_segment(&self) -> Option<Instant> {
+        if self.paused {
+            return None;
+        }
+
+        let PlayerState { player, .. } = self.player.as_ref()?;
+        let position = player.position();
+
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.end > position)?;
+
+        Some(Instant::now() + segment.start.saturating_sub(position))
+    }
+
+    /// Seeks past the segment the playing track is in.
+    fn skip_segment(&mut self) {
+        let (Some(track), Some(PlayerState { player, .. })) =
+            (self.playing.as_ref(), self.player.as_ref())
+        else {
+            return;
+        };
+
+        let position = player.position();
+
+        let Some(segment) = self
+            .segments
+            .iter()
+            .find(|segment| segment.end > position)
+            .copied()
+        else {
+            return;
+        };
+
+        // the timer went off early
+        if segment.start > position + SEGMENT_TOLERANCE {
+            return;
         }
-    }
 
-    #[instrument(name = "handle_query", skip(self))]
-    pub async fn handle_query(&mut self, result: QueryMessage<QueryResult>) {
-        let QueryMessage {
-            data: command,
-            message,
-        } = result;
+        debug!(?segment, "skipping segment");
+        self.segments.retain(|s| s.start > segment.end);
 
-        match message {
-            Ok(QueryInfo { query, playnow }) => {
-                self.play_after_query(&command, query, playnow).await
-            }
-            Err(err) => {
-                let _ = command
-                    .respond(&self.queue_server.http_client)
-                    .error(format!("failed to query: {}", err))
-                    .update()
-                    .await;
-            }
+        if let Err(err) = player.play(track, segment.end) {
+            error!(%err, "failed to skip segment");
         }
     }
 
-    /// Executes the final result of a play command and their query.
-    async fn play_after_query(&mut self, command: &CommandData, query: YtdlQuery, playnow: bool) {
-        match query {
-            YtdlQuery::Track(track) => {
-                let _ = command
-                    .respond(&self.queue_server.http_client)
-                    .embed(Embed {
-                        description: Some(String::from("enqueued track")),
-                        ..track.as_embed()
-                    })
-                    .update()
-                    .await;
-
-                // enqueue track
-                if playnow {
-                    self.place_tracks_front(once(track));
-                } else {
-                    self.place_tracks(once(track));
+    /// Sets the bot's presence to listening to `track`, or clears it.
+    fn update_presence(&self, track: Option<&Track>) {
+        let activities = track
+            .map(|track| {
+                MinimalActivity {
+                    kind: ActivityType::Listening,
+                    name: track.title.clone(),
+                    url: None,
                 }
-            }
-            YtdlQuery::Playlist(playlist) => {
-                let _ = command
-                    .respond(&self.queue_server.http_client)
-                    .embed(Embed {
-                        description: Some(String::from("enqueued playlist")),
-                        ..playlist.as_embed()
-                    })
-                    .update()
-                    .await;
+                .into()
+            })
+            .into_iter()
+            .collect();
+
+        // built by hand since `UpdatePresence::new` refuses to clear the
+        // activities
+        let presence = UpdatePresence {
+            d: UpdatePresencePayload {
+                activities,
+                afk: false,
+                since: None,
+                status: Status::Online,
+            },
+            op: OpCode::PresenceUpdate,
+        };
 
-                // enqueue track
-                if playnow {
-                    self.place_tracks_front(playlist.tracks);
-                } else {
-                    self.place_tracks(playlist.tracks);
-                }
-            }
+        if let Err(err) = self.queue_server.gateway.command(&presence) {
+            error!(%err, "failed to update presence");
         }
     }
 
-    /// Enqueues a track onto the player.
-    ///
-    /// Starts playing the song immediately if there is no song playing.
-    /// Otherwise, enqueue the track on the queue.
-    ///
-    /// To enqueue one track, use [`std::iter::once`].
-    pub fn place_tracks(&mut self, tracks: impl IntoIterator<Item = Track>) {
-        let mut tracks = tracks.into_iter();
+    /// Reacts to an error from the player.
+    #[instrument(name = "handle_player_error", skip(self))]
+    async fn handle_player_error(&mut self, err: voice::Error) {
+        self.last_error = Some(err.to_string());
+        let track = self.playing.as_ref().map(|track| track.title.clone());
 
-        self.pull_track_if_not_playing(&mut tracks);
+        match err.kind() {
+            ErrorKind::SourceError => {
+                error!(%err, ?track, "track");
 
-        // place other tracks on queue
-        self.track_queue.extend(tracks);
-    }
+                // a broken track shouldn't loop forever
+                self.skip_loop = true;
 
-    /// Enqueues a track onto the player at the front.
-    ///
-    /// Starts playing the song immediately if there is no song playing.
-    /// Otherwise, enqueue the track on the queue.
-    ///
-    /// To enqueue one track, use [`std::iter::once`].
-    pub fn place_tracks_front(&mut self, tracks: impl IntoIterator<Item = Track>) {
-        let mut tracks = tracks.into_iter();
+                let Some(track) = self.playing.clone() else {
+                    return;
+                };
 
-        self.pull_track_if_not_playing(&mut tracks);
+                let config = &self.queue_server.quarantine_config;
+                let quarantined = self.queue_server.quarantine(self.guild_id, |quarantine| {
+                    quarantine.record_failure(&track, config)
+                });
 
-        // place other tracks on front (there is no ExtendFront)
-        for track in tracks {
-            self.track_queue.push_front(track);
-        }
-    }
+                if !quarantined && self.source_retry.as_deref() != Some(&track.url) {
+                    // the player skips the track on its own, so put it back
+                    // for one more try
+                    self.source_retry = Some(track.url.clone());
+                    self.track_queue.push_front(track);
+                } else {
+                    self.source_retry = None;
+
+                    let locale = self.guild_locale();
+                    let mut description =
+                        i18n::tr(locale.as_deref(), "failed to play track").to_owned();
+
+                    if quarantined {
+                        description.push('\n');
+                        description.push_str(&i18n::trf(
+                            locale.as_deref(),
+                            "it keeps failing, so it can't be enqueued for {time}",
+                            &[("time", &format_duration(config.cooldown))],
+                        ));
+                    }
 
-    fn pull_track_if_not_playing<T>(&mut self, tracks: &mut T)
-    where
-        T: Iterator<Item = Track>,
-    {
-        if self.playing.is_none() {
-            if let Some(track) = tracks.next() {
-                // get player
-                let player = self.unwrap_player();
+                    let embed = Embed {
+                        description: Some(description),
+                        fields: vec![EmbedField {
+                            inline: false,
+                            name: i18n::tr(locale.as_deref(), "reason").to_owned(),
+                            value: err.to_string(),
+                        }],
+                        ..track.as_embed(self.large_thumbnails)
+                    };
+
+                    self.announce(embed).await;
+                }
+            }
+            ErrorKind::ConnectionError => {
+                error!(%err, ?track, "connection");
+
+                let (channel_id, position) = match self.player.take() {
+                    Some(PlayerState { player, .. }) => {
+                        let channel_id = match player.voice_state().await {
+                            Ok(voice_state) => voice_state.channel_id,
+                            Err(_) => None,
+                        };
+
+                        (channel_id, player.position())
+                    }
+                    None => (None, Duration::ZERO),
+                };
+
+                // don't rebuild players forever if the connection keeps
+                // failing
+                let now = Instant::now();
+                let recently_rebuilt = self
+                    .last_rebuild
+                    .is_some_and(|at| now - at < PLAYER_REBUILD_COOLDOWN);
 
-                // play track immediately
-                let source = Source::ytdl(&track.url).unwrap();
-                player.play(source).unwrap();
+                match channel_id {
+                    Some(channel_id) if !recently_rebuilt => {
+                        self.last_rebuild = Some(now);
 
-                self.playing = Some(track);
+                        self.join(channel_id).await;
+
+                        // resume the track that was cut off where it left off
+                        if let Some(track) = self.playing.as_ref() {
+                            if let Err(err) = self.unwrap_player().play(track, position) {
+                                error!(%err, "failed to resume track");
+                                self.drop_player();
+                            }
+                        } else {
+                            self.next_track();
+                        }
+                    }
+                    _ => {
+                        self.set_playing(None);
+                        self.track_queue.clear();
+                        self.shuffle = None;
+                        self.slot = None;
+                    }
+                }
+            }
+            ErrorKind::Disconnected => {
+                info!(%err, "player disconnected");
+                self.drop_player();
             }
         }
     }
 
-    /// Skips the current track by stopping the player.
-    pub fn skip_track(&mut self) {
-        let Some(PlayerState { player, .. }) = self.player.as_ref() else {
-            return;
-        };
+    /// Drops the player and clears the queue.
+    ///
+    /// The player only refuses a request once it has closed, so this is also
+    /// how a failed request is handled.
+    fn drop_player(&mut self) {
+        // clear queue
+        self.set_playing(None);
+        self.track_queue.clear();
+        self.shuffle = None;
 
-        if player.playing() {
-            player.stop().unwrap();
-        } else {
-            // do not wait for stop event and enqueue new song now
-            self.next_track();
-        }
+        // drop player
+        self.player = None;
+        self.slot = None;
     }
 
-    /// Plays a new track onto the player.
-    pub fn next_track(&mut self) {
-        let Some(PlayerState { player, .. }) = self.player.as_ref() else {
+    /// Sends an embed to the announce channel, if there is one.
+    pub async fn announce(&self, embed: Embed) {
+        let Some(channel_id) = self.announce_channel else {
             return;
         };
 
-        if let Some(track) = self.track_queue.pop_front() {
-            player.play(Source::ytdl(&track.url).unwrap()).unwrap();
-            self.playing = Some(track);
-        } else {
-            self.playing = None;
+        let embeds = [embed];
+        let res = match self
+            .queue_server
+            .http_client
+            .create_message(channel_id)
+            .embeds(&embeds)
+        {
+            Ok(req) => req.await.map(|_| ()),
+            Err(err) => {
+                error!(%err, "invalid announcement");
+                return;
+            }
+        };
+
+        if let Err(err) = res {
+            error!(%err, "failed to announce");
         }
     }
 
+    /// Gets the preferred locale of the guild, for messages that aren't a
+    /// response to anyone.
+    fn guild_locale(&self) -> Option<String> {
+        self.queue_server
+            .cache
+            .guild(self.guild_id)
+            .map(|guild| guild.preferred_locale().to_owned())
+    }
+
     /// Returns the current voice state of the bot, or `None` if there is no
     /// current state (the player is closed or None).
-    pub async fn voice_state(&self) -> Option<RwLockReadGuard<VoiceState>> {
+    pub async fn voice_state(&self) -> Option<RwLockReadGuard<'_, VoiceState>> {
         if let Some(PlayerState { player, .. }) = self.player.as_ref() {
             player.voice_state().await.ok()
         } else {
@@ -590,6 +3296,35 @@ impl QueueState {
         }
     }
 
+    /// The bitrate tracks should play at.
+    ///
+    /// In high quality, this is the bitrate of the bot's voice channel, if
+    /// it allows more than the default.
+    fn bitrate(&self) -> Bitrate {
+        if !self.high_quality {
+            return DEFAULT_BITRATE;
+        }
+
+        let cache = &self.queue_server.cache;
+        let channel_bitrate = cache
+            .voice_state(self.queue_server.user_id, self.guild_id)
+            .and_then(|voice_state| cache.channel(voice_state.channel_id()))
+            .and_then(|channel| channel.bitrate)
+            .and_then(|bits| i32::try_from(bits).ok());
+
+        match (channel_bitrate, DEFAULT_BITRATE) {
+            (Some(bits), Bitrate::Bits(default)) if bits > default => Bitrate::Bits(bits),
+            _ => DEFAULT_BITRATE,
+        }
+    }
+
+    /// Tells the player what bitrate the next track plays at.
+    fn update_bitrate(&self) {
+        if let Some(PlayerState { player, .. }) = self.player.as_ref() {
+            player.set_bitrate(self.bitrate());
+        }
+    }
+
     /// Joins or moves the bot to a Discord channel.
     #[instrument(name = "join_channel", skip(self))]
     pub async fn join(&mut self, channel_id: Id<ChannelMarker>) {
@@ -622,20 +3357,32 @@ impl QueueState {
     /// Disconnects the bot.
     #[instrument(name = "disconnect_channel", skip(self))]
     pub async fn disconnect(&mut self) {
+        // clear stuff
+        self.set_playing(None);
+        self.track_queue.clear();
+        self.shuffle = None;
+        self.session = farewell::Session::default();
+
+        if let Some(related) = self.related.take() {
+            related.abort();
+        }
+
+        if let Some(check) = self.track_check.take() {
+            check.abort();
+        }
+
         // drop player
-        if let Some(player) = self.player.as_ref() {
+        if let Some(player) = self.player.take() {
             let _ = player.player.disconnect();
-            self.player = None;
         }
-
-        // clear stuff
-        self.playing = None;
-        self.track_queue.clear();
+        self.slot = None;
 
         self.queue_server
             .gateway
             .command(&UpdateVoiceState::new(self.guild_id, None, false, false))
             .unwrap();
+
+        self.forget_session().await;
     }
 
     async fn check_autodisconnect(&mut self) {
@@ -658,9 +3405,30 @@ impl QueueState {
             .filter(|state| state.user_id() != self.queue_server.user_id)
             .count();
 
+        // the cache may lag behind the voice server, so trust whichever saw
+        // more users
+        let listener_count = self
+            .player
+            .as_ref()
+            .map(|player| player.listeners.len())
+            .unwrap_or_default();
+        let user_count = user_count.max(listener_count);
+
         // true rust moment
         drop(voice_state);
 
+        // the cache may have missed users joining, like right after a
+        // reconnect, so ask before leaving
+        let user_count = if user_count == 0
+            && self.autodisconnect.enabled
+            && self.autodisconnect.disconnect_at.is_none()
+            && self.requesters_in_channel(channel_id).await
+        {
+            1
+        } else {
+            user_count
+        };
+
         if user_count == 0 {
             debug!("autodisconnect set");
             self.autodisconnect.start();
@@ -669,24 +3437,80 @@ impl QueueState {
         }
     }
 
-    fn unwrap_player(&self) -> &Player {
+    /// Removes the queue from the [`QueueServer`] so it can stop.
+    ///
+    /// Commands are only sent while the server's lock is held, so if there
+    /// are no commands waiting while we hold the write lock, none can be lost
+    /// after the queue is removed. Returns `false` if a command came in, in
+    /// which case it is handled and the queue keeps running.
+    async fn try_stop(&mut self) -> bool {
+        let queue_server = self.queue_server.clone();
+        let mut queues = queue_server.queues.write().await;
+
+        match self.command_rx.try_recv() {
+            Ok(command) => {
+                drop(queues);
+
+                self.last_active = Instant::now();
+                self.handle_command(command).await;
+                false
+            }
+            Err(_) => {
+                queues.remove(&self.guild_id);
+                true
+            }
+        }
+    }
+
+    /// Checks if the queue has nothing to do.
+    fn is_idle(&self) -> bool {
+        // a status left on a channel is cleared first
+        self.player.is_none() && self.query_queue.is_empty() && !self.channel_status.is_shown()
+    }
+
+    fn unwrap_player(&self) -> &dyn PlaybackBackend {
         let PlayerState { player, .. } = self.player.as_ref().expect("audio player");
 
-        player
+        player.as_ref()
     }
 
     fn start_player(&mut self) {
+        self.acquire_slot();
         let (event_tx, event_rx) = mpsc::unbounded_channel();
 
-        let player = Player::new(self.queue_server.user_id, self.guild_id, event_tx);
+        let player: Box<dyn PlaybackBackend> = match self.queue_server.lavalink.as_ref() {
+            Some(node) => Box::new(node.player(self.queue_server.user_id, self.guild_id, event_tx)),
+            None => Box::new(Player::with_config(
+                self.queue_server.user_id,
+                self.guild_id,
+                event_tx,
+                self.queue_server.player_config.clone(),
+            )),
+        };
 
-        self.player = Some(PlayerState { player, event_rx });
+        self.player = Some(PlayerState {
+            player,
+            event_rx,
+            listeners: HashSet::new(),
+        });
     }
 }
 
+/// What became of the first of the tracks being placed.
+enum Pull {
+    /// Something is playing already, so the tracks go on the queue.
+    NotPulled,
+    /// The track is playing, or being checked before it plays.
+    Played,
+    /// The track couldn't be played, so the player was dropped.
+    PlayerGone(crate::Error),
+}
+
 struct PlayerState {
-    player: Player,
+    player: Box<dyn PlaybackBackend>,
     event_rx: UnboundedReceiver<voice::Event>,
+    /// Users the voice server told us are connected.
+    listeners: HashSet<Id<UserMarker>>,
 }
 
 impl PlayerState {
@@ -739,16 +3563,58 @@ impl Default for AutoDisconnect {
 }
 
 async fn queue_run(mut state: QueueState) {
+    (
+        state.large_thumbnails,
+        state.high_quality,
+        state.farewell,
+        state.sponsorblock,
+        state.queue_mode,
+        state.channel_status.target,
+    ) = state
+        .queue_server
+        .store
+        .read(|store| {
+            (
+                store.large_thumbnails.contains(&state.guild_id),
+                store.high_quality.contains(&state.guild_id),
+                store.farewell.contains(&state.guild_id),
+                store
+                    .sponsorblock
+                    .get(&state.guild_id)
+                    .copied()
+                    .unwrap_or_default(),
+                store
+                    .queue_modes
+                    .get(&state.guild_id)
+                    .copied()
+                    .unwrap_or_default(),
+                store
+                    .now_playing_status
+                    .get(&state.guild_id)
+                    .copied()
+                    .unwrap_or_default(),
+            )
+        })
+        .await;
+
     loop {
+        let next_segment = state.next_segment();
+        let next_progress = state.next_panel_progress();
+        let next_status = state.next_status_update();
+        let next_session_save = state.next_session_save();
+        let lock_expires = state.lock.as_ref().map(|lock| lock.until);
+
         tokio::select! {
             biased;
 
             // high level command
             Some(command) = state.command_rx.recv() => {
+                state.last_active = Instant::now();
                 state.handle_command(command).await;
             }
             // high level queue event
             message = state.query_queue.next() => {
+                state.last_active = Instant::now();
                 state.handle_query(message).await;
             }
             // gateway event
@@ -771,6 +3637,15 @@ async fn queue_run(mut state: QueueState) {
                     }
                 }
             },
+            // saved session to pick up again
+            Some(session) = state.rejoin_rx.recv() => {
+                state.last_active = Instant::now();
+                state.rejoin(session).await;
+            }
+            // snapshot for /debug dump, which doesn't count as activity
+            Some(reply) = state.inspect_rx.recv() => {
+                let _ = reply.send(state.dump().await);
+            }
             // low level voice event
             Some(event) = PlayerState::next_event(state.player.as_mut()) => {
                 //tracing::debug!(?event, "got player event");
@@ -779,54 +3654,505 @@ async fn queue_run(mut state: QueueState) {
                     voice::EventType::Ready => {
                     }
                     voice::EventType::Error(err) => {
-                        error!(%err, "audio");
-
-                        // clear queue
-                        state.playing = None;
-                        state.track_queue.clear();
-
-                        // drop player
-                        state.player = None;
+                        state.handle_player_error(err).await;
                     }
                     voice::EventType::Playing => {
+                        // the track started, so it gets another retry if it
+                        // fails later on
+                        state.source_retry = None;
                     }
                     voice::EventType::Stopped => {
                         // enqueue new track
                         state.next_track();
                     }
+                    voice::EventType::ListenerJoined(user_id) => {
+                        if let Some(player) = state.player.as_mut() {
+                            if user_id != state.queue_server.user_id {
+                                player.listeners.insert(user_id);
+                            }
+                        }
+
+                        state.check_autodisconnect().await;
+                    }
+                    voice::EventType::ListenerLeft(user_id) => {
+                        if let Some(player) = state.player.as_mut() {
+                            player.listeners.remove(&user_id);
+                        }
+
+                        state.check_autodisconnect().await;
+                    }
                 };
             }
+            // segments of the playing track
+            result = async { state.segment_fetch.as_mut().unwrap().await }, if state.segment_fetch.is_some() => {
+                state.segment_fetch = None;
+
+                match result {
+                    Ok((url, Ok(segments))) => {
+                        if state.playing.as_ref().is_some_and(|track| track.url == url) {
+                            debug!(count = segments.len(), "got segments");
+                            state.segments = segments;
+                        }
+                    }
+                    Ok((_, Err(err))) => error!(%err, "failed to get segments"),
+                    Err(_) => (),
+                }
+            }
+            // update the progress shown on the panel
+            _ = sleep_until(next_progress.unwrap_or_else(Instant::now)), if next_progress.is_some() => {}
+            // update the status shown on a channel
+            _ = sleep_until(next_status.unwrap_or_else(Instant::now)), if next_status.is_some() => {}
+            // let the lock go
+            _ = sleep_until(lock_expires.unwrap_or_else(Instant::now)), if lock_expires.is_some() => {
+                state.expire_lock();
+            }
+            // save the session for rejoining after a restart
+            _ = sleep_until(next_session_save.unwrap_or_else(Instant::now)), if next_session_save.is_some() => {
+                state.save_session().await;
+            }
+            // skip the segment the playing track is in
+            _ = sleep_until(next_segment.unwrap_or_else(Instant::now)), if next_segment.is_some() => {
+                state.skip_segment();
+            }
+            // stale track about to play
+            result = async { state.track_check.as_mut().unwrap().await }, if state.track_check.is_some() => {
+                state.track_check = None;
+
+                if let Ok((track, result)) = result {
+                    state.handle_track_check(track, result).await;
+                }
+            }
+            // related track for autoplay
+            result = async { state.related.as_mut().unwrap().await }, if state.related.is_some() => {
+                state.related = None;
+
+                if let Ok(result) = result {
+                    state.handle_related(result).await;
+                }
+            }
             // wait for autodisconnect
             _ = state.autodisconnect.should_disconnect(), if state.player.is_some() => {
-                state.disconnect().await;
+                state.leave_unheard().await;
+            }
+            // stop the queue if nothing is happening
+            _ = sleep_until(state.last_active + QUEUE_IDLE_TIME), if state.is_idle() => {
+                if state.try_stop().await {
+                    debug!(guild_id = %state.guild_id, "queue idle, stopping");
+                    break;
+                }
             }
         }
+
+        state.refresh_panel().await;
+        state.refresh_status().await;
+    }
+}
+
+/// Creates a query for tracks related to `track`.
+///
+/// YouTube videos have a mix of related videos. Anything else falls back to
+/// searching for the track's title and author.
+fn related_query(track: &Track) -> String {
+    match youtube_id(&track.url) {
+        Some(id) => format!("https://www.youtube.com/watch?v={id}&list=RD{id}"),
+        None => format!("ytsearch10:{} {}", track.author.name, track.title),
+    }
+}
+
+/// Gets the id of a YouTube video from its url.
+pub(crate) fn youtube_id(url: &str) -> Option<&str> {
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let url = url.strip_prefix("www.").unwrap_or(url);
+    let url = url.strip_prefix("m.").unwrap_or(url);
+
+    let id = if let Some(rest) = url.strip_prefix("youtu.be/") {
+        rest.split(['?', '&', '#']).next()
+    } else if let Some(query) = url.strip_prefix("youtube.com/watch?") {
+        query
+            .split('&')
+            .find_map(|param| param.strip_prefix("v="))
+            .map(|id| id.split('#').next().unwrap_or(id))
+    } else {
+        None
+    };
+
+    id.filter(|id| !id.is_empty())
+}
+
+/// Whether a track should be checked before it plays.
+///
+/// Tracks that were never found by this process, like saved or imported
+/// ones, aren't known to be stale, so they play without a check.
+fn is_stale(track: &Track) -> bool {
+    track.speech.is_none()
+        && track
+            .resolved_at
+            .is_some_and(|at| at.elapsed() > STALE_TRACK_AGE)
+}
+
+/// Creates a track that says `text`.
+fn speech_track(command: &CommandData, text: String) -> Track {
+    // embed titles can only be so long
+    let title = match text.char_indices().nth(100) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.clone(),
+    };
+
+    Track {
+        url: String::new(),
+        title,
+        author: Author {
+            name: command.tr("text-to-speech").to_owned(),
+            url: None,
+            avatar_url: None,
+        },
+        thumbnail_url: None,
+        requester: Some(command.user_id),
+        start: Duration::ZERO,
+        duration: None,
+        speech: Some(text),
+        resolved_at: None,
+        picked: false,
+        live: false,
+        age_limit: 0,
+        chapters: Vec::new(),
+        album: None,
+        format: None,
+    }
+}
+
+/// Formats a track as a markdown link, or just its title if it has no url.
+fn link(track: &Track) -> String {
+    if track.url.is_empty() {
+        track.title.clone()
+    } else {
+        format!("[{}]({})", track.title, track.url)
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` if it is over an hour.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, mins, secs)
+    } else {
+        format!("{}:{:02}", mins, secs)
     }
 }
 
+/// An error caused by the user, shown back to them.
 #[derive(Debug)]
 #[allow(clippy::enum_variant_names)]
-enum UserError {
+pub enum UserError {
+    /// The user is in a different channel than the bot.
     UserInDifferentChannel,
+    /// The user isn't in a voice channel.
     UserNotInChannel,
+    /// The bot isn't in a voice channel, but the user is in this one.
     BotNotInChannel(Id<ChannelMarker>),
+    /// The user doesn't have the guild's DJ role.
+    NotDj,
+    /// The user can't manage the guild.
+    NotManager,
+    /// The user doesn't run the bot.
+    NotOperator,
+    /// The user is using too many commands at once.
+    RateLimited,
+    /// The operator doesn't let the guild use the bot.
+    GuildDenied,
+    /// The operator turned a feature off for the guild.
+    FeatureDisabled(Feature),
+    /// yt-dlp or ffmpeg don't run, so nothing can be played.
+    AudioUnavailable,
+    /// Another user locked the queue.
+    Locked(Id<UserMarker>),
+    /// As many players are running as can, so the guild is waitlisted, at
+    /// this place in line.
+    AtCapacity(usize),
 }
 
-impl Display for UserError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl UserError {
+    /// The error as shown to the user, translated to `locale`.
+    pub fn localize(&self, locale: Option<&str>) -> String {
+        let tr = |text| i18n::tr(locale, text).to_owned();
+
         match self {
-            UserError::UserInDifferentChannel => f.write_str(
-                "you must be in the same voice channel as the bot to use \
-                    this!",
-            ),
-            UserError::UserNotInChannel => {
-                f.write_str("you must be in a voice channel to use this!")
-            }
-            UserError::BotNotInChannel(_) => {
-                f.write_str("the bot must be in a voice channel to use this!")
+            UserError::UserInDifferentChannel => {
+                tr("you must be in the same voice channel as the bot to use this!")
             }
+            UserError::UserNotInChannel => tr("you must be in a voice channel to use this!"),
+            UserError::BotNotInChannel(_) => tr("the bot must be in a voice channel to use this!"),
+            UserError::NotDj => tr("you must have the DJ role to use this!"),
+            UserError::NotManager => tr("you must be able to manage the server to use this!"),
+            UserError::NotOperator => tr("only the bot's operators can use this!"),
+            UserError::RateLimited => tr("you're using commands too quickly, slow down!"),
+            UserError::GuildDenied => tr("the bot isn't available in this server"),
+            UserError::FeatureDisabled(feature) => i18n::trf(
+                locale,
+                "`{feature}` is turned off in this server",
+                &[("feature", &feature.name())],
+            ),
+            UserError::AudioUnavailable => tr("audio backend unavailable"),
+            UserError::Locked(user_id) => i18n::trf(
+                locale,
+                "<@{user}> locked the queue for now",
+                &[("user", user_id)],
+            ),
+            UserError::AtCapacity(place) => i18n::trf(
+                locale,
+                "the bot is playing in as many servers as it can right now, so this server is \
+                    #{place} in line. I'll say so here when there's room!",
+                &[("place", place)],
+            ),
         }
     }
 }
 
+impl Display for UserError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(None))
+    }
+}
+
 impl std::error::Error for UserError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_util::future::BoxFuture;
+    use twilight_gateway::{Shard, ShardId};
+
+    fn test_queue() -> QueueState {
+        let shard = Shard::new(ShardId::ONE, String::new(), Intents::empty());
+        let queue_server = QueueServer::new(
+            shard.sender(),
+            Arc::new(InMemoryCache::new()),
+            Arc::new(HttpClient::new(String::new())),
+            Arc::new(Store::in_memory()),
+            Id::new(1),
+        );
+
+        let mut queue = QueueState::new(
+            Arc::new(queue_server),
+            Id::new(1),
+            mpsc::unbounded_channel().1,
+            mpsc::unbounded_channel().1,
+            mpsc::unbounded_channel().1,
+            mpsc::unbounded_channel().1,
+        );
+        // so new tracks are queued instead of played
+        queue.playing = Some(Track::default());
+        queue
+    }
+
+    fn test_track(url: &str, requester: u64) -> Track {
+        Track {
+            url: url.to_owned(),
+            requester: Some(Id::new(requester)),
+            ..Default::default()
+        }
+    }
+
+    /// A backend that can't play anything.
+    struct FailingBackend;
+
+    impl PlaybackBackend for FailingBackend {
+        fn play(&self, _track: &Track, _start: Duration) -> Result<(), crate::Error> {
+            Err(crate::Error::internal("can't play"))
+        }
+
+        fn interject(&self, _source: crate::voice::Source) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn announce(
+            &self,
+            _overlay: crate::voice::Overlay,
+            _gain: f32,
+        ) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn pause(&self) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn resume(&self) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn disconnect(&self) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn playing(&self) -> bool {
+            false
+        }
+
+        fn position(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn voice_state(
+            &self,
+        ) -> BoxFuture<'_, Result<RwLockReadGuard<'_, VoiceState>, crate::Error>> {
+            Box::pin(async { Err(crate::Error::internal("not connected")) })
+        }
+
+        fn voice_state_update(&self, _ev: Box<VoiceStateUpdate>) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn voice_server_update(&self, _ev: VoiceServerUpdate) -> Result<(), crate::Error> {
+            Ok(())
+        }
+    }
+
+    fn queued_urls(queue: &QueueState) -> Vec<&str> {
+        queue
+            .track_queue
+            .iter()
+            .map(|track| track.url.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn picks_play_next() {
+        let mut queue = test_queue();
+        queue.track_queue.extend([
+            test_track("a", 1),
+            test_track("b", 2),
+            test_track("c", 1),
+            test_track("d", 3),
+        ]);
+
+        assert_eq!(
+            queue.pick_next(Id::new(1), 4),
+            Err("you can only pick your own tracks")
+        );
+        assert_eq!(
+            queue.pick_next(Id::new(1), 5),
+            Err("there's no track at that position")
+        );
+        assert_eq!(queue.pick_next(Id::new(3), 4), Ok(0));
+        assert_eq!(queue.pick_next(Id::new(1), 4), Ok(1));
+        assert_eq!(
+            queue.pick_next(Id::new(1), 3),
+            Err("you already picked a track to play next")
+        );
+        assert_eq!(queued_urls(&queue), ["d", "c", "a", "b"]);
+
+        // picks still go first if a track was placed ahead of them
+        queue.track_queue.push_front(test_track("e", 2));
+        assert_eq!(queue.pop_next().unwrap().url, "d");
+        assert_eq!(queue.pop_next().unwrap().url, "c");
+        assert_eq!(queue.pop_next().unwrap().url, "e");
+    }
+
+    #[tokio::test]
+    async fn places_tracks_at_position() {
+        let mut queue = test_queue();
+        queue
+            .track_queue
+            .extend([test_track("a", 1), test_track("b", 1)]);
+
+        assert_eq!(
+            queue
+                .place_tracks_at([test_track("c", 1), test_track("d", 1)], 1)
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(queued_urls(&queue), ["a", "c", "d", "b"]);
+
+        // past the end of the queue, the tracks go at the end
+        assert_eq!(
+            queue.place_tracks_at([test_track("e", 1)], 10).unwrap(),
+            Some(4)
+        );
+        assert_eq!(queued_urls(&queue), ["a", "c", "d", "b", "e"]);
+    }
+
+    #[tokio::test]
+    async fn places_tracks_fairly() {
+        let mut queue = test_queue();
+
+        for (url, requester) in [("a1", 1), ("a2", 1), ("a3", 1), ("b1", 2), ("c1", 3)] {
+            queue.place_track_fair(test_track(url, requester));
+        }
+        assert_eq!(queued_urls(&queue), ["a1", "b1", "c1", "a2", "a3"]);
+
+        // a requester's next track goes in the first round they're missing from
+        assert_eq!(queue.place_track_fair(test_track("b2", 2)), 4);
+        assert_eq!(queue.place_track_fair(test_track("c2", 3)), 5);
+        assert_eq!(
+            queued_urls(&queue),
+            ["a1", "b1", "c1", "a2", "b2", "c2", "a3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn moving_tracks_ahead_clears_picks() {
+        let mut queue = test_queue();
+        queue
+            .track_queue
+            .extend([test_track("a", 1), test_track("b", 2)]);
+        queue.pick_next(Id::new(2), 2).unwrap();
+
+        // placing after the pick keeps it
+        queue.place_tracks_at([test_track("c", 3)], 1).unwrap();
+        assert!(queue.track_queue[0].picked);
+
+        queue.place_tracks_at([test_track("d", 3)], 0).unwrap();
+        assert!(queue.track_queue.iter().all(|track| !track.picked));
+        assert_eq!(queue.pop_next().unwrap().url, "d");
+    }
+
+    #[tokio::test]
+    async fn failing_player_drops_tracks() {
+        let mut queue = test_queue();
+        queue.playing = None;
+        queue.player = Some(PlayerState {
+            player: Box::new(FailingBackend),
+            event_rx: mpsc::unbounded_channel().1,
+            listeners: HashSet::new(),
+        });
+
+        assert!(queue
+            .place_tracks([test_track("a", 1), test_track("b", 1)])
+            .is_err());
+        assert!(queue.player.is_none());
+        assert!(queue.playing.is_none());
+        assert!(queue.track_queue.is_empty());
+
+        // without a player, nothing plays
+        assert!(queue.place_tracks([test_track("c", 1)]).is_err());
+        assert!(queue.track_queue.is_empty());
+    }
+
+    #[test]
+    fn youtube_ids() {
+        assert_eq!(
+            youtube_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            Some("dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            youtube_id("https://youtube.com/watch?list=PL1&v=dQw4w9WgXcQ&t=5"),
+            Some("dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            youtube_id("https://youtu.be/dQw4w9WgXcQ?t=5"),
+            Some("dQw4w9WgXcQ")
+        );
+        assert_eq!(youtube_id("https://soundcloud.com/a/b"), None);
+        assert_eq!(youtube_id("https://www.youtube.com/watch?v="), None);
+    }
+}
@@ -0,0 +1,220 @@
+//! Controls for whoever runs the bot.
+//!
+//! The operator decides which guilds can use the bot and turns features off
+//! for guilds with `/operator`, which is only registered in the developer's
+//! guild. Only the users given to [`QueueServer::with_operators`] can use it,
+//! or the other developer commands, no matter where they're registered.
+//! Everything is kept in the store, and checked by
+//! [`Layer::Access`](super::middleware::Layer::Access) before each command.
+//!
+//! Each process reads the store when it starts and keeps its own copy, so
+//! when guilds are split between processes with a
+//! [`GuildClaim`](super::GuildClaim), a change only applies to the process
+//! that handled the command, the one with the developer's guild, until the
+//! others restart.
+
+use tracing::error;
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
+
+use super::commands::{Action, CommandData, OperatorAction};
+use super::{QueueServer, QueueState, UserError};
+use crate::store::Feature;
+
+impl Action {
+    /// The feature the action belongs to, if the operator can turn it off.
+    pub fn feature(&self) -> Option<Feature> {
+        match self {
+            Action::Tts(..) => Some(Feature::Tts),
+            Action::Announce(_) => Some(Feature::Announce),
+            Action::Autoplay(_) => Some(Feature::Autoplay),
+            Action::Playlist(_) => Some(Feature::Playlists),
+            Action::Import(_) => Some(Feature::Import),
+            _ => None,
+        }
+    }
+}
+
+impl QueueServer {
+    /// Lets users use `/operator` and the other developer commands.
+    ///
+    /// Without this, nobody can.
+    pub fn with_operators(
+        self,
+        operators: impl IntoIterator<Item = Id<UserMarker>>,
+    ) -> QueueServer {
+        QueueServer {
+            operators: operators.into_iter().collect(),
+            ..self
+        }
+    }
+}
+
+impl QueueState {
+    /// Checks if the user of a command runs the bot.
+    pub(super) fn is_operator(&self, command: &CommandData) -> bool {
+        self.queue_server.operators.contains(&command.user_id)
+    }
+
+    /// Checks that the guild can use the bot, and the feature of the action.
+    pub(super) async fn check_access(&self, action: &Action) -> Result<(), UserError> {
+        let guild_id = self.guild_id;
+        let feature = action.feature();
+
+        self.queue_server
+            .store
+            .read(|store| {
+                if !store.guild_access.allows(guild_id) {
+                    return Err(UserError::GuildDenied);
+                }
+
+                match feature {
+                    Some(feature) if !store.feature_enabled(guild_id, feature) => {
+                        Err(UserError::FeatureDisabled(feature))
+                    }
+                    _ => Ok(()),
+                }
+            })
+            .await
+    }
+
+    pub(super) async fn operator(
+        &self,
+        command: &CommandData,
+        action: OperatorAction,
+    ) -> Result<(), UserError> {
+        if !self.is_operator(command) {
+            return Err(UserError::NotOperator);
+        }
+
+        let res = self
+            .queue_server
+            .store
+            .update(|store| match action {
+                OperatorAction::Allow(guild_id) => {
+                    store.guild_access.denied.remove(&guild_id);
+                    store.guild_access.allowed.insert(guild_id);
+                    Some(guild_id)
+                }
+                OperatorAction::Deny(guild_id) => {
+                    store.guild_access.allowed.remove(&guild_id);
+                    store.guild_access.denied.insert(guild_id);
+                    Some(guild_id)
+                }
+                OperatorAction::Reset(guild_id) => {
+                    store.guild_access.allowed.remove(&guild_id);
+                    store.guild_access.denied.remove(&guild_id);
+                    store.disabled_features.remove(&guild_id);
+                    Some(guild_id)
+                }
+                OperatorAction::Allowlist(allowlist) => {
+                    store.guild_access.allowlist = allowlist;
+                    None
+                }
+                OperatorAction::Feature(guild_id, feature, enabled) => {
+                    let disabled = store.disabled_features.entry(guild_id).or_default();
+
+                    if enabled {
+                        disabled.remove(&feature);
+                    } else {
+                        disabled.insert(feature);
+                    }
+
+                    if disabled.is_empty() {
+                        store.disabled_features.remove(&guild_id);
+                    }
+
+                    Some(guild_id)
+                }
+                OperatorAction::Show(guild_id) => Some(guild_id),
+            })
+            .await;
+
+        let guild_id = match res {
+            Ok(guild_id) => guild_id,
+            Err(err) => {
+                error!(%err, "failed to save guild access");
+
+                command
+                    .respond(&self.queue_server.http_client)
+                    .error(command.tr("failed to save the change"))
+                    .ephemeral()
+                    .respond()
+                    .await;
+
+                return Ok(());
+            }
+        };
+
+        let msg = self.access_summary(command, guild_id).await;
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .ephemeral()
+            .respond()
+            .await;
+
+        Ok(())
+    }
+
+    /// Describes the access settings, and those of a guild.
+    async fn access_summary(
+        &self,
+        command: &CommandData,
+        guild_id: Option<Id<GuildMarker>>,
+    ) -> String {
+        let (allowlist, guild) = self
+            .queue_server
+            .store
+            .read(|store| {
+                let guild = guild_id.map(|guild_id| {
+                    let disabled = Feature::ALL
+                        .into_iter()
+                        .filter(|feature| !store.feature_enabled(guild_id, *feature))
+                        .map(|feature| feature.name())
+                        .collect::<Vec<_>>();
+
+                    (guild_id, store.guild_access.allows(guild_id), disabled)
+                });
+
+                (store.guild_access.allowlist, guild)
+            })
+            .await;
+
+        let enabled = |enabled| {
+            if enabled {
+                command.tr("enabled")
+            } else {
+                command.tr("disabled")
+            }
+        };
+
+        let mut msg = command.trf(
+            "allowlist: {allowlist}",
+            &[("allowlist", &enabled(allowlist))],
+        );
+
+        if let Some((guild_id, allowed, disabled)) = guild {
+            let disabled = if disabled.is_empty() {
+                command.tr("none").to_owned()
+            } else {
+                disabled.join(", ")
+            };
+
+            msg.push('\n');
+            msg.push_str(&command.trf(
+                "server {guild}: {access}\nturned off: {features}",
+                &[
+                    ("guild", &guild_id),
+                    ("access", &enabled(allowed)),
+                    ("features", &disabled),
+                ],
+            ));
+        }
+
+        msg
+    }
+}
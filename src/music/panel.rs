@@ -0,0 +1,355 @@
+//! The player control panel.
+//!
+//! `/player` posts a message showing the playing track with buttons to
+//! control it. The queue keeps the message up to date, and the buttons are
+//! routed to the same [`Action`]s as the slash commands, by their custom ids.
+//!
+//! A panel can also show how far into the playing track it is. The progress
+//! is only updated every [`PROGRESS_INTERVAL`], and not while paused, to keep
+//! clear of Discord's rate limits on editing messages.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, error};
+use twilight_model::channel::message::component::{ActionRow, Button, ButtonStyle, Component};
+use twilight_model::channel::message::embed::{EmbedField, EmbedFooter};
+use twilight_model::channel::message::Embed;
+use twilight_model::id::{
+    marker::{ChannelMarker, MessageMarker},
+    Id,
+};
+
+use super::commands::{Action, CommandData, LoopMode};
+use super::respond::is_unknown_message;
+use super::{format_duration, QueueState, UserError};
+use crate::i18n;
+
+/// The prefix of the custom ids of the panel's buttons.
+const CUSTOM_ID_PREFIX: &str = "player:";
+
+/// How often the progress on a panel is updated.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How many segments the progress bar has.
+const PROGRESS_BAR_LEN: usize = 12;
+
+/// Gets the action of a button on the panel from its custom id.
+///
+/// Returns `None` if the button isn't from the panel.
+pub fn action(custom_id: &str) -> Option<Action> {
+    match custom_id.strip_prefix(CUSTOM_ID_PREFIX)? {
+        "pause" => Some(Action::Pause),
+        "skip" => Some(Action::Skip),
+        "stop" => Some(Action::Stop),
+        "shuffle" => Some(Action::Shuffle(None)),
+        "loop" => Some(Action::Loop(None)),
+        _ => None,
+    }
+}
+
+/// A posted control panel.
+#[derive(Debug)]
+pub struct Panel {
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    /// Whether the panel shows the progress of the playing track.
+    progress: bool,
+    /// What the panel shows right now.
+    shown: PanelState,
+    /// When the panel was last posted or edited, or failed to be.
+    updated_at: Instant,
+    /// Whether the last edit failed, so the next one waits for
+    /// [`PROGRESS_INTERVAL`].
+    failing: bool,
+}
+
+/// What a panel shows, to know when it has to be updated.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PanelState {
+    playing: Option<String>,
+    paused: bool,
+    loop_mode: LoopMode,
+    queued: usize,
+    /// How far into the playing track it is, for panels that show it.
+    progress: Option<Duration>,
+}
+
+impl QueueState {
+    /// Posts a new control panel in the command's channel, replacing the old
+    /// one.
+    pub(super) async fn player_panel(
+        &mut self,
+        command: &CommandData,
+        progress: bool,
+    ) -> Result<(), UserError> {
+        let http = &self.queue_server.http_client;
+
+        if let Some(panel) = self.panel.take() {
+            let _ = http
+                .delete_message(panel.channel_id, panel.message_id)
+                .await;
+        }
+
+        let state = self.panel_state(progress);
+        let (embed, components) = self.panel_message(&state);
+
+        let res = http
+            .create_message(command.channel_id)
+            .embeds(&[embed])
+            .unwrap()
+            .components(&components)
+            .unwrap()
+            .await;
+
+        let message = match res {
+            Ok(res) => res.model().await.ok(),
+            Err(err) => {
+                error!(%err, "failed to post panel");
+                None
+            }
+        };
+
+        let mut respond = command.respond(http);
+
+        match message {
+            Some(message) => {
+                self.panel = Some(Panel {
+                    channel_id: message.channel_id,
+                    message_id: message.id,
+                    progress,
+                    shown: state,
+                    updated_at: Instant::now(),
+                    failing: false,
+                });
+
+                respond
+                    .content(command.tr("posted the player controls"))
+                    .ephemeral()
+            }
+            None => respond.error(command.tr("failed to post the player controls")),
+        };
+
+        respond.respond().await;
+
+        Ok(())
+    }
+
+    /// When the panel should be updated next, if it shows progress and the
+    /// track is moving, or the last edit failed.
+    pub(super) fn next_panel_progress(&self) -> Option<Instant> {
+        let panel = self.panel.as_ref()?;
+        let moving = panel.progress && self.playing.is_some() && !self.paused;
+
+        (moving || panel.failing).then_some(panel.updated_at + PROGRESS_INTERVAL)
+    }
+
+    /// Updates the control panel if what it shows has changed.
+    ///
+    /// Changes to only the progress wait for [`PROGRESS_INTERVAL`], so they
+    /// are batched with anything else that changes in the meantime. A panel
+    /// that was deleted is forgotten, and if editing it fails otherwise, the
+    /// next edit waits for [`PROGRESS_INTERVAL`] too.
+    pub(super) async fn refresh_panel(&mut self) {
+        let Some(panel) = self.panel.as_ref() else {
+            return;
+        };
+
+        let state = self.panel_state(panel.progress);
+
+        if panel.shown == state {
+            // nothing is left to retry
+            if let Some(panel) = self.panel.as_mut() {
+                panel.failing = false;
+            }
+
+            return;
+        }
+
+        let progress_only = PanelState {
+            progress: panel.shown.progress,
+            ..state.clone()
+        } == panel.shown;
+
+        let wait = progress_only || panel.failing;
+        if wait && panel.updated_at.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+
+        let (embed, components) = self.panel_message(&state);
+
+        let res = self
+            .queue_server
+            .http_client
+            .update_message(panel.channel_id, panel.message_id)
+            .embeds(Some(&[embed]))
+            .unwrap()
+            .components(Some(&components))
+            .unwrap()
+            .await;
+
+        match res {
+            Ok(_) => {
+                if let Some(panel) = self.panel.as_mut() {
+                    panel.shown = state;
+                    panel.updated_at = Instant::now();
+                    panel.failing = false;
+                }
+            }
+            Err(err) if is_unknown_message(&err) => {
+                debug!(%err, "panel was deleted, forgetting it");
+                self.panel = None;
+            }
+            Err(err) => {
+                error!(%err, "failed to update panel");
+
+                if let Some(panel) = self.panel.as_mut() {
+                    panel.updated_at = Instant::now();
+                    panel.failing = true;
+                }
+            }
+        }
+    }
+
+    fn panel_state(&self, progress: bool) -> PanelState {
+        let progress = self
+            .player
+            .as_ref()
+            .filter(|_| progress && self.playing.is_some())
+            .map(|state| state.player.position());
+
+        PanelState {
+            playing: self.playing.as_ref().map(|track| track.url.clone()),
+            paused: self.paused,
+            loop_mode: self.loop_mode,
+            queued: self.track_queue.len(),
+            progress,
+        }
+    }
+
+    /// Renders the panel.
+    fn panel_message(&self, state: &PanelState) -> (Embed, Vec<Component>) {
+        let locale = self.guild_locale();
+        let tr = |text| i18n::tr(locale.as_deref(), text);
+
+        let footer = EmbedFooter {
+            icon_url: None,
+            proxy_icon_url: None,
+            text: i18n::trf(
+                locale.as_deref(),
+                "loop: {mode} · {count} queued",
+                &[("mode", &state.loop_mode.name()), ("count", &state.queued)],
+            ),
+        };
+
+        let embed = match self.playing.as_ref() {
+            Some(track) => Embed {
+                description: Some(if state.paused {
+                    tr("paused").to_owned()
+                } else {
+                    tr("now playing").to_owned()
+                }),
+                fields: state
+                    .progress
+                    .map(|position| EmbedField {
+                        inline: false,
+                        name: tr("progress").to_owned(),
+                        value: progress_bar(position, track.duration),
+                    })
+                    .into_iter()
+                    .collect(),
+                footer: Some(footer),
+                ..track.as_embed(self.large_thumbnails)
+            },
+            None => Embed {
+                description: Some(tr("nothing currently playing").to_owned()),
+                footer: Some(footer),
+                ..crate::embed()
+            },
+        };
+
+        let idle = state.playing.is_none();
+        let buttons = [
+            (
+                "pause",
+                if state.paused { "resume" } else { "pause" },
+                ButtonStyle::Primary,
+                idle,
+            ),
+            ("skip", "skip", ButtonStyle::Secondary, idle),
+            ("stop", "stop", ButtonStyle::Danger, idle),
+            (
+                "shuffle",
+                "shuffle",
+                ButtonStyle::Secondary,
+                state.queued == 0,
+            ),
+            ("loop", "loop", ButtonStyle::Secondary, false),
+        ];
+
+        let row = Component::ActionRow(ActionRow {
+            components: buttons
+                .into_iter()
+                .map(|(id, label, style, disabled)| {
+                    Component::Button(Button {
+                        custom_id: Some(format!("{}{}", CUSTOM_ID_PREFIX, id)),
+                        disabled,
+                        emoji: None,
+                        label: Some(tr(label).to_owned()),
+                        style,
+                        url: None,
+                    })
+                })
+                .collect(),
+        });
+
+        (embed, vec![row])
+    }
+}
+
+/// Renders how far into a track it is, like `1:23 ▬▬▬🔘▬▬▬▬▬▬▬▬ 4:56`.
+///
+/// Tracks without a duration only show the time.
+fn progress_bar(position: Duration, duration: Option<Duration>) -> String {
+    let Some(duration) = duration.filter(|duration| !duration.is_zero()) else {
+        return format_duration(position);
+    };
+
+    let done = position.as_secs_f64() / duration.as_secs_f64();
+    let knob = ((done * PROGRESS_BAR_LEN as f64) as usize).min(PROGRESS_BAR_LEN - 1);
+
+    let bar = (0..PROGRESS_BAR_LEN)
+        .map(|i| if i == knob { "🔘" } else { "▬" })
+        .collect::<String>();
+
+    format!(
+        "{} {} {}",
+        format_duration(position),
+        bar,
+        format_duration(duration)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_buttons() {
+        assert!(matches!(action("player:pause"), Some(Action::Pause)));
+        assert!(matches!(action("player:loop"), Some(Action::Loop(None))));
+        assert!(action("player:nope").is_none());
+        assert!(action("pause").is_none());
+    }
+
+    #[test]
+    fn renders_progress() {
+        let bar = progress_bar(Duration::from_secs(30), Some(Duration::from_secs(120)));
+        assert_eq!(bar, "0:30 ▬▬▬🔘▬▬▬▬▬▬▬▬ 2:00");
+
+        let bar = progress_bar(Duration::from_secs(200), Some(Duration::from_secs(120)));
+        assert!(bar.contains("▬🔘 2:00"));
+
+        assert_eq!(progress_bar(Duration::from_secs(61), None), "1:01");
+    }
+}
@@ -0,0 +1,230 @@
+//! What tracks a guild lets be enqueued.
+//!
+//! Every track is checked against the guild's [`Blocklist`], [`TrackLimits`]
+//! and [`AgePolicy`] before it is enqueued, whether it comes from a query, a
+//! saved playlist or autoplay, with [`QueueState::refuse_tracks`]. Users
+//! with the DJ role get past the limits, but nothing else.
+
+use std::time::Duration;
+
+use super::commands::CommandData;
+use super::{format_duration, quarantine, QueueState};
+use crate::store::{AgePolicy, BlockKind, Blocklist, TrackLimits};
+use crate::ytdl::Track;
+
+/// The rules a user's tracks are checked against.
+#[derive(Debug, Default)]
+pub(super) struct Policy {
+    blocklist: Blocklist,
+    /// The limits of the guild, or `None` if the user gets past them.
+    limits: Option<TrackLimits>,
+    age_policy: AgePolicy,
+    /// Whether the voice channel the tracks would play in is NSFW.
+    nsfw: bool,
+}
+
+/// Why a track can't be enqueued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Refusal {
+    /// The track is on the blocklist.
+    Blocked(BlockKind),
+    /// The track is longer than the limit.
+    TooLong(Duration),
+    /// The track is a livestream, and the guild doesn't allow them.
+    Livestream,
+    /// The track is age-restricted, and can't play in the channel.
+    AgeRestricted(AgePolicy),
+    /// The track keeps failing to play, so it's quarantined for this long.
+    Quarantined(Duration),
+}
+
+impl Policy {
+    /// Checks if a track can be enqueued.
+    pub fn check(&self, track: &Track) -> Option<Refusal> {
+        if let Some(kind) = self.blocklist.blocks(track) {
+            return Some(Refusal::Blocked(kind));
+        }
+
+        let age_allowed = match self.age_policy {
+            AgePolicy::Deny => false,
+            AgePolicy::Nsfw => self.nsfw,
+            AgePolicy::Allow => true,
+        };
+
+        if track.age_limit > 0 && !age_allowed {
+            return Some(Refusal::AgeRestricted(self.age_policy));
+        }
+
+        let limits = self.limits.as_ref()?;
+
+        if limits.deny_livestreams && track.live {
+            return Some(Refusal::Livestream);
+        }
+
+        match (track.duration, limits.max_duration()) {
+            (Some(duration), Some(max)) if duration > max => Some(Refusal::TooLong(max)),
+            _ => None,
+        }
+    }
+}
+
+impl Refusal {
+    /// Tells the requester why their track wasn't enqueued.
+    pub fn message(&self, command: &CommandData) -> String {
+        match self {
+            Refusal::Blocked(BlockKind::Url) => command
+                .tr("this track is blocked in this server")
+                .to_owned(),
+            Refusal::Blocked(BlockKind::Channel) => command
+                .tr("this track's channel is blocked in this server")
+                .to_owned(),
+            Refusal::Blocked(BlockKind::Keyword) => command
+                .tr("this track's title has a word blocked in this server")
+                .to_owned(),
+            Refusal::TooLong(max) => command.trf(
+                "this track is longer than this server's limit of {max}",
+                &[("max", &format_duration(*max))],
+            ),
+            Refusal::Livestream => command
+                .tr("livestreams can't be enqueued in this server")
+                .to_owned(),
+            Refusal::AgeRestricted(AgePolicy::Nsfw) => command
+                .tr("this track is age-restricted, so it can only play in NSFW voice channels")
+                .to_owned(),
+            Refusal::AgeRestricted(_) => command
+                .tr("this track is age-restricted, and those can't be enqueued in this server")
+                .to_owned(),
+            Refusal::Quarantined(remaining) => quarantine::quarantine_message(command, *remaining),
+        }
+    }
+}
+
+impl QueueState {
+    /// Takes the tracks that can't be enqueued out of `tracks`, returning
+    /// their titles and why.
+    ///
+    /// Tracks are refused if they're quarantined or the guild's policy
+    /// doesn't allow them. Tracks that weren't enqueued by a user, like
+    /// autoplayed ones, are checked without a `command`, and don't get past
+    /// the limits.
+    pub(super) async fn refuse_tracks(
+        &self,
+        command: Option<&CommandData>,
+        tracks: &mut Vec<Track>,
+    ) -> Vec<(String, Refusal)> {
+        let policy = self.policy(command).await;
+        let mut refused = Vec::new();
+
+        tracks.retain(|track| {
+            let refusal = match self.quarantined(&track.url) {
+                Some(remaining) => Refusal::Quarantined(remaining),
+                None => match policy.check(track) {
+                    Some(refusal) => refusal,
+                    None => return true,
+                },
+            };
+
+            refused.push((track.title.clone(), refusal));
+            false
+        });
+
+        refused
+    }
+
+    /// Gets the rules the tracks of a user, or of nobody, are checked
+    /// against.
+    async fn policy(&self, command: Option<&CommandData>) -> Policy {
+        let dj = match command {
+            Some(command) => self.is_dj(command).await,
+            None => false,
+        };
+
+        // the track plays where the bot is, which is where the user is if the
+        // bot hasn't joined yet
+        let channel_id = match self.voice_state().await {
+            Some(voice_state) => voice_state.channel_id,
+            None => command.and_then(|command| {
+                self.queue_server
+                    .cache
+                    .voice_state(command.user_id, self.guild_id)
+                    .map(|voice_state| voice_state.channel_id())
+            }),
+        };
+        let nsfw = channel_id
+            .and_then(|channel_id| self.queue_server.cache.channel(channel_id))
+            .and_then(|channel| channel.nsfw)
+            .unwrap_or_default();
+
+        self.queue_server
+            .store
+            .read(|store| Policy {
+                blocklist: store
+                    .blocklists
+                    .get(&self.guild_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                limits: store
+                    .track_limits
+                    .get(&self.guild_id)
+                    .filter(|_| !dj)
+                    .cloned(),
+                age_policy: store
+                    .age_policies
+                    .get(&self.guild_id)
+                    .copied()
+                    .unwrap_or_default(),
+                nsfw,
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_tracks() {
+        let policy = Policy {
+            blocklist: Blocklist::default(),
+            limits: Some(TrackLimits {
+                max_duration: Some(600),
+                deny_livestreams: true,
+            }),
+            age_policy: AgePolicy::Nsfw,
+            nsfw: false,
+        };
+
+        let mut track = Track {
+            url: String::from("https://www.youtube.com/watch?v=a"),
+            title: String::from("a song"),
+            duration: Some(Duration::from_secs(300)),
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&track), None);
+
+        track.duration = Some(Duration::from_secs(601));
+        assert_eq!(
+            policy.check(&track),
+            Some(Refusal::TooLong(Duration::from_secs(600)))
+        );
+
+        track.duration = None;
+        track.live = true;
+        assert_eq!(policy.check(&track), Some(Refusal::Livestream));
+
+        track.live = false;
+        track.age_limit = 18;
+        assert_eq!(
+            policy.check(&track),
+            Some(Refusal::AgeRestricted(AgePolicy::Nsfw))
+        );
+
+        let exempt = Policy {
+            limits: None,
+            nsfw: true,
+            ..policy
+        };
+        assert_eq!(exempt.check(&track), None);
+    }
+}
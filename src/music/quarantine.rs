@@ -0,0 +1,283 @@
+//! Tracks that keep failing to play.
+//!
+//! Every time a track fails to stream, its url is counted. Once it fails
+//! [`QuarantineConfig::threshold`] times in a row, it's put in the guild's
+//! [`Quarantine`] for [`QuarantineConfig::cooldown`], and enqueueing it is
+//! refused until then, so one broken video can't keep interrupting playback.
+//! Moderators see and empty the quarantine with `/quarantine`.
+//!
+//! Quarantines are kept by the [`QueueServer`] in memory, so they outlast the
+//! queue task but not the bot.
+
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+use twilight_model::guild::Permissions;
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use super::blocklist::url_key;
+use super::commands::{CommandData, QuarantineAction};
+use super::{format_duration, QueueServer, QueueState, UserError};
+use crate::ytdl::Track;
+
+/// How many failures in a row quarantine a track, by default.
+pub const DEFAULT_THRESHOLD: u32 = 3;
+
+/// How long a track stays quarantined, by default.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// How long a track has to play before its failures are forgotten.
+pub const PLAYED_THROUGH: Duration = Duration::from_secs(10);
+
+/// When tracks are quarantined.
+#[derive(Clone, Debug)]
+pub struct QuarantineConfig {
+    /// How many failures in a row quarantine a track.
+    pub threshold: u32,
+    /// How long a track stays quarantined.
+    pub cooldown: Duration,
+}
+
+impl QuarantineConfig {
+    /// Reads the config from the environment.
+    ///
+    /// `QUARANTINE_THRESHOLD` is a count, and `QUARANTINE_COOLDOWN` is in
+    /// seconds.
+    pub fn from_env() -> QuarantineConfig {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+
+        QuarantineConfig {
+            threshold: var("QUARANTINE_THRESHOLD")
+                .map(|threshold| threshold.clamp(1, u32::MAX as u64) as u32)
+                .unwrap_or(DEFAULT_THRESHOLD),
+            cooldown: var("QUARANTINE_COOLDOWN")
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_COOLDOWN),
+        }
+    }
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> QuarantineConfig {
+        QuarantineConfig {
+            threshold: DEFAULT_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+}
+
+/// The tracks of a guild that keep failing.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    /// How many times in a row each url failed, by its key.
+    failures: HashMap<String, u32>,
+    /// The quarantined tracks, by the key of their url.
+    entries: HashMap<String, Entry>,
+}
+
+/// A quarantined track.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The url of the track.
+    pub url: String,
+    /// The title of the track.
+    pub title: String,
+    /// When the track is let out.
+    pub until: Instant,
+}
+
+impl Quarantine {
+    /// Counts a failure of a track, quarantining it if it failed too many
+    /// times in a row.
+    ///
+    /// Returns `true` if the track was just quarantined.
+    pub fn record_failure(&mut self, track: &Track, config: &QuarantineConfig) -> bool {
+        let key = url_key(&track.url).to_owned();
+        let failures = self.failures.entry(key.clone()).or_default();
+        *failures += 1;
+
+        if *failures < config.threshold {
+            return false;
+        }
+
+        self.failures.remove(&key);
+        self.entries.insert(
+            key,
+            Entry {
+                url: track.url.clone(),
+                title: track.title.clone(),
+                until: Instant::now() + config.cooldown,
+            },
+        );
+
+        true
+    }
+
+    /// Forgets the failures of a track that played.
+    pub fn record_success(&mut self, url: &str) {
+        self.failures.remove(url_key(url));
+    }
+
+    /// How long a track is still quarantined for, if it is.
+    pub fn remaining(&mut self, url: &str) -> Option<Duration> {
+        self.prune();
+
+        self.entries
+            .get(url_key(url))
+            .map(|entry| entry.until.saturating_duration_since(Instant::now()))
+    }
+
+    /// The quarantined tracks, those let out soonest first.
+    pub fn entries(&mut self) -> Vec<Entry> {
+        self.prune();
+
+        let mut entries = self.entries.values().cloned().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.until);
+        entries
+    }
+
+    /// Lets every track out, returning how many there were.
+    pub fn clear(&mut self) -> usize {
+        self.prune();
+        self.failures.clear();
+        self.entries.drain().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.failures.is_empty() && self.entries.is_empty()
+    }
+
+    fn prune(&mut self) {
+        let now = Instant::now();
+        self.entries.retain(|_, entry| entry.until > now);
+    }
+}
+
+impl QueueServer {
+    /// Runs `f` with the quarantine of a guild.
+    pub fn quarantine<F, T>(&self, guild_id: Id<GuildMarker>, f: F) -> T
+    where
+        F: FnOnce(&mut Quarantine) -> T,
+    {
+        let mut quarantines = self.quarantines.lock().unwrap();
+        let quarantine = quarantines.entry(guild_id).or_default();
+        let res = f(quarantine);
+
+        if quarantine.is_empty() {
+            quarantines.remove(&guild_id);
+        }
+
+        res
+    }
+}
+
+impl QueueState {
+    /// How long a track is still quarantined for in the guild, if it is.
+    pub(super) fn quarantined(&self, url: &str) -> Option<Duration> {
+        self.queue_server
+            .quarantine(self.guild_id, |quarantine| quarantine.remaining(url))
+    }
+
+    pub(super) async fn command_quarantine(
+        &self,
+        command: &CommandData,
+        action: QuarantineAction,
+    ) -> Result<(), UserError> {
+        if !command.permissions.contains(Permissions::MANAGE_GUILD) {
+            return Err(UserError::NotManager);
+        }
+
+        let msg = match action {
+            QuarantineAction::List => {
+                let entries = self
+                    .queue_server
+                    .quarantine(self.guild_id, |quarantine| quarantine.entries());
+
+                if entries.is_empty() {
+                    command
+                        .tr("nothing is quarantined in this server")
+                        .to_owned()
+                } else {
+                    let now = Instant::now();
+
+                    entries
+                        .iter()
+                        .map(|entry| {
+                            command.trf(
+                                "- [{title}]({url}) for another {time}",
+                                &[
+                                    ("title", &entry.title),
+                                    ("url", &entry.url),
+                                    (
+                                        "time",
+                                        &format_duration(
+                                            entry.until.saturating_duration_since(now),
+                                        ),
+                                    ),
+                                ],
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }
+            }
+            QuarantineAction::Clear => {
+                let count = self
+                    .queue_server
+                    .quarantine(self.guild_id, |quarantine| quarantine.clear());
+
+                command.trf("let {count} tracks out of quarantine", &[("count", &count)])
+            }
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .content(msg)
+            .ephemeral()
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Tells the requester that their track is quarantined.
+pub(super) fn quarantine_message(command: &CommandData, remaining: Duration) -> String {
+    command.trf(
+        "this track keeps failing to play, so it can't be enqueued for another {time}",
+        &[("time", &format_duration(remaining))],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantines_after_threshold() {
+        let config = QuarantineConfig {
+            threshold: 2,
+            cooldown: Duration::from_secs(60),
+        };
+        let track = Track {
+            url: String::from("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            title: String::from("a song"),
+            ..Default::default()
+        };
+
+        let mut quarantine = Quarantine::default();
+        assert!(!quarantine.record_failure(&track, &config));
+        quarantine.record_success(&track.url);
+        assert!(!quarantine.record_failure(&track, &config));
+        assert!(quarantine.record_failure(&track, &config));
+
+        // the same video with a timestamp is quarantined too
+        assert!(quarantine
+            .remaining("https://youtu.be/dQw4w9WgXcQ?t=5")
+            .is_some());
+
+        assert_eq!(quarantine.clear(), 1);
+        assert!(quarantine.remaining(&track.url).is_none());
+    }
+}
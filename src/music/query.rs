@@ -1,17 +1,250 @@
 //! Offloads query work to other tasks.
 //!
 //! `youtube-dl` takes a notoriously long time to query youtube for track info.
+//!
+//! Every query is its own `youtube-dl` process, so queries wait for one of a
+//! fixed number of [`QuerySlots`] before they start. Guilds take turns for
+//! free slots, so one guild enqueueing lots of queries doesn't hold up the
+//! rest.
 
+use futures_util::stream::{self, StreamExt};
+use serde_json::{json, Value};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
 
 use twilight_http::Client as HttpClient;
+use twilight_model::id::{marker::GuildMarker, Id};
 
+use std::collections::VecDeque;
+use std::env;
 use std::future::Future;
-use std::sync::Arc;
+use std::iter::once;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use tracing::instrument;
+use tracing::{debug, instrument, warn};
 
 use super::commands::CommandData;
+use super::summary::{Failure, PlaylistSummary, ProgressReporter};
+use crate::ytdl::{Author, Playlist, Query as YtdlQuery, YtdlConfig};
+
+/// The most queries that can be enqueued at once with [`query_batch`].
+pub const MAX_BATCH_QUERIES: usize = 25;
+
+/// How many queries of a batch wait for one of the [`query_slots`] at once.
+///
+/// Each query still needs its own slot, so a batch never runs more queries
+/// than the slots let it.
+pub const BATCH_CONCURRENCY: usize = 4;
+
+/// How many queries run at once, if not set with `QUERY_CONCURRENCY`.
+pub const DEFAULT_QUERY_CONCURRENCY: usize = 4;
+
+/// How long a query can wait for a slot before it is logged as slow.
+const SLOW_QUERY_WAIT: Duration = Duration::from_secs(5);
+
+static QUERY_SLOTS: OnceLock<QuerySlots> = OnceLock::new();
+
+/// The slots every query of the bot waits for.
+///
+/// If the slots were never initialized, [`DEFAULT_QUERY_CONCURRENCY`] queries
+/// run at once.
+pub fn query_slots() -> &'static QuerySlots {
+    QUERY_SLOTS.get_or_init(|| QuerySlots::new(DEFAULT_QUERY_CONCURRENCY))
+}
+
+pub fn init_query_slots<F>(f: F) -> &'static QuerySlots
+where
+    F: FnOnce() -> QuerySlots,
+{
+    QUERY_SLOTS.get_or_init(f)
+}
+
+/// Limits how many queries run at once.
+///
+/// Waiting queries are grouped by guild, and each freed slot goes to the next
+/// guild in turn.
+#[derive(Debug)]
+pub struct QuerySlots {
+    slots: Arc<Mutex<Slots>>,
+}
+
+#[derive(Debug)]
+struct Slots {
+    max: usize,
+    running: usize,
+    /// The waiting queries of each guild, in the order the guilds get a turn.
+    /// Guilds without waiting queries are removed.
+    waiting: VecDeque<(Id<GuildMarker>, VecDeque<oneshot::Sender<QueryPermit>>)>,
+    stats: QueryStats,
+}
+
+/// How long queries have waited for a slot.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueryStats {
+    /// How many queries got a slot.
+    pub acquired: u64,
+    /// How long queries waited in total.
+    pub total_wait: Duration,
+    /// The longest a query waited.
+    pub max_wait: Duration,
+    /// How many queries are running right now.
+    pub running: usize,
+    /// How many queries are waiting right now.
+    pub waiting: usize,
+}
+
+impl QueryStats {
+    /// The stats, as JSON.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "acquired": self.acquired,
+            "running": self.running,
+            "waiting": self.waiting,
+            "mean_wait_ms": self.average_wait().as_secs_f64() * 1000.,
+            "max_wait_ms": self.max_wait.as_secs_f64() * 1000.,
+        })
+    }
+
+    /// How long queries waited on average.
+    pub fn average_wait(&self) -> Duration {
+        self.total_wait
+            .checked_div(self.acquired as u32)
+            .unwrap_or_default()
+    }
+}
+
+impl QuerySlots {
+    /// Creates slots for `max` queries at once, or at least one.
+    pub fn new(max: usize) -> QuerySlots {
+        QuerySlots {
+            slots: Arc::new(Mutex::new(Slots {
+                max: max.max(1),
+                running: 0,
+                waiting: VecDeque::new(),
+                stats: QueryStats::default(),
+            })),
+        }
+    }
+
+    /// Creates slots for `QUERY_CONCURRENCY` queries at once, or
+    /// [`DEFAULT_QUERY_CONCURRENCY`] if it isn't set.
+    pub fn from_env() -> QuerySlots {
+        QuerySlots::new(
+            env::var("QUERY_CONCURRENCY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_QUERY_CONCURRENCY),
+        )
+    }
+
+    /// Waits for a slot for a query of a guild.
+    ///
+    /// The slot is freed when the permit is dropped.
+    pub async fn acquire(&self, guild_id: Id<GuildMarker>) -> QueryPermit {
+        let started = Instant::now();
+
+        let rx = {
+            let mut slots = self.slots.lock().unwrap();
+
+            if slots.running < slots.max && slots.waiting.is_empty() {
+                slots.running += 1;
+                slots.record_wait(Duration::ZERO);
+
+                return QueryPermit {
+                    slots: Some(self.slots.clone()),
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+
+            match slots.waiting.iter_mut().find(|(id, _)| *id == guild_id) {
+                Some((_, waiters)) => waiters.push_back(tx),
+                None => slots.waiting.push_back((guild_id, VecDeque::from([tx]))),
+            }
+
+            rx
+        };
+
+        // every slot is taken, so the permits keep the slots alive
+        let permit = rx.await.expect("slots dropped with waiting queries");
+        let waited = started.elapsed();
+
+        self.slots.lock().unwrap().record_wait(waited);
+
+        if waited > SLOW_QUERY_WAIT {
+            warn!(%guild_id, ?waited, "query waited long for a slot");
+        } else {
+            debug!(%guild_id, ?waited, "query got a slot");
+        }
+
+        permit
+    }
+
+    /// How long queries have waited for a slot so far.
+    pub fn stats(&self) -> QueryStats {
+        let slots = self.slots.lock().unwrap();
+
+        QueryStats {
+            running: slots.running,
+            waiting: slots.waiting.iter().map(|(_, waiters)| waiters.len()).sum(),
+            ..slots.stats
+        }
+    }
+}
+
+impl Slots {
+    fn record_wait(&mut self, waited: Duration) {
+        self.stats.acquired += 1;
+        self.stats.total_wait += waited;
+        self.stats.max_wait = self.stats.max_wait.max(waited);
+    }
+
+    /// Takes the next waiting query, moving its guild to the back.
+    fn next_waiter(&mut self) -> Option<oneshot::Sender<QueryPermit>> {
+        let (guild_id, mut waiters) = self.waiting.pop_front()?;
+        let tx = waiters.pop_front();
+
+        if !waiters.is_empty() {
+            self.waiting.push_back((guild_id, waiters));
+        }
+
+        tx
+    }
+}
+
+/// A slot for a query, freed when dropped.
+#[derive(Debug)]
+pub struct QueryPermit {
+    slots: Option<Arc<Mutex<Slots>>>,
+}
+
+impl Drop for QueryPermit {
+    fn drop(&mut self) {
+        let Some(arc) = self.slots.take() else {
+            return;
+        };
+
+        let mut slots = arc.lock().unwrap();
+
+        // hand the slot straight to the next query
+        while let Some(tx) = slots.next_waiter() {
+            let permit = QueryPermit {
+                slots: Some(arc.clone()),
+            };
+
+            match tx.send(permit) {
+                Ok(()) => return,
+                // the query gave up waiting; forget the permit without
+                // freeing the slot again
+                Err(mut permit) => drop(permit.slots.take()),
+            }
+        }
+
+        slots.running -= 1;
+    }
+}
 
 /// A query queue.
 pub struct QueryQueue<T> {
@@ -19,6 +252,7 @@ pub struct QueryQueue<T> {
 
     query_tx: UnboundedSender<QueryResult<T>>,
     query_rx: UnboundedReceiver<QueryResult<T>>,
+    pending: usize,
 }
 
 impl<T> QueryQueue<T>
@@ -33,6 +267,7 @@ where
             http_client,
             query_tx,
             query_rx,
+            pending: 0,
         }
     }
 
@@ -40,7 +275,7 @@ where
     ///
     /// When the result is ready, it will be retrieved with
     /// [`QueryQueue::next`].
-    pub async fn enqueue<F, Fut>(&self, data: CommandData, task: F)
+    pub async fn enqueue<F, Fut>(&mut self, data: CommandData, task: F)
     where
         F: FnOnce(&CommandData) -> Fut + Send + 'static,
         Fut: Future<Output = T> + Send + 'static,
@@ -48,12 +283,25 @@ where
         let http_client = self.http_client.clone();
         let query_tx = self.query_tx.clone();
 
+        self.pending += 1;
         tokio::spawn(process(data, http_client, query_tx, task));
     }
 
     /// Fetches the next ready result.
     pub async fn next(&mut self) -> QueryResult<T> {
-        self.query_rx.recv().await.unwrap()
+        let result = self.query_rx.recv().await.unwrap();
+        self.pending -= 1;
+        result
+    }
+
+    /// How many queries are being processed.
+    pub fn len(&self) -> usize {
+        self.pending
+    }
+
+    /// Checks if there are no queries being processed.
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
     }
 }
 
@@ -67,17 +315,156 @@ async fn process<F, Fut, T>(
     F: FnOnce(&CommandData) -> Fut + Send + 'static,
     Fut: Future<Output = T> + Send + 'static,
 {
-    // ack response
-    data.respond(&http_client).ack().await.unwrap();
+    data.respond(&http_client).ack().await;
 
+    // the task takes a slot for each query it runs
     let result = task(&data).await;
 
-    query_tx
-        .send(QueryResult {
-            data,
-            message: result,
+    // the queue may have stopped in the meantime
+    let _ = query_tx.send(QueryResult {
+        data,
+        message: result,
+    });
+}
+
+/// Queries `youtube-dl` for a guild, once one of the [`query_slots`] is
+/// free.
+pub async fn query(
+    ytdl: &YtdlConfig,
+    guild_id: Id<GuildMarker>,
+    query: &str,
+) -> Result<YtdlQuery, crate::Error> {
+    let _permit = query_slots().acquire(guild_id).await;
+    YtdlQuery::query(ytdl, query).await
+}
+
+/// Queries a batch of urls or search queries for a guild, as a playlist of
+/// their tracks in order.
+///
+/// Up to [`BATCH_CONCURRENCY`] queries run at once, each with its own slot,
+/// but their tracks are still added in the order of the queries.
+///
+/// Queries that fail are skipped, and returned with the playlist. If every
+/// query fails, the first error is returned. If there is a `reporter`, it's
+/// shown the summary of the playlist so far after every query.
+#[instrument(name = "query_batch", skip(ytdl, reporter))]
+pub async fn query_batch(
+    ytdl: &YtdlConfig,
+    guild_id: Id<GuildMarker>,
+    queries: Vec<String>,
+    mut reporter: Option<ProgressReporter>,
+) -> Result<(Playlist, Vec<Failure>), crate::Error> {
+    let mut playlist = Playlist {
+        url: String::new(),
+        title: String::from("bulk play"),
+        author: Author {
+            name: String::from("bulk play"),
+            url: None,
+            avatar_url: None,
+        },
+        thumbnail_url: None,
+        tracks: Vec::new(),
+        album: None,
+    };
+
+    let mut summary = PlaylistSummary::new(playlist.as_embed());
+    let mut first_err = None;
+    let total = queries.len();
+
+    let mut results = stream::iter(queries)
+        .map(|query| async move {
+            let res = self::query(ytdl, guild_id, &query).await;
+            (query, res)
         })
-        .unwrap();
+        .buffered(BATCH_CONCURRENCY)
+        .enumerate();
+
+    while let Some((done, (query, res))) = results.next().await {
+        match res {
+            Ok(YtdlQuery::Track(track)) => {
+                summary.add_tracks(once(&track));
+                playlist.tracks.push(track);
+            }
+            Ok(YtdlQuery::Playlist(queried)) => {
+                summary.add_tracks(&queried.tracks);
+                playlist.tracks.extend(queried.tracks);
+            }
+            Err(err) => {
+                warn!(%err, query, "skipping query");
+
+                summary.add_failure(Failure {
+                    item: query,
+                    reason: err.to_string(),
+                });
+                first_err.get_or_insert(err);
+            }
+        }
+
+        if let Some(reporter) = reporter.as_mut() {
+            summary = summary.with_progress(Some((done + 1, total)));
+            reporter.report(&summary).await;
+        }
+    }
+
+    if let (true, Some(err)) = (playlist.tracks.is_empty(), first_err) {
+        return Err(err);
+    }
+
+    Ok((playlist, summary.failures().to_vec()))
+}
+
+/// Splits a query into the urls in it, if it's more than one url separated
+/// by whitespace or commas.
+///
+/// At most [`MAX_BATCH_QUERIES`] are returned.
+pub fn split_urls(query: &str) -> Option<Vec<String>> {
+    let urls = query
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+
+    let all_urls = urls
+        .iter()
+        .all(|url| url.starts_with("https://") || url.starts_with("http://"));
+
+    if urls.len() < 2 || !all_urls {
+        return None;
+    }
+
+    Some(
+        urls.into_iter()
+            .take(MAX_BATCH_QUERIES)
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Finds the urls in a message, like one shared in a music channel, in the
+/// order they appear, without duplicates.
+///
+/// At most [`MAX_BATCH_QUERIES`] are returned.
+pub fn find_urls(text: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+
+    let found = text
+        .split(|c: char| c.is_whitespace() || c == '<' || c == '>')
+        .map(|word| {
+            word.trim_start_matches(['(', '*', '_', '|'])
+                .trim_end_matches([')', '.', ',', '!', '?', '*', '_', '|'])
+        })
+        .filter(|word| word.starts_with("https://") || word.starts_with("http://"));
+
+    for url in found {
+        if urls.len() == MAX_BATCH_QUERIES {
+            break;
+        }
+
+        if !urls.iter().any(|seen| seen == url) {
+            urls.push(url.to_owned());
+        }
+    }
+
+    urls
 }
 
 #[derive(Debug)]
@@ -85,3 +472,69 @@ pub struct QueryResult<T> {
     pub data: CommandData,
     pub message: T,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_urls() {
+        let urls = find_urls(
+            "listen to this <https://youtu.be/a> (https://youtu.be/b), \
+                also https://youtu.be/a.",
+        );
+
+        assert_eq!(urls, ["https://youtu.be/a", "https://youtu.be/b"]);
+        assert!(find_urls("no links here").is_empty());
+    }
+
+    #[test]
+    fn splits_urls() {
+        assert_eq!(
+            split_urls("https://youtu.be/a, https://youtu.be/b https://youtu.be/c").unwrap(),
+            [
+                "https://youtu.be/a",
+                "https://youtu.be/b",
+                "https://youtu.be/c"
+            ]
+        );
+        assert!(split_urls("https://youtu.be/a").is_none());
+        assert!(split_urls("https://youtu.be/a and more").is_none());
+    }
+
+    #[tokio::test]
+    async fn guilds_take_turns() {
+        let slots = Arc::new(QuerySlots::new(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (a, b) = (Id::new(1), Id::new(2));
+
+        let held = slots.acquire(a).await;
+
+        let mut tasks = Vec::new();
+        for (label, guild_id) in [("a1", a), ("a2", a), ("b1", b)] {
+            let slots = slots.clone();
+            let order = order.clone();
+
+            tasks.push(tokio::spawn(async move {
+                let _permit = slots.acquire(guild_id).await;
+                order.lock().unwrap().push(label);
+            }));
+
+            // let the task start waiting
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(slots.stats().waiting, 3);
+        drop(held);
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), ["a1", "b1", "a2"]);
+
+        let stats = slots.stats();
+        assert_eq!(stats.acquired, 4);
+        assert_eq!((stats.running, stats.waiting), (0, 0));
+    }
+}
@@ -0,0 +1,205 @@
+//! Picking up where the bot left off.
+//!
+//! With [`QueueServer::with_rejoin`], every queue that's in a voice channel
+//! saves its channel, its tracks and how far into the playing one it is to
+//! the [`Store`](crate::store::Store) every [`SESSION_SAVE_INTERVAL`], and
+//! forgets them when it leaves. A restart doesn't leave, so when the bot
+//! comes back, [`QueueServer::rejoin_sessions`] joins each channel again with
+//! its queue, paused on the track that was playing until someone resumes it
+//! with `/pause`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{error, info};
+use twilight_model::channel::message::Embed;
+
+use super::{QueueServer, QueueState};
+use crate::i18n;
+use crate::store::{SavedSession, SessionTrack};
+use crate::ytdl::Track;
+
+/// How often a queue in a voice channel saves its session.
+pub const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+impl QueueServer {
+    /// Saves the session of every queue in a voice channel, so it can be
+    /// rejoined with [`QueueServer::rejoin_sessions`] after a restart.
+    pub fn with_rejoin(self) -> QueueServer {
+        QueueServer {
+            rejoin: true,
+            ..self
+        }
+    }
+
+    /// Rejoins the sessions that were going when the bot last stopped, in
+    /// the guilds this process handles.
+    ///
+    /// Without [`QueueServer::with_rejoin`], the saved sessions are forgotten
+    /// instead.
+    pub async fn rejoin_sessions(self: &Arc<QueueServer>) {
+        if !self.rejoin {
+            if self.store.read(|store| store.sessions.is_empty()).await {
+                return;
+            }
+
+            let res = self.store.update(|store| store.sessions.clear()).await;
+
+            if let Err(err) = res {
+                error!(%err, "failed to forget sessions");
+            }
+
+            return;
+        }
+
+        let sessions = self
+            .store
+            .read(|store| {
+                store
+                    .sessions
+                    .iter()
+                    .filter(|(guild_id, _)| self.claim.claims(**guild_id))
+                    .map(|(guild_id, session)| (*guild_id, session.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .await;
+
+        for (guild_id, session) in sessions {
+            info!(%guild_id, channel_id = %session.channel_id, "rejoining session");
+
+            self.with_queue(guild_id, |queue| {
+                let _ = queue.rejoin_tx.send(session);
+            })
+            .await;
+        }
+    }
+}
+
+impl QueueState {
+    /// When the session should be saved next, if it needs to be.
+    pub(super) fn next_session_save(&self) -> Option<Instant> {
+        if !self.queue_server.rejoin {
+            return None;
+        }
+
+        match self.last_session_save {
+            Some(saved_at) => Some(saved_at + SESSION_SAVE_INTERVAL),
+            // nothing is saved yet
+            None if self.player.is_some() => Some(Instant::now()),
+            None => None,
+        }
+    }
+
+    /// Saves the session, or forgets it if the bot isn't in a voice channel.
+    pub(super) async fn save_session(&mut self) {
+        let cache = &self.queue_server.cache;
+        let channel_id = cache
+            .voice_state(self.queue_server.user_id, self.guild_id)
+            .map(|voice_state| voice_state.channel_id())
+            .filter(|_| self.player.is_some());
+
+        let Some(channel_id) = channel_id else {
+            self.forget_session().await;
+            return;
+        };
+
+        let position = self
+            .player
+            .as_ref()
+            .filter(|_| self.playing.is_some())
+            .map(|player| player.player.position())
+            .unwrap_or_default();
+
+        let session = SavedSession {
+            channel_id,
+            announce_channel: self.announce_channel,
+            tracks: self
+                .playing
+                .iter()
+                .filter(|track| track.speech.is_none())
+                .chain(self.track_queue.iter())
+                .map(SessionTrack::from)
+                .collect(),
+            position: position.as_millis() as u64,
+        };
+
+        let guild_id = self.guild_id;
+        let res = self
+            .queue_server
+            .store
+            .update(|store| store.sessions.insert(guild_id, session))
+            .await;
+
+        if let Err(err) = res {
+            error!(%err, "failed to save session");
+        }
+
+        self.last_session_save = Some(Instant::now());
+    }
+
+    /// Forgets the saved session, if there is one.
+    pub(super) async fn forget_session(&mut self) {
+        if self.last_session_save.take().is_none() {
+            return;
+        }
+
+        let guild_id = self.guild_id;
+        let res = self
+            .queue_server
+            .store
+            .update(|store| store.sessions.remove(&guild_id))
+            .await;
+
+        if let Err(err) = res {
+            error!(%err, "failed to forget session");
+        }
+    }
+
+    /// Joins the channel of a saved session and queues its tracks, paused
+    /// on the one that was playing.
+    pub(super) async fn rejoin(&mut self, session: SavedSession) {
+        // someone already started playing
+        if self.player.is_some() {
+            return;
+        }
+
+        self.join(session.channel_id).await;
+        self.announce_channel = session.announce_channel;
+
+        let mut tracks = session.tracks.into_iter().map(Track::from);
+        let playing = tracks.next().map(|track| Track {
+            start: Duration::from_millis(session.position).max(track.start),
+            ..track
+        });
+        self.track_queue.extend(tracks);
+
+        let Some(track) = playing else {
+            return;
+        };
+
+        self.play_track(Some(track));
+        let _ = self.unwrap_player().pause();
+        self.paused = true;
+
+        // the session is the queue's now
+        self.last_session_save = Some(Instant::now());
+
+        let Some(track) = self.playing.as_ref() else {
+            return;
+        };
+
+        let locale = self.guild_locale();
+        let embed = Embed {
+            description: Some(i18n::trf(
+                locale.as_deref(),
+                "I'm back! {count} tracks are queued, and this one is paused until someone uses \
+                    /pause",
+                &[("count", &(self.track_queue.len() + 1))],
+            )),
+            ..track.as_embed(self.large_thumbnails)
+        };
+
+        self.announce(embed).await;
+    }
+}
@@ -0,0 +1,644 @@
+//! Where the responses to commands go.
+//!
+//! Handlers build a response with [`CommandData::respond`], which hands it
+//! to the command's [`Responder`]. Slash commands and buttons answer their
+//! interaction with an [`InteractionResponder`], text commands reply in their
+//! channel with a [`ChannelResponder`], and anything else, like an API, can
+//! take the responses for itself with a [`ForwardResponder`].
+//!
+//! Responses aren't sent by the queue that made them. Each command has an
+//! [`Outbox`] that sends its responses in order from a task of its own, so a
+//! response waiting out a rate limit doesn't hold up the queue.
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, warn};
+
+use twilight_http::api_error::ApiError;
+use twilight_http::{client::Client as HttpClient, error::ErrorType, Error as HttpError};
+use twilight_model::{
+    channel::message::{Embed, MessageFlags},
+    http::{
+        attachment::Attachment,
+        interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
+    },
+    id::{
+        marker::{
+            ApplicationMarker, ChannelMarker, GuildMarker, InteractionMarker, MessageMarker,
+            UserMarker,
+        },
+        Id,
+    },
+};
+use twilight_validate::message::MessageValidationError;
+
+use super::commands::CommandData;
+
+/// The error code Discord answers with for an interaction it forgot, like
+/// one that wasn't acked in time.
+const UNKNOWN_INTERACTION: u64 = 10062;
+
+/// How many times a failed response is retried.
+pub const MAX_RETRIES: u32 = 3;
+
+/// How long the first retry of a response waits, doubling after.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The longest a response waits out a rate limit, instead of giving up.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// How long an interaction token can be used for.
+///
+/// Discord gives tokens 15 minutes; this leaves some room so a request
+/// doesn't expire on the way.
+pub const INTERACTION_TOKEN_LIFETIME: Duration = Duration::from_secs(14 * 60);
+
+/// Sends the responses of a command to wherever the command came from.
+pub trait Responder: Debug + Send + Sync {
+    /// Tells the user the command is being worked on, for responses that
+    /// take a while.
+    fn ack<'a>(&'a self, http: &'a HttpClient) -> BoxFuture<'a, Result<(), ResponseError>>;
+
+    /// Sends a response.
+    fn send<'a>(
+        &'a self,
+        http: &'a HttpClient,
+        delivery: Delivery,
+        reply: Reply,
+    ) -> BoxFuture<'a, Result<(), ResponseError>>;
+
+    /// Whether a response can be sent again after a request that may have
+    /// gone through, without the user seeing it twice.
+    ///
+    /// Only these are retried after network errors. Rate limited requests
+    /// never went through, so every response is retried after those.
+    fn repeatable(&self, _delivery: Delivery) -> bool {
+        false
+    }
+}
+
+/// How a response relates to the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// The first response.
+    Respond,
+    /// Replaces the first response, which was usually an ack.
+    Update,
+    /// Another response after the first.
+    Followup,
+}
+
+/// The contents of a response.
+#[derive(Clone, Debug, Default)]
+pub struct Reply {
+    pub content: Option<String>,
+    pub embeds: Option<Vec<Embed>>,
+    pub attachments: Option<Vec<Attachment>>,
+    /// Whether only the user that used the command should see it, where
+    /// that is possible.
+    pub ephemeral: bool,
+}
+
+impl CommandData {
+    /// Begins a command response.
+    pub fn respond<'a>(&'a self, client: &'a Arc<HttpClient>) -> CommandResponse<'a> {
+        CommandResponse {
+            command: self,
+            http: client,
+            reply: Reply::default(),
+        }
+    }
+}
+
+/// A builder for a response to a command.
+///
+/// Responses go to the command's [`Outbox`], so they're sent after the
+/// methods that send them return. Responses that fail are logged.
+pub struct CommandResponse<'a> {
+    command: &'a CommandData,
+    http: &'a Arc<HttpClient>,
+    reply: Reply,
+}
+
+impl<'a> CommandResponse<'a> {
+    /// Sets the response as a quick, user friendly error.
+    pub fn error(&mut self, error: impl Display) -> &mut Self {
+        self.reply.content = Some(error.to_string());
+        self.reply.ephemeral = true;
+
+        self
+    }
+
+    /// Sets the content of the message.
+    pub fn content(&mut self, content: impl Display) -> &mut Self {
+        self.reply.content = Some(content.to_string());
+
+        self
+    }
+
+    /// Only shows the response to the user that used the command.
+    pub fn ephemeral(&mut self) -> &mut Self {
+        self.reply.ephemeral = true;
+
+        self
+    }
+
+    /// Adds an embed to the response.
+    pub fn embed(&mut self, embed: Embed) -> &mut Self {
+        self.reply.embeds.get_or_insert_with(Vec::new).push(embed);
+
+        self
+    }
+
+    /// Attaches a file to the response.
+    pub fn attachment(&mut self, filename: impl Into<String>, file: Vec<u8>) -> &mut Self {
+        let attachments = self.reply.attachments.get_or_insert_with(Vec::new);
+        let id = attachments.len() as u64;
+
+        attachments.push(Attachment::from_bytes(filename.into(), file, id));
+
+        self
+    }
+
+    /// Acks the response.
+    ///
+    /// The final message must be updated with [`CommandResponse::update`].
+    pub async fn ack(&mut self) {
+        self.command
+            .outbox
+            .push(self.command, self.http, Request::Ack);
+    }
+
+    /// Updates the previous message (mostly an ACK).
+    pub async fn update(&mut self) {
+        self.send(Delivery::Update);
+    }
+
+    /// Sends another message after the response, for when one isn't enough.
+    pub async fn followup(&mut self) {
+        self.send(Delivery::Followup);
+    }
+
+    /// Responds with a new message.
+    pub async fn respond(&mut self) {
+        self.send(Delivery::Respond);
+    }
+
+    fn send(&mut self, delivery: Delivery) {
+        let reply = std::mem::take(&mut self.reply);
+
+        self.command
+            .outbox
+            .push(self.command, self.http, Request::Send(delivery, reply));
+    }
+}
+
+/// Sends the responses of a command in order, from a task of its own.
+///
+/// Clones of a command share its outbox. The task is started with the first
+/// response, and ends once every clone is dropped and the responses left
+/// are sent.
+#[derive(Clone, Debug, Default)]
+pub struct Outbox {
+    tx: Arc<OnceLock<UnboundedSender<Request>>>,
+}
+
+/// A response waiting in an [`Outbox`].
+#[derive(Debug)]
+enum Request {
+    Ack,
+    Send(Delivery, Reply),
+}
+
+impl Outbox {
+    fn push(&self, command: &CommandData, http: &Arc<HttpClient>, request: Request) {
+        let tx = self.tx.get_or_init(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+
+            tokio::spawn(outbox_run(
+                rx,
+                command.responder.clone(),
+                http.clone(),
+                command.guild_id,
+                command.user_id,
+            ));
+
+            tx
+        });
+
+        let _ = tx.send(request);
+    }
+}
+
+async fn outbox_run(
+    mut rx: UnboundedReceiver<Request>,
+    responder: Arc<dyn Responder>,
+    http: Arc<HttpClient>,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) {
+    while let Some(request) = rx.recv().await {
+        let mut attempt = 0;
+
+        let (what, repeatable) = match &request {
+            // acking twice fails harmlessly
+            Request::Ack => ("ack", true),
+            Request::Send(Delivery::Respond, _) => ("respond", false),
+            Request::Send(Delivery::Update, _) => ("update", false),
+            Request::Send(Delivery::Followup, _) => ("followup", false),
+        };
+
+        loop {
+            let res = match &request {
+                Request::Ack => responder.ack(&http).await,
+                Request::Send(delivery, reply) => {
+                    responder.send(&http, *delivery, reply.clone()).await
+                }
+            };
+
+            let Err(err) = res else {
+                break;
+            };
+
+            let repeatable = repeatable
+                || matches!(&request, Request::Send(delivery, _) if responder.repeatable(*delivery));
+
+            let delay = match &err {
+                ResponseError::Http(err) => retry_delay(err, attempt, repeatable),
+                ResponseError::Invalid(_) => None,
+            };
+
+            match delay {
+                Some(delay) if attempt < MAX_RETRIES => {
+                    debug!(%err, response = what, attempt, ?delay, "retrying response");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => {
+                    warn!(
+                        ?err,
+                        response = what,
+                        attempts = attempt + 1,
+                        %guild_id,
+                        %user_id,
+                        "failed to respond",
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Responds to an interaction, like a slash command or a button press.
+#[derive(Debug)]
+pub struct InteractionResponder {
+    pub application_id: Id<ApplicationMarker>,
+    pub interaction_id: Id<InteractionMarker>,
+    pub token: String,
+    /// The channel of the interaction, where responses go once the token
+    /// expires.
+    pub channel_id: Id<ChannelMarker>,
+    /// When the interaction was received, for knowing when its token
+    /// expires.
+    pub received_at: Instant,
+    /// Whether the interaction is a button press instead of a slash command.
+    /// Responses to buttons are only shown to the user that pressed them.
+    pub from_component: bool,
+    /// Whether Discord forgot the interaction, like when it wasn't acked in
+    /// time. Like an expired token, responses go to the channel instead.
+    pub lost: AtomicBool,
+}
+
+impl InteractionResponder {
+    /// Whether the interaction token has expired, or was lost, after which
+    /// the command can only be responded to in its channel.
+    pub fn token_expired(&self) -> bool {
+        self.received_at.elapsed() >= INTERACTION_TOKEN_LIFETIME
+            || self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Remembers that Discord forgot the interaction, if that's why a
+    /// request failed.
+    fn check_lost(&self, err: &HttpError) -> bool {
+        let lost = is_unknown_interaction(err);
+
+        if lost {
+            self.lost.store(true, Ordering::Relaxed);
+        }
+
+        lost
+    }
+
+    fn flags(&self, reply: &Reply) -> MessageFlags {
+        // keep button presses from flooding the channel
+        if reply.ephemeral || self.from_component {
+            MessageFlags::EPHEMERAL
+        } else {
+            MessageFlags::empty()
+        }
+    }
+
+    async fn respond(&self, http: &HttpClient, reply: Reply) -> Result<(), ResponseError> {
+        if self.token_expired() {
+            return channel_message(http, self.channel_id, None, &reply).await;
+        }
+
+        let flags = self.flags(&reply);
+
+        let res = http
+            .interaction(self.application_id)
+            .create_response(
+                self.interaction_id,
+                &self.token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        flags: Some(flags),
+                        embeds: reply.embeds.clone(),
+                        content: reply.content.clone(),
+                        attachments: reply.attachments.clone(),
+                        ..Default::default()
+                    }),
+                },
+            )
+            .await;
+
+        match res {
+            Err(err) if self.check_lost(&err) => {
+                channel_message(http, self.channel_id, None, &reply).await
+            }
+            res => res.map(drop).map_err(ResponseError::Http),
+        }
+    }
+
+    /// Updates the previous message.
+    ///
+    /// If the previous message can't be edited anymore, because the token
+    /// expired or the message was deleted, sends a followup instead.
+    async fn update(&self, http: &HttpClient, reply: Reply) -> Result<(), ResponseError> {
+        if self.token_expired() {
+            return self.followup(http, reply).await;
+        }
+
+        let client = http.interaction(self.application_id);
+        let mut request = client
+            .update_response(&self.token)
+            .content(reply.content.as_deref())
+            .and_then(|request| request.embeds(reply.embeds.as_deref()))
+            .map_err(ResponseError::Invalid)?;
+
+        if let Some(attachments) = reply.attachments.as_deref() {
+            request = request
+                .attachments(attachments)
+                .map_err(ResponseError::Invalid)?;
+        }
+
+        match request.await {
+            Err(err) if is_unknown_message(&err) => self.followup(http, reply).await,
+            res => res.map(drop).map_err(ResponseError::Http),
+        }
+    }
+
+    /// Sends another message after the response.
+    ///
+    /// Once the token has expired, this is sent to the command's channel
+    /// as a plain message.
+    async fn followup(&self, http: &HttpClient, reply: Reply) -> Result<(), ResponseError> {
+        if self.token_expired() {
+            return channel_message(http, self.channel_id, None, &reply).await;
+        }
+
+        let client = http.interaction(self.application_id);
+        let mut request = client
+            .create_followup(&self.token)
+            .flags(self.flags(&reply))
+            .embeds(reply.embeds.as_deref().unwrap_or_default())
+            .map_err(ResponseError::Invalid)?;
+
+        if let Some(content) = reply.content.as_deref() {
+            request = request.content(content).map_err(ResponseError::Invalid)?;
+        }
+
+        if let Some(attachments) = reply.attachments.as_deref() {
+            request = request
+                .attachments(attachments)
+                .map_err(ResponseError::Invalid)?;
+        }
+
+        request.await.map(drop).map_err(ResponseError::Http)
+    }
+}
+
+impl Responder for InteractionResponder {
+    fn repeatable(&self, delivery: Delivery) -> bool {
+        // an interaction can only be responded to once, and the previous
+        // message only holds the last update, but followups and messages in
+        // the channel are new every time
+        !self.token_expired() && delivery != Delivery::Followup
+    }
+
+    fn ack<'a>(&'a self, http: &'a HttpClient) -> BoxFuture<'a, Result<(), ResponseError>> {
+        Box::pin(async move {
+            let res = http
+                .interaction(self.application_id)
+                .create_response(
+                    self.interaction_id,
+                    &self.token,
+                    &InteractionResponse {
+                        kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                        data: None,
+                    },
+                )
+                .await;
+
+            if let Err(err) = &res {
+                self.check_lost(err);
+            }
+
+            res.map(drop).map_err(ResponseError::Http)
+        })
+    }
+
+    fn send<'a>(
+        &'a self,
+        http: &'a HttpClient,
+        delivery: Delivery,
+        reply: Reply,
+    ) -> BoxFuture<'a, Result<(), ResponseError>> {
+        Box::pin(async move {
+            match delivery {
+                Delivery::Respond => self.respond(http, reply).await,
+                Delivery::Update => self.update(http, reply).await,
+                Delivery::Followup => self.followup(http, reply).await,
+            }
+        })
+    }
+}
+
+/// Responds with plain messages in a channel, like to a text command.
+///
+/// Messages can't be ephemeral, so every response is seen by everyone.
+#[derive(Debug)]
+pub struct ChannelResponder {
+    pub channel_id: Id<ChannelMarker>,
+    /// The message responses reply to, if any.
+    pub message_id: Option<Id<MessageMarker>>,
+}
+
+impl Responder for ChannelResponder {
+    fn ack<'a>(&'a self, http: &'a HttpClient) -> BoxFuture<'a, Result<(), ResponseError>> {
+        // show that the bot is typing instead
+        Box::pin(async move {
+            http.create_typing_trigger(self.channel_id)
+                .await
+                .map(drop)
+                .map_err(ResponseError::Http)
+        })
+    }
+
+    fn send<'a>(
+        &'a self,
+        http: &'a HttpClient,
+        _delivery: Delivery,
+        reply: Reply,
+    ) -> BoxFuture<'a, Result<(), ResponseError>> {
+        Box::pin(
+            async move { channel_message(http, self.channel_id, self.message_id, &reply).await },
+        )
+    }
+}
+
+/// Forwards responses to whoever made the command, like an API answering
+/// its caller or an internal event that doesn't need them.
+#[derive(Debug)]
+pub struct ForwardResponder {
+    tx: UnboundedSender<(Delivery, Reply)>,
+}
+
+impl ForwardResponder {
+    /// Creates a `ForwardResponder`, and the receiver its responses are
+    /// sent to.
+    ///
+    /// Responses are dropped once the receiver is.
+    pub fn new() -> (ForwardResponder, UnboundedReceiver<(Delivery, Reply)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        (ForwardResponder { tx }, rx)
+    }
+}
+
+impl Responder for ForwardResponder {
+    fn ack<'a>(&'a self, _http: &'a HttpClient) -> BoxFuture<'a, Result<(), ResponseError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn send<'a>(
+        &'a self,
+        _http: &'a HttpClient,
+        delivery: Delivery,
+        reply: Reply,
+    ) -> BoxFuture<'a, Result<(), ResponseError>> {
+        let _ = self.tx.send((delivery, reply));
+
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Sends a response as a plain message in a channel.
+async fn channel_message(
+    http: &HttpClient,
+    channel_id: Id<ChannelMarker>,
+    reply_to: Option<Id<MessageMarker>>,
+    reply: &Reply,
+) -> Result<(), ResponseError> {
+    let mut request = http
+        .create_message(channel_id)
+        .embeds(reply.embeds.as_deref().unwrap_or_default())
+        .map_err(ResponseError::Invalid)?;
+
+    if let Some(message_id) = reply_to {
+        request = request.reply(message_id);
+    }
+
+    if let Some(content) = reply.content.as_deref() {
+        request = request.content(content).map_err(ResponseError::Invalid)?;
+    }
+
+    if let Some(attachments) = reply.attachments.as_deref() {
+        request = request
+            .attachments(attachments)
+            .map_err(ResponseError::Invalid)?;
+    }
+
+    request.await.map(drop).map_err(ResponseError::Http)
+}
+
+/// An error sending a response.
+#[derive(Debug)]
+pub enum ResponseError {
+    /// The request to Discord failed.
+    Http(HttpError),
+    /// The response is too long, or has too many embeds or attachments, for
+    /// Discord to take.
+    Invalid(MessageValidationError),
+}
+
+impl Display for ResponseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseError::Http(err) => Display::fmt(err, f),
+            ResponseError::Invalid(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+/// How long to wait before retrying a failed request, if it can be retried.
+///
+/// Requests that may have gone through are only retried if they're
+/// `repeatable`.
+fn retry_delay(err: &HttpError, attempt: u32, repeatable: bool) -> Option<Duration> {
+    let backoff = RETRY_BACKOFF * 2u32.pow(attempt);
+
+    match err.kind() {
+        ErrorType::Response {
+            error: ApiError::Ratelimited(ratelimited),
+            ..
+        } => Some(Duration::from_secs_f64(ratelimited.retry_after.max(0.0)))
+            .filter(|&retry_after| retry_after <= MAX_RETRY_AFTER),
+        ErrorType::Response { status, .. } if status.is_server_error() && repeatable => {
+            Some(backoff)
+        }
+        ErrorType::RequestError
+        | ErrorType::RequestTimedOut
+        | ErrorType::ServiceUnavailable { .. }
+            if repeatable =>
+        {
+            Some(backoff)
+        }
+        _ => None,
+    }
+}
+
+/// Whether a request failed because Discord forgot the interaction.
+fn is_unknown_interaction(err: &HttpError) -> bool {
+    matches!(
+        err.kind(),
+        ErrorType::Response {
+            error: ApiError::General(general),
+            ..
+        } if general.code == UNKNOWN_INTERACTION
+    )
+}
+
+/// Whether a request failed because the message it was for is gone.
+pub(super) fn is_unknown_message(err: &HttpError) -> bool {
+    matches!(err.kind(), ErrorType::Response { status, .. } if status.get() == 404)
+}
@@ -0,0 +1,339 @@
+//! What guilds listen to.
+//!
+//! The [`StatsRecorder`] listens to [`QueueEvent`]s and keeps the tracks each
+//! guild played in the [`Store`], along with how long it has listened in
+//! total. Only the last [`KEPT_PLAYS`] worth of plays are kept, which is
+//! enough for `/stats` to rank the top tracks and requesters of the week or
+//! month.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+use twilight_model::channel::message::embed::{EmbedField, EmbedFooter};
+use twilight_model::channel::message::Embed;
+use twilight_model::id::{marker::UserMarker, Id};
+
+use super::commands::{CommandData, StatsPeriod};
+use super::{format_duration, QueueEvent, QueueState, UserError};
+use crate::store::{ListeningStats, Play, Store};
+use crate::ytdl::Track;
+
+/// How long plays are kept for.
+pub const KEPT_PLAYS: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// A track counts as played after this much of it, or all of it if it's
+/// shorter.
+const MIN_LISTEN: Duration = Duration::from_secs(30);
+
+/// How many tracks and requesters are ranked.
+const TOP_COUNT: usize = 5;
+
+/// Records the tracks guilds play.
+pub struct StatsRecorder {
+    store: Arc<Store>,
+}
+
+impl StatsRecorder {
+    /// Creates a new `StatsRecorder`.
+    pub fn new(store: Arc<Store>) -> StatsRecorder {
+        StatsRecorder { store }
+    }
+
+    /// Starts recording the events from `events`.
+    ///
+    /// The task stops once the sender is dropped.
+    pub fn start(self, mut events: broadcast::Receiver<QueueEvent>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => self.handle_event(event).await,
+                    Err(RecvError::Lagged(count)) => warn!(count, "stats missed events"),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    async fn handle_event(&self, event: QueueEvent) {
+        let QueueEvent::TrackEnded {
+            guild_id,
+            track,
+            played,
+        } = event
+        else {
+            return;
+        };
+
+        if !counts_as_played(&track, played) {
+            return;
+        }
+
+        let play = Play {
+            url: track.url,
+            title: track.title,
+            requester: track.requester,
+            at: unix_now(),
+            listened: played.as_secs(),
+        };
+
+        let res = self
+            .store
+            .update(|store| store.listening.entry(guild_id).or_default().record(play))
+            .await;
+
+        if let Err(err) = res {
+            error!(%err, "failed to save listening stats");
+        }
+    }
+}
+
+impl ListeningStats {
+    /// Records a play, forgetting plays older than [`KEPT_PLAYS`].
+    pub fn record(&mut self, play: Play) {
+        let cutoff = play.at.saturating_sub(KEPT_PLAYS.as_secs());
+        self.plays.retain(|play| play.at >= cutoff);
+
+        if let Some(requester) = play.requester {
+            *self.requests.entry(requester).or_default() += 1;
+        }
+
+        self.total_plays += 1;
+        self.total_listened += play.listened;
+        self.plays.push(play);
+    }
+
+    /// The tracks played most since `since`, in seconds since the Unix
+    /// epoch, with how many times they were played.
+    pub fn top_tracks(&self, since: u64) -> Vec<(&Play, usize)> {
+        let mut counts = HashMap::<&str, (&Play, usize)>::new();
+
+        for play in self.plays.iter().filter(|play| play.at >= since) {
+            let entry = counts.entry(&play.url).or_insert((play, 0));
+            // the newest title wins
+            entry.0 = play;
+            entry.1 += 1;
+        }
+
+        let mut top = counts.into_values().collect::<Vec<_>>();
+        top.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(b.at.cmp(&a.at)));
+        top.truncate(TOP_COUNT);
+        top
+    }
+
+    /// The users whose requests were played most since `since`, with how
+    /// many were played.
+    pub fn top_requesters(&self, since: u64) -> Vec<(Id<UserMarker>, usize)> {
+        let mut counts = HashMap::<Id<UserMarker>, usize>::new();
+
+        for play in self.plays.iter().filter(|play| play.at >= since) {
+            if let Some(requester) = play.requester {
+                *counts.entry(requester).or_default() += 1;
+            }
+        }
+
+        let mut top = counts.into_iter().collect::<Vec<_>>();
+        top.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        top.truncate(TOP_COUNT);
+        top
+    }
+}
+
+impl QueueState {
+    pub(super) async fn stats(
+        &self,
+        command: &CommandData,
+        period: StatsPeriod,
+    ) -> Result<(), UserError> {
+        let stats = self
+            .queue_server
+            .store
+            .read(|store| store.listening.get(&self.guild_id).cloned())
+            .await
+            .unwrap_or_default();
+
+        let since = unix_now().saturating_sub(period.duration().as_secs());
+        let (plays, listened) = stats
+            .plays
+            .iter()
+            .filter(|play| play.at >= since)
+            .fold((0, 0), |(plays, listened), play| {
+                (plays + 1, listened + play.listened)
+            });
+
+        if plays == 0 {
+            let msg = match period {
+                StatsPeriod::Week => command.tr("nothing was played this week"),
+                StatsPeriod::Month => command.tr("nothing was played this month"),
+            };
+
+            command
+                .respond(&self.queue_server.http_client)
+                .error(msg)
+                .respond()
+                .await;
+
+            return Ok(());
+        }
+
+        let mut tracks = String::new();
+        for (i, (play, count)) in stats.top_tracks(since).into_iter().enumerate() {
+            let title = if play.url.is_empty() {
+                play.title.clone()
+            } else {
+                format!("[{}]({})", play.title, play.url)
+            };
+
+            if i > 0 {
+                tracks.push('\n');
+            }
+
+            write!(
+                &mut tracks,
+                "{}. {}",
+                i + 1,
+                command.trf(
+                    "{title} · {count} plays",
+                    &[("title", &title), ("count", &count)]
+                )
+            )
+            .unwrap();
+        }
+
+        let mut requesters = String::new();
+        for (i, (user_id, count)) in stats.top_requesters(since).into_iter().enumerate() {
+            if i > 0 {
+                requesters.push('\n');
+            }
+
+            write!(
+                &mut requesters,
+                "{}. {}",
+                i + 1,
+                command.trf(
+                    "{user} · {count} tracks",
+                    &[("user", &format!("<@{}>", user_id)), ("count", &count)]
+                )
+            )
+            .unwrap();
+        }
+
+        let mut fields = vec![EmbedField {
+            inline: false,
+            name: command.tr("top tracks").to_owned(),
+            value: tracks,
+        }];
+
+        if !requesters.is_empty() {
+            fields.push(EmbedField {
+                inline: false,
+                name: command.tr("top requesters").to_owned(),
+                value: requesters,
+            });
+        }
+
+        let title = match period {
+            StatsPeriod::Week => command.tr("this week"),
+            StatsPeriod::Month => command.tr("this month"),
+        };
+
+        let embed = Embed {
+            description: Some(command.trf(
+                "{plays} tracks played for {time}",
+                &[
+                    ("plays", &plays),
+                    ("time", &format_duration(Duration::from_secs(listened))),
+                ],
+            )),
+            fields,
+            footer: Some(EmbedFooter {
+                icon_url: None,
+                proxy_icon_url: None,
+                text: command.trf(
+                    "all time: {plays} tracks played for {time}",
+                    &[
+                        ("plays", &stats.total_plays),
+                        (
+                            "time",
+                            &format_duration(Duration::from_secs(stats.total_listened)),
+                        ),
+                    ],
+                ),
+            }),
+            title: Some(title.to_owned()),
+            ..crate::embed()
+        };
+
+        command
+            .respond(&self.queue_server.http_client)
+            .embed(embed)
+            .respond()
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Checks if a track that ended after `played` counts as played.
+pub(super) fn counts_as_played(track: &Track, played: Duration) -> bool {
+    // speech isn't music
+    if track.speech.is_some() {
+        return false;
+    }
+
+    played >= track.duration.unwrap_or(MIN_LISTEN).min(MIN_LISTEN)
+}
+
+/// The time in seconds since the Unix epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(url: &str, requester: u64, at: u64) -> Play {
+        Play {
+            url: url.to_owned(),
+            title: url.to_owned(),
+            requester: Some(Id::new(requester)),
+            at,
+            listened: 60,
+        }
+    }
+
+    #[test]
+    fn ranks_plays() {
+        let mut stats = ListeningStats::default();
+        stats.record(play("old", 1, 0));
+        stats.record(play("a", 1, 100));
+        stats.record(play("b", 2, 200));
+        stats.record(play("b", 2, 300));
+
+        let top = stats
+            .top_tracks(50)
+            .into_iter()
+            .map(|(play, count)| (play.url.as_str(), count))
+            .collect::<Vec<_>>();
+        assert_eq!(top, [("b", 2), ("a", 1)]);
+
+        assert_eq!(stats.top_requesters(50), [(Id::new(2), 2), (Id::new(1), 1)]);
+
+        assert_eq!(stats.total_plays, 4);
+        assert_eq!(stats.total_listened, 240);
+        assert_eq!(stats.requests[&Id::new(1)], 2);
+
+        // plays past a month are forgotten, but still counted
+        stats.record(play("c", 1, KEPT_PLAYS.as_secs() + 150));
+        assert_eq!(stats.plays.len(), 3);
+        assert_eq!(stats.total_plays, 5);
+    }
+}
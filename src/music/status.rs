@@ -0,0 +1,356 @@
+//! Showing the playing track on the guild's channels.
+//!
+//! With `/settings status`, a guild can have the queue keep the status of the
+//! bot's voice channel, or the topic of the channel tracks are requested
+//! from, set to the playing track and how many are queued after it. Discord
+//! limits how often these can change, topics especially, so changes wait out
+//! [`VOICE_STATUS_INTERVAL`] or [`TOPIC_INTERVAL`] and are batched with
+//! anything else that changes in the meantime. Once nothing is playing, the
+//! status is cleared, and a topic is put back the way it was.
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Request, StatusCode};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{error, warn};
+use twilight_http::api_error::ApiError;
+use twilight_http::error::ErrorType;
+use twilight_model::id::{marker::ChannelMarker, Id};
+
+use super::QueueState;
+use crate::https::https_client;
+use crate::i18n;
+use crate::store::NowPlayingStatus;
+
+const DISCORD_API: &str = "https://discord.com/api/v10";
+
+/// How often the status of a voice channel is changed, at most.
+pub const VOICE_STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the topic of a channel is changed, at most.
+///
+/// Discord only lets a channel's topic change twice every ten minutes.
+pub const TOPIC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a status can be, in characters.
+const MAX_STATUS_LEN: usize = 500;
+
+/// What a queue shows on a channel.
+#[derive(Debug, Default)]
+pub(super) struct ChannelStatus {
+    /// Where the guild wants the playing track shown.
+    pub target: NowPlayingStatus,
+    shown: Option<Shown>,
+    /// When the status can change next.
+    ready_at: Option<Instant>,
+    /// Whether a change is waiting for `ready_at`.
+    pending: bool,
+}
+
+/// A status that was set.
+#[derive(Debug)]
+struct Shown {
+    kind: NowPlayingStatus,
+    channel_id: Id<ChannelMarker>,
+    text: String,
+    /// The topic the status replaced, for topics.
+    old_topic: String,
+}
+
+impl ChannelStatus {
+    /// Whether a status is shown that still has to be cleared.
+    pub fn is_shown(&self) -> bool {
+        self.shown.is_some()
+    }
+}
+
+impl QueueState {
+    /// When the status should be refreshed, if a change is waiting on the
+    /// rate limit.
+    pub(super) fn next_status_update(&self) -> Option<Instant> {
+        let status = &self.channel_status;
+
+        status.ready_at.filter(|_| status.pending)
+    }
+
+    /// Where the playing track should be shown, and what with.
+    async fn wanted_status(&self) -> Option<(Id<ChannelMarker>, String)> {
+        let track = self.playing.as_ref()?;
+
+        let channel_id = match self.channel_status.target {
+            NowPlayingStatus::Off => return None,
+            NowPlayingStatus::Voice => self.voice_state().await?.channel_id?,
+            NowPlayingStatus::Topic => self.announce_channel?,
+        };
+
+        let locale = self.guild_locale();
+        let text = if self.track_queue.is_empty() {
+            i18n::trf(
+                locale.as_deref(),
+                "now playing {title}",
+                &[("title", &track.title)],
+            )
+        } else {
+            i18n::trf(
+                locale.as_deref(),
+                "now playing {title} · {count} queued",
+                &[("title", &track.title), ("count", &self.track_queue.len())],
+            )
+        };
+
+        let text = match text.char_indices().nth(MAX_STATUS_LEN - 1) {
+            Some((end, _)) if text.chars().count() > MAX_STATUS_LEN => {
+                format!("{}…", &text[..end])
+            }
+            _ => text,
+        };
+
+        Some((channel_id, text))
+    }
+
+    /// Updates the status if what it should show has changed.
+    pub(super) async fn refresh_status(&mut self) {
+        if self.channel_status.target == NowPlayingStatus::Off && !self.channel_status.is_shown() {
+            return;
+        }
+
+        let wanted = self.wanted_status().await;
+        let target = self.channel_status.target;
+        let status = &mut self.channel_status;
+
+        let unchanged = match (&status.shown, &wanted) {
+            (Some(shown), Some((channel_id, text))) => {
+                shown.kind == target && shown.channel_id == *channel_id && shown.text == *text
+            }
+            (None, None) => true,
+            _ => false,
+        };
+
+        if unchanged {
+            status.pending = false;
+            return;
+        }
+
+        if status.ready_at.is_some_and(|at| at > Instant::now()) {
+            status.pending = true;
+            return;
+        }
+
+        status.pending = false;
+
+        // the status moved, so the old one goes first
+        let moved = |shown: &Shown| {
+            shown.kind != target || Some(shown.channel_id) != wanted.as_ref().map(|(id, _)| *id)
+        };
+
+        let mut old_topic = None;
+
+        if let Some(shown) = status.shown.take() {
+            if moved(&shown) {
+                let cleared = match shown.kind {
+                    NowPlayingStatus::Topic => &shown.old_topic,
+                    _ => "",
+                };
+
+                match self.set_status(shown.kind, shown.channel_id, cleared).await {
+                    Ok(()) if shown.kind == NowPlayingStatus::Topic => {
+                        self.save_old_topic(shown.channel_id, None).await;
+                    }
+                    Ok(()) => (),
+                    Err(err) => warn!(%err, "failed to clear channel status"),
+                }
+            } else {
+                old_topic = Some(shown.old_topic);
+            }
+        }
+
+        let Some((channel_id, text)) = wanted else {
+            return;
+        };
+
+        // keep the topic from before the first track, which is still saved if
+        // the bot restarted before putting it back
+        let old_topic = match old_topic {
+            Some(old_topic) => old_topic,
+            None if target == NowPlayingStatus::Topic => {
+                let saved = self
+                    .queue_server
+                    .store
+                    .read(|store| store.replaced_topics.get(&channel_id).cloned())
+                    .await;
+
+                let old_topic = saved.unwrap_or_else(|| {
+                    self.queue_server
+                        .cache
+                        .channel(channel_id)
+                        .and_then(|channel| channel.topic.clone())
+                        .unwrap_or_default()
+                });
+
+                self.save_old_topic(channel_id, Some(&old_topic)).await;
+                old_topic
+            }
+            None => String::new(),
+        };
+
+        match self.set_status(target, channel_id, &text).await {
+            Ok(()) => {
+                self.channel_status.shown = Some(Shown {
+                    kind: target,
+                    channel_id,
+                    text,
+                    old_topic,
+                });
+            }
+            Err(err) => {
+                warn!(%err, "failed to set channel status");
+
+                // try again later
+                let status = &mut self.channel_status;
+                status.pending = true;
+
+                if let StatusError::RateLimited(retry_after) = err {
+                    status.ready_at = Some(Instant::now() + retry_after);
+                }
+            }
+        }
+    }
+
+    /// Saves the topic a channel had before its status was set, or forgets it
+    /// once it's put back.
+    async fn save_old_topic(&self, channel_id: Id<ChannelMarker>, old_topic: Option<&str>) {
+        let res = self
+            .queue_server
+            .store
+            .update(|store| match old_topic {
+                Some(old_topic) => {
+                    store
+                        .replaced_topics
+                        .insert(channel_id, old_topic.to_owned());
+                }
+                None => {
+                    store.replaced_topics.remove(&channel_id);
+                }
+            })
+            .await;
+
+        if let Err(err) = res {
+            error!(%err, "failed to save channel topic");
+        }
+    }
+
+    /// Sets the status of a channel.
+    async fn set_status(
+        &mut self,
+        kind: NowPlayingStatus,
+        channel_id: Id<ChannelMarker>,
+        text: &str,
+    ) -> Result<(), StatusError> {
+        match kind {
+            NowPlayingStatus::Off => Ok(()),
+            NowPlayingStatus::Voice => {
+                self.channel_status.ready_at = Some(Instant::now() + VOICE_STATUS_INTERVAL);
+
+                let token = self.queue_server.http_client.token().unwrap_or_default();
+                set_voice_status(token, channel_id, text).await
+            }
+            NowPlayingStatus::Topic => {
+                self.channel_status.ready_at = Some(Instant::now() + TOPIC_INTERVAL);
+
+                let res = self
+                    .queue_server
+                    .http_client
+                    .update_channel(channel_id)
+                    .topic(text)
+                    .unwrap()
+                    .await;
+
+                match res {
+                    Ok(_) => Ok(()),
+                    Err(err) => match err.kind() {
+                        ErrorType::Response {
+                            error: ApiError::Ratelimited(ratelimited),
+                            ..
+                        } => Err(StatusError::RateLimited(Duration::from_secs_f64(
+                            ratelimited.retry_after,
+                        ))),
+                        _ => Err(StatusError::Discord(err)),
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Sets the status of a voice channel, which Twilight doesn't know about.
+async fn set_voice_status(
+    token: &str,
+    channel_id: Id<ChannelMarker>,
+    text: &str,
+) -> Result<(), StatusError> {
+    #[derive(Deserialize)]
+    struct Ratelimited {
+        retry_after: f64,
+    }
+
+    let body = serde_json::json!({ "status": text }).to_string();
+    let req = Request::put(format!(
+        "{}/channels/{}/voice-status",
+        DISCORD_API, channel_id
+    ))
+    .header(AUTHORIZATION, token)
+    .header(CONTENT_TYPE, "application/json")
+    .body(Body::from(body))
+    .unwrap();
+
+    let res = https_client()
+        .request(req)
+        .await
+        .map_err(StatusError::Http)?;
+
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        StatusCode::TOO_MANY_REQUESTS => {
+            let body = hyper::body::to_bytes(res.into_body())
+                .await
+                .map_err(StatusError::Http)?;
+            let retry_after = serde_json::from_slice::<Ratelimited>(&body)
+                .map(|ratelimited| Duration::from_secs_f64(ratelimited.retry_after))
+                .unwrap_or(VOICE_STATUS_INTERVAL);
+
+            Err(StatusError::RateLimited(retry_after))
+        }
+        status => Err(StatusError::Status(status)),
+    }
+}
+
+/// An error setting the status of a channel.
+#[derive(Debug)]
+pub enum StatusError {
+    /// The request to set a voice channel status failed.
+    Http(hyper::Error),
+    /// The request to set a topic failed.
+    Discord(twilight_http::Error),
+    /// Discord won't let the status change for a while.
+    RateLimited(Duration),
+    /// Discord refused to set a voice channel status.
+    Status(StatusCode),
+}
+
+impl Display for StatusError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusError::Http(err) => Display::fmt(err, f),
+            StatusError::Discord(err) => Display::fmt(err, f),
+            StatusError::RateLimited(retry_after) => {
+                write!(f, "rate limited for {}s", retry_after.as_secs_f64())
+            }
+            StatusError::Status(status) => write!(f, "status code {}", status),
+        }
+    }
+}
+
+impl std::error::Error for StatusError {}
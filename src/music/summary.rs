@@ -0,0 +1,235 @@
+//! Summaries of enqueued playlists.
+//!
+//! A playlist is shown as one embed, [`PlaylistSummary`], with how many of
+//! its tracks were queued, how long they are together, what plays first, and
+//! what couldn't be queued. Batches of queries take a while, so the embed is
+//! updated with a [`ProgressReporter`] while they run.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use twilight_http::Client as HttpClient;
+use twilight_model::channel::message::embed::EmbedField;
+use twilight_model::channel::message::Embed;
+
+use super::commands::CommandData;
+use super::format_duration;
+use crate::ytdl::Track;
+
+/// How many failures are listed on a summary before the rest are counted.
+pub const MAX_FAILURE_NOTES: usize = 8;
+
+/// How often a summary is updated while its playlist is queried.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the name of a failed item can be on a summary.
+const MAX_NOTE_LENGTH: usize = 60;
+
+/// A playlist item that couldn't be queued.
+#[derive(Clone, Debug)]
+pub struct Failure {
+    /// The query or title of the item.
+    pub item: String,
+    /// Why it couldn't be queued.
+    pub reason: String,
+}
+
+/// A summary of an enqueued playlist, shown as an embed.
+#[derive(Clone, Debug)]
+pub struct PlaylistSummary {
+    embed: Embed,
+    count: usize,
+    length: Duration,
+    /// Whether a track's length is unknown, so `length` is too short.
+    partial_length: bool,
+    first: Option<String>,
+    failures: Vec<Failure>,
+    progress: Option<(usize, usize)>,
+    note: Option<String>,
+}
+
+impl PlaylistSummary {
+    /// Creates an empty summary on the embed of a playlist.
+    pub fn new(embed: Embed) -> PlaylistSummary {
+        PlaylistSummary {
+            embed,
+            count: 0,
+            length: Duration::ZERO,
+            partial_length: false,
+            first: None,
+            failures: Vec::new(),
+            progress: None,
+            note: None,
+        }
+    }
+
+    /// Shows how many of the playlist's queries are done, as `(done, total)`,
+    /// while it's still being queried.
+    pub fn with_progress(self, progress: Option<(usize, usize)>) -> PlaylistSummary {
+        PlaylistSummary { progress, ..self }
+    }
+
+    /// Adds a line under the summary, like that the playlist is an album.
+    pub fn with_note(self, note: impl Into<String>) -> PlaylistSummary {
+        PlaylistSummary {
+            note: Some(note.into()),
+            ..self
+        }
+    }
+
+    /// Counts tracks that were queued.
+    pub fn add_tracks<'a>(&mut self, tracks: impl IntoIterator<Item = &'a Track>) {
+        for track in tracks {
+            if self.first.is_none() {
+                self.first = Some(track.title.clone());
+            }
+
+            match track.duration {
+                Some(duration) => self.length += duration.saturating_sub(track.start),
+                None => self.partial_length = true,
+            }
+
+            self.count += 1;
+        }
+    }
+
+    /// Notes an item that couldn't be queued.
+    pub fn add_failure(&mut self, failure: Failure) {
+        self.failures.push(failure);
+    }
+
+    /// The items that couldn't be queued.
+    pub fn failures(&self) -> &[Failure] {
+        &self.failures
+    }
+
+    /// Builds the embed, with `fields` after the failures.
+    pub fn build(&self, command: &CommandData, mut fields: Vec<EmbedField>) -> Embed {
+        let mut lines = Vec::new();
+
+        if let Some((done, total)) = self.progress {
+            lines.push(command.trf(
+                "querying... {done}/{total}",
+                &[("done", &done), ("total", &total)],
+            ));
+        }
+
+        let title = self.embed.title.as_deref().unwrap_or_default();
+
+        if self.count > 0 {
+            let length = match self.partial_length {
+                true => format!("{}+", format_duration(self.length)),
+                false => format_duration(self.length),
+            };
+
+            lines.push(command.trf(
+                "queued {count} tracks from {title}, total length {length}, starting with \
+                    {first}",
+                &[
+                    ("count", &self.count),
+                    ("title", &title),
+                    ("length", &length),
+                    ("first", &self.first.as_deref().unwrap_or_default()),
+                ],
+            ));
+        } else if self.progress.is_none() {
+            lines.push(command.trf("queued nothing from {title}", &[("title", &title)]));
+        }
+
+        if let Some(note) = &self.note {
+            lines.push(note.clone());
+        }
+
+        if !self.failures.is_empty() {
+            fields.insert(0, self.failure_field(command));
+        }
+
+        Embed {
+            description: Some(lines.join("\n")).filter(|lines| !lines.is_empty()),
+            fields,
+            ..self.embed.clone()
+        }
+    }
+
+    fn failure_field(&self, command: &CommandData) -> EmbedField {
+        let mut value = self
+            .failures
+            .iter()
+            .take(MAX_FAILURE_NOTES)
+            .map(|failure| format!("- {}: {}", shorten(&failure.item), shorten(&failure.reason)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if self.failures.len() > MAX_FAILURE_NOTES {
+            let more = self.failures.len() - MAX_FAILURE_NOTES;
+            value.push('\n');
+            value.push_str(&command.trf("...and {count} more", &[("count", &more)]));
+        }
+
+        EmbedField {
+            inline: false,
+            name: command.trf("failed ({count})", &[("count", &self.failures.len())]),
+            // fields can only be so long
+            value: value.chars().take(1024).collect(),
+        }
+    }
+}
+
+/// Updates a command's response with a summary as its playlist is queried,
+/// at most every [`PROGRESS_INTERVAL`].
+pub struct ProgressReporter {
+    command: CommandData,
+    http_client: Arc<HttpClient>,
+    last_update: Option<Instant>,
+}
+
+impl ProgressReporter {
+    /// Creates a new `ProgressReporter` for the response of `command`.
+    pub fn new(command: CommandData, http_client: Arc<HttpClient>) -> ProgressReporter {
+        ProgressReporter {
+            command,
+            http_client,
+            last_update: None,
+        }
+    }
+
+    /// Shows `summary`, unless the last one was shown too recently.
+    pub async fn report(&mut self, summary: &PlaylistSummary) {
+        let now = Instant::now();
+
+        if let Some(last_update) = self.last_update {
+            if now.duration_since(last_update) < PROGRESS_INTERVAL {
+                return;
+            }
+        }
+
+        self.last_update = Some(now);
+
+        self.command
+            .respond(&self.http_client)
+            .embed(summary.build(&self.command, Vec::new()))
+            .update()
+            .await;
+    }
+}
+
+fn shorten(item: &str) -> String {
+    match item.char_indices().nth(MAX_NOTE_LENGTH) {
+        Some((end, _)) => format!("{}...", &item[..end]),
+        None => item.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortens_long_items() {
+        assert_eq!(shorten("short"), "short");
+
+        let long = "a".repeat(MAX_NOTE_LENGTH + 10);
+        assert_eq!(shorten(&long).len(), MAX_NOTE_LENGTH + 3);
+    }
+}
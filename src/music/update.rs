@@ -0,0 +1,365 @@
+//! Keeping yt-dlp working.
+//!
+//! Sites change, and yt-dlp can't extract them until it's updated. The
+//! [`YtdlUpdater`] logs the version of yt-dlp at startup and every
+//! [`YtdlUpdateConfig::check_interval`], and can update yt-dlp when its
+//! [extraction failures](YtdlConfig::extraction_failures) spike. The
+//! operator can also update it with `/admin update-ytdl`.
+//!
+//! The executable in use is never touched. It's copied next to itself, the
+//! copy is updated and checked, and then swapped in with
+//! [`YtdlConfig::set_executable`]. Streams that already started keep running
+//! on the old one.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::fs;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{error, info, warn};
+
+use super::commands::CommandData;
+use super::{QueueServer, QueueState, UserError};
+use crate::ytdl::{executable_version, YtdlConfig};
+
+/// How often extraction failures are counted for a spike.
+pub const FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// How often the version is checked, by default.
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How many failures in [`FAILURE_WINDOW`] are a spike, by default.
+pub const DEFAULT_FAILURE_THRESHOLD: u64 = 10;
+
+static YTDL_UPDATES: OnceLock<YtdlUpdateConfig> = OnceLock::new();
+
+/// The executables swapped in by updates, and the ones they replaced first.
+///
+/// Only one update runs at a time.
+static UPDATES: Mutex<BTreeMap<PathBuf, PathBuf>> = Mutex::const_new(BTreeMap::new());
+
+/// How yt-dlp is kept up to date.
+///
+/// If it was never initialized, yt-dlp is only updated by the operator.
+pub fn ytdl_updates() -> &'static YtdlUpdateConfig {
+    YTDL_UPDATES.get_or_init(YtdlUpdateConfig::default)
+}
+
+pub fn init_ytdl_updates<F>(f: F) -> &'static YtdlUpdateConfig
+where
+    F: FnOnce() -> YtdlUpdateConfig,
+{
+    YTDL_UPDATES.get_or_init(f)
+}
+
+/// How yt-dlp is kept up to date.
+#[derive(Clone, Debug)]
+pub struct YtdlUpdateConfig {
+    /// Whether yt-dlp is updated when extraction failures spike.
+    pub auto_update: bool,
+    /// How often the version of yt-dlp is checked.
+    pub check_interval: Duration,
+    /// How many extraction failures in [`FAILURE_WINDOW`] are a spike.
+    pub failure_threshold: u64,
+    /// The release to update to, like `stable@2024.08.06`, instead of the
+    /// latest one.
+    pub target: Option<String>,
+}
+
+impl YtdlUpdateConfig {
+    /// Reads the config from the environment.
+    ///
+    /// `YTDL_AUTO_UPDATE` turns on updates when failures spike.
+    /// `YTDL_CHECK_INTERVAL` is in seconds, `YTDL_FAILURE_THRESHOLD` is a
+    /// count, and `YTDL_UPDATE_TO` pins a release.
+    pub fn from_env() -> YtdlUpdateConfig {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+
+        YtdlUpdateConfig {
+            auto_update: env::var("YTDL_AUTO_UPDATE")
+                .map(|v| matches!(&*v, "1" | "true" | "yes"))
+                .unwrap_or_default(),
+            check_interval: var("YTDL_CHECK_INTERVAL")
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CHECK_INTERVAL),
+            failure_threshold: var("YTDL_FAILURE_THRESHOLD").unwrap_or(DEFAULT_FAILURE_THRESHOLD),
+            target: env::var("YTDL_UPDATE_TO").ok().filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+impl Default for YtdlUpdateConfig {
+    fn default() -> YtdlUpdateConfig {
+        YtdlUpdateConfig {
+            auto_update: false,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            target: None,
+        }
+    }
+}
+
+/// A finished update.
+#[derive(Clone, Debug)]
+pub struct Updated {
+    /// The version before the update, if it ran.
+    pub from: Option<String>,
+    /// The version after the update.
+    pub to: String,
+}
+
+impl Updated {
+    /// Whether yt-dlp was already up to date.
+    pub fn is_noop(&self) -> bool {
+        self.from.as_ref() == Some(&self.to)
+    }
+}
+
+/// An error updating yt-dlp.
+#[derive(Debug)]
+pub enum UpdateError {
+    /// The executable couldn't be copied or swapped.
+    Io(std::io::Error),
+    /// The executable isn't a file that can be found.
+    NotFound(String),
+    /// yt-dlp refused to update, like if it was installed with pip.
+    Failed(String),
+    /// The updated executable doesn't run.
+    Broken(std::io::Error),
+}
+
+impl Display for UpdateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Io(err) => Display::fmt(err, f),
+            UpdateError::NotFound(executable) => write!(f, "can't find {}", executable),
+            UpdateError::Failed(msg) => write!(f, "update failed: {}", msg),
+            UpdateError::Broken(err) => write!(f, "updated executable doesn't run: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UpdateError::Io(err) | UpdateError::Broken(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for UpdateError {
+    fn from(err: std::io::Error) -> UpdateError {
+        UpdateError::Io(err)
+    }
+}
+
+/// Updates the yt-dlp of `ytdl` to `target`, or the latest release, and
+/// swaps it in.
+pub async fn update_ytdl(ytdl: &YtdlConfig, target: Option<&str>) -> Result<Updated, UpdateError> {
+    let mut updates = UPDATES.lock().await;
+
+    let executable = ytdl.executable();
+    let path = resolve(&executable).ok_or_else(|| UpdateError::NotFound(executable.to_string()))?;
+    // updates are named after the executable they first replaced
+    let original = updates.get(&path).cloned().unwrap_or_else(|| path.clone());
+    let name = original
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let from = ytdl.version().await.ok();
+
+    let staging = original.with_file_name(format!("{}.update", name));
+    fs::copy(&path, &staging).await?;
+
+    let mut update = Command::new(&staging);
+    match target {
+        Some(target) => update.args(["--update-to", target]),
+        None => update.arg("-U"),
+    };
+
+    let out = update
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await;
+
+    let to = match out {
+        Ok(out) if out.status.success() => executable_version(&staging)
+            .await
+            .map_err(UpdateError::Broken),
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            let msg = stderr.lines().last().unwrap_or("no output").to_owned();
+            Err(UpdateError::Failed(msg))
+        }
+        Err(err) => Err(err.into()),
+    };
+
+    let to = match to {
+        Ok(to) => to,
+        Err(err) => {
+            let _ = fs::remove_file(&staging).await;
+            return Err(err);
+        }
+    };
+
+    let updated = Updated { from, to };
+
+    if updated.is_noop() {
+        fs::remove_file(&staging).await?;
+        return Ok(updated);
+    }
+
+    let new_path = original.with_file_name(format!("{}-{}", name, updated.to));
+    fs::rename(&staging, &new_path).await?;
+    ytdl.set_executable(new_path.to_string_lossy());
+    updates.insert(new_path.clone(), original.clone());
+
+    // the last update isn't needed anymore
+    if path != original && path != new_path {
+        updates.remove(&path);
+
+        if let Err(err) = fs::remove_file(&path).await {
+            warn!(%err, path = %path.display(), "failed to remove old yt-dlp");
+        }
+    }
+
+    info!(from = ?updated.from, to = updated.to, "updated yt-dlp");
+
+    Ok(updated)
+}
+
+/// Finds the file of an executable, searching `PATH` if it's just a name.
+fn resolve(executable: &str) -> Option<PathBuf> {
+    let path = Path::new(executable);
+
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_owned());
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(executable))
+        .find(|path| path.is_file())
+}
+
+/// Checks on yt-dlp in the background.
+pub struct YtdlUpdater {
+    config: YtdlUpdateConfig,
+    queue_server: Arc<QueueServer>,
+}
+
+impl YtdlUpdater {
+    /// Creates a new `YtdlUpdater` for the yt-dlp of a [`QueueServer`].
+    ///
+    /// After an update, the server's audio backend is checked again.
+    pub fn new(config: YtdlUpdateConfig, queue_server: Arc<QueueServer>) -> YtdlUpdater {
+        YtdlUpdater {
+            config,
+            queue_server,
+        }
+    }
+
+    /// Starts checking on yt-dlp, starting with its version.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut check = interval(self.config.check_interval);
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut failure_check = interval(FAILURE_WINDOW);
+            failure_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            let ytdl = self.queue_server.ytdl();
+            let mut failures = ytdl.extraction_failures();
+
+            loop {
+                tokio::select! {
+                    _ = check.tick() => match ytdl.version().await {
+                        Ok(version) => info!(version, "yt-dlp is working"),
+                        Err(err) => error!(%err, "yt-dlp doesn't run"),
+                    },
+                    _ = failure_check.tick() => {
+                        let spike = ytdl.extraction_failures() - failures;
+
+                        if spike >= self.config.failure_threshold {
+                            warn!(spike, "yt-dlp extraction failures spiked");
+
+                            if self.config.auto_update {
+                                let target = self.config.target.as_deref();
+
+                                match update_ytdl(ytdl, target).await {
+                                    Ok(updated) if !updated.is_noop() => {
+                                        self.queue_server.recheck_audio_backend().await;
+                                    }
+                                    Ok(_) => (),
+                                    Err(err) => error!(%err, "failed to update yt-dlp"),
+                                }
+                            }
+                        }
+
+                        failures = ytdl.extraction_failures();
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl QueueState {
+    pub(super) async fn update_ytdl(&self, command: &CommandData) -> Result<(), UserError> {
+        if !self.is_operator(command) {
+            return Err(UserError::NotOperator);
+        }
+
+        let http_client = self.queue_server.http_client.clone();
+        let queue_server = self.queue_server.clone();
+        let command = command.clone();
+
+        // updating takes a while, and the queue shouldn't wait on it
+        tokio::spawn(async move {
+            command.respond(&http_client).ack().await;
+
+            let ytdl = queue_server.ytdl();
+
+            let msg = match update_ytdl(ytdl, ytdl_updates().target.as_deref()).await {
+                Ok(updated) if updated.is_noop() => {
+                    command.trf("yt-dlp is up to date on {to}", &[("to", &updated.to)])
+                }
+                Ok(updated) => {
+                    queue_server.recheck_audio_backend().await;
+
+                    command.trf(
+                        "updated yt-dlp from {from} to {to}",
+                        &[
+                            ("from", &updated.from.as_deref().unwrap_or("?")),
+                            ("to", &updated.to),
+                        ],
+                    )
+                }
+                Err(err) => {
+                    error!(%err, "failed to update yt-dlp");
+
+                    command
+                        .respond(&http_client)
+                        .error(command.trf("failed to update yt-dlp: {err}", &[("err", &err)]))
+                        .update()
+                        .await;
+
+                    return;
+                }
+            };
+
+            command.respond(&http_client).content(msg).update().await;
+        });
+
+        Ok(())
+    }
+}
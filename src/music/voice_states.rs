@@ -0,0 +1,175 @@
+//! Voice states the cache doesn't have.
+//!
+//! The cache learns voice states from the gateway: all of a guild's when the
+//! guild becomes available, and changes as they happen. Right after a
+//! reconnect, before the guild syncs again, it can miss users joining, so a
+//! user in the bot's channel looks like they aren't in one. When the cache
+//! has no channel for a user, the queue asks Discord instead, and
+//! [warms](QueueServer::warm_voice_state) the cache with the answer.
+
+use std::fmt::{self, Display, Formatter};
+
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Request, StatusCode};
+use tracing::{debug, warn};
+use twilight_model::gateway::payload::incoming::VoiceStateUpdate;
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker, UserMarker},
+    Id,
+};
+use twilight_model::voice::VoiceState;
+
+use super::{QueueServer, QueueState};
+use crate::https::https_client;
+
+const DISCORD_API: &str = "https://discord.com/api/v10";
+
+/// The most requesters checked before the bot leaves an empty channel.
+const MAX_LISTENER_CHECKS: usize = 5;
+
+impl QueueServer {
+    /// The channel a user is in, asking Discord if the cache doesn't have
+    /// one.
+    pub async fn user_channel(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Option<Id<ChannelMarker>> {
+        if let Some(voice_state) = self.cache.voice_state(user_id, guild_id) {
+            return Some(voice_state.channel_id());
+        }
+
+        match self.warm_voice_state(guild_id, user_id).await {
+            Ok(channel_id) => channel_id,
+            Err(err) => {
+                warn!(%err, %guild_id, %user_id, "failed to fetch voice state");
+                None
+            }
+        }
+    }
+
+    /// Fetches a user's voice state from Discord and puts it in the cache.
+    ///
+    /// Returns the channel the user is in, if any.
+    pub async fn warm_voice_state(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<Option<Id<ChannelMarker>>, VoiceStateError> {
+        let token = self.http_client.token().unwrap_or_default();
+
+        let Some(mut voice_state) = fetch_voice_state(token, guild_id, user_id).await? else {
+            return Ok(None);
+        };
+
+        debug!(%guild_id, %user_id, channel_id = ?voice_state.channel_id, "warmed voice state");
+
+        // the cache only keeps voice states it knows the guild of
+        voice_state.guild_id = Some(guild_id);
+        let channel_id = voice_state.channel_id;
+        self.cache.update(&VoiceStateUpdate(voice_state));
+
+        Ok(channel_id)
+    }
+}
+
+impl QueueState {
+    /// Checks with Discord if anyone whose tracks are queued is still in
+    /// `channel_id`, for when the cache thinks the channel is empty.
+    pub(super) async fn requesters_in_channel(&self, channel_id: Id<ChannelMarker>) -> bool {
+        let mut requesters = Vec::new();
+
+        for track in self.playing.iter().chain(self.track_queue.iter()) {
+            match track.requester {
+                Some(user_id) if !requesters.contains(&user_id) => requesters.push(user_id),
+                _ => (),
+            }
+
+            if requesters.len() >= MAX_LISTENER_CHECKS {
+                break;
+            }
+        }
+
+        for user_id in requesters {
+            let res = self
+                .queue_server
+                .warm_voice_state(self.guild_id, user_id)
+                .await;
+
+            match res {
+                Ok(Some(user_channel_id)) if user_channel_id == channel_id => return true,
+                Ok(_) => (),
+                Err(err) => warn!(%err, %user_id, "failed to fetch voice state"),
+            }
+        }
+
+        false
+    }
+}
+
+/// Gets a user's voice state, which Twilight can't.
+///
+/// Discord answers with a `404` if the user isn't in a voice channel.
+async fn fetch_voice_state(
+    token: &str,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Option<VoiceState>, VoiceStateError> {
+    let req = Request::get(format!(
+        "{}/guilds/{}/voice-states/{}",
+        DISCORD_API, guild_id, user_id
+    ))
+    .header(AUTHORIZATION, token)
+    .body(Body::empty())
+    .unwrap();
+
+    let res = https_client()
+        .request(req)
+        .await
+        .map_err(VoiceStateError::Http)?;
+
+    match res.status() {
+        StatusCode::OK => {
+            let body = hyper::body::to_bytes(res.into_body())
+                .await
+                .map_err(VoiceStateError::Http)?;
+
+            serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(VoiceStateError::Json)
+        }
+        StatusCode::NOT_FOUND => Ok(None),
+        status => Err(VoiceStateError::Status(status)),
+    }
+}
+
+/// An error fetching a voice state.
+#[derive(Debug)]
+pub enum VoiceStateError {
+    /// The request failed.
+    Http(hyper::Error),
+    /// Discord refused to give the voice state.
+    Status(StatusCode),
+    /// The voice state couldn't be read.
+    Json(serde_json::Error),
+}
+
+impl Display for VoiceStateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VoiceStateError::Http(err) => Display::fmt(err, f),
+            VoiceStateError::Status(status) => write!(f, "status code {}", status),
+            VoiceStateError::Json(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl std::error::Error for VoiceStateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VoiceStateError::Http(err) => Some(err),
+            VoiceStateError::Json(err) => Some(err),
+            VoiceStateError::Status(_) => None,
+        }
+    }
+}
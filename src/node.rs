@@ -0,0 +1,478 @@
+//! Serving the voice client to other bots.
+//!
+//! This is the other side of [Lavalink][1]: instead of swc playing on a
+//! remote node, [`serve`] makes swc the node. Another bot, in any language,
+//! connects over a websocket, forwards its voice events, and tells swc what
+//! to load and play. Each guild gets a native [`Player`], so the audio work
+//! happens here.
+//!
+//! Anyone who can reach the node can have it run yt-dlp, so it won't be
+//! served without a password.
+//!
+//! # Protocol
+//! Every message is a JSON object with an `op`. The first message of a client
+//! has to be `identify`:
+//! ```json
+//! {"op": "identify", "userId": "...", "password": "..."}
+//! ```
+//! after which the node sends `{"op": "ready"}`. The client can then send:
+//! - `voiceUpdate`, with `guildId`, `channelId`, `sessionId`, `token` and
+//!   `endpoint`, from the main gateway's voice state and voice server
+//!   updates. This connects the guild's player.
+//! - `load`, with a `query` and a `nonce`. The node answers with `loaded` and
+//!   the same `nonce`, and a list of `tracks`, or `loadFailed` and a
+//!   `message`.
+//! - `play`, with a `guildId`, a track `url` and an optional `position` in
+//!   milliseconds.
+//! - `pause`, `resume`, `stop` and `destroy`, with a `guildId`.
+//!
+//! The node sends an `event` with the `guildId` and a `type` of `ready`,
+//! `playing`, `stopped` or `error` whenever a player has one, and a
+//! `playerUpdate` with the `position` of every player every few seconds.
+//! Messages the node doesn't understand are answered with an `error`.
+//!
+//! A client's players are destroyed when it disconnects.
+//!
+//! [1]: https://lavalink.dev
+
+use async_tungstenite::tokio::accept_async;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+use tungstenite::Message;
+use twilight_model::gateway::payload::incoming::{VoiceServerUpdate, VoiceStateUpdate};
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker, UserMarker},
+    Id,
+};
+use twilight_model::voice::VoiceState;
+
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+
+use crate::voice::{Event, EventType, Player, PlayerConfig, SourceBuilder};
+use crate::ytdl::{Query, Track};
+
+/// How often players' positions are sent.
+const PLAYER_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How the node is served.
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    /// The address to listen on.
+    pub addr: SocketAddr,
+    /// The password clients have to identify with. It can't be empty.
+    pub password: String,
+    /// The config of every player.
+    pub player: PlayerConfig,
+}
+
+impl NodeConfig {
+    /// Reads the config from `NODE_ADDR` and `NODE_PASSWORD`.
+    ///
+    /// Returns `None` if `NODE_ADDR` isn't set or isn't an address, or if
+    /// `NODE_PASSWORD` isn't set or is empty.
+    pub fn from_env() -> Option<NodeConfig> {
+        Some(NodeConfig {
+            addr: env::var("NODE_ADDR").ok()?.parse().ok()?,
+            password: env::var("NODE_PASSWORD").ok().filter(|p| !p.is_empty())?,
+            player: PlayerConfig::from_env(),
+        })
+    }
+}
+
+/// Listens for clients, serving each one in its own task.
+///
+/// This only returns if the address can't be listened on, or if the config
+/// has no password.
+pub async fn serve(config: NodeConfig) -> Result<(), std::io::Error> {
+    if config.password.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "refusing to serve without a password",
+        ));
+    }
+
+    let listener = TcpListener::bind(config.addr).await?;
+    info!(addr = %config.addr, "serving audio node");
+
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(%err, "failed to accept client");
+                continue;
+            }
+        };
+
+        let config = config.clone();
+        tokio::spawn(async move {
+            debug!(%addr, "client connected");
+            client_run(stream, config).await;
+            debug!(%addr, "client disconnected");
+        });
+    }
+}
+
+/// A message from a client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum Op {
+    #[serde(rename_all = "camelCase")]
+    Identify {
+        user_id: Id<UserMarker>,
+        #[serde(default)]
+        password: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    VoiceUpdate {
+        guild_id: Id<GuildMarker>,
+        channel_id: Option<Id<ChannelMarker>>,
+        session_id: String,
+        token: String,
+        endpoint: String,
+    },
+    Load {
+        query: String,
+        #[serde(default)]
+        nonce: Value,
+    },
+    #[serde(rename_all = "camelCase")]
+    Play {
+        guild_id: Id<GuildMarker>,
+        url: String,
+        /// Milliseconds into the track.
+        #[serde(default)]
+        position: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Pause { guild_id: Id<GuildMarker> },
+    #[serde(rename_all = "camelCase")]
+    Resume { guild_id: Id<GuildMarker> },
+    #[serde(rename_all = "camelCase")]
+    Stop { guild_id: Id<GuildMarker> },
+    #[serde(rename_all = "camelCase")]
+    Destroy { guild_id: Id<GuildMarker> },
+}
+
+/// The players of an identified client.
+struct Client {
+    user_id: Id<UserMarker>,
+    config: PlayerConfig,
+    players: HashMap<Id<GuildMarker>, Player>,
+    event_tx: UnboundedSender<Event>,
+    /// Messages from tasks the client started, like loads.
+    out_tx: UnboundedSender<Value>,
+}
+
+async fn client_run(stream: TcpStream, config: NodeConfig) {
+    let mut ws = match accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(err) => {
+            debug!(%err, "websocket handshake failed");
+            return;
+        }
+    };
+
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+    let mut client: Option<Client> = None;
+
+    let mut updates = interval(PLAYER_UPDATE_INTERVAL);
+    updates.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let out = tokio::select! {
+            msg = ws.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => {
+                        debug!(%err, "client websocket error");
+                        break;
+                    }
+                };
+
+                let op = match serde_json::from_str::<Op>(&text) {
+                    Ok(op) => op,
+                    Err(err) => {
+                        send(&mut ws, vec![error_message(err)]).await;
+                        continue;
+                    }
+                };
+
+                match (op, client.as_mut()) {
+                    (Op::Identify { user_id, password }, None) => {
+                        if !password_matches(&password, &config.password) {
+                            let _ = ws.send(text_message(error_message("wrong password"))).await;
+                            break;
+                        }
+
+                        client = Some(Client {
+                            user_id,
+                            config: config.player.clone(),
+                            players: HashMap::new(),
+                            event_tx: event_tx.clone(),
+                            out_tx: out_tx.clone(),
+                        });
+
+                        vec![json!({ "op": "ready" })]
+                    }
+                    (_, None) => vec![error_message("identify first")],
+                    (Op::Identify { .. }, Some(_)) => vec![error_message("already identified")],
+                    (op, Some(client)) => client.handle(op).err().map(error_message).into_iter().collect(),
+                }
+            }
+            Some(event) = event_rx.recv() => event_message(event).into_iter().collect(),
+            Some(out) = out_rx.recv() => vec![out],
+            _ = updates.tick(), if client.is_some() => {
+                client.as_mut().map(Client::player_updates).unwrap_or_default()
+            }
+        };
+
+        if !send(&mut ws, out).await {
+            break;
+        }
+    }
+
+    if let Some(client) = client {
+        for player in client.players.values() {
+            let _ = player.disconnect();
+        }
+    }
+}
+
+impl Client {
+    /// Handles a message, returning an error to send back if it failed.
+    fn handle(&mut self, op: Op) -> Result<(), String> {
+        match op {
+            Op::Identify { .. } => unreachable!(),
+            Op::VoiceUpdate {
+                guild_id,
+                channel_id,
+                session_id,
+                token,
+                endpoint,
+            } => {
+                let player = self
+                    .players
+                    .entry(guild_id)
+                    .and_modify(|player| {
+                        // a player that crashed is replaced
+                        if player.is_closed() {
+                            *player = Player::with_config(
+                                self.user_id,
+                                guild_id,
+                                self.event_tx.clone(),
+                                self.config.clone(),
+                            );
+                        }
+                    })
+                    .or_insert_with(|| {
+                        Player::with_config(
+                            self.user_id,
+                            guild_id,
+                            self.event_tx.clone(),
+                            self.config.clone(),
+                        )
+                    });
+
+                let voice_state = VoiceState {
+                    channel_id,
+                    guild_id: Some(guild_id),
+                    user_id: self.user_id,
+                    deaf: false,
+                    mute: false,
+                    self_deaf: false,
+                    self_mute: false,
+                    self_stream: false,
+                    self_video: false,
+                    suppress: false,
+                    session_id,
+                    member: None,
+                    request_to_speak_timestamp: None,
+                };
+
+                player
+                    .voice_state_update(Box::new(VoiceStateUpdate(voice_state)))
+                    .and_then(|_| {
+                        player.voice_server_update(VoiceServerUpdate {
+                            endpoint: Some(endpoint),
+                            guild_id,
+                            token,
+                        })
+                    })
+                    .map_err(|err| err.to_string())
+            }
+            Op::Load { query, nonce } => {
+                let out_tx = self.out_tx.clone();
+                let ytdl = self.config.ytdl.clone();
+
+                // loads are slow, so they don't hold up the client
+                tokio::spawn(async move {
+                    let msg = match Query::query(&ytdl, &query).await {
+                        Ok(Query::Track(track)) => json!({
+                            "op": "loaded",
+                            "nonce": nonce,
+                            "tracks": [track_json(&track)],
+                        }),
+                        Ok(Query::Playlist(playlist)) => json!({
+                            "op": "loaded",
+                            "nonce": nonce,
+                            "tracks": playlist.tracks.iter().map(track_json).collect::<Vec<_>>(),
+                        }),
+                        Err(err) => json!({
+                            "op": "loadFailed",
+                            "nonce": nonce,
+                            "message": err.to_string(),
+                        }),
+                    };
+
+                    let _ = out_tx.send(msg);
+                });
+
+                Ok(())
+            }
+            Op::Play {
+                guild_id,
+                url,
+                position,
+            } => {
+                let player = self.player(guild_id)?;
+                let source = SourceBuilder::ytdl(url)
+                    .offset(Duration::from_millis(position))
+                    .fade_in(player.fade_in())
+                    .ytdl_config(player.ytdl().clone())
+                    .build()
+                    .map_err(|err| err.to_string())?;
+
+                player.play(source).map_err(|err| err.to_string())
+            }
+            Op::Pause { guild_id } => self.player(guild_id)?.pause().map_err(|e| e.to_string()),
+            Op::Resume { guild_id } => self.player(guild_id)?.resume().map_err(|e| e.to_string()),
+            Op::Stop { guild_id } => self.player(guild_id)?.stop().map_err(|e| e.to_string()),
+            Op::Destroy { guild_id } => {
+                if let Some(player) = self.players.remove(&guild_id) {
+                    let _ = player.disconnect();
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn player(&self, guild_id: Id<GuildMarker>) -> Result<&Player, String> {
+        self.players
+            .get(&guild_id)
+            .ok_or_else(|| String::from("no player for guild, send voiceUpdate first"))
+    }
+
+    fn player_updates(&mut self) -> Vec<Value> {
+        self.players.retain(|_, player| !player.is_closed());
+
+        self.players
+            .iter()
+            .map(|(guild_id, player)| {
+                json!({
+                    "op": "playerUpdate",
+                    "guildId": guild_id.to_string(),
+                    "playing": player.playing(),
+                    "paused": player.paused(),
+                    "position": player.position().as_millis() as u64,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Sends messages to the client, returning `false` if it's gone.
+async fn send<S>(ws: &mut S, out: Vec<Value>) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    for msg in out {
+        if let Err(err) = ws.send(text_message(msg)).await {
+            error!(%err, "failed to send to client");
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Compares passwords in constant time, so how long it takes doesn't give
+/// away how much of the password was right.
+fn password_matches(password: &str, expected: &str) -> bool {
+    password.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+fn text_message(msg: Value) -> Message {
+    Message::Text(msg.to_string())
+}
+
+fn error_message(err: impl ToString) -> Value {
+    json!({ "op": "error", "message": err.to_string() })
+}
+
+/// Converts a player event to a message, if clients care about it.
+fn event_message(event: Event) -> Option<Value> {
+    let (kind, message) = match event.kind {
+        EventType::Ready => ("ready", None),
+        EventType::Playing => ("playing", None),
+        EventType::Stopped => ("stopped", None),
+        EventType::Error(err) => ("error", Some(err.to_string())),
+        EventType::ListenerJoined(_) | EventType::ListenerLeft(_) => return None,
+    };
+
+    Some(json!({
+        "op": "event",
+        "guildId": event.guild_id.to_string(),
+        "type": kind,
+        "message": message,
+    }))
+}
+
+fn track_json(track: &Track) -> Value {
+    json!({
+        "url": track.url,
+        "title": track.title,
+        "author": track.author.name,
+        "thumbnailUrl": track.thumbnail_url,
+        "duration": track.duration.map(|d| d.as_millis() as u64),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ops() {
+        let op = serde_json::from_str::<Op>(
+            r#"{"op": "play", "guildId": "1234", "url": "https://example.com"}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            op,
+            Op::Play { guild_id, position: 0, .. } if guild_id == Id::new(1234)
+        ));
+
+        let op = serde_json::from_str::<Op>(r#"{"op": "identify", "userId": "5"}"#).unwrap();
+        assert!(matches!(op, Op::Identify { password, .. } if password.is_empty()));
+
+        assert!(serde_json::from_str::<Op>(r#"{"op": "nope"}"#).is_err());
+
+        assert!(password_matches("hunter2", "hunter2"));
+        assert!(!password_matches("hunter3", "hunter2"));
+        assert!(!password_matches("", "hunter2"));
+    }
+}
@@ -0,0 +1,411 @@
+//! Scrobbling to Last.fm and ListenBrainz.
+//!
+//! The [`Scrobbler`] listens to [`QueueEvent`]s and submits the tracks that
+//! are played. Each guild can have its own service in the [`Store`], set
+//! with `/scrobble`; guilds without one fall back to the operator's service,
+//! if there is one.
+
+use crate::https::{https_client, HttpsClient};
+use crate::music::QueueEvent;
+use crate::store::Store;
+use crate::ytdl::Track;
+
+use hyper::{header, Body, Method, Request, StatusCode};
+
+use md5::{Digest, Md5};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use std::env;
+use std::fmt::{self, Display, Formatter, Write as _};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, error, warn};
+
+const LASTFM_API: &str = "https://ws.audioscrobbler.com/2.0/";
+const LISTENBRAINZ_API: &str = "https://api.listenbrainz.org/1/submit-listens";
+
+/// Tracks shorter than this are never scrobbled.
+const MIN_LENGTH: Duration = Duration::from_secs(30);
+
+/// A track counts as listened to after this much of it was played, even if
+/// that's less than half of it.
+const MAX_LISTEN: Duration = Duration::from_secs(240);
+
+/// A service to scrobble to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "service", rename_all = "lowercase")]
+pub enum Service {
+    /// Last.fm, with an authenticated session.
+    LastFm {
+        api_key: String,
+        api_secret: String,
+        session_key: String,
+    },
+    /// ListenBrainz, with a user token.
+    ListenBrainz { token: String },
+}
+
+impl Service {
+    /// Gets the operator's service from the environment.
+    ///
+    /// Last.fm is configured with `LASTFM_API_KEY`, `LASTFM_API_SECRET` and
+    /// `LASTFM_SESSION_KEY`, and ListenBrainz with `LISTENBRAINZ_TOKEN`. If
+    /// both are configured, Last.fm is used.
+    pub fn from_env() -> Option<Service> {
+        let lastfm = (
+            env::var("LASTFM_API_KEY"),
+            env::var("LASTFM_API_SECRET"),
+            env::var("LASTFM_SESSION_KEY"),
+        );
+
+        if let (Ok(api_key), Ok(api_secret), Ok(session_key)) = lastfm {
+            Some(Service::LastFm {
+                api_key,
+                api_secret,
+                session_key,
+            })
+        } else {
+            env::var("LISTENBRAINZ_TOKEN")
+                .ok()
+                .map(|token| Service::ListenBrainz { token })
+        }
+    }
+}
+
+/// Submits played tracks to scrobbling services.
+pub struct Scrobbler {
+    client: HttpsClient,
+    store: Arc<Store>,
+    operator: Option<Service>,
+}
+
+impl Scrobbler {
+    /// Creates a new `Scrobbler`.
+    ///
+    /// `operator` is used for guilds that don't have their own service.
+    pub fn new(store: Arc<Store>, operator: Option<Service>) -> Scrobbler {
+        Scrobbler {
+            client: https_client().clone(),
+            store,
+            operator,
+        }
+    }
+
+    /// Starts scrobbling the events from `events`.
+    ///
+    /// The task stops once the sender is dropped.
+    pub fn start(self, mut events: broadcast::Receiver<QueueEvent>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => self.handle_event(event).await,
+                    Err(RecvError::Lagged(count)) => warn!(count, "scrobbler missed events"),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    async fn handle_event(&self, event: QueueEvent) {
+        let (QueueEvent::TrackStarted { track, .. } | QueueEvent::TrackEnded { track, .. }) =
+            &event
+        else {
+            return;
+        };
+
+        // speech isn't music
+        if track.speech.is_some() {
+            return;
+        }
+
+        let res = match event {
+            QueueEvent::TrackStarted { guild_id, track } => {
+                let Some(service) = self.service(guild_id).await else {
+                    return;
+                };
+
+                self.now_playing(&service, &track).await
+            }
+            QueueEvent::TrackEnded {
+                guild_id,
+                track,
+                played,
+            } => {
+                if !should_scrobble(track.duration, played) {
+                    return;
+                }
+
+                let Some(service) = self.service(guild_id).await else {
+                    return;
+                };
+
+                let started_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .saturating_sub(played);
+
+                self.scrobble(&service, &track, started_at).await
+            }
+            QueueEvent::TrackUnavailable { .. } | QueueEvent::Guardrail(_) => return,
+        };
+
+        if let Err(err) = res {
+            error!(%err, "failed to scrobble");
+        }
+    }
+
+    /// Gets the service to scrobble to for a guild.
+    async fn service(&self, guild_id: Id<GuildMarker>) -> Option<Service> {
+        self.store
+            .read(|store| store.scrobblers.get(&guild_id).cloned())
+            .await
+            .or_else(|| self.operator.clone())
+    }
+
+    async fn now_playing(&self, service: &Service, track: &Track) -> Result<(), Error> {
+        match service {
+            Service::LastFm {
+                api_key,
+                api_secret,
+                session_key,
+            } => {
+                let service = LastFm {
+                    api_key,
+                    api_secret,
+                    session_key,
+                };
+
+                self.lastfm(&service, "track.updateNowPlaying", track, &[])
+                    .await
+            }
+            Service::ListenBrainz { token } => {
+                let body = json!({
+                    "listen_type": "playing_now",
+                    "payload": [{ "track_metadata": listenbrainz_metadata(track) }],
+                });
+
+                self.listenbrainz(token, body).await
+            }
+        }
+    }
+
+    async fn scrobble(
+        &self,
+        service: &Service,
+        track: &Track,
+        started_at: Duration,
+    ) -> Result<(), Error> {
+        let timestamp = started_at.as_secs().to_string();
+
+        match service {
+            Service::LastFm {
+                api_key,
+                api_secret,
+                session_key,
+            } => {
+                let service = LastFm {
+                    api_key,
+                    api_secret,
+                    session_key,
+                };
+
+                self.lastfm(
+                    &service,
+                    "track.scrobble",
+                    track,
+                    &[("timestamp", &timestamp)],
+                )
+                .await
+            }
+            Service::ListenBrainz { token } => {
+                let body = json!({
+                    "listen_type": "single",
+                    "payload": [{
+                        "listened_at": started_at.as_secs(),
+                        "track_metadata": listenbrainz_metadata(track),
+                    }],
+                });
+
+                self.listenbrainz(token, body).await
+            }
+        }
+    }
+
+    /// Calls a signed Last.fm method about a track.
+    async fn lastfm(
+        &self,
+        service: &LastFm<'_>,
+        method: &str,
+        track: &Track,
+        extra: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        let LastFm {
+            api_key,
+            api_secret,
+            session_key,
+        } = service;
+
+        let duration = track.duration.map(|d| d.as_secs().to_string());
+
+        let mut params = vec![
+            ("api_key", *api_key),
+            ("artist", track.author.name.as_str()),
+            ("method", method),
+            ("sk", *session_key),
+            ("track", track.title.as_str()),
+        ];
+        if let Some(duration) = duration.as_deref() {
+            params.push(("duration", duration));
+        }
+        params.extend_from_slice(extra);
+
+        // the signature is the md5 of every parameter, sorted by name, with
+        // the secret at the end
+        params.sort_by_key(|(name, _)| *name);
+
+        let mut sig = Md5::new();
+        for (name, value) in params.iter() {
+            sig.update(name);
+            sig.update(value);
+        }
+        sig.update(api_secret);
+
+        let mut api_sig = String::with_capacity(32);
+        for byte in sig.finalize() {
+            write!(&mut api_sig, "{:02x}", byte).unwrap();
+        }
+
+        let body = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .append_pair("api_sig", &api_sig)
+            .append_pair("format", "json")
+            .finish();
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(LASTFM_API)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .map_err(Error::Request)?;
+
+        self.send(request).await
+    }
+
+    /// Submits listens to ListenBrainz.
+    async fn listenbrainz(&self, token: &str, body: serde_json::Value) -> Result<(), Error> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(LISTENBRAINZ_API)
+            .header(header::AUTHORIZATION, format!("Token {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .map_err(Error::Request)?;
+
+        self.send(request).await
+    }
+
+    async fn send(&self, request: Request<Body>) -> Result<(), Error> {
+        let response = self.client.request(request).await.map_err(Error::Http)?;
+
+        match response.status() {
+            status if status.is_success() => {
+                debug!("scrobbled");
+                Ok(())
+            }
+            status => Err(Error::Status(status)),
+        }
+    }
+}
+
+/// Borrowed Last.fm credentials.
+struct LastFm<'a> {
+    api_key: &'a str,
+    api_secret: &'a str,
+    session_key: &'a str,
+}
+
+/// Checks if enough of a track was played to scrobble it.
+///
+/// A track is scrobbled if it is longer than 30 seconds and at least half of
+/// it or 4 minutes of it, whichever is shorter, was played. Tracks without a
+/// known length only need the 4 minutes.
+pub fn should_scrobble(duration: Option<Duration>, played: Duration) -> bool {
+    match duration {
+        Some(duration) if duration < MIN_LENGTH => false,
+        Some(duration) => played >= (duration / 2).min(MAX_LISTEN),
+        None => played >= MAX_LISTEN,
+    }
+}
+
+fn listenbrainz_metadata(track: &Track) -> serde_json::Value {
+    json!({
+        "artist_name": track.author.name,
+        "track_name": track.title,
+        "additional_info": {
+            "origin_url": track.url,
+            "duration_ms": track.duration.map(|d| d.as_millis() as u64),
+            "submission_client": "swc",
+        },
+    })
+}
+
+/// An error submitting a scrobble.
+#[derive(Debug)]
+pub enum Error {
+    /// The request couldn't be built.
+    Request(hyper::http::Error),
+    /// Http error.
+    Http(hyper::Error),
+    /// The service rejected the request.
+    Status(StatusCode),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Request(err) => Display::fmt(err, f),
+            Error::Http(err) => Display::fmt(err, f),
+            Error::Status(status) => write!(f, "service responded with {}", status),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Request(err) => Some(err),
+            Error::Http(err) => Some(err),
+            Error::Status(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrobbles_listened_tracks() {
+        let secs = Duration::from_secs;
+
+        // too short to ever scrobble
+        assert!(!should_scrobble(Some(secs(20)), secs(20)));
+        // half of the track
+        assert!(!should_scrobble(Some(secs(120)), secs(59)));
+        assert!(should_scrobble(Some(secs(120)), secs(60)));
+        // 4 minutes of a long track
+        assert!(!should_scrobble(Some(secs(3600)), secs(239)));
+        assert!(should_scrobble(Some(secs(3600)), secs(240)));
+        // no known length
+        assert!(!should_scrobble(None, secs(120)));
+        assert!(should_scrobble(None, secs(240)));
+    }
+}
@@ -0,0 +1,108 @@
+//! Checking that the host gives the bot what it needs.
+//!
+//! Queues read guilds, channels and voice states from the cache, which only
+//! has them if the shard asked Discord for them with its intents and the
+//! cache was told to keep them. Without them, nothing fails outright: users
+//! just look like they aren't in a voice channel. [`validate_setup`] catches
+//! this at startup instead.
+
+use std::fmt::{self, Display, Formatter};
+
+use twilight_cache_inmemory::{Config as CacheConfig, ResourceType};
+use twilight_gateway::Intents;
+
+/// The intents queues need, with their names.
+const INTENTS: &[(Intents, &str)] = &[
+    (Intents::GUILDS, "GUILDS"),
+    (Intents::GUILD_VOICE_STATES, "GUILD_VOICE_STATES"),
+    #[cfg(feature = "text-commands")]
+    (Intents::GUILD_MESSAGES, "GUILD_MESSAGES"),
+    #[cfg(feature = "text-commands")]
+    (Intents::MESSAGE_CONTENT, "MESSAGE_CONTENT"),
+];
+
+/// The resource types queues need cached, with their names.
+const RESOURCE_TYPES: &[(ResourceType, &str)] = &[
+    (ResourceType::GUILD, "GUILD"),
+    (ResourceType::CHANNEL, "CHANNEL"),
+    (ResourceType::VOICE_STATE, "VOICE_STATE"),
+    // text commands work out permissions from roles
+    #[cfg(feature = "text-commands")]
+    (ResourceType::ROLE, "ROLE"),
+];
+
+/// Checks that a shard's intents and a cache's config are enough for a
+/// [`QueueServer`](crate::music::QueueServer).
+pub fn validate_setup(intents: Intents, cache_config: &CacheConfig) -> Result<(), SetupError> {
+    let resource_types = cache_config.resource_types();
+
+    let err = SetupError {
+        intents: INTENTS
+            .iter()
+            .filter(|(intent, _)| !intents.contains(*intent))
+            .map(|(_, name)| *name)
+            .collect(),
+        resource_types: RESOURCE_TYPES
+            .iter()
+            .filter(|(resource_type, _)| !resource_types.contains(*resource_type))
+            .map(|(_, name)| *name)
+            .collect(),
+    };
+
+    if err.intents.is_empty() && err.resource_types.is_empty() {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+/// What the host didn't give the bot.
+#[derive(Debug)]
+pub struct SetupError {
+    /// The names of the missing intents.
+    pub intents: Vec<&'static str>,
+    /// The names of the resource types the cache doesn't keep.
+    pub resource_types: Vec<&'static str>,
+}
+
+impl Display for SetupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("the bot isn't set up right:")?;
+
+        if !self.intents.is_empty() {
+            write!(f, " missing intents {}", self.intents.join(", "))?;
+        }
+
+        if !self.resource_types.is_empty() {
+            if !self.intents.is_empty() {
+                f.write_str(";")?;
+            }
+
+            write!(f, " cache doesn't keep {}", self.resource_types.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_missing() {
+        let mut cache_config = CacheConfig::new();
+        *cache_config.resource_types_mut() = ResourceType::GUILD | ResourceType::CHANNEL;
+
+        let err = validate_setup(Intents::GUILDS, &cache_config).unwrap_err();
+        assert!(err.intents.contains(&"GUILD_VOICE_STATES"));
+        assert!(!err.intents.contains(&"GUILDS"));
+        assert!(err.resource_types.contains(&"VOICE_STATE"));
+
+        *cache_config.resource_types_mut() = ResourceType::all();
+        let intents = Intents::all();
+        assert!(validate_setup(intents, &cache_config).is_ok());
+    }
+}
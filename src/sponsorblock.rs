@@ -0,0 +1,209 @@
+//! Skipping sponsored segments with [SponsorBlock][1].
+//!
+//! SponsorBlock is a crowdsourced database of the parts of YouTube videos
+//! that aren't the video itself: sponsor reads, intros, outros and so on. The
+//! queue looks up the segments of the playing track and seeks past them.
+//!
+//! [1]: https://sponsor.ajay.app
+
+use hyper::StatusCode;
+
+use serde::Deserialize;
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use crate::https::{https_client, HttpsClient};
+
+const SPONSORBLOCK_API: &str = "https://sponsor.ajay.app/api/skipSegments";
+
+/// A kind of segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    /// A paid promotion.
+    Sponsor,
+    /// An unpaid promotion, like the creator's merch.
+    SelfPromo,
+    /// A reminder to like and subscribe.
+    Interaction,
+    /// An intro animation or a recap.
+    Intro,
+    /// Credits or an endcard.
+    Outro,
+}
+
+impl Category {
+    /// The name SponsorBlock uses for the category.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Sponsor => "sponsor",
+            Category::SelfPromo => "selfpromo",
+            Category::Interaction => "interaction",
+            Category::Intro => "intro",
+            Category::Outro => "outro",
+        }
+    }
+}
+
+/// A part of a video to skip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// A SponsorBlock client.
+pub struct SponsorBlock {
+    client: HttpsClient,
+}
+
+impl SponsorBlock {
+    /// Creates a new `SponsorBlock` client.
+    pub fn new() -> SponsorBlock {
+        SponsorBlock {
+            client: https_client().clone(),
+        }
+    }
+
+    /// Gets the segments of a YouTube video in any of `categories`, in order.
+    ///
+    /// Overlapping segments are merged, so they can be skipped one after
+    /// another.
+    pub async fn segments(
+        &self,
+        video_id: &str,
+        categories: &[Category],
+    ) -> Result<Vec<Segment>, Error> {
+        #[derive(Deserialize)]
+        struct ApiSegment {
+            segment: (f64, f64),
+        }
+
+        let categories = format!(
+            "[{}]",
+            categories
+                .iter()
+                .map(|category| format!("\"{}\"", category.name()))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("videoID", video_id)
+            .append_pair("categories", &categories)
+            .finish();
+
+        let uri = format!("{}?{}", SPONSORBLOCK_API, query)
+            .parse()
+            .map_err(|_| Error::InvalidId)?;
+        let res = self.client.get(uri).await.map_err(Error::Http)?;
+
+        // no segments are a 404
+        match res.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Ok(Vec::new()),
+            status => return Err(Error::Status(status)),
+        }
+
+        let body = hyper::body::to_bytes(res.into_body())
+            .await
+            .map_err(Error::Http)?;
+        let segments: Vec<ApiSegment> = serde_json::from_slice(&body).map_err(Error::Json)?;
+
+        Ok(merge(segments.into_iter().filter_map(
+            |ApiSegment { segment }| {
+                let (start, end) = segment;
+
+                // the API has been known to return garbage
+                if start.is_finite() && end.is_finite() && 0. <= start && start < end {
+                    Some(Segment {
+                        start: Duration::from_secs_f64(start),
+                        end: Duration::from_secs_f64(end),
+                    })
+                } else {
+                    None
+                }
+            },
+        )))
+    }
+}
+
+impl Default for SponsorBlock {
+    fn default() -> SponsorBlock {
+        SponsorBlock::new()
+    }
+}
+
+/// Sorts segments and merges the ones that overlap.
+fn merge(segments: impl IntoIterator<Item = Segment>) -> Vec<Segment> {
+    let mut segments = segments.into_iter().collect::<Vec<_>>();
+    segments.sort_by_key(|segment| segment.start);
+
+    let mut merged: Vec<Segment> = Vec::with_capacity(segments.len());
+
+    for segment in segments {
+        match merged.last_mut() {
+            Some(last) if segment.start <= last.end => last.end = last.end.max(segment.end),
+            _ => merged.push(segment),
+        }
+    }
+
+    merged
+}
+
+/// An error from SponsorBlock.
+#[derive(Debug)]
+pub enum Error {
+    /// The video id can't be put in a url.
+    InvalidId,
+    /// The request failed.
+    Http(hyper::Error),
+    /// SponsorBlock responded with an error.
+    Status(StatusCode),
+    /// The response couldn't be deserialized.
+    Json(serde_json::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidId => f.write_str("invalid video id"),
+            Error::Http(err) => Display::fmt(err, f),
+            Error::Status(status) => write!(f, "sponsorblock responded with {}", status),
+            Error::Json(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: u64, end: u64) -> Segment {
+        Segment {
+            start: Duration::from_secs(start),
+            end: Duration::from_secs(end),
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_segments() {
+        let merged = merge([
+            segment(50, 60),
+            segment(0, 10),
+            segment(5, 20),
+            segment(20, 30),
+        ]);
+
+        assert_eq!(merged, [segment(0, 30), segment(50, 60)]);
+    }
+}
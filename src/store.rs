@@ -0,0 +1,593 @@
+//! Persistent storage.
+//!
+//! Everything is kept in memory and written out to a single JSON file
+//! whenever it changes. The bot doesn't store much, so this is plenty.
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::Mutex;
+
+use crate::music::{QueueMode, SponsorBlockMode};
+use crate::scrobble::Service;
+use crate::ytdl::{Author, Track};
+
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker, RoleMarker, UserMarker},
+    Id,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tracing::debug;
+
+/// Persistent storage, backed by a JSON file.
+#[derive(Debug)]
+pub struct Store {
+    path: Option<PathBuf>,
+    data: Mutex<StoreData>,
+}
+
+impl Store {
+    /// Opens a store at `path`.
+    ///
+    /// If the file doesn't exist yet, the store starts out empty and the file
+    /// is created on the first write.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Store, Error> {
+        let path = path.into();
+
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(Error::Json)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => StoreData::default(),
+            Err(err) => return Err(Error::Io(err)),
+        };
+
+        Ok(Store {
+            path: Some(path),
+            data: Mutex::new(data),
+        })
+    }
+
+    /// Creates a store that isn't saved anywhere.
+    pub fn in_memory() -> Store {
+        Store {
+            path: None,
+            data: Mutex::default(),
+        }
+    }
+
+    /// Reads from the store.
+    pub async fn read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&StoreData) -> R,
+    {
+        f(&*self.data.lock().await)
+    }
+
+    /// Changes the store, saving it afterwards.
+    pub async fn update<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut StoreData) -> R,
+    {
+        let mut data = self.data.lock().await;
+
+        let result = f(&mut data);
+
+        if let Some(path) = self.path.as_ref() {
+            let json = serde_json::to_vec(&*data).map_err(Error::Json)?;
+
+            // write to a temporary file first so a crash halfway through
+            // doesn't eat the store
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+
+            tokio::fs::write(&tmp, json).await.map_err(Error::Io)?;
+            tokio::fs::rename(&tmp, path).await.map_err(Error::Io)?;
+
+            debug!(?path, "saved store");
+        }
+
+        Ok(result)
+    }
+}
+
+/// Everything in a [`Store`].
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StoreData {
+    /// Each user's bookmarks.
+    #[serde(default)]
+    pub bookmarks: HashMap<Id<UserMarker>, Vec<Bookmark>>,
+    /// Where each guild scrobbles to.
+    #[serde(default)]
+    pub scrobblers: HashMap<Id<GuildMarker>, Service>,
+    /// Each guild's saved playlists.
+    #[serde(default)]
+    pub playlists: HashMap<Id<GuildMarker>, Vec<SavedPlaylist>>,
+    /// The role each guild needs to control the queue, if any.
+    #[serde(default)]
+    pub dj_roles: HashMap<Id<GuildMarker>, Id<RoleMarker>>,
+    /// Which guilds can use the bot, set by the operator.
+    #[serde(default)]
+    pub guild_access: GuildAccess,
+    /// The features the operator turned off in each guild.
+    #[serde(default)]
+    pub disabled_features: HashMap<Id<GuildMarker>, HashSet<Feature>>,
+    /// What each guild doesn't let anyone play.
+    #[serde(default)]
+    pub blocklists: HashMap<Id<GuildMarker>, Blocklist>,
+    /// What tracks each guild lets users without the DJ role enqueue.
+    #[serde(default)]
+    pub track_limits: HashMap<Id<GuildMarker>, TrackLimits>,
+    /// Where each guild lets age-restricted tracks play, if it changed it.
+    #[serde(default)]
+    pub age_policies: HashMap<Id<GuildMarker>, AgePolicy>,
+    /// Which segments each guild skips with SponsorBlock, if any.
+    #[serde(default)]
+    pub sponsorblock: HashMap<Id<GuildMarker>, SponsorBlockMode>,
+    /// How each guild places new tracks in the queue, if it changed it.
+    #[serde(default)]
+    pub queue_modes: HashMap<Id<GuildMarker>, QueueMode>,
+    /// The guilds that turned on text commands.
+    #[serde(default)]
+    pub text_commands: HashSet<Id<GuildMarker>>,
+    /// The guilds that show track thumbnails full-size.
+    #[serde(default)]
+    pub large_thumbnails: HashSet<Id<GuildMarker>>,
+    /// The guilds that play tracks at the bitrate of the voice channel.
+    #[serde(default)]
+    pub high_quality: HashSet<Id<GuildMarker>>,
+    /// The guilds that get a summary of the session when the bot leaves on
+    /// its own.
+    #[serde(default)]
+    pub farewell: HashSet<Id<GuildMarker>>,
+    /// Where each guild shows the playing track, if anywhere.
+    #[serde(default)]
+    pub now_playing_status: HashMap<Id<GuildMarker>, NowPlayingStatus>,
+    /// What each guild has listened to.
+    #[serde(default)]
+    pub listening: HashMap<Id<GuildMarker>, ListeningStats>,
+    /// The voice channel and queue of each guild the bot was playing in, for
+    /// rejoining them after a restart.
+    #[serde(default)]
+    pub sessions: HashMap<Id<GuildMarker>, SavedSession>,
+    /// The topic each channel had before the playing track replaced it, to
+    /// put back even if the bot restarted in the meantime.
+    #[serde(default)]
+    pub replaced_topics: HashMap<Id<ChannelMarker>, String>,
+}
+
+impl StoreData {
+    /// Checks if a guild can use a feature.
+    pub fn feature_enabled(&self, guild_id: Id<GuildMarker>, feature: Feature) -> bool {
+        self.disabled_features
+            .get(&guild_id)
+            .is_none_or(|disabled| !disabled.contains(&feature))
+    }
+}
+
+/// Which guilds can use the bot.
+///
+/// A denied guild can never use the bot. If the allowlist is on, only
+/// allowed guilds can.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GuildAccess {
+    /// Whether only allowed guilds can use the bot.
+    #[serde(default)]
+    pub allowlist: bool,
+    /// The guilds on the allowlist.
+    #[serde(default)]
+    pub allowed: HashSet<Id<GuildMarker>>,
+    /// The guilds that can't use the bot.
+    #[serde(default)]
+    pub denied: HashSet<Id<GuildMarker>>,
+}
+
+impl GuildAccess {
+    /// Checks if a guild can use the bot.
+    pub fn allows(&self, guild_id: Id<GuildMarker>) -> bool {
+        !self.denied.contains(&guild_id) && (!self.allowlist || self.allowed.contains(&guild_id))
+    }
+}
+
+/// A feature the operator can turn off for a guild.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// `/tts`.
+    Tts,
+    /// `/announce`.
+    Announce,
+    /// `/autoplay`.
+    Autoplay,
+    /// `/playlist`.
+    Playlists,
+    /// `/import`.
+    Import,
+}
+
+impl Feature {
+    /// Every feature.
+    pub const ALL: [Feature; 5] = [
+        Feature::Tts,
+        Feature::Announce,
+        Feature::Autoplay,
+        Feature::Playlists,
+        Feature::Import,
+    ];
+
+    /// Gets a feature from its name.
+    pub fn from_name(name: &str) -> Option<Feature> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.name() == name)
+    }
+
+    /// The name of the feature.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Tts => "tts",
+            Feature::Announce => "announce",
+            Feature::Autoplay => "autoplay",
+            Feature::Playlists => "playlists",
+            Feature::Import => "import",
+        }
+    }
+}
+
+/// Tracks, channels and keywords a guild doesn't let anyone play.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Blocklist {
+    /// The urls of blocked tracks.
+    #[serde(default)]
+    pub urls: HashSet<String>,
+    /// Blocked channels, by url or name.
+    #[serde(default)]
+    pub channels: HashSet<String>,
+    /// Blocked words in track titles, in lowercase.
+    #[serde(default)]
+    pub keywords: HashSet<String>,
+}
+
+impl Blocklist {
+    /// The blocked entries of a kind.
+    pub fn entries(&self, kind: BlockKind) -> &HashSet<String> {
+        match kind {
+            BlockKind::Url => &self.urls,
+            BlockKind::Channel => &self.channels,
+            BlockKind::Keyword => &self.keywords,
+        }
+    }
+
+    /// The blocked entries of a kind, mutably.
+    pub fn entries_mut(&mut self, kind: BlockKind) -> &mut HashSet<String> {
+        match kind {
+            BlockKind::Url => &mut self.urls,
+            BlockKind::Channel => &mut self.channels,
+            BlockKind::Keyword => &mut self.keywords,
+        }
+    }
+
+    /// Checks if nothing is blocked.
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty() && self.channels.is_empty() && self.keywords.is_empty()
+    }
+}
+
+/// What a guild has listened to.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListeningStats {
+    /// The tracks played recently, oldest first.
+    #[serde(default)]
+    pub plays: Vec<Play>,
+    /// How many of each user's requests were played, ever.
+    #[serde(default)]
+    pub requests: HashMap<Id<UserMarker>, u64>,
+    /// How many tracks were played, ever.
+    #[serde(default)]
+    pub total_plays: u64,
+    /// How long tracks were played for, ever, in seconds.
+    #[serde(default)]
+    pub total_listened: u64,
+}
+
+/// A track that was played.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Play {
+    pub url: String,
+    pub title: String,
+    #[serde(default)]
+    pub requester: Option<Id<UserMarker>>,
+    /// When the track stopped playing, in seconds since the Unix epoch.
+    pub at: u64,
+    /// How long the track was played for, in seconds.
+    pub listened: u64,
+}
+
+/// Limits on the tracks users without the DJ role can enqueue.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrackLimits {
+    /// The longest a track can be, in seconds.
+    #[serde(default)]
+    pub max_duration: Option<u64>,
+    /// Whether livestreams can't be enqueued.
+    #[serde(default)]
+    pub deny_livestreams: bool,
+}
+
+impl TrackLimits {
+    /// The longest a track can be.
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration.map(Duration::from_secs)
+    }
+
+    /// Checks if nothing is limited.
+    pub fn is_empty(&self) -> bool {
+        self.max_duration.is_none() && !self.deny_livestreams
+    }
+}
+
+/// Where age-restricted tracks can play.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgePolicy {
+    /// Age-restricted tracks can't play anywhere.
+    Deny,
+    /// Age-restricted tracks can only play in NSFW voice channels.
+    #[default]
+    Nsfw,
+    /// Age-restricted tracks can play anywhere.
+    Allow,
+}
+
+impl AgePolicy {
+    /// Gets an age policy from its name.
+    pub fn from_name(name: &str) -> Option<AgePolicy> {
+        match name {
+            "deny" => Some(AgePolicy::Deny),
+            "nsfw" => Some(AgePolicy::Nsfw),
+            "allow" => Some(AgePolicy::Allow),
+            _ => None,
+        }
+    }
+
+    /// The name of the age policy.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgePolicy::Deny => "deny",
+            AgePolicy::Nsfw => "nsfw",
+            AgePolicy::Allow => "allow",
+        }
+    }
+}
+
+/// Where a guild shows the playing track, outside of commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NowPlayingStatus {
+    /// The playing track isn't shown.
+    #[default]
+    Off,
+    /// The playing track is the status of the bot's voice channel.
+    Voice,
+    /// The playing track is the topic of the channel tracks are requested
+    /// from.
+    Topic,
+}
+
+impl NowPlayingStatus {
+    /// Gets a now playing status from its name.
+    pub fn from_name(name: &str) -> Option<NowPlayingStatus> {
+        match name {
+            "off" => Some(NowPlayingStatus::Off),
+            "voice" => Some(NowPlayingStatus::Voice),
+            "topic" => Some(NowPlayingStatus::Topic),
+            _ => None,
+        }
+    }
+
+    /// The name of the now playing status.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NowPlayingStatus::Off => "off",
+            NowPlayingStatus::Voice => "voice",
+            NowPlayingStatus::Topic => "topic",
+        }
+    }
+}
+
+/// What a [`Blocklist`] entry matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+    /// A single track.
+    Url,
+    /// Every track from a channel.
+    Channel,
+    /// Every track with a word in its title.
+    Keyword,
+}
+
+impl BlockKind {
+    /// Every kind of entry.
+    pub const ALL: [BlockKind; 3] = [BlockKind::Url, BlockKind::Channel, BlockKind::Keyword];
+
+    /// Gets a kind of entry from its name.
+    pub fn from_name(name: &str) -> Option<BlockKind> {
+        BlockKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// The name of the kind of entry.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockKind::Url => "url",
+            BlockKind::Channel => "channel",
+            BlockKind::Keyword => "keyword",
+        }
+    }
+}
+
+/// A saved position in a track.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Bookmark {
+    /// The name of the bookmark.
+    pub name: String,
+    /// The url of the track.
+    pub url: String,
+    /// The title of the track.
+    pub title: String,
+    /// How far into the track the bookmark is, in milliseconds.
+    pub position: u64,
+}
+
+impl Bookmark {
+    /// How far into the track the bookmark is.
+    pub fn position(&self) -> Duration {
+        Duration::from_millis(self.position)
+    }
+}
+
+/// A named list of tracks saved for a guild.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedPlaylist {
+    /// The name of the playlist.
+    pub name: String,
+    /// The tracks of the playlist, in order.
+    pub tracks: Vec<SavedTrack>,
+}
+
+/// A track of a [`SavedPlaylist`].
+///
+/// This keeps enough to show the track without querying it again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedTrack {
+    /// The url of the track.
+    pub url: String,
+    /// The title of the track.
+    pub title: String,
+    /// The name of the author of the track.
+    pub author: String,
+    /// The url of the author of the track.
+    #[serde(default)]
+    pub author_url: Option<String>,
+    /// The url of the thumbnail of the track.
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    /// How long the track is, in milliseconds, if known.
+    #[serde(default)]
+    pub duration: Option<u64>,
+}
+
+impl From<&Track> for SavedTrack {
+    fn from(track: &Track) -> SavedTrack {
+        SavedTrack {
+            url: track.url.clone(),
+            title: track.title.clone(),
+            author: track.author.name.clone(),
+            author_url: track.author.url.clone(),
+            thumbnail_url: track.thumbnail_url.clone(),
+            duration: track.duration.map(|duration| duration.as_millis() as u64),
+        }
+    }
+}
+
+impl From<SavedTrack> for Track {
+    fn from(track: SavedTrack) -> Track {
+        Track {
+            url: track.url,
+            title: track.title,
+            author: Author {
+                name: track.author,
+                url: track.author_url,
+                avatar_url: None,
+            },
+            thumbnail_url: track.thumbnail_url,
+            requester: None,
+            start: Duration::ZERO,
+            duration: track.duration.map(Duration::from_millis),
+            speech: None,
+            resolved_at: None,
+            picked: false,
+            live: false,
+            age_limit: 0,
+            chapters: Vec::new(),
+            album: None,
+            format: None,
+        }
+    }
+}
+
+/// A guild's voice session, saved so it can be picked up again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedSession {
+    /// The voice channel the bot was in.
+    pub channel_id: Id<ChannelMarker>,
+    /// The channel playback problems were reported to.
+    #[serde(default)]
+    pub announce_channel: Option<Id<ChannelMarker>>,
+    /// The playing track, then the queue, in order.
+    pub tracks: Vec<SessionTrack>,
+    /// How far into the playing track the bot was, in milliseconds.
+    #[serde(default)]
+    pub position: u64,
+}
+
+/// A track of a [`SavedSession`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionTrack {
+    #[serde(flatten)]
+    pub track: SavedTrack,
+    /// The user who requested the track.
+    #[serde(default)]
+    pub requester: Option<Id<UserMarker>>,
+    /// Where to start playing the track from, in milliseconds.
+    #[serde(default)]
+    pub start: u64,
+}
+
+impl From<&Track> for SessionTrack {
+    fn from(track: &Track) -> SessionTrack {
+        SessionTrack {
+            track: SavedTrack::from(track),
+            requester: track.requester,
+            start: track.start.as_millis() as u64,
+        }
+    }
+}
+
+impl From<SessionTrack> for Track {
+    fn from(track: SessionTrack) -> Track {
+        Track {
+            requester: track.requester,
+            start: Duration::from_millis(track.start),
+            ..Track::from(track.track)
+        }
+    }
+}
+
+/// An error from a [`Store`].
+#[derive(Debug)]
+pub enum Error {
+    /// Io error.
+    Io(std::io::Error),
+    /// The store couldn't be (de)serialized.
+    Json(serde_json::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => Display::fmt(err, f),
+            Error::Json(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+        }
+    }
+}
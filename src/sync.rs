@@ -0,0 +1,395 @@
+//! Keeps the commands registered with Discord in sync with the bot.
+//!
+//! Overwriting every command on startup makes Discord redo them all, which
+//! can take a while to reach every client. [`CommandSync`] compares what the
+//! bot has with what Discord reports, and only creates, edits or deletes the
+//! commands that changed.
+
+use twilight_http::client::InteractionClient;
+use twilight_http::response::DeserializeBodyError;
+use twilight_model::application::command::{Command, CommandOption, CommandType};
+use twilight_model::id::{
+    marker::{CommandMarker, GuildMarker},
+    Id,
+};
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use tracing::{debug, info};
+
+/// Syncs the global commands, and the extra commands of some guilds.
+///
+/// ```no_run
+/// # async fn sync(client: twilight_http::client::InteractionClient<'_>) {
+/// # use twilight_model::id::Id;
+/// use swc::sync::CommandSync;
+///
+/// CommandSync::new(swc::commands())
+///     .guild(Id::new(1), swc::dev_commands())
+///     .run(&client)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CommandSync {
+    global: Vec<Command>,
+    guilds: HashMap<Id<GuildMarker>, Vec<Command>>,
+}
+
+impl CommandSync {
+    /// Creates a new `CommandSync` with the commands for every guild.
+    pub fn new(global: Vec<Command>) -> CommandSync {
+        CommandSync {
+            global,
+            guilds: HashMap::new(),
+        }
+    }
+
+    /// Adds commands only for one guild.
+    ///
+    /// A guild that is given no commands has its old ones deleted.
+    pub fn guild(mut self, guild_id: Id<GuildMarker>, commands: Vec<Command>) -> CommandSync {
+        // guild commands are never in DMs, so Discord doesn't keep this
+        let commands = commands.into_iter().map(|command| Command {
+            dm_permission: None,
+            ..command
+        });
+
+        self.guilds.entry(guild_id).or_default().extend(commands);
+        self
+    }
+
+    /// Applies the changes, returning how many commands changed.
+    pub async fn run(&self, client: &InteractionClient<'_>) -> Result<usize, SyncError> {
+        let mut changed = self.sync(client, None, &self.global).await?;
+
+        for (guild_id, commands) in &self.guilds {
+            changed += self.sync(client, Some(*guild_id), commands).await?;
+        }
+
+        Ok(changed)
+    }
+
+    async fn sync(
+        &self,
+        client: &InteractionClient<'_>,
+        guild_id: Option<Id<GuildMarker>>,
+        desired: &[Command],
+    ) -> Result<usize, SyncError> {
+        let existing = match guild_id {
+            Some(guild_id) => {
+                client
+                    .guild_commands(guild_id)
+                    .with_localizations(true)
+                    .await
+            }
+            None => client.global_commands().with_localizations(true).await,
+        }?
+        .models()
+        .await?;
+
+        let plan = Plan::new(desired, &existing);
+
+        if plan.is_empty() {
+            debug!(?guild_id, "commands up to date");
+            return Ok(0);
+        }
+
+        info!(
+            ?guild_id,
+            create = plan.create.len(),
+            update = plan.update.len(),
+            delete = plan.delete.len(),
+            "syncing commands"
+        );
+
+        // creating a command with the name of an existing one overwrites it
+        for command in plan.create.iter().chain(&plan.update) {
+            create(client, guild_id, command).await?;
+        }
+
+        for command_id in &plan.delete {
+            match guild_id {
+                Some(guild_id) => client.delete_guild_command(guild_id, *command_id).await,
+                None => client.delete_global_command(*command_id).await,
+            }?;
+        }
+
+        Ok(plan.len())
+    }
+}
+
+/// Creates or overwrites a command.
+async fn create(
+    client: &InteractionClient<'_>,
+    guild_id: Option<Id<GuildMarker>>,
+    command: &Command,
+) -> Result<(), SyncError> {
+    // the builders of each kind of command, global or not, are different
+    // types with the same methods
+    macro_rules! build {
+        ($req:expr) => {{
+            let mut req = $req.map_err(SyncError::invalid)?;
+
+            if let Some(localizations) = &command.name_localizations {
+                req = req
+                    .name_localizations(localizations)
+                    .map_err(SyncError::invalid)?;
+            }
+
+            if let Some(permissions) = command.default_member_permissions {
+                req = req.default_member_permissions(permissions);
+            }
+
+            if let Some(nsfw) = command.nsfw {
+                req = req.nsfw(nsfw);
+            }
+
+            req
+        }};
+    }
+
+    // only chat commands have options and a description
+    macro_rules! chat_input {
+        ($req:expr) => {{
+            let mut req = build!($req)
+                .command_options(&command.options)
+                .map_err(SyncError::invalid)?;
+
+            if let Some(localizations) = &command.description_localizations {
+                req = req
+                    .description_localizations(localizations)
+                    .map_err(SyncError::invalid)?;
+            }
+
+            req
+        }};
+    }
+
+    macro_rules! global {
+        ($req:expr) => {{
+            let mut req = $req;
+
+            if let Some(dm_permission) = command.dm_permission {
+                req = req.dm_permission(dm_permission);
+            }
+
+            req.await?;
+        }};
+    }
+
+    let name = &command.name;
+
+    match guild_id {
+        Some(guild_id) => {
+            let client = client.create_guild_command(guild_id);
+
+            match command.kind {
+                CommandType::Message => build!(client.message(name)).await?,
+                CommandType::User => build!(client.user(name)).await?,
+                _ => chat_input!(client.chat_input(name, &command.description)).await?,
+            };
+        }
+        None => {
+            let client = client.create_global_command();
+
+            match command.kind {
+                CommandType::Message => global!(build!(client.message(name))),
+                CommandType::User => global!(build!(client.user(name))),
+                _ => global!(chat_input!(client.chat_input(name, &command.description))),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The changes needed to get from one set of commands to another.
+#[derive(Debug, Default)]
+struct Plan<'a> {
+    create: Vec<&'a Command>,
+    update: Vec<&'a Command>,
+    delete: Vec<Id<CommandMarker>>,
+}
+
+impl<'a> Plan<'a> {
+    fn new(desired: &'a [Command], existing: &[Command]) -> Plan<'a> {
+        let mut plan = Plan::default();
+
+        for command in desired {
+            match existing.iter().find(|old| same_name(old, command)) {
+                Some(old) if normalize(old) == normalize(command) => (),
+                Some(_) => plan.update.push(command),
+                None => plan.create.push(command),
+            }
+        }
+
+        plan.delete = existing
+            .iter()
+            .filter(|old| !desired.iter().any(|command| same_name(old, command)))
+            .filter_map(|old| old.id)
+            .collect();
+
+        plan
+    }
+
+    fn len(&self) -> usize {
+        self.create.len() + self.update.len() + self.delete.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn same_name(a: &Command, b: &Command) -> bool {
+    a.kind == b.kind && a.name == b.name
+}
+
+/// Clears what Discord fills in or leaves out, so a command the bot has can
+/// be compared with the one Discord reports.
+fn normalize(command: &Command) -> Command {
+    Command {
+        application_id: None,
+        guild_id: None,
+        id: None,
+        version: Id::new(1),
+        // these default to true and false
+        dm_permission: command.dm_permission.filter(|dm| !dm),
+        nsfw: command.nsfw.filter(|nsfw| *nsfw),
+        name_localizations: command.name_localizations.clone().filter(|l| !l.is_empty()),
+        description_localizations: command
+            .description_localizations
+            .clone()
+            .filter(|l| !l.is_empty()),
+        options: command.options.iter().map(normalize_option).collect(),
+        ..command.clone()
+    }
+}
+
+fn normalize_option(option: &CommandOption) -> CommandOption {
+    CommandOption {
+        autocomplete: option.autocomplete.filter(|a| *a),
+        required: option.required.filter(|r| *r),
+        choices: option.choices.clone().filter(|c| !c.is_empty()),
+        channel_types: option.channel_types.clone().filter(|c| !c.is_empty()),
+        name_localizations: option.name_localizations.clone().filter(|l| !l.is_empty()),
+        description_localizations: option
+            .description_localizations
+            .clone()
+            .filter(|l| !l.is_empty()),
+        options: option
+            .options
+            .as_ref()
+            .map(|options| options.iter().map(normalize_option).collect::<Vec<_>>())
+            .filter(|o| !o.is_empty()),
+        ..option.clone()
+    }
+}
+
+/// An error syncing commands.
+#[derive(Debug)]
+pub enum SyncError {
+    /// A command isn't valid.
+    Invalid(Box<dyn std::error::Error + Send + Sync>),
+    /// A request failed.
+    Http(twilight_http::Error),
+    /// Discord's commands couldn't be read.
+    Deserialize(DeserializeBodyError),
+}
+
+impl SyncError {
+    fn invalid(err: impl std::error::Error + Send + Sync + 'static) -> SyncError {
+        SyncError::Invalid(Box::new(err))
+    }
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SyncError::Invalid(err) => write!(f, "invalid command: {}", err),
+            SyncError::Http(err) => Display::fmt(err, f),
+            SyncError::Deserialize(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<twilight_http::Error> for SyncError {
+    fn from(err: twilight_http::Error) -> SyncError {
+        SyncError::Http(err)
+    }
+}
+
+impl From<DeserializeBodyError> for SyncError {
+    fn from(err: DeserializeBodyError) -> SyncError {
+        SyncError::Deserialize(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command;
+
+    #[test]
+    fn plans_only_changes() {
+        let existing = vec![
+            Command {
+                id: Some(Id::new(1)),
+                dm_permission: Some(false),
+                nsfw: Some(false),
+                ..command("play", "plays a track")
+            },
+            Command {
+                id: Some(Id::new(2)),
+                ..command("skip", "skips a track")
+            },
+            Command {
+                id: Some(Id::new(3)),
+                ..command("old", "was removed")
+            },
+        ];
+
+        let desired = vec![
+            command("play", "plays a track"),
+            command("skip", "skips the playing track"),
+            command("queue", "lists the queue"),
+        ];
+
+        let plan = Plan::new(&desired, &existing);
+        assert_eq!(plan.create.len(), 1);
+        assert_eq!(plan.create[0].name, "queue");
+        assert_eq!(plan.update.len(), 1);
+        assert_eq!(plan.update[0].name, "skip");
+        assert_eq!(plan.delete, [Id::new(3)]);
+
+        assert!(Plan::new(&desired, &desired).is_empty());
+    }
+
+    #[test]
+    fn matches_by_kind() {
+        let desired = vec![command("play", "plays a track")];
+        let existing = vec![Command {
+            id: Some(Id::new(1)),
+            kind: CommandType::User,
+            ..command("play", "")
+        }];
+
+        let plan = Plan::new(&desired, &existing);
+        assert_eq!(plan.create.len(), 1);
+        assert_eq!(plan.delete, [Id::new(1)]);
+    }
+
+    #[test]
+    fn commands_are_valid() {
+        for command in crate::commands().iter().chain(&crate::dev_commands()) {
+            twilight_validate::command::command(command)
+                .and_then(|_| twilight_validate::command::options(&command.options))
+                .unwrap_or_else(|err| panic!("/{} is invalid: {}", command.name, err));
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Text commands, for servers that would rather type `!play` than use slash
+//! commands.
+//!
+//! Reading messages needs the `MESSAGE_CONTENT` intent, so this is behind the
+//! `text-commands` feature, and each guild still has to turn it on with
+//! `/settings textcommands`. A text command becomes the same
+//! [`Command`](crate::music::Command) as its slash command, and is answered
+//! with messages in its channel instead of through an interaction.
+
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use twilight_cache_inmemory::InMemoryCache;
+use twilight_model::{channel::Message, guild::Permissions};
+
+use crate::i18n;
+use crate::music::{respond::ChannelResponder, Action, CommandData, LoopMode, PlayOptions, Reply};
+
+/// The prefix of text commands if `TEXT_COMMAND_PREFIX` isn't set.
+pub const DEFAULT_PREFIX: &str = "!";
+
+/// Gets the prefix of text commands from `TEXT_COMMAND_PREFIX`.
+pub fn prefix_from_env() -> String {
+    env::var("TEXT_COMMAND_PREFIX")
+        .ok()
+        .filter(|prefix| !prefix.is_empty())
+        .unwrap_or_else(|| String::from(DEFAULT_PREFIX))
+}
+
+/// Parses a message into an action.
+///
+/// `play` without a query, in `reply` to another message, plays the links in
+/// that message instead. Returns `None` if the message isn't a text command
+/// the bot knows.
+pub fn parse(
+    prefix: &str,
+    content: &str,
+    reply: Option<Reply>,
+) -> Option<Result<Action, ParseError>> {
+    let content = content.strip_prefix(prefix)?;
+    let (name, args) = content
+        .split_once(char::is_whitespace)
+        .unwrap_or((content, ""));
+    let args = args.trim();
+
+    let action = match &*name.to_lowercase() {
+        "play" | "p" => play(args, false, reply),
+        "playnow" => play(args, true, reply),
+        "skip" | "s" => Ok(Action::Skip),
+        "queue" | "q" => Ok(Action::Queue),
+        "shuffle" => Ok(Action::Shuffle(None)),
+        "unshuffle" => Ok(Action::Unshuffle),
+        "pause" => Ok(Action::Pause),
+        "stop" => Ok(Action::Stop),
+        "disconnect" | "dc" => Ok(Action::Disconnect),
+        "loop" => optional(args, "mode", LoopMode::from_name).map(Action::Loop),
+        "autoplay" => optional(args, "setting", toggle).map(Action::Autoplay),
+        "mynext" => args
+            .parse::<usize>()
+            .map(Action::MyNext)
+            .map_err(|_| ParseError::Invalid("position")),
+        _ => return None,
+    };
+
+    Some(action)
+}
+
+/// Parses the arguments of `play` and `playnow`.
+fn play(args: &str, playnow: bool, reply: Option<Reply>) -> Result<Action, ParseError> {
+    let options = PlayOptions {
+        playnow,
+        ..Default::default()
+    };
+
+    match reply {
+        _ if !args.is_empty() => Ok(Action::Play(args.to_owned(), options)),
+        Some(reply) => Ok(Action::PlayReply(reply, options)),
+        None => Err(ParseError::Missing("query")),
+    }
+}
+
+/// Parses an optional argument.
+fn optional<T>(
+    args: &str,
+    name: &'static str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>, ParseError> {
+    if args.is_empty() {
+        return Ok(None);
+    }
+
+    parse(&args.to_lowercase())
+        .map(Some)
+        .ok_or(ParseError::Invalid(name))
+}
+
+/// Gets the message a message replied to, if it's a reply.
+pub fn reply(message: &Message) -> Option<Reply> {
+    let reference = message.reference.as_ref()?;
+
+    Some(Reply {
+        channel_id: reference.channel_id.unwrap_or(message.channel_id),
+        message_id: reference.message_id?,
+        content: message
+            .referenced_message
+            .as_ref()
+            .map(|replied| replied.content.clone()),
+    })
+}
+
+/// Parses `on` or `off`.
+fn toggle(arg: &str) -> Option<bool> {
+    match arg {
+        "on" | "true" | "yes" => Some(true),
+        "off" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Gets what the queue needs to know about a text command.
+///
+/// Returns `None` if the message isn't from a member of a guild.
+pub fn command_data(cache: &InMemoryCache, message: &Message) -> Option<CommandData> {
+    let guild_id = message.guild_id?;
+    let roles = message.member.as_ref()?.roles.clone();
+    let guild = cache.guild(guild_id)?;
+
+    // there is no interaction to tell us, so this works out the permissions
+    // from the member's roles, without channel overwrites
+    let permissions = if guild.owner_id() == message.author.id {
+        Permissions::all()
+    } else {
+        let permissions = roles
+            .iter()
+            .chain([&guild_id.cast()])
+            .filter_map(|role_id| cache.role(*role_id))
+            .fold(Permissions::empty(), |acc, role| acc | role.permissions);
+
+        if permissions.contains(Permissions::ADMINISTRATOR) {
+            Permissions::all()
+        } else {
+            permissions
+        }
+    };
+
+    Some(CommandData {
+        responder: Arc::new(ChannelResponder {
+            channel_id: message.channel_id,
+            message_id: Some(message.id),
+        }),
+        outbox: Default::default(),
+        guild_id,
+        channel_id: message.channel_id,
+        user_id: message.author.id,
+        roles,
+        permissions,
+        locale: Some(guild.preferred_locale().to_owned()),
+    })
+}
+
+/// A text command the bot knows, but with bad arguments.
+#[derive(Debug)]
+pub enum ParseError {
+    /// An argument the command needs is missing.
+    Missing(&'static str),
+    /// An argument couldn't be understood.
+    Invalid(&'static str),
+}
+
+impl ParseError {
+    /// The error as shown to the user, translated to `locale`.
+    pub fn localize(&self, locale: Option<&str>) -> String {
+        let (text, name) = match self {
+            ParseError::Missing(name) => ("missing {name}", name),
+            ParseError::Invalid(name) => ("invalid {name}", name),
+        };
+
+        i18n::trf(locale, text, &[("name", name)])
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(None))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use twilight_model::id::Id;
+
+    #[test]
+    fn parses_commands() {
+        assert!(matches!(
+            parse("!", "!play never gonna give you up", None),
+            Some(Ok(Action::Play(query, PlayOptions { playnow: false, .. })))
+                if query == "never gonna give you up"
+        ));
+        assert!(matches!(
+            parse("!", "!LOOP track", None),
+            Some(Ok(Action::Loop(Some(LoopMode::Track))))
+        ));
+        assert!(matches!(parse("!", "!skip", None), Some(Ok(Action::Skip))));
+        assert!(matches!(
+            parse("!", "!play", None),
+            Some(Err(ParseError::Missing("query")))
+        ));
+
+        let reply = Reply {
+            channel_id: Id::new(1),
+            message_id: Id::new(2),
+            content: None,
+        };
+        assert!(matches!(
+            parse("!", "!playnow", Some(reply)),
+            Some(Ok(Action::PlayReply(_, PlayOptions { playnow: true, .. })))
+        ));
+        assert!(parse("!", "!dance", None).is_none());
+        assert!(parse("!", "play something", None).is_none());
+    }
+}
@@ -0,0 +1,116 @@
+//! Text-to-speech.
+//!
+//! Speech is synthesized by a [`TtsBackend`], either a local program like
+//! `espeak-ng` or `piper`, or an HTTP API. Whatever audio it produces is
+//! decoded by `ffmpeg` into a [`Source`] like any other track.
+
+use crate::voice::encoder::Signal;
+use crate::voice::source::{Error, Overlay, Source, SourceBuilder};
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use std::env;
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+/// The longest text that can be spoken at once, in characters.
+pub const MAX_TEXT_LEN: usize = 300;
+
+static TTS_BACKEND: OnceLock<Option<TtsBackend>> = OnceLock::new();
+
+/// The text-to-speech backend.
+///
+/// If the backend was never initialized, there is none, and text-to-speech is
+/// unavailable.
+pub fn tts_backend() -> Option<&'static TtsBackend> {
+    TTS_BACKEND.get_or_init(|| None).as_ref()
+}
+
+pub fn init_tts_backend<F>(f: F) -> Option<&'static TtsBackend>
+where
+    F: FnOnce() -> Option<TtsBackend>,
+{
+    TTS_BACKEND.get_or_init(f).as_ref()
+}
+
+/// Where speech comes from.
+#[derive(Clone, Debug)]
+pub enum TtsBackend {
+    /// A program that reads text on `stdin` and writes audio to `stdout`, in
+    /// any format `ffmpeg` understands.
+    ///
+    /// For example, `espeak-ng --stdin --stdout`.
+    Command { program: String, args: Vec<String> },
+    /// An HTTP API that responds to a `GET` with audio.
+    ///
+    /// `{text}` in the url is replaced with the url-encoded text.
+    Http { url: String },
+}
+
+impl TtsBackend {
+    /// Gets the backend from the environment.
+    ///
+    /// `TTS_COMMAND` is split on whitespace into a program and its arguments.
+    /// Otherwise, `TTS_URL` is used as an HTTP API. If neither is set, there
+    /// is no backend.
+    pub fn from_env() -> Option<TtsBackend> {
+        if let Ok(command) = env::var("TTS_COMMAND") {
+            let mut args = command.split_whitespace().map(String::from);
+
+            args.next().map(|program| TtsBackend::Command {
+                program,
+                args: args.collect(),
+            })
+        } else {
+            env::var("TTS_URL").ok().map(|url| TtsBackend::Http { url })
+        }
+    }
+
+    /// Synthesizes `text` into a [`Source`].
+    pub fn speak(&self, text: &str) -> Result<Source, Error> {
+        self.builder(text)?.signal(Signal::Voice).build()
+    }
+
+    /// Synthesizes `text` into an [`Overlay`], to announce over a track.
+    pub fn overlay(&self, text: &str) -> Result<Overlay, Error> {
+        self.builder(text)?.build_overlay()
+    }
+
+    /// Starts synthesizing `text`, leaving the rest of the source up to the
+    /// caller.
+    ///
+    /// For [`TtsBackend::Command`], this already starts the program.
+    pub fn builder(&self, text: &str) -> Result<SourceBuilder, Error> {
+        match self {
+            TtsBackend::Command { program, args } => {
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(Error::Io)?;
+
+                // closing stdin tells the program the text is over
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                let text = text.to_owned();
+                tokio::spawn(async move {
+                    if let Err(err) = stdin.write_all(text.as_bytes()).await {
+                        warn!(%err, "failed to write tts text");
+                    }
+                });
+
+                Ok(SourceBuilder::piped(child))
+            }
+            TtsBackend::Http { url } => {
+                let text = form_urlencoded::byte_serialize(text.as_bytes()).collect::<String>();
+
+                Ok(SourceBuilder::url(url.replace("{text}", &text)))
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Player configuration.
+
+use super::constants::{DEFAULT_FADE_IN, DEFAULT_FADE_OUT, DEFAULT_SILENCE_FRAMES};
+use super::timing::StreamTimings;
+use super::ws::payload::SpeakingFlags;
+use crate::ytdl::YtdlConfig;
+
+use std::env;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Configuration for a [`Player`][1].
+///
+/// [1]: super::Player
+#[derive(Clone, Debug)]
+pub struct PlayerConfig {
+    /// How the player reconnects to the voice server.
+    pub reconnect: ReconnectConfig,
+    /// How the player speaks while it is sending audio.
+    ///
+    /// This can be changed later with [`Player::set_speaking`][1].
+    ///
+    /// [1]: super::Player::set_speaking
+    pub speaking: SpeakingFlags,
+    /// The local ports the voice UDP socket may bind to.
+    ///
+    /// If a port is in use, the next one in the range is tried. If `None`, the
+    /// OS picks a port.
+    pub udp_ports: Option<RangeInclusive<u16>>,
+    /// How long sources built for the player should fade in, with
+    /// [`SourceBuilder::fade_in`][1].
+    ///
+    /// [1]: super::SourceBuilder::fade_in
+    pub fade_in: Duration,
+    /// How long the playing source fades out when it is stopped, instead of
+    /// cutting off. Zero cuts it off.
+    pub fade_out: Duration,
+    /// How many frames of silence are sent before a break in the audio, so
+    /// Discord doesn't try to fill it in. At least one is always sent.
+    pub silence_frames: usize,
+    /// How much silence is sent before the first audio after joining, so
+    /// clients that start listening late don't clip it. Zero sends none.
+    pub pre_roll: Duration,
+    /// How sources built for the player run `ytdl`, with
+    /// [`SourceBuilder::ytdl_config`][1].
+    ///
+    /// [1]: super::SourceBuilder::ytdl_config
+    pub ytdl: YtdlConfig,
+    /// Where every player made with this config, or a clone of it, also
+    /// records how long streaming takes, for totals across players.
+    pub timings: Arc<StreamTimings>,
+}
+
+impl PlayerConfig {
+    /// Creates a config from the environment, using the defaults for
+    /// anything that isn't set.
+    ///
+    /// `VOICE_UDP_PORTS` sets [`PlayerConfig::udp_ports`], either as a single
+    /// port or as an inclusive range like `50000-50100`. `VOICE_FADE_IN_MS`
+    /// and `VOICE_FADE_OUT_MS` set [`PlayerConfig::fade_in`] and
+    /// [`PlayerConfig::fade_out`] in milliseconds, and `VOICE_PRE_ROLL_MS`
+    /// sets [`PlayerConfig::pre_roll`]. `VOICE_SILENCE_FRAMES` sets
+    /// [`PlayerConfig::silence_frames`]. [`PlayerConfig::ytdl`] is read with
+    /// [`YtdlConfig::from_env`].
+    pub fn from_env() -> PlayerConfig {
+        let millis = |name| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+        };
+
+        PlayerConfig {
+            udp_ports: env::var("VOICE_UDP_PORTS")
+                .ok()
+                .and_then(|v| parse_port_range(&v)),
+            fade_in: millis("VOICE_FADE_IN_MS").unwrap_or(DEFAULT_FADE_IN),
+            fade_out: millis("VOICE_FADE_OUT_MS").unwrap_or(DEFAULT_FADE_OUT),
+            silence_frames: env::var("VOICE_SILENCE_FRAMES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_SILENCE_FRAMES),
+            pre_roll: millis("VOICE_PRE_ROLL_MS").unwrap_or_default(),
+            ytdl: YtdlConfig::from_env(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for PlayerConfig {
+    fn default() -> PlayerConfig {
+        PlayerConfig {
+            reconnect: ReconnectConfig::default(),
+            speaking: SpeakingFlags::MICROPHONE,
+            udp_ports: None,
+            fade_in: DEFAULT_FADE_IN,
+            fade_out: DEFAULT_FADE_OUT,
+            silence_frames: DEFAULT_SILENCE_FRAMES,
+            pre_roll: Duration::ZERO,
+            ytdl: YtdlConfig::default(),
+            timings: Arc::default(),
+        }
+    }
+}
+
+/// Reconnection behavior of a player.
+///
+/// Failed connection attempts are retried with exponential backoff and jitter,
+/// so brief voice outages don't kill the player.
+#[derive(Clone, Debug)]
+pub struct ReconnectConfig {
+    /// How many times to attempt to connect before giving up.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each retry doubles it.
+    pub base_delay: Duration,
+    /// The maximum delay between two attempts.
+    pub max_delay: Duration,
+    /// How long a single connection attempt may take.
+    pub connect_timeout: Duration,
+    /// How long to wait for the main gateway to tell us where to reconnect
+    /// to before trying to reconnect on our own.
+    pub gateway_timeout: Duration,
+}
+
+impl ReconnectConfig {
+    /// The delay before retrying after the `attempt`th (0-based) failure.
+    ///
+    /// Half of the delay is fixed, and the other half is random, so players
+    /// that failed together don't all reconnect at the same time.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay);
+
+        delay / 2 + (delay / 2).mul_f64(rand::random::<f64>())
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> ReconnectConfig {
+        ReconnectConfig {
+            max_attempts: 6,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(5),
+            gateway_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Parses a port range like `50000-50100`, or a single port.
+fn parse_port_range(range: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+
+    if start <= end {
+        Some(start..=end)
+    } else {
+        None
+    }
+}
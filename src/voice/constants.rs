@@ -36,5 +36,40 @@ pub const DEFAULT_BITRATE: Bitrate = Bitrate::Bits(128_000);
 /// Set a safe amount below the Ethernet MTU to avoid fragmentation/rejection.
 pub const VOICE_PACKET_MAX: usize = 1460;
 
+/// How often to send UDP keepalives while no audio is being sent.
+///
+/// Discord expects these about every 5 seconds, otherwise NAT mappings along
+/// the way may expire and audio stops reaching the voice server.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a source can go without producing audio before it is considered
+/// stalled and killed.
+///
+/// This is generous since `ytdl` can take a while to start streaming.
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much audio a source decodes ahead of the encoder.
+pub const DEFAULT_READ_AHEAD: Duration = Duration::from_millis(300);
+
 /// A frame of silence.
 pub const SILENCE_FRAME: &[u8] = &[0xF8, 0xFF, 0xFE];
+
+/// How many frames of silence are sent before a break in the audio by
+/// default, so Discord doesn't try to fill it in.
+pub const DEFAULT_SILENCE_FRAMES: usize = 5;
+
+/// How loud a source plays under an overlay by default.
+pub const DUCK_GAIN: f32 = 0.25;
+
+/// How long it takes to duck a source, and to bring it back up.
+pub const DUCK_RAMP: Duration = Duration::from_millis(200);
+
+/// How long a track fades in when it starts, by default.
+pub const DEFAULT_FADE_IN: Duration = Duration::from_millis(150);
+
+/// How long a track fades out when it is stopped, by default.
+pub const DEFAULT_FADE_OUT: Duration = Duration::from_millis(250);
+
+/// How long a gapless player waits for the next source after one ends
+/// before it gives up and plays silence.
+pub const GAPLESS_HOLD: Duration = Duration::from_secs(2);
@@ -0,0 +1,215 @@
+//! The Opus encoder of sources.
+//!
+//! The `opus` crate doesn't expose every setting of the encoder, like DTX, so
+//! this wraps `libopus` directly for the settings sources use.
+
+use std::ffi::CStr;
+use std::fmt::{self, Display, Formatter};
+use std::os::raw::c_int;
+
+use audiopus_sys as ffi;
+use opus::{Application, Bitrate, Channels};
+
+/// An Opus encoder.
+pub struct Encoder {
+    ptr: *mut ffi::OpusEncoder,
+    channels: Channels,
+}
+
+// the encoder is only ever used from one thread at a time
+unsafe impl Send for Encoder {}
+
+impl Encoder {
+    /// Creates a new encoder.
+    pub fn new(
+        sample_rate: u32,
+        channels: Channels,
+        application: Application,
+    ) -> Result<Encoder, CodecError> {
+        let mut error = 0;
+        let ptr = unsafe {
+            ffi::opus_encoder_create(
+                sample_rate as i32,
+                channels as c_int,
+                application as c_int,
+                &mut error,
+            )
+        };
+
+        if error != ffi::OPUS_OK || ptr.is_null() {
+            Err(CodecError::new("opus_encoder_create", error))
+        } else {
+            Ok(Encoder { ptr, channels })
+        }
+    }
+
+    /// Encodes a frame of PCM into a new packet of at most `max_len` bytes.
+    ///
+    /// With [DTX](Encoder::set_dtx) on, packets of [`DTX_PACKET_LEN`] bytes
+    /// or less don't need to be sent.
+    pub fn encode_vec_float(
+        &mut self,
+        input: &[f32],
+        max_len: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        let mut output = vec![0; max_len];
+
+        let len = unsafe {
+            ffi::opus_encode_float(
+                self.ptr,
+                input.as_ptr(),
+                input.len() as c_int / self.channels as c_int,
+                output.as_mut_ptr(),
+                max_len as i32,
+            )
+        };
+
+        if len < 0 {
+            return Err(CodecError::new("opus_encode_float", len));
+        }
+
+        output.truncate(len as usize);
+        Ok(output)
+    }
+
+    /// Sets the bitrate.
+    pub fn set_bitrate(&mut self, bitrate: Bitrate) -> Result<(), CodecError> {
+        let bits = match bitrate {
+            Bitrate::Bits(bits) => bits,
+            Bitrate::Max => ffi::OPUS_BITRATE_MAX,
+            Bitrate::Auto => ffi::OPUS_AUTO,
+        };
+
+        self.ctl("OPUS_SET_BITRATE", ffi::OPUS_SET_BITRATE_REQUEST, bits)
+    }
+
+    /// Sets whether silence is encoded as packets that don't need to be
+    /// sent, called discontinuous transmission.
+    pub fn set_dtx(&mut self, dtx: bool) -> Result<(), CodecError> {
+        self.ctl("OPUS_SET_DTX", ffi::OPUS_SET_DTX_REQUEST, dtx as c_int)
+    }
+
+    /// Sets whether packets carry a lower quality copy of the frame before
+    /// them, so the frame can be recovered if its packet is lost.
+    pub fn set_inband_fec(&mut self, fec: bool) -> Result<(), CodecError> {
+        self.ctl(
+            "OPUS_SET_INBAND_FEC",
+            ffi::OPUS_SET_INBAND_FEC_REQUEST,
+            fec as c_int,
+        )
+    }
+
+    /// Sets how many packets, in percent, are expected to be lost, which
+    /// decides how much [inband FEC](Encoder::set_inband_fec) is used.
+    pub fn set_packet_loss_perc(&mut self, percent: u8) -> Result<(), CodecError> {
+        self.ctl(
+            "OPUS_SET_PACKET_LOSS_PERC",
+            ffi::OPUS_SET_PACKET_LOSS_PERC_REQUEST,
+            percent as c_int,
+        )
+    }
+
+    /// Sets the complexity of the encoder, from 0 to 10. Higher is better
+    /// and slower.
+    pub fn set_complexity(&mut self, complexity: u8) -> Result<(), CodecError> {
+        self.ctl(
+            "OPUS_SET_COMPLEXITY",
+            ffi::OPUS_SET_COMPLEXITY_REQUEST,
+            complexity as c_int,
+        )
+    }
+
+    /// Sets what kind of audio is encoded.
+    pub fn set_signal(&mut self, signal: Signal) -> Result<(), CodecError> {
+        let signal = match signal {
+            Signal::Auto => ffi::OPUS_AUTO,
+            Signal::Voice => ffi::OPUS_SIGNAL_VOICE,
+            Signal::Music => ffi::OPUS_SIGNAL_MUSIC,
+        };
+
+        self.ctl("OPUS_SET_SIGNAL", ffi::OPUS_SET_SIGNAL_REQUEST, signal)
+    }
+
+    fn ctl(&mut self, name: &'static str, request: c_int, value: c_int) -> Result<(), CodecError> {
+        let code = unsafe { ffi::opus_encoder_ctl(self.ptr, request, value) };
+
+        if code < 0 {
+            Err(CodecError::new(name, code))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { ffi::opus_encoder_destroy(self.ptr) }
+    }
+}
+
+/// A hint of what kind of audio is encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Signal {
+    /// The encoder works it out.
+    #[default]
+    Auto,
+    /// Mostly speech.
+    Voice,
+    /// Mostly music.
+    Music,
+}
+
+/// The highest complexity of an encoder.
+pub const MAX_COMPLEXITY: u8 = 10;
+
+/// The longest packet an encoder with DTX on makes for a frame it leaves
+/// out.
+pub const DTX_PACKET_LEN: usize = 2;
+
+/// An error from `libopus`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodecError {
+    /// The function or setting that failed.
+    pub function: &'static str,
+    /// The error code.
+    pub code: i32,
+}
+
+impl CodecError {
+    fn new(function: &'static str, code: i32) -> CodecError {
+        CodecError { function, code }
+    }
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // always ASCII, even for unknown codes
+        let description = unsafe { CStr::from_ptr(ffi::opus_strerror(self.code)) };
+
+        write!(f, "{}: {}", self.function, description.to_string_lossy())
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voice::constants::{SAMPLE_RATE, STEREO_FRAME_SIZE};
+
+    #[test]
+    fn leaves_out_silence() {
+        let mut encoder =
+            Encoder::new(SAMPLE_RATE as u32, Channels::Stereo, Application::Audio).unwrap();
+        encoder.set_dtx(true).unwrap();
+
+        let silence = [0f32; STEREO_FRAME_SIZE];
+        let left_out = (0..50)
+            .map(|_| encoder.encode_vec_float(&silence, 1275).unwrap())
+            .filter(|packet| packet.len() <= DTX_PACKET_LEN)
+            .count();
+
+        assert!(left_out > 0);
+    }
+}
@@ -22,6 +22,38 @@ pub enum Error {
     CannotJoin,
     /// The bot was disconnected from the channel.
     Disconnected,
+    /// The player was closed, so it can't take commands anymore.
+    Closed,
+}
+
+impl Error {
+    /// The kind of the error, which describes how to recover from it.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Audio(_) => ErrorKind::SourceError,
+            Error::Ws(err) if err.disconnected() => ErrorKind::Disconnected,
+            Error::Ws(_) | Error::Rtp(_) | Error::Timeout => ErrorKind::ConnectionError,
+            Error::GatewayClosed | Error::CannotJoin | Error::Disconnected | Error::Closed => {
+                ErrorKind::Disconnected
+            }
+        }
+    }
+}
+
+/// A broad category of [`Error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The audio source (`ffmpeg` or `ytdl`) died.
+    ///
+    /// The player is still running and has skipped the source.
+    SourceError,
+    /// The voice websocket or UDP connection failed.
+    ///
+    /// The player has crashed, but the bot may still be in the channel, so
+    /// building a new player may recover.
+    ConnectionError,
+    /// The bot left the channel, or could never join it.
+    Disconnected,
 }
 
 impl From<ws::Error> for Error {
@@ -52,6 +84,7 @@ impl Display for Error {
             Error::Timeout => f.write_str("operation timed out"),
             Error::CannotJoin => f.write_str("unable to join Discord channel"),
             Error::Disconnected => f.write_str("bot disconnected from channel"),
+            Error::Closed => f.write_str("player closed"),
         }
     }
 }
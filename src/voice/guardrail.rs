@@ -0,0 +1,246 @@
+//! Keeping encoding from starving the CPU.
+//!
+//! Every player records how long encoding its audio takes to the
+//! [`StreamTimings`] shared by all of them. When many guilds play at once,
+//! that adds up, and once the host runs out of CPU every player stutters at
+//! the same time. A [`Guardrail`] watches how much of the host's CPU time
+//! goes to encoding, and past [`GuardrailConfig::threshold`] it lowers the
+//! Opus complexity and bitrate of every [`Source`](super::Source) built
+//! after that, until the load is back down. Sources that are already playing
+//! keep their quality.
+
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use opus::Bitrate;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use super::timing::StreamTimings;
+
+/// How much of the CPU encoding can use before quality is lowered, by
+/// default.
+pub const DEFAULT_THRESHOLD: f64 = 0.8;
+
+/// The complexity of sources built while quality is lowered, by default.
+pub const DEFAULT_COMPLEXITY: u8 = 5;
+
+/// The bitrate of sources built while quality is lowered, by default.
+pub const DEFAULT_BITRATE: i32 = 64_000;
+
+/// How often the load is checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How far under the threshold the load has to fall before quality is
+/// raised again, so it doesn't flap around the threshold.
+pub const RECOVER_RATIO: f64 = 0.75;
+
+static GUARDRAIL: OnceLock<GuardrailConfig> = OnceLock::new();
+static DEGRADED: AtomicBool = AtomicBool::new(false);
+
+/// The guardrail config.
+///
+/// If the config was never initialized, the defaults are used.
+pub fn guardrail() -> &'static GuardrailConfig {
+    GUARDRAIL.get_or_init(GuardrailConfig::default)
+}
+
+pub fn init_guardrail<F>(f: F) -> &'static GuardrailConfig
+where
+    F: FnOnce() -> GuardrailConfig,
+{
+    GUARDRAIL.get_or_init(f)
+}
+
+/// Checks if new sources are built at a lower quality right now.
+pub fn degraded() -> bool {
+    DEGRADED.load(Ordering::Relaxed)
+}
+
+/// When quality is lowered, and to what.
+#[derive(Clone, Debug)]
+pub struct GuardrailConfig {
+    /// The share of the host's CPU time, from 0 to 1, that encoding can use
+    /// before quality is lowered. `None` never lowers it.
+    pub threshold: Option<f64>,
+    /// The highest complexity of sources built while quality is lowered.
+    pub complexity: u8,
+    /// The highest bitrate of sources built while quality is lowered, in
+    /// bits per second.
+    pub bitrate: i32,
+}
+
+impl GuardrailConfig {
+    /// Reads the config from the environment.
+    ///
+    /// `CPU_GUARDRAIL_PERCENT` is the threshold in percent, and `0` turns the
+    /// guardrail off. `CPU_GUARDRAIL_COMPLEXITY` and `CPU_GUARDRAIL_BITRATE`
+    /// set what quality is lowered to.
+    pub fn from_env() -> GuardrailConfig {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+
+        GuardrailConfig {
+            threshold: match var("CPU_GUARDRAIL_PERCENT") {
+                Some(0) => None,
+                Some(percent) => Some(percent.min(100) as f64 / 100.),
+                None => Some(DEFAULT_THRESHOLD),
+            },
+            complexity: var("CPU_GUARDRAIL_COMPLEXITY")
+                .map(|complexity| complexity.min(10) as u8)
+                .unwrap_or(DEFAULT_COMPLEXITY),
+            bitrate: var("CPU_GUARDRAIL_BITRATE")
+                .map(|bitrate| bitrate.clamp(500, 512_000) as i32)
+                .unwrap_or(DEFAULT_BITRATE),
+        }
+    }
+
+    /// Lowers a bitrate and complexity to what sources are built with while
+    /// quality is lowered.
+    pub fn limit(&self, bitrate: Bitrate, complexity: u8) -> (Bitrate, u8) {
+        let bitrate = match bitrate {
+            Bitrate::Bits(bits) => Bitrate::Bits(bits.min(self.bitrate)),
+            Bitrate::Auto | Bitrate::Max => Bitrate::Bits(self.bitrate),
+        };
+
+        (bitrate, complexity.min(self.complexity))
+    }
+}
+
+impl Default for GuardrailConfig {
+    fn default() -> GuardrailConfig {
+        GuardrailConfig {
+            threshold: Some(DEFAULT_THRESHOLD),
+            complexity: DEFAULT_COMPLEXITY,
+            bitrate: DEFAULT_BITRATE,
+        }
+    }
+}
+
+/// A change in whether quality is lowered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GuardrailEvent {
+    /// Encoding is using too much of the CPU, so new sources are built at a
+    /// lower quality.
+    Degraded {
+        /// The share of the CPU encoding used.
+        load: f64,
+    },
+    /// The load is back down, so new sources are built at full quality
+    /// again.
+    Recovered {
+        /// The share of the CPU encoding used.
+        load: f64,
+    },
+}
+
+/// Watches how much of the CPU encoding uses.
+#[derive(Debug)]
+pub struct Guardrail {
+    timings: Arc<StreamTimings>,
+    threshold: f64,
+    cores: u32,
+    last_busy: Duration,
+    last_check: Instant,
+    degraded: bool,
+}
+
+impl Guardrail {
+    /// Creates a `Guardrail` reading the encode time recorded to `timings`,
+    /// or `None` if the [`guardrail`] config turns it off.
+    pub fn new(timings: Arc<StreamTimings>) -> Option<Guardrail> {
+        let threshold = guardrail().threshold?;
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get() as u32);
+
+        Some(Guardrail {
+            last_busy: timings.encode.sum(),
+            last_check: Instant::now(),
+            timings,
+            threshold,
+            cores,
+            degraded: false,
+        })
+    }
+
+    /// Checks the load since the last check, returning an event if quality
+    /// was lowered or raised again.
+    pub fn check(&mut self) -> Option<GuardrailEvent> {
+        let now = Instant::now();
+        let busy = self.timings.encode.sum();
+        let elapsed = now - self.last_check;
+
+        let load = (busy - self.last_busy).as_secs_f64()
+            / (elapsed.as_secs_f64() * self.cores as f64).max(f64::EPSILON);
+
+        self.last_busy = busy;
+        self.last_check = now;
+
+        let event = self.update(load)?;
+        DEGRADED.store(self.degraded, Ordering::Relaxed);
+
+        match event {
+            GuardrailEvent::Degraded { load } => warn!(
+                load,
+                "encoding is close to saturating the cpu, lowering the quality of new sources",
+            ),
+            GuardrailEvent::Recovered { load } => {
+                info!(load, "cpu load is back down, raising quality again")
+            }
+        }
+
+        Some(event)
+    }
+
+    fn update(&mut self, load: f64) -> Option<GuardrailEvent> {
+        if !self.degraded && load >= self.threshold {
+            self.degraded = true;
+            Some(GuardrailEvent::Degraded { load })
+        } else if self.degraded && load < self.threshold * RECOVER_RATIO {
+            self.degraded = false;
+            Some(GuardrailEvent::Recovered { load })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrades_with_hysteresis() {
+        let mut guardrail = Guardrail {
+            timings: Arc::default(),
+            threshold: 0.8,
+            cores: 4,
+            last_busy: Duration::ZERO,
+            last_check: Instant::now(),
+            degraded: false,
+        };
+
+        assert_eq!(guardrail.update(0.5), None);
+        assert_eq!(
+            guardrail.update(0.9),
+            Some(GuardrailEvent::Degraded { load: 0.9 })
+        );
+        // still too close to the threshold
+        assert_eq!(guardrail.update(0.7), None);
+        assert_eq!(
+            guardrail.update(0.5),
+            Some(GuardrailEvent::Recovered { load: 0.5 })
+        );
+
+        let config = GuardrailConfig::default();
+        assert_eq!(
+            config.limit(Bitrate::Bits(128_000), 10),
+            (Bitrate::Bits(DEFAULT_BITRATE), DEFAULT_COMPLEXITY)
+        );
+        assert_eq!(
+            config.limit(Bitrate::Bits(32_000), 3),
+            (Bitrate::Bits(32_000), 3)
+        );
+    }
+}
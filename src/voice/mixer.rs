@@ -0,0 +1,576 @@
+//! Mixing audio together.
+//!
+//! A [`Mixer`] is an [`AudioSource`] that plays any number of inputs at once,
+//! each at its own gain, like a soundboard over music or two tracks crossing
+//! over. Inputs are added and changed while it plays through a
+//! [`MixerHandle`].
+//!
+//! Overlays of a [`Source`](super::Source) are mixed differently: the
+//! [`OverlayMixer`] runs on the encoder thread of the source, so overlays are
+//! mixed in before the audio is encoded, and cost nothing when there are
+//! none.
+
+use super::constants::{DUCK_RAMP, SAMPLE_RATE, STEREO_FRAME_SIZE, TIMESTEP_LENGTH};
+use super::encoder::{CodecError, Encoder};
+use super::source::{self, read_ahead, Overlay};
+use super::streamer::AudioSource;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+use std::future::poll_fn;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::task::Poll;
+
+use opus::{Application, Channels};
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc::{self as tokio_mpsc, error::TryRecvError};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// How many frames of PCM each input of a [`Mixer`] decodes ahead.
+const INPUT_FRAMES: usize = 4;
+
+/// The largest Opus packet a single frame can encode to.
+const MAX_PACKET_LEN: usize = 1275;
+
+/// Fades a source in or out over a number of frames.
+#[derive(Debug)]
+pub(super) struct Fade {
+    frame: u32,
+    frames: u32,
+    rising: bool,
+}
+
+impl Fade {
+    /// Creates a fade out over `frames` frames.
+    pub fn new(frames: u32) -> Fade {
+        Fade {
+            frame: 0,
+            frames: frames.max(1),
+            rising: false,
+        }
+    }
+
+    /// Creates a fade in over `frames` frames.
+    pub fn rising(frames: u32) -> Fade {
+        Fade {
+            rising: true,
+            ..Fade::new(frames)
+        }
+    }
+
+    /// Fades a frame, returning `false` once the fade is over.
+    ///
+    /// A source should end once it has faded out.
+    pub fn apply(&mut self, frame: &mut [f32]) -> bool {
+        let gain = |frame: u32| {
+            let faded = frame as f32 / self.frames as f32;
+            if self.rising {
+                faded
+            } else {
+                1. - faded
+            }
+        };
+        let start = gain(self.frame);
+        let end = gain(self.frame + 1);
+
+        // interpolate across the frame so there's no click
+        let samples = (frame.len() / 2).max(1);
+        for (i, pair) in frame.chunks_mut(2).enumerate() {
+            let gain = start + (end - start) * (i + 1) as f32 / samples as f32;
+            pair.iter_mut().for_each(|sample| *sample *= gain);
+        }
+
+        self.frame += 1;
+        self.frame < self.frames
+    }
+}
+
+/// An overlay and how loud the source plays under it.
+pub(super) struct Ducking {
+    pub overlay: Overlay,
+    pub gain: f32,
+}
+
+/// Mixes overlays into frames of PCM, ducking the frames under them.
+pub(super) struct OverlayMixer {
+    overlays: Receiver<Ducking>,
+    queue: VecDeque<Ducking>,
+    /// Whether the front overlay has started producing audio.
+    started: bool,
+    /// The gain at the end of the last frame.
+    gain: f32,
+}
+
+impl OverlayMixer {
+    /// Creates a new `OverlayMixer`, and the sender to give it overlays with.
+    pub fn new() -> (Sender<Ducking>, OverlayMixer) {
+        let (tx, rx) = mpsc::channel();
+
+        let mixer = OverlayMixer {
+            overlays: rx,
+            queue: VecDeque::new(),
+            started: false,
+            gain: 1.,
+        };
+
+        (tx, mixer)
+    }
+
+    /// Ducks a frame and mixes the current overlay into it.
+    ///
+    /// The frame is ramped down before the overlay starts, so the overlay
+    /// isn't talked over, and back up after it ends.
+    pub fn mix(&mut self, frame: &mut [f32]) {
+        self.queue.extend(self.overlays.try_iter());
+
+        let target = self.queue.front().map(|ducking| ducking.gain).unwrap_or(1.);
+        self.ramp(frame, target);
+
+        if self.gain != target {
+            return;
+        }
+
+        let Some(ducking) = self.queue.front_mut() else {
+            return;
+        };
+
+        // don't hold up the source if the overlay is slow to start, but once
+        // it has, it can keep up
+        match ducking.overlay.frame(self.started) {
+            Ok(Some(overlay)) => {
+                self.started = true;
+
+                for (sample, overlay) in frame.iter_mut().zip(overlay) {
+                    *sample = (*sample + overlay).clamp(-1., 1.);
+                }
+            }
+            Ok(None) => (),
+            Err(err) => self.next(err),
+        }
+    }
+
+    /// Receives the next frame of the overlays left once the source has
+    /// ended.
+    ///
+    /// Returns `None` once there are no more.
+    pub fn drain(&mut self) -> Option<Vec<f32>> {
+        self.queue.extend(self.overlays.try_iter());
+
+        loop {
+            let ducking = self.queue.front_mut()?;
+
+            match ducking.overlay.frame(true) {
+                Ok(Some(frame)) => return Some(frame),
+                Ok(None) => (),
+                Err(err) => self.next(err),
+            }
+        }
+    }
+
+    /// Moves on from the current overlay.
+    fn next(&mut self, err: Option<std::io::Error>) {
+        if let Some(err) = err {
+            warn!(%err, "overlay error");
+        }
+
+        self.queue.pop_front();
+        self.started = false;
+    }
+
+    /// Ramps the gain of a frame toward `target`, [`DUCK_RAMP`] at a time.
+    fn ramp(&mut self, frame: &mut [f32], target: f32) {
+        let start = self.gain;
+
+        if start == target {
+            if start != 1. {
+                frame.iter_mut().for_each(|sample| *sample *= start);
+            }
+            return;
+        }
+
+        let frames = DUCK_RAMP.as_millis() as f32 / TIMESTEP_LENGTH.as_millis() as f32;
+        let step = 1. / frames.max(1.);
+
+        let end = if start < target {
+            (start + step).min(target)
+        } else {
+            (start - step).max(target)
+        };
+
+        // interpolate across the frame so there's no click
+        let samples = (frame.len() / 2).max(1);
+        for (i, pair) in frame.chunks_mut(2).enumerate() {
+            let gain = start + (end - start) * (i + 1) as f32 / samples as f32;
+            pair.iter_mut().for_each(|sample| *sample *= gain);
+        }
+
+        self.gain = end;
+    }
+}
+
+/// Plays inputs of PCM at once, encoding them into one stream of Opus.
+///
+/// The mixer ends once it has no inputs left and every [`MixerHandle`] is
+/// dropped. Until then, it waits for new inputs when it runs out.
+pub struct Mixer {
+    encoder: Encoder,
+    inputs: Vec<Input>,
+    new_inputs: tokio_mpsc::UnboundedReceiver<Input>,
+}
+
+/// Adds inputs to a [`Mixer`].
+#[derive(Clone, Debug)]
+pub struct MixerHandle {
+    inputs: tokio_mpsc::UnboundedSender<Input>,
+}
+
+/// Changes an input of a [`Mixer`] while it plays.
+#[derive(Clone, Debug)]
+pub struct InputHandle {
+    /// The gain, as the bits of an `f32`.
+    gain: Arc<AtomicU32>,
+    stopped: Arc<AtomicBool>,
+}
+
+struct Input {
+    frames: InputFrames,
+    gain: Arc<AtomicU32>,
+    stopped: Arc<AtomicBool>,
+}
+
+enum InputFrames {
+    /// Frames read from a reader by a task.
+    Reader {
+        frames: tokio_mpsc::Receiver<io::Result<Vec<f32>>>,
+        reader: JoinHandle<()>,
+    },
+    /// Frames decoded by `ffmpeg`.
+    Overlay(Box<Overlay>),
+}
+
+impl Mixer {
+    /// Creates a new, empty `Mixer`, and the handle to add inputs with.
+    pub fn new() -> Result<(MixerHandle, Mixer), CodecError> {
+        let (tx, rx) = tokio_mpsc::unbounded_channel();
+
+        let mixer = Mixer {
+            encoder: Encoder::new(SAMPLE_RATE as u32, Channels::Stereo, Application::Audio)?,
+            inputs: Vec::new(),
+            new_inputs: rx,
+        };
+
+        Ok((MixerHandle { inputs: tx }, mixer))
+    }
+
+    /// Mixes the next frame of PCM.
+    ///
+    /// This waits until any input has a frame. The inputs that don't have
+    /// theirs yet are left out of the frame, instead of holding the rest up.
+    /// Returns `None` once the mixer ends.
+    pub async fn next_frame(&mut self) -> Option<Vec<f32>> {
+        loop {
+            self.inputs
+                .retain(|input| !input.stopped.load(Ordering::Acquire));
+
+            let ready = poll_fn(|cx| {
+                while let Poll::Ready(input) = self.new_inputs.poll_recv(cx) {
+                    match input {
+                        Some(input) => self.inputs.push(input),
+                        None if self.inputs.is_empty() => return Poll::Ready(None),
+                        None => break,
+                    }
+                }
+
+                for (i, input) in self.inputs.iter_mut().enumerate() {
+                    if let Poll::Ready(frame) = input.frames().poll_recv(cx) {
+                        return Poll::Ready(Some((i, frame)));
+                    }
+                }
+
+                Poll::Pending
+            })
+            .await;
+
+            let (first, frame) = ready?;
+            let mut first_frame = Some(frame);
+            let mut mix = vec![0f32; STEREO_FRAME_SIZE];
+            let mut mixed = false;
+            let mut i = 0;
+
+            self.inputs.retain_mut(|input| {
+                let frame = match first_frame.take_if(|_| i == first) {
+                    Some(frame) => frame,
+                    None => match input.frames().try_recv() {
+                        Ok(frame) => Some(frame),
+                        // an input that hasn't decoded its frame yet sits
+                        // this one out
+                        Err(TryRecvError::Empty) => {
+                            i += 1;
+                            return true;
+                        }
+                        Err(TryRecvError::Disconnected) => None,
+                    },
+                };
+
+                i += 1;
+
+                match frame {
+                    Some(Ok(frame)) => {
+                        input.add(&mut mix, &frame);
+                        mixed = true;
+                        true
+                    }
+                    Some(Err(err)) => {
+                        warn!(%err, "mixer input error");
+                        false
+                    }
+                    None => false,
+                }
+            });
+
+            if mixed {
+                mix.iter_mut()
+                    .for_each(|sample| *sample = sample.clamp(-1., 1.));
+                return Some(mix);
+            }
+        }
+    }
+}
+
+impl AudioSource for Mixer {
+    async fn read_opus(&mut self, buf: &mut [u8]) -> Result<usize, source::Error> {
+        let Some(frame) = self.next_frame().await else {
+            return Ok(0);
+        };
+
+        let packet = self
+            .encoder
+            .encode_vec_float(&frame, buf.len().min(MAX_PACKET_LEN))
+            .map_err(source::Error::Codec)?;
+        buf[..packet.len()].copy_from_slice(&packet);
+
+        Ok(packet.len())
+    }
+}
+
+impl MixerHandle {
+    /// Adds an input of raw PCM: interleaved stereo `f32` samples, in native
+    /// byte order, at 48kHz.
+    pub fn add_reader<R>(&self, reader: R, gain: f32) -> InputHandle
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let (tx, rx) = tokio_mpsc::channel(INPUT_FRAMES);
+
+        self.add(
+            InputFrames::Reader {
+                frames: rx,
+                reader: tokio::spawn(read_ahead(reader, tx)),
+            },
+            gain,
+        )
+    }
+
+    /// Adds an input decoded by `ffmpeg`, built with
+    /// [`SourceBuilder::build_overlay`](super::SourceBuilder::build_overlay).
+    pub fn add_overlay(&self, overlay: Overlay, gain: f32) -> InputHandle {
+        self.add(InputFrames::Overlay(Box::new(overlay)), gain)
+    }
+
+    fn add(&self, frames: InputFrames, gain: f32) -> InputHandle {
+        let handle = InputHandle {
+            gain: Arc::new(AtomicU32::new(gain.to_bits())),
+            stopped: Arc::default(),
+        };
+
+        // if the mixer is gone, so is the input
+        let _ = self.inputs.send(Input {
+            frames,
+            gain: handle.gain.clone(),
+            stopped: handle.stopped.clone(),
+        });
+
+        handle
+    }
+}
+
+impl InputHandle {
+    /// Sets how loud the input plays, where `1.0` is as loud as it is.
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Release);
+    }
+
+    /// How loud the input plays.
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Acquire))
+    }
+
+    /// Takes the input out of the mixer.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+impl Input {
+    fn frames(&mut self) -> &mut tokio_mpsc::Receiver<io::Result<Vec<f32>>> {
+        match &mut self.frames {
+            InputFrames::Reader { frames, .. } => frames,
+            InputFrames::Overlay(overlay) => overlay.frames(),
+        }
+    }
+
+    /// Adds a frame of the input, at its gain, to `mix`.
+    fn add(&self, mix: &mut [f32], frame: &[f32]) {
+        let gain = f32::from_bits(self.gain.load(Ordering::Acquire));
+
+        for (sample, input) in mix.iter_mut().zip(frame) {
+            *sample += input * gain;
+        }
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        if let InputFrames::Reader { reader, .. } = &self.frames {
+            reader.abort();
+        }
+    }
+}
+
+impl Debug for Mixer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mixer")
+            .field("inputs", &self.inputs.len())
+            .finish()
+    }
+}
+
+impl Debug for Input {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Input(_)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn ramps_down_and_back_up() {
+        let (_tx, mut mixer) = OverlayMixer::new();
+        let frames = (DUCK_RAMP.as_millis() / TIMESTEP_LENGTH.as_millis()) as usize;
+
+        for _ in 0..frames {
+            let mut frame = vec![1.; STEREO_FRAME_SIZE];
+            mixer.ramp(&mut frame, 0.25);
+
+            // the gain only ever moves toward the target
+            assert!(frame.windows(2).all(|w| w[1] <= w[0]));
+        }
+
+        assert_eq!(mixer.gain, 0.25);
+
+        let mut frame = vec![1.; STEREO_FRAME_SIZE];
+        mixer.ramp(&mut frame, 0.25);
+        assert!(frame.iter().all(|&sample| sample == 0.25));
+
+        for _ in 0..frames {
+            let mut frame = vec![1.; STEREO_FRAME_SIZE];
+            mixer.ramp(&mut frame, 1.);
+        }
+
+        assert_eq!(mixer.gain, 1.);
+    }
+
+    #[test]
+    fn fades_to_silence() {
+        let mut fade = Fade::new(2);
+
+        let mut frame = vec![1.; STEREO_FRAME_SIZE];
+        assert!(fade.apply(&mut frame));
+        assert!(frame.windows(2).all(|w| w[1] <= w[0]));
+        assert_eq!(frame[STEREO_FRAME_SIZE - 1], 0.5);
+
+        let mut frame = vec![1.; STEREO_FRAME_SIZE];
+        assert!(!fade.apply(&mut frame));
+        assert_eq!(frame[STEREO_FRAME_SIZE - 1], 0.);
+
+        let mut fade = Fade::rising(2);
+
+        let mut frame = vec![1.; STEREO_FRAME_SIZE];
+        assert!(fade.apply(&mut frame));
+        assert!(frame.windows(2).all(|w| w[1] >= w[0]));
+        assert_eq!(frame[STEREO_FRAME_SIZE - 1], 0.5);
+
+        let mut frame = vec![1.; STEREO_FRAME_SIZE];
+        assert!(!fade.apply(&mut frame));
+        assert_eq!(frame[STEREO_FRAME_SIZE - 1], 1.);
+    }
+
+    /// Raw PCM of `frames` frames of a constant signal.
+    fn constant(value: f32, frames: usize) -> io::Cursor<Vec<u8>> {
+        let samples = vec![value; STEREO_FRAME_SIZE * frames];
+        io::Cursor::new(bytemuck::cast_slice(&samples).to_vec())
+    }
+
+    #[tokio::test]
+    async fn mixes_inputs_at_their_gain() {
+        let (handle, mut mixer) = Mixer::new().unwrap();
+
+        handle.add_reader(constant(0.25, 2), 1.);
+        let quiet = handle.add_reader(constant(0.5, 2), 0.5);
+
+        // wait for both inputs to decode
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let frame = mixer.next_frame().await.unwrap();
+        assert!(frame.iter().all(|&sample| sample == 0.5));
+
+        quiet.stop();
+        let frame = mixer.next_frame().await.unwrap();
+        assert!(frame.iter().all(|&sample| sample == 0.25));
+
+        // the mixer ends once its inputs and handles are gone
+        drop(handle);
+        assert!(mixer.next_frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn clips_and_encodes() {
+        let (handle, mut mixer) = Mixer::new().unwrap();
+
+        // a sine wave, and a loud signal that clips it
+        let sine = (0..STEREO_FRAME_SIZE)
+            .map(|i| ((i / 2) as f32 * 440. * std::f32::consts::TAU / SAMPLE_RATE as f32).sin())
+            .collect::<Vec<_>>();
+        handle.add_reader(io::Cursor::new(bytemuck::cast_slice(&sine).to_vec()), 1.);
+        handle.add_reader(constant(0.75, 1), 1.);
+        drop(handle);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let frame = mixer.next_frame().await.unwrap();
+        assert!(frame.iter().all(|sample| (-1. ..=1.).contains(sample)));
+        assert!(frame.contains(&1.));
+
+        // nothing is left to encode
+        let mut buf = [0; MAX_PACKET_LEN];
+        assert_eq!(mixer.read_opus(&mut buf).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn passes_through_without_overlays() {
+        let (_tx, mut mixer) = OverlayMixer::new();
+
+        let mut frame = vec![0.5; STEREO_FRAME_SIZE];
+        mixer.mix(&mut frame);
+
+        assert!(frame.iter().all(|&sample| sample == 0.5));
+    }
+}
@@ -24,6 +24,14 @@
 //! be produced when these methods are called. These are just simple ways to
 //! pause audio playback until it needs to be resumed.
 //!
+//! # Low-level client
+//! `Player` is only one way to drive a voice connection. [`ws::Connection`]
+//! handles the voice gateway, and the [`rtp::Socket`] it hands back sends
+//! Opus frames wrapped in [`rtp::Packet`]s. Anything that makes its own audio
+//! can use these directly, as long as it keeps polling
+//! [`ws::Connection::recv`] so heartbeats get sent. See `examples/tone.rs` for
+//! a complete example. None of this needs the `music` feature.
+//!
 //! # Caveats
 //! This is poorly designed! Not my amazing voice protocol, the Discord voice
 //! protocol is poorly designed! Why is the main gateway and voice gateway so
@@ -84,39 +92,55 @@
 //! [2]: https://github.com/serenity-rs/serenity
 //! [3]: https://github.com/serenity-rs/songbird/blob/df8ee0ffcad03c26db489c356e183a7e1190b04c/src/driver/tasks/mixer.rs#L523-L527
 
+pub mod config;
 pub mod constants;
+pub mod encoder;
 pub mod error;
+pub mod guardrail;
+pub mod mixer;
+mod passthrough;
 pub mod rtp;
 pub mod source;
-mod streamer;
+pub mod streamer;
+pub mod timing;
 pub mod ws;
 
-pub use error::Error;
-pub use source::Source;
+pub use config::{PlayerConfig, ReconnectConfig};
+pub use error::{Error, ErrorKind};
+pub use mixer::{InputHandle, Mixer, MixerHandle};
+pub use source::{Overlay, Source, SourceBuilder};
+pub use streamer::{AudioSource, PacketStreamer, Status};
+pub use timing::{Histogram, StreamTimings};
 
-use streamer::{PacketStreamer, Status};
+use constants::{DEFAULT_BITRATE, KEEPALIVE_INTERVAL};
 
 use tracing::{debug, error, info, instrument, warn};
 
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
 };
 
+use opus::Bitrate;
+
+use crate::ytdl::YtdlConfig;
+
 use rtp::Socket;
 use ws::{payload::Speaking, Connection, Session};
 
+pub use ws::payload::SpeakingFlags;
+
 use tokio::sync::{
     mpsc::{self, UnboundedReceiver, UnboundedSender},
     RwLock, RwLockReadGuard,
 };
 use tokio::task::JoinHandle;
-use tokio::time::{timeout_at, Duration, Instant};
+use tokio::time::{interval, sleep, timeout, timeout_at, Duration, Instant, Interval};
 
 use twilight_model::{
     gateway::payload::incoming::{VoiceServerUpdate, VoiceStateUpdate},
     id::{
-        marker::{GuildMarker, UserMarker},
+        marker::{ChannelMarker, GuildMarker, UserMarker},
         Id,
     },
     voice::VoiceState,
@@ -133,6 +157,9 @@ pub struct Player {
     state: Arc<PlayerState>,
     gateway_tx: UnboundedSender<GatewayEvent>,
     command_tx: UnboundedSender<Command>,
+    fade_in: Duration,
+    bitrate: Mutex<Bitrate>,
+    ytdl: YtdlConfig,
 }
 
 impl Player {
@@ -143,6 +170,16 @@ impl Player {
         user_id: impl Into<Id<UserMarker>>,
         guild_id: impl Into<Id<GuildMarker>>,
         event_tx: UnboundedSender<Event>,
+    ) -> Player {
+        Player::with_config(user_id, guild_id, event_tx, PlayerConfig::default())
+    }
+
+    /// Creates a new `Player` with a config.
+    pub fn with_config(
+        user_id: impl Into<Id<UserMarker>>,
+        guild_id: impl Into<Id<GuildMarker>>,
+        event_tx: UnboundedSender<Event>,
+        config: PlayerConfig,
     ) -> Player {
         let user_id = user_id.into();
         let guild_id = guild_id.into();
@@ -172,17 +209,37 @@ impl Player {
             guild_id,
             voice_state: RwLock::new(initial_state),
             playing: AtomicBool::default(),
+            paused: AtomicBool::default(),
             ready: AtomicBool::default(),
+            position: Arc::default(),
+            latency: AtomicU64::default(),
+            timings: Arc::new(StreamTimings::with_parent(config.timings.clone())),
         });
         let state_clone = state.clone();
+        let fade_in = config.fade_in;
+        let ytdl = config.ytdl.clone();
 
         // start player task
         let task = tokio::spawn(async move {
-            let task = PlayerTask::new(state_clone, event_tx, gateway_rx, command_rx).await;
+            let task = PlayerTask::new(
+                state_clone,
+                config,
+                event_tx.clone(),
+                gateway_rx,
+                command_rx,
+            )
+            .await;
 
             match task {
                 Ok(task) => task.run().await,
-                Err(err) => error!(%err, "voice init error"),
+                Err(err) => {
+                    error!(%err, "voice init error");
+
+                    let _ = event_tx.send(Event {
+                        guild_id,
+                        kind: EventType::Error(err),
+                    });
+                }
             }
         });
 
@@ -191,93 +248,207 @@ impl Player {
             gateway_tx,
             command_tx,
             state,
+            fade_in,
+            bitrate: Mutex::new(DEFAULT_BITRATE),
+            ytdl,
         }
     }
 
+    /// How long sources should fade in, from [`PlayerConfig::fade_in`].
+    ///
+    /// Sources are built before they are given to the player, so this is up
+    /// to whoever builds them, with [`SourceBuilder::fade_in`].
+    pub fn fade_in(&self) -> Duration {
+        self.fade_in
+    }
+
+    /// How sources should run `ytdl`, from [`PlayerConfig::ytdl`].
+    ///
+    /// Like [`Player::fade_in`], this is up to whoever builds the sources,
+    /// with [`SourceBuilder::ytdl_config`].
+    pub fn ytdl(&self) -> &YtdlConfig {
+        &self.ytdl
+    }
+
+    /// The bitrate sources should be encoded at, for the channel the player
+    /// is in.
+    ///
+    /// Like [`Player::fade_in`], this is up to whoever builds the sources,
+    /// with [`SourceBuilder::bitrate`].
+    pub fn bitrate(&self) -> Bitrate {
+        *self.bitrate.lock().unwrap()
+    }
+
+    /// Sets the bitrate sources should be encoded at, from the next source
+    /// on.
+    pub fn set_bitrate(&self, bitrate: Bitrate) {
+        *self.bitrate.lock().unwrap() = bitrate;
+    }
+
     /// Checks if the player is closed or dead.
     pub fn is_closed(&self) -> bool {
         self.task.is_finished()
     }
 
     /// Plays a new source.
-    pub fn play(&self, source: Source) -> Result<(), PlayerClosed> {
+    pub fn play(&self, source: Source) -> Result<(), crate::Error> {
+        self.command_tx
+            .send(Command::Play(Box::new(source)))
+            .map_err(|_| Error::Closed.into())
+    }
+
+    /// Plays a source over the current one, like an announcement.
+    ///
+    /// The current source is paused until the interjection is over, and then
+    /// picks up where it left off. Interjections don't change
+    /// [`Player::playing`] or produce events.
+    pub fn interject(&self, source: Source) -> Result<(), crate::Error> {
         self.command_tx
-            .send(Command::Play(source))
-            .map_err(|_| PlayerClosed)
+            .send(Command::Interject(Box::new(source)))
+            .map_err(|_| Error::Closed.into())
+    }
+
+    /// Plays an overlay over the current source, like an announcement.
+    ///
+    /// Unlike [`Player::interject`], the source keeps playing, ducked to
+    /// `gain` until the overlay is over. If nothing is playing, the overlay
+    /// is interjected instead.
+    pub fn announce(&self, overlay: Overlay, gain: f32) -> Result<(), crate::Error> {
+        self.command_tx
+            .send(Command::Announce(Box::new(overlay), gain))
+            .map_err(|_| Error::Closed.into())
     }
 
     /// Pauses the currently playing source.
-    pub fn pause(&self) -> Result<(), PlayerClosed> {
+    ///
+    /// Playing another source or stopping resumes the player. Interjections
+    /// still play while paused.
+    pub fn pause(&self) -> Result<(), crate::Error> {
         self.command_tx
             .send(Command::Pause)
-            .map_err(|_| PlayerClosed)
+            .map_err(|_| Error::Closed.into())
     }
 
     /// Resumes any currently playing source.
-    pub fn resume(&self) -> Result<(), PlayerClosed> {
+    pub fn resume(&self) -> Result<(), crate::Error> {
         self.command_tx
             .send(Command::Resume)
-            .map_err(|_| PlayerClosed)
+            .map_err(|_| Error::Closed.into())
     }
 
     /// Stops any playing sources.
-    pub fn stop(&self) -> Result<(), PlayerClosed> {
+    pub fn stop(&self) -> Result<(), crate::Error> {
         self.command_tx
             .send(Command::Stop)
-            .map_err(|_| PlayerClosed)
+            .map_err(|_| Error::Closed.into())
+    }
+
+    /// Changes how the player speaks while it is sending audio.
+    ///
+    /// For example, [`SpeakingFlags::PRIORITY`] lowers the volume of everyone
+    /// else while the player is speaking.
+    pub fn set_speaking(&self, flags: SpeakingFlags) -> Result<(), crate::Error> {
+        self.command_tx
+            .send(Command::SetSpeaking(flags))
+            .map_err(|_| Error::Closed.into())
+    }
+
+    /// Sets whether the player plays sources back to back, without silence
+    /// in between or fading out when stopped, like the tracks of an album.
+    ///
+    /// Sources fade in on their own, so whoever builds them should leave
+    /// that out too.
+    pub fn set_gapless(&self, gapless: bool) -> Result<(), crate::Error> {
+        self.command_tx
+            .send(Command::SetGapless(gapless))
+            .map_err(|_| Error::Closed.into())
     }
 
     /// Disconnects the player.
     ///
     /// The player should not be used after this.
-    pub fn disconnect(&self) -> Result<(), PlayerClosed> {
+    pub fn disconnect(&self) -> Result<(), crate::Error> {
         self.command_tx
             .send(Command::Disconnect)
-            .map_err(|_| PlayerClosed)
+            .map_err(|_| Error::Closed.into())
     }
 
     /// If the player is playing a sound.
+    ///
+    /// A paused source still counts as playing.
     pub fn playing(&self) -> bool {
         self.state.playing.load(Ordering::Acquire)
     }
 
+    /// If the player is paused.
+    pub fn paused(&self) -> bool {
+        self.state.paused.load(Ordering::Acquire)
+    }
+
+    /// How far into the current source the player is.
+    ///
+    /// This counts from the start of the audio, including the offset the
+    /// source was started at.
+    pub fn position(&self) -> Duration {
+        Duration::from_millis(self.state.position.load(Ordering::Acquire))
+    }
+
+    /// How long streaming packets takes, to find where stutter comes from.
+    pub fn timings(&self) -> &Arc<StreamTimings> {
+        &self.state.timings
+    }
+
+    /// The round trip time of the last heartbeat to the voice server.
+    ///
+    /// This is `None` until the first heartbeat has been acknowledged.
+    pub fn latency(&self) -> Option<Duration> {
+        match self.state.latency.load(Ordering::Acquire) {
+            0 => None,
+            latency => Some(Duration::from_micros(latency)),
+        }
+    }
+
     /// The guild id of the player.
     pub fn guild_id(&self) -> Id<GuildMarker> {
         self.state.guild_id
     }
 
     /// Gets the voice state of the player.
-    pub async fn voice_state(&self) -> Result<RwLockReadGuard<VoiceState>, PlayerClosed> {
+    pub async fn voice_state(&self) -> Result<RwLockReadGuard<'_, VoiceState>, crate::Error> {
         if self.is_closed() {
-            Err(PlayerClosed)
+            Err(Error::Closed.into())
         } else {
             Ok(self.state.voice_state.read().await)
         }
     }
 
+    /// Gets the channel the player was last connected to.
+    ///
+    /// Unlike [`Player::voice_state`], this works even if the player is
+    /// closed, so a crashed player can be rebuilt in the same channel.
+    pub async fn last_channel_id(&self) -> Option<Id<ChannelMarker>> {
+        self.state.voice_state.read().await.channel_id
+    }
+
     /// Sends a voice state update event to the player.
     ///
     /// You typically shouldn't call this manually.
-    pub fn voice_state_update(&self, ev: Box<VoiceStateUpdate>) -> Result<(), PlayerClosed> {
+    pub fn voice_state_update(&self, ev: Box<VoiceStateUpdate>) -> Result<(), crate::Error> {
         self.gateway_tx
             .send(GatewayEvent::VoiceStateUpdate(ev))
-            .map_err(|_| PlayerClosed)
+            .map_err(|_| Error::Closed.into())
     }
 
     /// Sends a voice server update event to the player.
     ///
     /// You typically shouldn't call this manually.
-    pub fn voice_server_update(&self, ev: VoiceServerUpdate) -> Result<(), PlayerClosed> {
+    pub fn voice_server_update(&self, ev: VoiceServerUpdate) -> Result<(), crate::Error> {
         self.gateway_tx
             .send(GatewayEvent::VoiceServerUpdate(ev))
-            .map_err(|_| PlayerClosed)
+            .map_err(|_| Error::Closed.into())
     }
 }
 
-/// An error for when a player is closed.
-#[derive(Debug)]
-pub struct PlayerClosed;
-
 /// An event that a [`Player`] can produce.
 #[derive(Debug)]
 pub struct Event {
@@ -293,16 +464,28 @@ pub enum EventType {
     Playing,
     /// The player stopped playing a sound.
     Stopped,
-    /// The player has crashed with an error.
+    /// A user connected to the voice channel.
+    ListenerJoined(Id<UserMarker>),
+    /// A user disconnected from the voice channel.
+    ListenerLeft(Id<UserMarker>),
+    /// The player encountered an error.
+    ///
+    /// If the error is an [`ErrorKind::SourceError`], only the playing source
+    /// failed; the player skips it and keeps running. Otherwise, the player
+    /// has crashed.
     Error(Error),
 }
 
 enum Command {
-    Play(Source),
+    Play(Box<Source>),
+    Interject(Box<Source>),
+    Announce(Box<Overlay>, f32),
     Pause,
     Resume,
     Stop,
     Disconnect,
+    SetSpeaking(SpeakingFlags),
+    SetGapless(bool),
 }
 
 #[derive(Debug)]
@@ -314,7 +497,15 @@ enum GatewayEvent {
 struct PlayerState {
     voice_state: RwLock<VoiceState>,
     playing: AtomicBool,
+    paused: AtomicBool,
     ready: AtomicBool,
+    /// Milliseconds into the current source.
+    position: Arc<AtomicU64>,
+    /// Microseconds it took for the last heartbeat to be acknowledged, or 0
+    /// if no heartbeats were acknowledged yet.
+    latency: AtomicU64,
+    /// How long streaming packets takes.
+    timings: Arc<StreamTimings>,
 
     user_id: Id<UserMarker>,
     guild_id: Id<GuildMarker>,
@@ -323,6 +514,7 @@ struct PlayerState {
 /// The task that runs behind each player.
 struct PlayerTask {
     state: Arc<PlayerState>,
+    config: PlayerConfig,
     gateway_rx: UnboundedReceiver<GatewayEvent>,
     command_rx: UnboundedReceiver<Command>,
     event_tx: UnboundedSender<Event>,
@@ -331,20 +523,22 @@ struct PlayerTask {
     rtp: Socket,
 
     streamer: PacketStreamer,
+    keepalive: Interval,
 }
 
 impl PlayerTask {
     /// Creates a new `PlayerTask`.
     ///
-    /// This will timeout with a [`Error::CannotJoin`] if 5 seconds pass
-    /// without a connection.
+    /// This will fail with a [`Error::CannotJoin`] if the gateway doesn't
+    /// tell us where to connect within [`ReconnectConfig::gateway_timeout`].
     pub async fn new(
         state: Arc<PlayerState>,
+        config: PlayerConfig,
         event_tx: UnboundedSender<Event>,
         mut gateway_rx: UnboundedReceiver<GatewayEvent>,
         command_rx: UnboundedReceiver<Command>,
     ) -> Result<PlayerTask, Error> {
-        let deadline = Instant::now() + Duration::from_millis(5000);
+        let deadline = Instant::now() + config.reconnect.gateway_timeout;
 
         // begin initialization: wait for events
         let mut vstu: Option<Box<VoiceStateUpdate>> = None;
@@ -371,7 +565,7 @@ impl PlayerTask {
             let res = Session {
                 guild_id: state.guild_id,
                 user_id: state.user_id,
-                endpoint: vseu.endpoint.unwrap(),
+                endpoint: vseu.endpoint.ok_or(missing_endpoint())?,
                 token: vseu.token,
                 session_id: vstu.0.session_id.clone(),
             };
@@ -384,10 +578,7 @@ impl PlayerTask {
             return Err(Error::CannotJoin);
         };
 
-        let (ws, rtp) = timeout_at(deadline, Connection::connect(session))
-            .await
-            .map_err(|_| Error::Timeout)?
-            .map_err(Error::from)?;
+        let (ws, rtp) = connect(&config, session).await?;
 
         state.ready.store(true, Ordering::Release);
 
@@ -396,8 +587,14 @@ impl PlayerTask {
             kind: EventType::Ready,
         });
 
+        let mut streamer = PacketStreamer::new(Duration::from_millis(200), state.position.clone());
+        streamer.set_silence_frames(config.silence_frames);
+        streamer.set_pre_roll(config.pre_roll);
+        streamer.set_timings(state.timings.clone());
+
         Ok(PlayerTask {
             state,
+            config,
             gateway_rx,
             command_rx,
             event_tx,
@@ -405,17 +602,22 @@ impl PlayerTask {
             ws,
             rtp,
 
-            streamer: PacketStreamer::new(Duration::from_millis(200)),
+            streamer,
+            keepalive: interval(KEEPALIVE_INTERVAL),
         })
     }
 
+    /// Reconnects after the voice server crashed.
+    ///
+    /// If the gateway doesn't move us to a new voice server, the old session
+    /// is reconnected to.
     #[instrument(skip(self))]
     async fn reconnect(&mut self) -> Result<(), Error> {
         if self.state.voice_state.read().await.channel_id.is_none() {
             return Err(Error::Disconnected);
         }
 
-        let deadline = Instant::now() + Duration::from_millis(5000);
+        let deadline = Instant::now() + self.config.reconnect.gateway_timeout;
 
         loop {
             match timeout_at(deadline, self.gateway_rx.recv()).await {
@@ -438,7 +640,8 @@ impl PlayerTask {
                     return Err(Error::GatewayClosed);
                 }
                 Err(_) => {
-                    return Err(Error::Timeout);
+                    // no new server, so try the old one again
+                    return self.reconnect_session().await;
                 }
             }
         }
@@ -448,7 +651,7 @@ impl PlayerTask {
     async fn wait_for_gateway(&mut self) -> Result<(), Error> {
         warn!("disconnected; waiting for gateway");
 
-        let deadline = Instant::now() + Duration::from_millis(5000);
+        let deadline = Instant::now() + self.config.reconnect.gateway_timeout;
 
         loop {
             match timeout_at(deadline, self.gateway_rx.recv()).await {
@@ -469,11 +672,25 @@ impl PlayerTask {
                 }
                 Ok(Some(GatewayEvent::VoiceStateUpdate(_))) => (),
                 Ok(None) => return Err(Error::GatewayClosed),
-                Err(_) => return Err(Error::Timeout),
+                Err(_) => {
+                    // the gateway never told us we left, so we might have
+                    // only lost the connection
+                    return self.reconnect_session().await;
+                }
             }
         }
     }
 
+    /// Reconnects to the voice server of the current session.
+    async fn reconnect_session(&mut self) -> Result<(), Error> {
+        if self.state.voice_state.read().await.channel_id.is_none() {
+            return Err(Error::Disconnected);
+        }
+
+        let session = self.ws.session().clone();
+        self.connect(session).await
+    }
+
     async fn set_playing(&mut self, playing: bool) {
         if self.state.playing.fetch_xor(playing, Ordering::Acquire) {
             self.state.playing.store(playing, Ordering::Release);
@@ -506,7 +723,8 @@ impl PlayerTask {
         }
 
         // attempt do cleanup
-        if let Some(mut source) = self.streamer.take_source() {
+        let sources = self.streamer.take_source().into_iter();
+        for mut source in sources.chain(self.streamer.take_interjections()) {
             if let Err(err) = source.close().await {
                 error!(%err, "close source error");
             }
@@ -522,6 +740,23 @@ impl PlayerTask {
                 // voice websocket event
                 ev = self.ws.recv() => {
                     match ev {
+                        Some(Ok(ws::Event::HeartbeatAck(latency))) => {
+                            self.state
+                                .latency
+                                .store(latency.as_micros() as u64, Ordering::Release);
+                        }
+                        Some(Ok(ws::Event::ClientConnect(ev))) => {
+                            let _ = self.event_tx.send(Event {
+                                guild_id: self.state.guild_id,
+                                kind: EventType::ListenerJoined(ev.user_id),
+                            });
+                        }
+                        Some(Ok(ws::Event::ClientDisconnect(ev))) => {
+                            let _ = self.event_tx.send(Event {
+                                guild_id: self.state.guild_id,
+                                kind: EventType::ListenerLeft(ev.user_id),
+                            });
+                        }
                         Some(Ok(ev)) => {
                             // discard event
                             debug!("voice ev: {:?}", ev);
@@ -563,21 +798,51 @@ impl PlayerTask {
 
                             // start new source
                             //self.streamer.add_silence(5);
-                            self.streamer.source(source);
+                            self.streamer.source(*source);
+                            self.streamer.resume();
+                            self.state.paused.store(false, Ordering::Release);
 
                             self.set_playing(true).await;
                         }
+                        Some(Command::Interject(source)) => {
+                            self.streamer.interject(*source);
+                        }
+                        Some(Command::Announce(overlay, gain)) => {
+                            if let Err(err) = self.streamer.duck(*overlay, gain) {
+                                warn!(%err, "announcement error");
+                            }
+                        }
                         Some(Command::Pause) => {
-                            //self.set_playing(false).await?;
+                            self.streamer.pause();
+                            self.state.paused.store(true, Ordering::Release);
                         }
                         Some(Command::Resume) => {
-                            //if self.streamer.has_source() {
-                            //    self.set_playing(true).await?;
-                            //}
+                            self.streamer.resume();
+                            self.state.paused.store(false, Ordering::Release);
                         }
                         Some(Command::Stop) => {
-                            self.close_source().await?;
-                            self.set_playing(false).await;
+                            self.close_interjections().await?;
+
+                            // a fading source stops on its own once it's
+                            // silent; stopping it again cuts it off
+                            let fade_out = self.config.fade_out;
+                            let fade = !fade_out.is_zero() && !self.streamer.gapless();
+                            if !fade || !self.streamer.fade_out(fade_out) {
+                                self.close_source().await?;
+                                self.streamer.resume();
+                                self.state.paused.store(false, Ordering::Release);
+                                self.set_playing(false).await;
+                            }
+                        }
+                        Some(Command::SetSpeaking(flags)) => {
+                            self.config.speaking = flags;
+
+                            if self.streamer.is_streaming() {
+                                self.speaking(self.rtp.ssrc(), flags).await?;
+                            }
+                        }
+                        Some(Command::SetGapless(gapless)) => {
+                            self.streamer.set_gapless(gapless);
                         }
                         Some(Command::Disconnect) => {
                             // disconnect
@@ -590,26 +855,41 @@ impl PlayerTask {
                 // streaming audio
                 result = self.streamer.stream(&mut self.rtp) => {
                     // send speaking events
-                    match result? {
-                        Status::Started(ssrc) => {
-                            self.ws.send(Speaking {
-                                speaking: 1,
-                                ssrc,
-                                delay: Some(0),
-                            })
-                            .await?;
+                    match result {
+                        Ok(Status::Started(ssrc)) => {
+                            self.speaking(ssrc, self.config.speaking).await?;
                         }
-                        Status::Stopped(ssrc) => {
-                            self.ws.send(Speaking {
-                                speaking: 0,
-                                ssrc,
-                                delay: Some(0),
-                            })
-                            .await?;
+                        Ok(Status::Stopped(ssrc)) => {
+                            self.speaking(ssrc, SpeakingFlags::empty()).await?;
                         }
-                        Status::SourceStopped => {
+                        Ok(Status::SourceStopped) => {
                             self.set_playing(false).await;
                         }
+                        Err(Error::Audio(err)) => {
+                            // only the source failed, so skip it instead of
+                            // taking down the whole player
+                            warn!(%err, "source error");
+
+                            if let Some(mut source) = self.streamer.take_source() {
+                                if let Err(err) = source.close().await {
+                                    error!(%err, "close source error");
+                                }
+                            }
+
+                            let _ = self.event_tx.send(Event {
+                                guild_id: self.state.guild_id,
+                                kind: EventType::Error(Error::Audio(err)),
+                            });
+
+                            self.set_playing(false).await;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                // keep the udp socket alive while idle
+                _ = self.keepalive.tick() => {
+                    if !self.streamer.is_streaming() {
+                        self.rtp.keepalive().await?;
                     }
                 }
             }
@@ -619,6 +899,14 @@ impl PlayerTask {
         Ok(())
     }
 
+    async fn close_interjections(&mut self) -> Result<(), Error> {
+        for mut interjection in self.streamer.take_interjections() {
+            interjection.close().await?;
+        }
+
+        Ok(())
+    }
+
     async fn close_source(&mut self) -> Result<(), Error> {
         //self.set_playing(false).await?;
 
@@ -635,28 +923,69 @@ impl PlayerTask {
         let session = Session {
             guild_id: self.state.guild_id,
             user_id: self.state.user_id,
-            endpoint: vseu.endpoint.unwrap(),
+            endpoint: vseu.endpoint.ok_or(missing_endpoint())?,
             token: vseu.token,
             session_id: self.ws.session().session_id.clone(),
         };
 
-        let deadline = Instant::now() + Duration::from_millis(5000);
-        (self.ws, self.rtp) = match timeout_at(deadline, Connection::connect(session)).await {
-            Ok(Ok(conn)) => conn,
-            Ok(Err(err)) => return Err(Error::from(err)),
-            Err(_) => return Err(Error::Timeout),
-        };
+        self.connect(session).await
+    }
+
+    /// Connects to a new session, replacing the old connection.
+    async fn connect(&mut self, session: Session) -> Result<(), Error> {
+        (self.ws, self.rtp) = connect(&self.config, session).await?;
 
         if self.streamer.is_streaming() {
-            self.ws
-                .send(Speaking {
-                    speaking: 1,
-                    ssrc: self.rtp.ssrc(),
-                    delay: Some(0),
-                })
-                .await?;
+            self.speaking(self.rtp.ssrc(), self.config.speaking).await?;
         }
 
         Ok(())
     }
+
+    /// Tells the voice server how we are speaking.
+    async fn speaking(&mut self, ssrc: u32, speaking: SpeakingFlags) -> Result<(), Error> {
+        self.ws
+            .send(Speaking {
+                speaking,
+                ssrc,
+                delay: Some(0),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Connects to a voice server, retrying with backoff.
+#[instrument(skip(config))]
+async fn connect(config: &PlayerConfig, session: Session) -> Result<(Connection, Socket), Error> {
+    let udp_ports = config.udp_ports.clone();
+    let config = &config.reconnect;
+    let mut attempt = 0;
+
+    loop {
+        let conn = Connection::connect(session.clone(), udp_ports.clone());
+        let err = match timeout(config.connect_timeout, conn).await {
+            Ok(Ok(conn)) => return Ok(conn),
+            Ok(Err(err)) => Error::from(err),
+            Err(_) => Error::Timeout,
+        };
+
+        attempt += 1;
+        if attempt >= config.max_attempts {
+            return Err(err);
+        }
+
+        let delay = config.delay(attempt - 1);
+        warn!(%err, attempt, ?delay, "failed to connect, retrying");
+
+        sleep(delay).await;
+    }
+}
+
+/// The error for a voice server update without an endpoint.
+fn missing_endpoint() -> Error {
+    Error::Ws(ws::Error::Protocol(
+        ws::error::ProtocolError::MissingEndpoint,
+    ))
 }
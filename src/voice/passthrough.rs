@@ -0,0 +1,205 @@
+//! Opus audio that is sent as it is, without decoding it.
+//!
+//! When a track is already Opus, like most of YouTube's audio formats,
+//! `ffmpeg` can copy it into an Ogg stream without touching the audio, and
+//! the packets are read out of that. This saves decoding and encoding it
+//! again, but leaves no PCM to filter, fade or mix overlays into.
+
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use super::constants::TIMESTEP_LENGTH;
+use super::source::Error;
+
+/// The magic at the start of every Ogg page.
+const CAPTURE_PATTERN: &[u8] = b"OggS";
+
+/// How many packets at the start of an Ogg Opus stream are headers.
+const HEADER_PACKETS: usize = 2;
+
+/// Reads the packets of an Ogg stream with a single logical stream in it.
+pub(super) struct OggReader<R> {
+    reader: R,
+    /// The lengths of the segments left in the page being read.
+    segments: Vec<u8>,
+}
+
+impl<R> OggReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(reader: R) -> OggReader<R> {
+        OggReader {
+            reader,
+            segments: Vec::new(),
+        }
+    }
+
+    /// Reads the next packet, or `None` at the end of the stream.
+    ///
+    /// A packet cut off by the end of the stream is dropped.
+    pub async fn packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut packet = Vec::new();
+
+        loop {
+            if self.segments.is_empty() {
+                if !self.page().await? {
+                    return Ok(None);
+                }
+
+                continue;
+            }
+
+            let len = self.segments.remove(0);
+            let start = packet.len();
+            packet.resize(start + len as usize, 0);
+            self.reader.read_exact(&mut packet[start..]).await?;
+
+            // a segment shorter than 255 bytes ends the packet
+            if len < 255 {
+                return Ok(Some(packet));
+            }
+        }
+    }
+
+    /// Reads the header of the next page, returning `false` at the end of
+    /// the stream.
+    async fn page(&mut self) -> io::Result<bool> {
+        let mut header = [0; 27];
+
+        match self.reader.read_exact(&mut header).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+
+        if &header[..4] != CAPTURE_PATTERN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected an ogg page",
+            ));
+        }
+
+        self.segments = vec![0; header[26] as usize];
+        self.reader.read_exact(&mut self.segments).await?;
+
+        Ok(true)
+    }
+}
+
+/// Gets how much audio an Opus packet holds, from its TOC byte.
+pub(super) fn packet_duration(packet: &[u8]) -> Option<Duration> {
+    let toc = *packet.first()?;
+    let config = (toc >> 3) as usize;
+
+    let frame_micros: u64 = match config {
+        // SILK
+        0..=11 => [10_000, 20_000, 40_000, 60_000][config % 4],
+        // hybrid
+        12..=15 => [10_000, 20_000][config % 2],
+        // CELT
+        _ => [2_500, 5_000, 10_000, 20_000][config % 4],
+    };
+
+    let frames = match toc & 0b11 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0b11_1111) as u64,
+    };
+
+    Some(Duration::from_micros(frame_micros * frames))
+}
+
+/// Reads Opus packets out of an Ogg stream until it ends, the
+/// [`Source`](super::Source) is dropped, or the source fades out.
+///
+/// Discord expects a frame of [`TIMESTEP_LENGTH`] in every packet, so the
+/// stream is stopped at the first packet that doesn't hold one. If that's
+/// the first packet, the source falls back to decoding the audio.
+pub(super) async fn passthrough<R>(
+    reader: R,
+    fade_out: Arc<AtomicU32>,
+    packets: mpsc::Sender<Result<Vec<u8>, Error>>,
+) where
+    R: AsyncRead + Unpin,
+{
+    let mut reader = OggReader::new(reader);
+    let mut headers = HEADER_PACKETS;
+    // there is no audio to fade, so the source is cut off when it would end
+    let mut fade = None;
+
+    loop {
+        let packet = match reader.packet().await {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(err) => {
+                let _ = packets.send(Err(Error::Io(err))).await;
+                break;
+            }
+        };
+
+        if headers > 0 {
+            headers -= 1;
+            continue;
+        }
+
+        let duration = packet_duration(&packet);
+
+        if duration != Some(TIMESTEP_LENGTH) {
+            warn!(?duration, "opus packet isn't a single frame, stopping");
+            break;
+        }
+
+        if fade.is_none() {
+            fade = Some(fade_out.load(Ordering::Acquire)).filter(|&frames| frames > 0);
+        }
+
+        match fade.as_mut() {
+            Some(0) => break,
+            Some(frames) => *frames -= 1,
+            None => (),
+        }
+
+        if packets.send(Ok(packet)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(segments: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut page = CAPTURE_PATTERN.to_vec();
+        page.resize(26, 0);
+        page.push(segments.len() as u8);
+        page.extend_from_slice(segments);
+        page.extend_from_slice(data);
+        page
+    }
+
+    #[tokio::test]
+    async fn reads_packets() {
+        // a 20ms CELT frame, and a packet spanning two pages
+        let mut stream = page(&[1, 255], &[0xFC; 256]);
+        stream.extend(page(&[10], &[0xFC; 10]));
+
+        let mut reader = OggReader::new(stream.as_slice());
+
+        let first = reader.packet().await.unwrap().unwrap();
+        assert_eq!(first, [0xFC]);
+        assert_eq!(packet_duration(&first), Some(TIMESTEP_LENGTH));
+
+        let second = reader.packet().await.unwrap().unwrap();
+        assert_eq!(second.len(), 265);
+
+        assert!(reader.packet().await.unwrap().is_none());
+    }
+}
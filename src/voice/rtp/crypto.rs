@@ -9,6 +9,8 @@ use rand::{
 
 use std::fmt::{self, Debug, Formatter};
 
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::XChaCha20Poly1305;
 use xsalsa20poly1305::{
     aead::{self, AeadInPlace, KeyInit},
     XSalsa20Poly1305, NONCE_SIZE,
@@ -24,30 +26,56 @@ pub enum EncryptionMode {
     /// The nonce bytes are 4 bytes incremented by 1 for each packet, and placed
     /// at the end of the packet. The rest of the nonce is 20 '\0' bytes.
     Lite,
+    /// AES-256-GCM over the payload and extension body, with the RTP header as
+    /// associated data. The nonce is a 4 byte counter placed at the end of the
+    /// packet, after the tag.
+    Aes256GcmRtpSize,
+    /// Same as [`EncryptionMode::Aes256GcmRtpSize`], but with
+    /// XChaCha20-Poly1305.
+    XChaCha20Poly1305RtpSize,
 }
 
-/// Encrypts outgoing packets using [`xsalsa20poly1305`].
+/// Encrypts outgoing packets.
 pub struct Encryptor {
-    aead: XSalsa20Poly1305,
     state: EncryptorState,
 }
 
 enum EncryptorState {
-    Normal,
-    Suffix(Box<StdRng>),
-    Lite(u32),
+    Normal(XSalsa20Poly1305),
+    Suffix(XSalsa20Poly1305, Box<StdRng>),
+    Lite(XSalsa20Poly1305, u32),
+    Aes256GcmRtpSize(Box<Aes256Gcm>, u32),
+    XChaCha20Poly1305RtpSize(XChaCha20Poly1305, u32),
 }
 
 impl Encryptor {
     /// Creates a new encryptor from a secret key and an encryption mode.
     pub fn new(mode: EncryptionMode, secret_key: [u8; 32]) -> Encryptor {
+        let xsalsa = || {
+            XSalsa20Poly1305::new_from_slice(&secret_key).expect("32-bytes enforced by compiler")
+        };
+
         Encryptor {
-            aead: XSalsa20Poly1305::new_from_slice(&secret_key)
-                .expect("32-bytes enforced by compiler"),
             state: match mode {
-                EncryptionMode::Normal => EncryptorState::Normal,
-                EncryptionMode::Suffix => EncryptorState::Suffix(Box::new(StdRng::from_entropy())),
-                EncryptionMode::Lite => EncryptorState::Lite(OsRng.gen()),
+                EncryptionMode::Normal => EncryptorState::Normal(xsalsa()),
+                EncryptionMode::Suffix => {
+                    EncryptorState::Suffix(xsalsa(), Box::new(StdRng::from_entropy()))
+                }
+                EncryptionMode::Lite => EncryptorState::Lite(xsalsa(), OsRng.gen()),
+                EncryptionMode::Aes256GcmRtpSize => EncryptorState::Aes256GcmRtpSize(
+                    Box::new(
+                        Aes256Gcm::new_from_slice(&secret_key)
+                            .expect("32-bytes enforced by compiler"),
+                    ),
+                    OsRng.gen(),
+                ),
+                EncryptionMode::XChaCha20Poly1305RtpSize => {
+                    EncryptorState::XChaCha20Poly1305RtpSize(
+                        XChaCha20Poly1305::new_from_slice(&secret_key)
+                            .expect("32-bytes enforced by compiler"),
+                        OsRng.gen(),
+                    )
+                }
             },
         }
     }
@@ -58,14 +86,15 @@ impl Encryptor {
         T: AsRef<[u8]> + AsMut<[u8]>,
     {
         match &mut self.state {
-            EncryptorState::Normal => {
+            EncryptorState::Normal(aead) => {
                 // use the packet header as a nonce
                 let mut nonce = [0u8; NONCE_SIZE];
-                nonce[0..Packet::<T>::HEADER_LEN_NO_TAG].copy_from_slice(pkt.header());
+                nonce[0..Packet::<T>::HEADER_LEN_NO_TAG]
+                    .copy_from_slice(&pkt.header()[..Packet::<T>::HEADER_LEN_NO_TAG]);
 
                 // encrypt
                 let payload_len = pkt.payload_len();
-                let tag = self.aead.encrypt_in_place_detached(
+                let tag = aead.encrypt_in_place_detached(
                     &nonce.into(),
                     b"",
                     &mut pkt.payload_mut()[..payload_len],
@@ -76,14 +105,14 @@ impl Encryptor {
                 // no need to finalize anything here; we're done.
                 Ok(())
             }
-            EncryptorState::Suffix(rng) => {
+            EncryptorState::Suffix(aead, rng) => {
                 // generate a new nonce
                 let mut nonce = [0u8; NONCE_SIZE];
                 rng.fill_bytes(&mut nonce);
 
                 // encrypt
                 let payload_len = pkt.payload_len();
-                let tag = self.aead.encrypt_in_place_detached(
+                let tag = aead.encrypt_in_place_detached(
                     &nonce.into(),
                     b"",
                     &mut pkt.payload_mut()[..payload_len],
@@ -98,7 +127,7 @@ impl Encryptor {
 
                 Ok(())
             }
-            EncryptorState::Lite(next_nonce) => {
+            EncryptorState::Lite(aead, next_nonce) => {
                 // get nonce and increment
                 let mut nonce = [0u8; NONCE_SIZE];
                 nonce[0..4].copy_from_slice(&next_nonce.to_be_bytes());
@@ -106,7 +135,7 @@ impl Encryptor {
 
                 // encrypt
                 let payload_len = pkt.payload_len();
-                let tag = self.aead.encrypt_in_place_detached(
+                let tag = aead.encrypt_in_place_detached(
                     &nonce.into(),
                     b"",
                     &mut pkt.payload_mut()[..payload_len],
@@ -121,12 +150,246 @@ impl Encryptor {
 
                 Ok(())
             }
+            EncryptorState::Aes256GcmRtpSize(aead, next_nonce) => {
+                encrypt_rtpsize(aead.as_ref(), next_nonce, pkt)
+            }
+            EncryptorState::XChaCha20Poly1305RtpSize(aead, next_nonce) => {
+                encrypt_rtpsize(aead, next_nonce, pkt)
+            }
         }
     }
 }
 
+/// Encrypts a packet with one of the `rtpsize` modes.
+///
+/// The RTP header, including the 4 byte prefix of the extension header, is
+/// left unencrypted and authenticated as associated data. The extension body
+/// and the payload are encrypted together, so the payload is moved back over
+/// the tag slot, and the tag and the 4 byte nonce are appended after it.
+fn encrypt_rtpsize<A, T>(
+    aead: &A,
+    next_nonce: &mut u32,
+    pkt: &mut Packet<T>,
+) -> Result<(), aead::Error>
+where
+    A: AeadInPlace,
+    T: AsRef<[u8]> + AsMut<[u8]>,
+{
+    const NONCE_LEN: usize = 4;
+
+    // get nonce and increment
+    let mut nonce = aead::Nonce::<A>::default();
+    nonce[0..NONCE_LEN].copy_from_slice(&next_nonce.to_be_bytes());
+    *next_nonce = next_nonce.overflowing_add(1).0;
+
+    let header_len = pkt.header_len();
+    let payload_len = pkt.payload_len();
+    let aad_len = match pkt.extension_len() {
+        0 => Packet::<T>::HEADER_LEN_NO_TAG,
+        _ => Packet::<T>::HEADER_LEN_NO_TAG + 4,
+    };
+
+    let buf = pkt.pkt.as_mut();
+
+    // close the gap the tag slot leaves between the extension and the payload
+    let payload_start = header_len + super::TAG_SIZE;
+    buf.copy_within(payload_start..payload_start + payload_len, header_len);
+
+    // encrypt
+    let (aad, rest) = buf.split_at_mut(aad_len);
+    let ciphertext_len = header_len - aad_len + payload_len;
+    let tag = aead.encrypt_in_place_detached(&nonce, aad, &mut rest[..ciphertext_len])?;
+
+    // append tag and nonce to the end
+    let end = header_len + payload_len;
+    buf[end..end + tag.len()].copy_from_slice(&tag);
+    buf[end + tag.len()..end + tag.len() + NONCE_LEN].copy_from_slice(&nonce[0..NONCE_LEN]);
+
+    // the tag slot was given up above, so only the nonce adds to the length
+    pkt.set_payload_len(payload_len + NONCE_LEN);
+
+    Ok(())
+}
+
 impl Debug for Encryptor {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.write_str("Encryptor(_)")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use xsalsa20poly1305::aead::{Aead, Payload};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Encrypts a `hello` packet, optionally with a one word extension.
+    fn encrypt(state: EncryptorState, extension: bool) -> Vec<u8> {
+        let mut encryptor = Encryptor { state };
+
+        let mut pkt = Packet::new([0u8; 128]);
+        pkt.set_sequence(1);
+        pkt.set_timestamp(960);
+        pkt.set_ssrc(42);
+        if extension {
+            pkt.set_extension(0xBEDE, &[0x10, 0xAA, 0, 0]);
+        }
+        pkt.payload_mut()[..5].copy_from_slice(b"hello");
+        pkt.set_payload_len(5);
+
+        encryptor.encrypt(&mut pkt).unwrap();
+
+        pkt.as_ref().to_vec()
+    }
+
+    fn xsalsa() -> XSalsa20Poly1305 {
+        XSalsa20Poly1305::new_from_slice(&KEY).unwrap()
+    }
+
+    fn aes256_gcm() -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&KEY).unwrap()
+    }
+
+    fn xchacha20() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new_from_slice(&KEY).unwrap()
+    }
+
+    /// Decrypts an xsalsa20 packet. The tag follows the header, just like in
+    /// NaCl's secretbox.
+    fn open_xsalsa(pkt: &[u8], nonce: [u8; NONCE_SIZE], payload_end: usize) -> Vec<u8> {
+        xsalsa()
+            .decrypt(&nonce.into(), &pkt[12..payload_end])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_normal() {
+        let pkt = encrypt(EncryptorState::Normal(xsalsa()), false);
+
+        assert_eq!(
+            pkt,
+            hex("80780001000003c00000002a3644408597b936b4ec30f2e5e7c9511d197e2d17ed")
+        );
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[..12].copy_from_slice(&pkt[..12]);
+        assert_eq!(open_xsalsa(&pkt, nonce, pkt.len()), b"hello");
+    }
+
+    #[test]
+    fn test_suffix() {
+        let rng = Box::new(StdRng::seed_from_u64(0));
+        let pkt = encrypt(EncryptorState::Suffix(xsalsa(), rng), false);
+
+        assert_eq!(pkt.len(), 12 + 16 + 5 + NONCE_SIZE);
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&pkt[pkt.len() - NONCE_SIZE..]);
+        assert_eq!(open_xsalsa(&pkt, nonce, pkt.len() - NONCE_SIZE), b"hello");
+    }
+
+    #[test]
+    fn test_lite() {
+        let pkt = encrypt(EncryptorState::Lite(xsalsa(), 0), false);
+
+        assert_eq!(
+            pkt,
+            hex("80780001000003c00000002a586df73531102680b9045c352eb975c3ab05a391a400000000")
+        );
+
+        assert_eq!(open_xsalsa(&pkt, [0; NONCE_SIZE], pkt.len() - 4), b"hello");
+    }
+
+    #[test]
+    fn test_lite_increments_nonce() {
+        let mut encryptor = Encryptor {
+            state: EncryptorState::Lite(xsalsa(), u32::MAX),
+        };
+
+        let mut pkt = Packet::new([0u8; 128]);
+        encryptor.encrypt(&mut pkt).unwrap();
+        assert_eq!(&pkt.as_ref()[28..], &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let mut pkt = Packet::new([0u8; 128]);
+        encryptor.encrypt(&mut pkt).unwrap();
+        assert_eq!(&pkt.as_ref()[28..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_aes256_gcm_rtpsize() {
+        let state = || EncryptorState::Aes256GcmRtpSize(Box::new(aes256_gcm()), 0);
+
+        let pkt = encrypt(state(), false);
+        assert_eq!(
+            pkt,
+            hex("80780001000003c00000002a09bdc8de94b9c5d34688b1df3b44bf6f81f267a78100000000")
+        );
+
+        let msg = aes256_gcm()
+            .decrypt(
+                &Default::default(),
+                Payload {
+                    msg: &pkt[12..pkt.len() - 4],
+                    aad: &pkt[..12],
+                },
+            )
+            .unwrap();
+        assert_eq!(msg, b"hello");
+
+        let pkt = encrypt(state(), true);
+        assert_eq!(
+            pkt,
+            hex(concat!(
+                "90780001000003c00000002abede0001",
+                "7172a4b293f767848e0da2b55e92df9c62f3c33a84e53d413a00000000",
+            ))
+        );
+
+        let msg = aes256_gcm()
+            .decrypt(
+                &Default::default(),
+                Payload {
+                    msg: &pkt[16..pkt.len() - 4],
+                    aad: &pkt[..16],
+                },
+            )
+            .unwrap();
+        assert_eq!(msg, b"\x10\xAA\0\0hello");
+    }
+
+    #[test]
+    fn test_xchacha20_poly1305_rtpsize() {
+        let pkt = encrypt(
+            EncryptorState::XChaCha20Poly1305RtpSize(xchacha20(), 0),
+            true,
+        );
+
+        assert_eq!(
+            pkt,
+            hex(concat!(
+                "90780001000003c00000002abede0001",
+                "20caeec21e48525e622ba2a12177dcde52622a56bcd880172900000000",
+            ))
+        );
+
+        let msg = xchacha20()
+            .decrypt(
+                &Default::default(),
+                Payload {
+                    msg: &pkt[16..pkt.len() - 4],
+                    aad: &pkt[..16],
+                },
+            )
+            .unwrap();
+        assert_eq!(msg, b"\x10\xAA\0\0hello");
+    }
+}
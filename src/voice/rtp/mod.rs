@@ -8,10 +8,12 @@ pub use error::Error;
 use tracing::instrument;
 
 use std::fmt::{self, Debug, Display, Formatter};
-use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::io;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::str::Utf8Error;
 
-use tokio::net::UdpSocket;
+use tokio::net::{lookup_host, UdpSocket};
 
 use super::constants::{MONO_FRAME_SIZE, VOICE_PACKET_MAX};
 
@@ -67,6 +69,26 @@ impl Socket {
         Ok(())
     }
 
+    /// Skips a frame that isn't sent, like one Opus left out with DTX, so the
+    /// timestamps of the packets after it still line up.
+    #[inline]
+    pub fn skip(&mut self) {
+        self.timestamp = self.timestamp.overflowing_add(MONO_FRAME_SIZE as u32).0;
+    }
+
+    /// Sends a UDP keepalive to the voice server.
+    ///
+    /// This keeps the NAT mapping for the socket alive while no audio is being
+    /// sent.
+    pub async fn keepalive(&mut self) -> Result<(), Error> {
+        let mut buf = [0u8; 8];
+        buf[..4].copy_from_slice(&self.ssrc.to_be_bytes());
+
+        self.udp.send(&buf).await.map_err(Error::Io)?;
+
+        Ok(())
+    }
+
     /// The ssrc of the socket.
     pub fn ssrc(&self) -> u32 {
         self.ssrc
@@ -81,6 +103,9 @@ impl Socket {
 pub struct Packet<T> {
     pkt: T,
     payload_len: usize,
+    /// The length of the extension header, including its 4 byte prefix, or
+    /// 0 if there is none.
+    extension_len: usize,
 }
 
 impl<T> Packet<T> {
@@ -100,6 +125,17 @@ impl<T> Packet<T> {
     pub fn payload_len(&self) -> usize {
         self.payload_len
     }
+
+    /// The length of the RTP header, including the extension header if there
+    /// is one, but not the Poly1305 tag.
+    pub fn header_len(&self) -> usize {
+        Self::HEADER_LEN_NO_TAG + self.extension_len
+    }
+
+    /// The length of the extension header, including its 4 byte prefix.
+    pub fn extension_len(&self) -> usize {
+        self.extension_len
+    }
 }
 
 impl<T> Packet<T>
@@ -113,21 +149,21 @@ where
     /// In fact, this must be managed manually.
     ///
     /// # Panics
-    /// Panics if `payload_len + HEADER_LEN` is greater than what the backing
-    /// buffer can hold.
+    /// Panics if the header, tag and `payload_len` are longer than what the
+    /// backing buffer can hold.
     pub fn set_payload_len(&mut self, payload_len: usize) {
-        assert!(self.pkt.as_ref().len() >= Packet::<()>::HEADER_LEN + payload_len);
+        assert!(self.pkt.as_ref().len() >= self.header_len() + TAG_SIZE + payload_len);
 
         self.payload_len = payload_len;
     }
 
     /// Returns a reference to the payload.
     pub fn payload(&self) -> &[u8] {
-        &self.pkt.as_ref()[Self::HEADER_LEN..]
+        &self.pkt.as_ref()[self.header_len() + TAG_SIZE..]
     }
 
     fn header(&self) -> &[u8] {
-        &self.pkt.as_ref()[..Self::HEADER_LEN]
+        &self.pkt.as_ref()[..self.header_len()]
     }
 }
 
@@ -154,9 +190,39 @@ where
         Packet {
             pkt,
             payload_len: 0,
+            extension_len: 0,
         }
     }
 
+    /// Adds an RTP extension header (RFC 3550 §5.3.1) with a profile and its
+    /// data.
+    ///
+    /// This moves the tag and the payload, so set it before writing the
+    /// payload.
+    ///
+    /// # Panics
+    /// Panics if the data isn't a multiple of 4 bytes long, or doesn't fit in
+    /// the backing buffer.
+    pub fn set_extension(&mut self, profile: u16, data: &[u8]) {
+        assert!(
+            data.len().is_multiple_of(4),
+            "extension must be 32-bit words"
+        );
+
+        let pkt = self.pkt.as_mut();
+        let start = Self::HEADER_LEN_NO_TAG;
+        assert!(pkt.len() >= Self::HEADER_LEN + 4 + data.len());
+
+        // set the extension bit
+        pkt[0] |= 0x10;
+
+        pkt[start..start + 2].copy_from_slice(&profile.to_be_bytes());
+        pkt[start + 2..start + 4].copy_from_slice(&((data.len() / 4) as u16).to_be_bytes());
+        pkt[start + 4..start + 4 + data.len()].copy_from_slice(data);
+
+        self.extension_len = 4 + data.len();
+    }
+
     /// Sets the sequence number of the RTP packet.
     pub fn set_sequence(&mut self, sequence: u16) {
         self.pkt.as_mut()[2..4].copy_from_slice(&sequence.to_be_bytes());
@@ -174,12 +240,14 @@ where
 
     /// Returns a mutable reference to the Poly1305 tag.
     pub fn tag_mut(&mut self) -> &mut [u8] {
-        &mut self.pkt.as_mut()[12..12 + TAG_SIZE]
+        let start = self.header_len();
+        &mut self.pkt.as_mut()[start..start + TAG_SIZE]
     }
 
     /// Returns a mutable reference to the rest of the buffer after the header.
     pub fn payload_mut(&mut self) -> &mut [u8] {
-        &mut self.pkt.as_mut()[Self::HEADER_LEN..]
+        let start = self.header_len() + TAG_SIZE;
+        &mut self.pkt.as_mut()[start..]
     }
 }
 
@@ -188,7 +256,7 @@ where
     T: AsRef<[u8]>,
 {
     fn as_ref(&self) -> &[u8] {
-        &self.pkt.as_ref()[0..Self::HEADER_LEN + self.payload_len]
+        &self.pkt.as_ref()[0..self.header_len() + TAG_SIZE + self.payload_len]
     }
 }
 
@@ -204,6 +272,79 @@ impl Default for Packet<[u8; VOICE_PACKET_MAX]> {
     }
 }
 
+/// Opens a UDP socket connected to a voice server.
+///
+/// The host is resolved first, and the socket is bound to the address family
+/// of whatever it resolves to, so this works on IPv4-only, IPv6-only and
+/// dual-stack hosts. Every resolved address is tried in order.
+///
+/// The socket binds to a local port in `local_ports`, or any port if `None`.
+pub async fn connect(
+    host: &str,
+    port: u16,
+    local_ports: Option<RangeInclusive<u16>>,
+) -> io::Result<UdpSocket> {
+    let mut last_err = None;
+
+    for addr in lookup_host((host, port)).await? {
+        let local: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+
+        let udp = match bind(local, local_ports.clone()).await {
+            Ok(udp) => udp,
+            Err(err) => {
+                last_err = Some(err);
+                continue;
+            }
+        };
+
+        match udp.connect(addr).await {
+            Ok(()) => return Ok(udp),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} did not resolve to any address", host),
+        )
+    }))
+}
+
+/// Binds a UDP socket to a port in `ports`, or any port if `None`.
+///
+/// Ports that are in use are skipped. The search starts at a random port in
+/// the range, so sockets bound together don't all race for the same ports.
+async fn bind(ip: IpAddr, ports: Option<RangeInclusive<u16>>) -> io::Result<UdpSocket> {
+    let Some(ports) = ports else {
+        return UdpSocket::bind((ip, 0)).await;
+    };
+
+    let (start, len) = (*ports.start(), ports.len() as u32);
+    let offset = rand::random::<u32>() % len.max(1);
+
+    for i in 0..len {
+        let port = start + ((offset + i) % len) as u16;
+
+        match UdpSocket::bind((ip, port)).await {
+            Ok(udp) => return Ok(udp),
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("no free udp port in {}-{}", ports.start(), ports.end()),
+    ))
+}
+
+/// The length of an IP discovery packet.
+pub const IP_DISCOVERY_LEN: usize = 74;
+
 /// Discord IP discovery.
 ///
 /// Accepts a UDP socket connected to a Discord endpoint. **While the client is
@@ -211,10 +352,9 @@ impl Default for Packet<[u8; VOICE_PACKET_MAX]> {
 #[instrument]
 pub async fn ip_discovery(udp: &UdpSocket, ssrc: u32) -> Result<SocketAddr, IpDiscoveryError> {
     const REQ_HEADER: &[u8] = &[0x00, 0x01, 0x00, 0x46];
-    const RES_HEADER: &[u8] = &[0x00, 0x02, 0x00, 0x46];
 
     // create IP discovery packet
-    let mut buf = [0u8; 74];
+    let mut buf = [0u8; IP_DISCOVERY_LEN];
     buf[..4].copy_from_slice(REQ_HEADER);
     buf[4..8].copy_from_slice(&ssrc.to_be_bytes());
 
@@ -222,47 +362,61 @@ pub async fn ip_discovery(udp: &UdpSocket, ssrc: u32) -> Result<SocketAddr, IpDi
     udp.send(&buf).await.map_err(IpDiscoveryError::Io)?;
 
     // wait for response
-    match udp.recv(&mut buf).await {
-        Ok(74) => {
-            // check header
-            if &buf[..4] != RES_HEADER {
-                let mut header = [0u8; 4];
-                header.copy_from_slice(&buf[..4]);
-                return Err(IpDiscoveryError::InvalidHeader(header));
-            }
+    let len = udp.recv(&mut buf).await.map_err(IpDiscoveryError::Io)?;
 
-            // check ssrc
-            let mut pkt_ssrc = [0u8; 4];
-            pkt_ssrc.copy_from_slice(&buf[4..8]);
-            let pkt_ssrc = u32::from_be_bytes(pkt_ssrc);
+    parse_ip_discovery(&buf[..len], ssrc)
+}
 
-            if pkt_ssrc != ssrc {
-                return Err(IpDiscoveryError::InvalidSsrc(ssrc, pkt_ssrc));
-            }
+/// Parses an IP discovery response, checking it against the `ssrc` the
+/// request was sent with.
+pub fn parse_ip_discovery(buf: &[u8], ssrc: u32) -> Result<SocketAddr, IpDiscoveryError> {
+    const RES_HEADER: &[u8] = &[0x00, 0x02, 0x00, 0x46];
 
-            // get port
-            let mut port = [0u8; 2];
-            port.copy_from_slice(&buf[72..74]);
-            let port = u16::from_be_bytes(port);
-
-            // get address
-            let addr = &buf[8..72];
-            let addr_end = addr.iter().position(|&x| x == 0).unwrap_or(64);
-
-            match std::str::from_utf8(&buf[8..8 + addr_end]) {
-                Ok(addr) => match addr.parse::<IpAddr>() {
-                    Ok(addr) => Ok((addr, port).into()),
-                    Err(err) => Err(IpDiscoveryError::InvalidAddr(err)),
-                },
-                Err(err) => Err(IpDiscoveryError::InvalidAddrUtf8(err)),
-            }
-        }
-        Ok(size) => Err(IpDiscoveryError::InvalidSize(size)),
-        Err(err) => Err(IpDiscoveryError::Io(err)),
+    if buf.len() != IP_DISCOVERY_LEN {
+        return Err(IpDiscoveryError::InvalidSize(buf.len()));
+    }
+
+    // check header
+    if &buf[..4] != RES_HEADER {
+        let mut header = [0u8; 4];
+        header.copy_from_slice(&buf[..4]);
+        return Err(IpDiscoveryError::InvalidHeader(header));
+    }
+
+    // check ssrc
+    let mut pkt_ssrc = [0u8; 4];
+    pkt_ssrc.copy_from_slice(&buf[4..8]);
+    let pkt_ssrc = u32::from_be_bytes(pkt_ssrc);
+
+    if pkt_ssrc != ssrc {
+        return Err(IpDiscoveryError::InvalidSsrc(ssrc, pkt_ssrc));
+    }
+
+    // get port
+    let mut port = [0u8; 2];
+    port.copy_from_slice(&buf[72..74]);
+    let port = u16::from_be_bytes(port);
+
+    // get address
+    let addr = &buf[8..72];
+    let addr_end = addr.iter().position(|&x| x == 0).unwrap_or(64);
+
+    let addr =
+        std::str::from_utf8(&buf[8..8 + addr_end]).map_err(IpDiscoveryError::InvalidAddrUtf8)?;
+    // IPv6 addresses may come bracketed
+    let addr = addr
+        .strip_prefix('[')
+        .and_then(|addr| addr.strip_suffix(']'))
+        .unwrap_or(addr);
+
+    match addr.parse::<IpAddr>() {
+        // a dual-stack socket sees IPv4 peers as IPv4-mapped IPv6 addresses
+        Ok(addr) => Ok((addr.to_canonical(), port).into()),
+        Err(err) => Err(IpDiscoveryError::InvalidAddr(err)),
     }
 }
 
-/// An error that is returned from [`ip_discovery`].
+/// An error that is returned from [`ip_discovery`] and [`parse_ip_discovery`].
 #[derive(Debug)]
 pub enum IpDiscoveryError {
     /// The header is badly formed.
@@ -311,3 +465,126 @@ impl std::error::Error for IpDiscoveryError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discovery_response(ssrc: u32, addr: &str, port: u16) -> [u8; IP_DISCOVERY_LEN] {
+        let mut buf = [0u8; IP_DISCOVERY_LEN];
+        buf[..4].copy_from_slice(&[0x00, 0x02, 0x00, 0x46]);
+        buf[4..8].copy_from_slice(&ssrc.to_be_bytes());
+        buf[8..8 + addr.len()].copy_from_slice(addr.as_bytes());
+        buf[72..74].copy_from_slice(&port.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_packet_header() {
+        let mut pkt = Packet::new([0u8; 64]);
+        pkt.set_sequence(0x0102);
+        pkt.set_timestamp(0x03040506);
+        pkt.set_ssrc(0x0708090A);
+
+        assert_eq!(
+            pkt.as_ref(),
+            &[
+                0x80, 0x78, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, //
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packet_payload_len() {
+        let mut pkt = Packet::new([0u8; 64]);
+        assert_eq!(pkt.as_ref().len(), Packet::<()>::HEADER_LEN);
+
+        pkt.payload_mut()[..3].copy_from_slice(b"abc");
+        pkt.set_payload_len(3);
+
+        assert_eq!(pkt.payload_len(), 3);
+        assert_eq!(pkt.as_ref().len(), Packet::<()>::HEADER_LEN + 3);
+        assert_eq!(&pkt.as_ref()[Packet::<()>::HEADER_LEN..], b"abc");
+
+        // fills the buffer exactly
+        pkt.set_payload_len(64 - Packet::<()>::HEADER_LEN);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_packet_payload_len_overflow() {
+        let mut pkt = Packet::new([0u8; 64]);
+        pkt.set_payload_len(64 - Packet::<()>::HEADER_LEN + 1);
+    }
+
+    #[test]
+    fn test_packet_extension() {
+        let mut pkt = Packet::new([0u8; 64]);
+        pkt.set_extension(0xBEDE, &[1, 2, 3, 4]);
+        pkt.payload_mut()[..2].copy_from_slice(b"hi");
+        pkt.set_payload_len(2);
+
+        assert_eq!(pkt.extension_len(), 8);
+        assert_eq!(pkt.header_len(), 20);
+        assert_eq!(pkt.as_ref()[0], 0x90);
+        assert_eq!(&pkt.as_ref()[12..20], &[0xBE, 0xDE, 0x00, 0x01, 1, 2, 3, 4]);
+        assert_eq!(&pkt.as_ref()[20 + TAG_SIZE..], b"hi");
+    }
+
+    #[test]
+    fn test_parse_ip_discovery() {
+        let buf = discovery_response(42, "203.0.113.7", 50000);
+
+        let addr = parse_ip_discovery(&buf, 42).unwrap();
+        assert_eq!(addr, "203.0.113.7:50000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_parse_ip_discovery_ipv6() {
+        let buf = discovery_response(42, "2001:db8::7", 50000);
+        let addr = parse_ip_discovery(&buf, 42).unwrap();
+        assert_eq!(addr, "[2001:db8::7]:50000".parse().unwrap());
+
+        let buf = discovery_response(42, "[2001:db8::7]", 50000);
+        let addr = parse_ip_discovery(&buf, 42).unwrap();
+        assert_eq!(addr, "[2001:db8::7]:50000".parse().unwrap());
+
+        let buf = discovery_response(42, "::ffff:203.0.113.7", 50000);
+        let addr = parse_ip_discovery(&buf, 42).unwrap();
+        assert_eq!(addr, "203.0.113.7:50000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_parse_ip_discovery_errors() {
+        let buf = discovery_response(42, "203.0.113.7", 50000);
+        assert!(matches!(
+            parse_ip_discovery(&buf, 43),
+            Err(IpDiscoveryError::InvalidSsrc(43, 42))
+        ));
+        assert!(matches!(
+            parse_ip_discovery(&buf[..70], 42),
+            Err(IpDiscoveryError::InvalidSize(70))
+        ));
+
+        let mut bad_header = buf;
+        bad_header[1] = 0x01;
+        assert!(matches!(
+            parse_ip_discovery(&bad_header, 42),
+            Err(IpDiscoveryError::InvalidHeader([0x00, 0x01, 0x00, 0x46]))
+        ));
+
+        let bad_addr = discovery_response(42, "not an address", 50000);
+        assert!(matches!(
+            parse_ip_discovery(&bad_addr, 42),
+            Err(IpDiscoveryError::InvalidAddr(_))
+        ));
+
+        let mut bad_utf8 = buf;
+        bad_utf8[8] = 0xFF;
+        assert!(matches!(
+            parse_ip_discovery(&bad_utf8, 42),
+            Err(IpDiscoveryError::InvalidAddrUtf8(_))
+        ));
+    }
+}
@@ -1,22 +1,66 @@
 //! Audio sources.
 //!
-//! Currently, this only supports ffmpeg and ytdl queries through an ffmpeg
-//! pipe.
+//! Everything is decoded by ffmpeg, either from a url it can open itself or
+//! from a pipe, usually from ytdl. Sources are built with a [`SourceBuilder`].
 //!
-//! These should not be doing any super heavy CPU-bound work, as this runs on
-//! the player thread. All of these features are cancel-safe.
+//! Decoding and encoding happen in their own tasks, so reading from a source
+//! on the player task only waits for packets that are already encoded. All of
+//! these features are cancel-safe.
+//!
+//! An [`Overlay`] is decoded but not encoded, so it can be mixed over a
+//! source with [`Source::duck`].
 
-use super::constants::{DEFAULT_BITRATE, SAMPLE_RATE, STEREO_FRAME_SIZE};
+use super::constants::{
+    DEFAULT_BITRATE, DEFAULT_READ_AHEAD, DEFAULT_STALL_TIMEOUT, SAMPLE_RATE, STEREO_FRAME_SIZE,
+    TIMESTEP_LENGTH,
+};
+use super::encoder::{CodecError, Encoder, Signal, MAX_COMPLEXITY};
+use super::guardrail::{degraded, guardrail};
+use super::mixer::{Ducking, Fade, OverlayMixer};
+use super::passthrough::passthrough;
+use super::timing::StreamTimings;
 
-use crate::ytdl::YtdlError;
+use crate::ffmpeg::{ffmpeg_executable, ffmpeg_options, log_stderr};
+use crate::ytdl::{AudioFormat, YtdlConfig, YtdlError};
 
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
 
 use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use opus::{Application, Bitrate, Channels};
+
+use tracing::warn;
+
+/// The format selectors passed to `ytdl`, in the order they are tried.
+///
+/// If `ytdl` fails to extract the first format (usually because it's being
+/// throttled), the next one is tried before giving up.
+pub const YTDL_FORMATS: &[&str] = &[
+    "webm[abr>0]/bestaudio/best",
+    "bestaudio[ext=m4a]/bestaudio*/best",
+];
 
-use opus::{Application, Channels, Encoder};
+/// The largest Opus packet a single frame can encode to.
+const MAX_PACKET_LEN: usize = 1275;
+
+/// How many frames of PCM are buffered between the reader and the encoder.
+const PCM_FRAMES: usize = 2;
+
+/// The lowest bitrate Opus supports.
+const MIN_BITRATE: i32 = 500;
+
+/// The highest bitrate Opus supports.
+const MAX_BITRATE: i32 = 512_000;
 
 /// A ytdl audio source.
 ///
@@ -25,89 +69,369 @@ use opus::{Application, Channels, Encoder};
 pub struct Source {
     piped: Option<Child>,
     ffmpeg: Child,
+    ytdl: Option<YtdlInput>,
+    /// The `ytdl` query to play instead if a direct stream fails before
+    /// producing anything.
+    fallback: Option<(String, SourceOptions)>,
+
+    /// Opus packets encoded ahead of time.
+    packets: mpsc::Receiver<Result<Vec<u8>, Error>>,
+    reader: JoinHandle<()>,
+    /// Overlays for the encoder to mix in.
+    overlays: std_mpsc::Sender<Ducking>,
+    /// How many frames the encoder should fade out over before ending the
+    /// source, or 0 to keep going.
+    fade_out: Arc<AtomicU32>,
+    produced: bool,
+    offset: Duration,
 
-    coder: Encoder,
-    buf: [f32; STEREO_FRAME_SIZE],
-    buf_len: usize,
+    /// When audio was last read, for the watchdog.
+    last_read: Instant,
+    stall_timeout: Duration,
+}
+
+/// The `ytdl` process feeding a [`Source`].
+struct YtdlInput {
+    query: String,
+    options: SourceOptions,
+    /// Index into [`SourceOptions::formats`] of the format being streamed.
+    format: usize,
+    /// Resolves to the error `ytdl` printed, if any.
+    error: Option<JoinHandle<Option<YtdlError>>>,
 }
 
 impl Source {
     /// Reads the next Opus packet into the buffer.
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
-        loop {
-            let len = self
-                .ffmpeg
-                .stdout
-                .as_mut()
-                .unwrap()
-                .read(bytemuck::cast_slice_mut(&mut self.buf[self.buf_len..]))
-                .await
-                .map_err(Error::Io)?;
-
-            if len > 0 {
-                self.buf_len += len / std::mem::size_of::<f32>();
-                if self.buf_len >= self.buf.len() {
-                    break;
+        let packet = loop {
+            // reads can be cancelled, so the deadline is kept across calls
+            let deadline = self.last_read + self.stall_timeout;
+
+            let packet = match timeout_at(deadline, self.packets.recv()).await {
+                Ok(packet) => packet,
+                Err(_) => {
+                    warn!(timeout = ?self.stall_timeout, "source stalled, killing");
+                    self.close().await?;
+                    return Err(Error::Stalled);
+                }
+            };
+
+            match packet {
+                Some(Ok(packet)) => break packet,
+                Some(Err(err)) => return Err(err),
+                None if self.retry().await? => {
+                    // try again with the next format
+                    continue;
                 }
-            } else {
-                return Ok(0);
+                None => return Ok(0),
             }
+        };
+
+        self.last_read = Instant::now();
+        self.produced = true;
+
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(packet.len())
+    }
+
+    /// Restarts `ytdl` with the next format if it exited before producing any
+    /// audio.
+    ///
+    /// Returns `Ok(true)` if the pipeline was restarted, `Ok(false)` if the
+    /// stream ended normally, and the `ytdl` error if there are no formats
+    /// left to try.
+    async fn retry(&mut self) -> Result<bool, Error> {
+        if self.produced {
+            return Ok(false);
         }
 
-        if self.buf_len > 0 {
-            // encode
-            let len = self
-                .coder
-                .encode_float(&self.buf[..self.buf_len], buf)
-                .map_err(Error::Codec)?;
-            self.buf_len = 0;
-            Ok(len)
-        } else {
-            Ok(0)
+        if let Some((query, options)) = self.fallback.take() {
+            warn!("direct stream failed, falling back to ytdl");
+
+            self.close().await?;
+            *self = Source::ytdl(query, options, 0)?;
+
+            return Ok(true);
         }
+
+        let Some(ytdl) = self.ytdl.as_mut() else {
+            return Ok(false);
+        };
+
+        let Some(error) = ytdl.error.as_mut() else {
+            return Ok(false);
+        };
+
+        // wait for ytdl to finish writing to stderr
+        let error = error.await.ok().flatten();
+        ytdl.error = None;
+
+        let Some(error) = error else {
+            return Ok(false);
+        };
+
+        let format = ytdl.format + 1;
+        let Some(next) = ytdl.options.formats.get(format) else {
+            return Err(Error::Ytdl(error));
+        };
+
+        warn!(%error, format = next, "ytdl failed, retrying");
+
+        let query = std::mem::take(&mut ytdl.query);
+        let options = ytdl.options.clone();
+        self.close().await?;
+        *self = Source::ytdl(query, options, format)?;
+
+        Ok(true)
+    }
+
+    /// Mixes an overlay over the source, lowering the source's volume to
+    /// `gain` until the overlay is over.
+    ///
+    /// The volume is ramped down and back up instead of cut. If the source
+    /// already has an overlay, this one plays after it. If the source ends
+    /// first, the overlay is played to the end on its own.
+    ///
+    /// Returns the overlay back if the source has already finished encoding.
+    pub fn duck(&self, overlay: Overlay, gain: f32) -> Result<(), Box<Overlay>> {
+        self.overlays
+            .send(Ducking { overlay, gain })
+            .map_err(|err| Box::new(err.0.overlay))
+    }
+
+    /// Fades the source out over `duration`, then ends it.
+    ///
+    /// Audio that was already encoded ahead plays at full volume first.
+    /// Returns `false` if the source is already fading out.
+    pub fn fade_out(&self, duration: Duration) -> bool {
+        let frames = (duration.as_millis() / TIMESTEP_LENGTH.as_millis()).max(1) as u32;
+
+        self.fade_out
+            .compare_exchange(0, frames, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Where in the audio the `Source` started.
+    pub fn offset(&self) -> Duration {
+        self.offset
     }
 
     /// Kills the processes associated with the `Source`.
+    ///
+    /// This waits for the processes to exit so they aren't left as zombies,
+    /// and can be called more than once.
     pub async fn close(&mut self) -> Result<(), Error> {
+        self.reader.abort();
+
+        // the processes may have exited already, which is fine
+        if let Some(piped) = self.piped.as_mut() {
+            let _ = piped.start_kill();
+        }
+        let _ = self.ffmpeg.start_kill();
+
         if let Some(mut piped) = self.piped.take() {
-            piped.kill().await.map_err(Error::Io)?;
+            piped.wait().await.map_err(Error::Io)?;
         }
-        self.ffmpeg.kill().await.map_err(Error::Io)?;
+        self.ffmpeg.wait().await.map_err(Error::Io)?;
+
         Ok(())
     }
 
-    /// Creates a new `Source` from a process that produces audio (probably
-    /// `ytdl`) and pipes it to `ffmpeg`.
+    /// Starts `ffmpeg` on an input and sets up the encoder.
+    fn ffmpeg(input: FfmpegInput, options: &SourceOptions) -> Result<Source, Error> {
+        let decoder = Decoder::new(input, options)?;
+
+        Source::encode(decoder, options)
+    }
+
+    /// Encodes the PCM of a decoder.
+    fn encode(decoder: Decoder, options: &SourceOptions) -> Result<Source, Error> {
+        let Decoder {
+            piped,
+            ffmpeg,
+            frames,
+            reader,
+        } = decoder;
+
+        let mut coder = Encoder::new(SAMPLE_RATE as u32, Channels::Stereo, Application::Audio)
+            .map_err(Error::Codec)?;
+        coder.set_bitrate(options.bitrate).map_err(Error::Codec)?;
+        coder.set_dtx(options.dtx).map_err(Error::Codec)?;
+        coder
+            .set_complexity(options.complexity)
+            .map_err(Error::Codec)?;
+        coder.set_signal(options.signal).map_err(Error::Codec)?;
+
+        if let Some(packet_loss) = options.fec {
+            coder.set_inband_fec(true).map_err(Error::Codec)?;
+            coder
+                .set_packet_loss_perc(packet_loss)
+                .map_err(Error::Codec)?;
+        }
+
+        // ffmpeg -> reader -> encoder -> player, with the read-ahead kept at
+        // the end so slow encodes are smoothed over too
+        let capacity = (options.read_ahead.as_millis() / TIMESTEP_LENGTH.as_millis()).max(1);
+        let (packets_tx, packets) = mpsc::channel(capacity as usize);
+        let (overlays, mixer) = OverlayMixer::new();
+        let fade_out = Arc::new(AtomicU32::new(0));
+
+        let fade_in = (!options.fade_in.is_zero()).then(|| {
+            let frames = options.fade_in.as_millis() / TIMESTEP_LENGTH.as_millis();
+            Fade::rising(frames as u32)
+        });
+        let encoder_fade_out = fade_out.clone();
+        let timings = options.timings.clone();
+        tokio::task::spawn_blocking(move || {
+            encode(
+                coder,
+                mixer,
+                fade_in,
+                encoder_fade_out,
+                frames,
+                packets_tx,
+                timings,
+            )
+        });
+
+        Ok(Source {
+            piped,
+            ffmpeg,
+            ytdl: None,
+            fallback: None,
+            packets,
+            reader,
+            overlays,
+            fade_out,
+            produced: false,
+            offset: options.offset,
+
+            last_read: Instant::now(),
+            stall_timeout: options.stall_timeout,
+        })
+    }
+
+    /// Starts `ffmpeg` copying the Opus audio of an input into an Ogg stream,
+    /// which is sent without decoding it.
     ///
-    /// # Panics
-    /// Panics if the process's `stdout` [`Stdio`] is not available. Remember
-    /// to set the process's `stdout` to [`Stdio::piped`].
-    ///
-    /// ```no_run
-    /// let mut ytdl = Command::new("youtube-dl")
-    ///     .args(&[
-    ///         "-f",
-    ///         "webm[abr>0]/bestaudio/best",
-    ///         "-R",
-    ///         "infinite",
-    ///         "-q",
-    ///         query,
-    ///         "-o",
-    ///         "-",
-    ///     ])
-    ///     // remember to set stdout to piped!
-    ///     .stdout(Stdio::piped())
-    ///     .stderr(Stdio::inherit())
-    ///     .spawn()
-    ///     .map_err(Error::Io)?;
-    /// ```
-    pub fn piped(mut piped: Child) -> Result<Source, Error> {
-        let piped_stdio: Stdio = piped.stdout.take().unwrap().try_into().unwrap();
-
-        let ffmpeg = Command::new("ffmpeg")
+    /// Nothing can be mixed into the audio, so overlays are turned away and
+    /// played on their own instead.
+    fn passthrough(input: FfmpegInput, options: &SourceOptions) -> Result<Source, Error> {
+        let mut ffmpeg = ffmpeg_command(options);
+        let piped = input_args(&mut ffmpeg, input);
+
+        if let Some(duration) = options.duration {
+            ffmpeg.args(["-t", &format!("{:.3}", duration.as_secs_f64())]);
+        }
+
+        let mut ffmpeg = ffmpeg
+            .args(["-vn", "-c:a", "copy", "-f", "ogg", "pipe:1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::Io)?;
+
+        tokio::spawn(log_stderr(ffmpeg.stderr.take().unwrap()));
+
+        let capacity = (options.read_ahead.as_millis() / TIMESTEP_LENGTH.as_millis()).max(1);
+        let (packets_tx, packets) = mpsc::channel(capacity as usize);
+        let (overlays, _) = std_mpsc::channel();
+        let fade_out = Arc::new(AtomicU32::new(0));
+
+        let reader = tokio::spawn(passthrough(
+            ffmpeg.stdout.take().unwrap(),
+            fade_out.clone(),
+            packets_tx,
+        ));
+
+        Ok(Source {
+            piped,
+            ffmpeg,
+            ytdl: None,
+            fallback: None,
+            packets,
+            reader,
+            overlays,
+            fade_out,
+            produced: false,
+            offset: options.offset,
+
+            last_read: Instant::now(),
+            stall_timeout: options.stall_timeout,
+        })
+    }
+
+    /// Starts `ytdl` with one of the formats and pipes it to `ffmpeg`.
+    fn ytdl(query: String, options: SourceOptions, format: usize) -> Result<Source, Error> {
+        let (ytdl, error) = spawn_ytdl(&query, &options.formats[format], &options)?;
+        let source = Source::ffmpeg(FfmpegInput::Piped(ytdl), &options)?;
+
+        Ok(Source {
+            ytdl: Some(YtdlInput {
+                query,
+                options,
+                format,
+                error: Some(error),
+            }),
+            ..source
+        })
+    }
+}
+
+/// Starts `ytdl` with a format, and a task that resolves to the error it
+/// printed, if any.
+fn spawn_ytdl(
+    query: &str,
+    format: &str,
+    options: &SourceOptions,
+) -> Result<(Child, JoinHandle<Option<YtdlError>>), Error> {
+    let mut ytdl = options
+        .ytdl
+        .command()
+        .args(["-f", format, "-R", "infinite", "-q", "-o", "-", "--", query])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(Error::Io)?;
+
+    // watch stderr for errors
+    let stderr = ytdl.stderr.take().unwrap();
+    let error = tokio::spawn(async move {
+        YtdlError::from_ytdl(BufReader::new(stderr))
+            .await
+            .ok()
+            .flatten()
+    });
+
+    Ok((ytdl, error))
+}
+
+/// An `ffmpeg` process decoding to PCM, and the task reading it.
+struct Decoder {
+    piped: Option<Child>,
+    ffmpeg: Child,
+    frames: mpsc::Receiver<io::Result<Vec<f32>>>,
+    reader: JoinHandle<()>,
+}
+
+impl Decoder {
+    /// Starts `ffmpeg` on an input.
+    fn new(input: FfmpegInput, options: &SourceOptions) -> Result<Decoder, Error> {
+        let mut ffmpeg = ffmpeg_command(options);
+        let piped = input_args(&mut ffmpeg, input);
+
+        if !options.filters.is_empty() {
+            ffmpeg.args(["-af", &options.filters.join(",")]);
+        }
+
+        if let Some(duration) = options.duration {
+            ffmpeg.args(["-t", &format!("{:.3}", duration.as_secs_f64())]);
+        }
+
+        let mut ffmpeg = ffmpeg
             .args([
-                "-i",
-                "pipe:0",
                 "-ac",
                 "2",
                 "-ar",
@@ -116,48 +440,628 @@ impl Source {
                 "s16le",
                 "-acodec",
                 "pcm_f32le",
-                "-loglevel",
-                "quiet",
                 "pipe:1",
             ])
-            .stdin(piped_stdio)
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
             .map_err(Error::Io)?;
 
-        let mut coder = Encoder::new(SAMPLE_RATE as u32, Channels::Stereo, Application::Audio)
-            .map_err(Error::Codec)?;
-        coder.set_bitrate(DEFAULT_BITRATE).map_err(Error::Codec)?;
+        tokio::spawn(log_stderr(ffmpeg.stderr.take().unwrap()));
 
-        Ok(Source {
-            piped: Some(piped),
+        let (frames_tx, frames) = mpsc::channel(PCM_FRAMES);
+        let reader = tokio::spawn(read_ahead(ffmpeg.stdout.take().unwrap(), frames_tx));
+
+        Ok(Decoder {
+            piped,
             ffmpeg,
-            coder,
-            buf: [0f32; STEREO_FRAME_SIZE],
-            buf_len: 0,
+            frames,
+            reader,
         })
     }
+}
 
-    /// Creates a new `Source` from a `ytdl` query.
-    pub fn ytdl(query: &str) -> Result<Source, Error> {
-        let ytdl = Command::new(crate::ytdl::ytdl_executable())
-            .args([
-                "-f",
-                "webm[abr>0]/bestaudio/best",
-                "-R",
-                "infinite",
-                "-q",
+/// Creates an `ffmpeg` command, starting at the offset of the options.
+fn ffmpeg_command(options: &SourceOptions) -> Command {
+    let mut ffmpeg = ffmpeg_options().command(
+        options
+            .ffmpeg_executable
+            .as_deref()
+            .unwrap_or_else(|| ffmpeg_executable()),
+    );
+
+    if !options.offset.is_zero() {
+        ffmpeg.args(["-ss", &format!("{:.3}", options.offset.as_secs_f64())]);
+    }
+
+    ffmpeg
+}
+
+/// Passes an input to `ffmpeg`, returning the process piped into it, if
+/// any.
+fn input_args(ffmpeg: &mut Command, input: FfmpegInput) -> Option<Child> {
+    match input {
+        FfmpegInput::Url(url) => {
+            ffmpeg.args(["-i", &url]).stdin(Stdio::null());
+            None
+        }
+        FfmpegInput::Http { url, headers } => {
+            if !headers.is_empty() {
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}\r\n", name, value))
+                    .collect::<String>();
+
+                ffmpeg.args(["-headers", &headers]);
+            }
+
+            ffmpeg.args(["-i", &url]).stdin(Stdio::null());
+            None
+        }
+        FfmpegInput::Piped(mut piped) => {
+            let piped_stdio: Stdio = piped.stdout.take().unwrap().try_into().unwrap();
+            ffmpeg.args(["-i", "pipe:0"]).stdin(piped_stdio);
+            Some(piped)
+        }
+    }
+}
+
+/// Where `ffmpeg` reads audio from.
+enum FfmpegInput {
+    /// A url or path `ffmpeg` can open itself.
+    Url(String),
+    /// A url with headers `ffmpeg` has to send with its requests.
+    Http {
+        url: String,
+        headers: Vec<(String, String)>,
+    },
+    /// The `stdout` of another process.
+    Piped(Child),
+}
+
+/// Where a [`SourceBuilder`] gets its audio.
+enum Input {
+    Ytdl(String),
+    /// A `ytdl` query, with the format it was found with.
+    Format {
+        query: String,
+        format: AudioFormat,
+    },
+    Ffmpeg(FfmpegInput),
+}
+
+/// How a [`Source`] gets from its input to Opus packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pipeline {
+    /// `ffmpeg` copies Opus audio out of a url without decoding it.
+    Passthrough,
+    /// `ffmpeg` streams a url itself, and the audio is decoded and encoded
+    /// again.
+    Direct,
+    /// Another process, usually `ytdl`, is piped into `ffmpeg`, and the
+    /// audio is decoded and encoded again.
+    Piped,
+}
+
+impl Display for Pipeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Pipeline::Passthrough => "opus passthrough",
+            Pipeline::Direct => "direct",
+            Pipeline::Piped => "piped",
+        })
+    }
+}
+
+/// The options of a [`SourceBuilder`], kept around so `ytdl` can be
+/// restarted with another format.
+#[derive(Clone, Debug)]
+struct SourceOptions {
+    formats: Vec<String>,
+    filters: Vec<String>,
+    fade_in: Duration,
+    offset: Duration,
+    duration: Option<Duration>,
+    bitrate: Bitrate,
+    dtx: bool,
+    /// The expected packet loss, in percent, if inband FEC is on.
+    fec: Option<u8>,
+    complexity: u8,
+    signal: Signal,
+    ffmpeg_executable: Option<String>,
+    ytdl: YtdlConfig,
+    stall_timeout: Duration,
+    read_ahead: Duration,
+    timings: Option<Arc<StreamTimings>>,
+}
+
+/// Builds a [`Source`].
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use swc::voice::source::{Error, Source, SourceBuilder};
+/// # fn ytdl(query: &str) -> Result<Source, Error> {
+/// let source = SourceBuilder::ytdl(query)
+///     .offset(Duration::from_secs(30))
+///     .filter("volume=0.5")
+///     .build()?;
+/// # Ok(source)
+/// # }
+/// ```
+pub struct SourceBuilder {
+    input: Input,
+    options: SourceOptions,
+}
+
+impl SourceBuilder {
+    fn new(input: Input) -> SourceBuilder {
+        SourceBuilder {
+            input,
+            options: SourceOptions {
+                formats: YTDL_FORMATS
+                    .iter()
+                    .map(|&format| format.to_owned())
+                    .collect(),
+                filters: Vec::new(),
+                fade_in: Duration::ZERO,
+                offset: Duration::ZERO,
+                duration: None,
+                bitrate: DEFAULT_BITRATE,
+                dtx: true,
+                fec: None,
+                complexity: MAX_COMPLEXITY,
+                signal: Signal::Auto,
+                ffmpeg_executable: None,
+                ytdl: YtdlConfig::default(),
+                stall_timeout: DEFAULT_STALL_TIMEOUT,
+                read_ahead: DEFAULT_READ_AHEAD,
+                timings: None,
+            },
+        }
+    }
+
+    /// Plays the result of a `ytdl` query.
+    ///
+    /// If `ytdl` fails to extract the audio before producing anything, the
+    /// source retries with the other formats before failing with
+    /// [`Error::Ytdl`].
+    pub fn ytdl(query: impl Into<String>) -> SourceBuilder {
+        SourceBuilder::new(Input::Ytdl(query.into()))
+    }
+
+    /// Plays the result of a `ytdl` query, in a format it found for it.
+    ///
+    /// If the format can be streamed without `ytdl`, it is, and Opus audio
+    /// is sent without decoding it when nothing needs to be done to it. See
+    /// [`SourceBuilder::pipeline`]. If the stream fails before producing
+    /// anything, the source falls back to `ytdl`.
+    ///
+    /// `ytdl` is asked for the exact format first, so it doesn't have to
+    /// pick one again, before the other formats are tried.
+    pub fn format(query: impl Into<String>, format: &AudioFormat) -> SourceBuilder {
+        let formats = std::iter::once(format.id.as_str())
+            .chain(YTDL_FORMATS.iter().copied())
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        SourceBuilder::new(Input::Format {
+            query: query.into(),
+            format: format.clone(),
+        })
+        .formats(formats)
+    }
+
+    /// Plays a url or file that `ffmpeg` can open itself.
+    pub fn url(url: impl Into<String>) -> SourceBuilder {
+        SourceBuilder::new(Input::Ffmpeg(FfmpegInput::Url(url.into())))
+    }
+
+    /// Plays the output of a process.
+    ///
+    /// # Panics
+    /// [`SourceBuilder::build`] panics if the process's `stdout` [`Stdio`] is
+    /// not available. Remember to set the process's `stdout` to
+    /// [`Stdio::piped`].
+    pub fn piped(piped: Child) -> SourceBuilder {
+        SourceBuilder::new(Input::Ffmpeg(FfmpegInput::Piped(piped)))
+    }
+
+    /// Sets the format selectors passed to `ytdl`, in the order they are
+    /// tried. Defaults to [`YTDL_FORMATS`].
+    pub fn formats<I>(mut self, formats: I) -> SourceBuilder
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.options.formats = formats.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds an `ffmpeg` audio filter, like `volume=0.5`.
+    ///
+    /// Filters are applied in the order they are added.
+    pub fn filter(mut self, filter: impl Into<String>) -> SourceBuilder {
+        self.options.filters.push(filter.into());
+        self
+    }
+
+    /// Fades the audio in over `duration` when it starts.
+    ///
+    /// The fade is applied as the audio is encoded, so Opus audio that is
+    /// passed through doesn't fade in.
+    pub fn fade_in(mut self, duration: Duration) -> SourceBuilder {
+        self.options.fade_in = duration;
+        self
+    }
+
+    /// Skips the first `offset` of the audio.
+    pub fn offset(mut self, offset: Duration) -> SourceBuilder {
+        self.options.offset = offset;
+        self
+    }
+
+    /// Stops the audio after `duration`, counted from the offset.
+    pub fn duration(mut self, duration: Duration) -> SourceBuilder {
+        self.options.duration = Some(duration);
+        self
+    }
+
+    /// Sets the bitrate of the encoded audio. Defaults to
+    /// [`DEFAULT_BITRATE`].
+    pub fn bitrate(mut self, bitrate: Bitrate) -> SourceBuilder {
+        self.options.bitrate = bitrate;
+        self
+    }
+
+    /// Sets whether silence in the audio is left out instead of sent, which
+    /// saves bandwidth in quiet parts. Defaults to `true`.
+    pub fn dtx(mut self, dtx: bool) -> SourceBuilder {
+        self.options.dtx = dtx;
+        self
+    }
+
+    /// Turns on inband forward error correction, expecting `packet_loss`
+    /// percent of packets to be lost. Lost frames can then be recovered from
+    /// the packet after them, at the cost of some bitrate. Off by default.
+    pub fn fec(mut self, packet_loss: u8) -> SourceBuilder {
+        self.options.fec = Some(packet_loss);
+        self
+    }
+
+    /// Sets the complexity of the encoder, from 0 to 10. Lower complexities
+    /// use less CPU and sound worse. Defaults to 10.
+    pub fn complexity(mut self, complexity: u8) -> SourceBuilder {
+        self.options.complexity = complexity;
+        self
+    }
+
+    /// Hints whether the audio is speech or music. Defaults to
+    /// [`Signal::Auto`].
+    pub fn signal(mut self, signal: Signal) -> SourceBuilder {
+        self.options.signal = signal;
+        self
+    }
+
+    /// Sets the `ffmpeg` executable to run, instead of the one set with
+    /// [`init_ffmpeg_executable`](crate::ffmpeg::init_ffmpeg_executable).
+    pub fn ffmpeg_executable(mut self, executable: impl Into<String>) -> SourceBuilder {
+        self.options.ffmpeg_executable = Some(executable.into());
+        self
+    }
+
+    /// Sets how `ytdl` is run. Defaults to `youtube-dl` with no extra
+    /// options.
+    pub fn ytdl_config(mut self, config: YtdlConfig) -> SourceBuilder {
+        self.options.ytdl = config;
+        self
+    }
+
+    /// Sets how long the source can go without producing audio before it is
+    /// killed with [`Error::Stalled`]. Defaults to [`DEFAULT_STALL_TIMEOUT`].
+    pub fn stall_timeout(mut self, timeout: Duration) -> SourceBuilder {
+        self.options.stall_timeout = timeout;
+        self
+    }
+
+    /// Sets how much audio is decoded ahead of the encoder, to smooth over
+    /// `ffmpeg` being slow to schedule. Defaults to [`DEFAULT_READ_AHEAD`].
+    pub fn read_ahead(mut self, read_ahead: Duration) -> SourceBuilder {
+        self.options.read_ahead = read_ahead;
+        self
+    }
+
+    /// Records how long encoding takes to `timings`.
+    pub fn timings(mut self, timings: Arc<StreamTimings>) -> SourceBuilder {
+        self.options.timings = Some(timings);
+        self
+    }
+
+    /// Gets the cheapest way the source can be played.
+    ///
+    /// Opus formats that can be streamed without `ytdl` are passed through
+    /// as they are, unless filters have to be applied to them. Passed
+    /// through audio keeps the bitrate it was uploaded with, and doesn't
+    /// fade in.
+    pub fn pipeline(&self) -> Pipeline {
+        match &self.input {
+            Input::Format { format, .. } if format.url.is_some() => {
+                if format.codec == "opus" && self.options.filters.is_empty() {
+                    Pipeline::Passthrough
+                } else {
+                    Pipeline::Direct
+                }
+            }
+            Input::Ffmpeg(FfmpegInput::Url(_) | FfmpegInput::Http { .. }) => Pipeline::Direct,
+            _ => Pipeline::Piped,
+        }
+    }
+
+    /// Checks the options and starts the `Source`.
+    ///
+    /// While the [guardrail](super::guardrail) has lowered quality, the
+    /// source is encoded at that quality instead of the one it was built
+    /// with.
+    pub fn build(self) -> Result<Source, Error> {
+        let pipeline = self.pipeline();
+        let SourceBuilder { input, mut options } = self;
+
+        options.check()?;
+
+        if degraded() {
+            (options.bitrate, options.complexity) =
+                guardrail().limit(options.bitrate, options.complexity);
+        }
+
+        match input {
+            Input::Format {
                 query,
-                "-o",
-                "-",
-            ])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .map_err(Error::Io)?;
+                format:
+                    AudioFormat {
+                        url: Some(url),
+                        http_headers,
+                        ..
+                    },
+            } => {
+                if options.formats.is_empty() {
+                    return Err(Error::NoFormats);
+                }
+
+                let input = FfmpegInput::Http {
+                    url,
+                    headers: http_headers,
+                };
+                let source = match pipeline {
+                    Pipeline::Passthrough => Source::passthrough(input, &options)?,
+                    _ => Source::ffmpeg(input, &options)?,
+                };
+
+                Ok(Source {
+                    fallback: Some((query, options)),
+                    ..source
+                })
+            }
+            Input::Ytdl(query) | Input::Format { query, .. } => {
+                if options.formats.is_empty() {
+                    return Err(Error::NoFormats);
+                }
+
+                Source::ytdl(query, options, 0)
+            }
+            Input::Ffmpeg(input) => Source::ffmpeg(input, &options),
+        }
+    }
 
-        Source::piped(ytdl)
+    /// Checks the options and starts decoding an [`Overlay`].
+    ///
+    /// Overlays are mixed into another source, so `ytdl` sources are not
+    /// retried with the other formats.
+    pub fn build_overlay(self) -> Result<Overlay, Error> {
+        let SourceBuilder { input, options } = self;
+
+        options.check()?;
+
+        let input = match input {
+            Input::Format {
+                format:
+                    AudioFormat {
+                        url: Some(url),
+                        http_headers,
+                        ..
+                    },
+                ..
+            } => FfmpegInput::Http {
+                url,
+                headers: http_headers,
+            },
+            Input::Ytdl(query) | Input::Format { query, .. } => {
+                let format = options.formats.first().ok_or(Error::NoFormats)?;
+                FfmpegInput::Piped(spawn_ytdl(&query, format, &options)?.0)
+            }
+            Input::Ffmpeg(input) => input,
+        };
+
+        Ok(Overlay {
+            decoder: Decoder::new(input, &options)?,
+            options,
+        })
+    }
+}
+
+impl SourceOptions {
+    fn check(&self) -> Result<(), Error> {
+        if let Bitrate::Bits(bits) = self.bitrate {
+            if !(MIN_BITRATE..=MAX_BITRATE).contains(&bits) {
+                return Err(Error::InvalidBitrate(bits));
+            }
+        }
+
+        if self.complexity > MAX_COMPLEXITY {
+            return Err(Error::InvalidComplexity(self.complexity));
+        }
+
+        if let Some(packet_loss) = self.fec.filter(|loss| *loss > 100) {
+            return Err(Error::InvalidPacketLoss(packet_loss));
+        }
+
+        if self.filters.iter().any(|filter| filter.is_empty()) {
+            return Err(Error::InvalidFilter);
+        }
+
+        Ok(())
+    }
+}
+
+/// Audio to mix over a [`Source`] with [`Source::duck`], like an
+/// announcement.
+///
+/// Built with [`SourceBuilder::build_overlay`].
+pub struct Overlay {
+    decoder: Decoder,
+    options: SourceOptions,
+}
+
+impl Overlay {
+    /// Plays the overlay on its own instead, for when there is nothing to mix
+    /// it over.
+    pub fn into_source(self) -> Result<Source, Error> {
+        Source::encode(self.decoder, &self.options)
+    }
+
+    /// The frames of PCM the overlay decodes.
+    pub(super) fn frames(&mut self) -> &mut mpsc::Receiver<io::Result<Vec<f32>>> {
+        &mut self.decoder.frames
+    }
+
+    /// Receives the next frame of PCM.
+    ///
+    /// Unless `wait` is set, this returns `Ok(None)` if the frame isn't
+    /// decoded yet. Returns `Err(None)` at the end of the audio.
+    pub(super) fn frame(&mut self, wait: bool) -> Result<Option<Vec<f32>>, Option<io::Error>> {
+        let frame = if wait {
+            self.decoder.frames.blocking_recv()
+        } else {
+            match self.decoder.frames.try_recv() {
+                Ok(frame) => Some(frame),
+                Err(mpsc::error::TryRecvError::Empty) => return Ok(None),
+                Err(mpsc::error::TryRecvError::Disconnected) => None,
+            }
+        };
+
+        match frame {
+            Some(Ok(frame)) => Ok(Some(frame)),
+            Some(Err(err)) => Err(Some(err)),
+            None => Err(None),
+        }
+    }
+}
+
+impl Debug for Overlay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Overlay(_)")
+    }
+}
+
+/// Reads whole frames of PCM from `ffmpeg` until it closes its `stdout` or the
+/// [`Source`] is dropped.
+///
+/// A partial frame at the end of the stream is dropped, since Opus can only
+/// encode whole frames.
+pub(super) async fn read_ahead<R>(mut stdout: R, frames: mpsc::Sender<io::Result<Vec<f32>>>)
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        let mut frame = vec![0f32; STEREO_FRAME_SIZE];
+
+        let res = match stdout
+            .read_exact(bytemuck::cast_slice_mut(&mut frame))
+            .await
+        {
+            Ok(_) => Ok(frame),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => Err(err),
+        };
+
+        let failed = res.is_err();
+
+        if frames.send(res).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+/// Encodes frames of PCM into Opus packets until either side of the pipeline
+/// closes.
+///
+/// This runs on a blocking thread so encoding can't hold up the player task,
+/// which has to send packets on time.
+fn encode(
+    mut coder: Encoder,
+    mut mixer: OverlayMixer,
+    mut fade_in: Option<Fade>,
+    fade_out: Arc<AtomicU32>,
+    mut frames: mpsc::Receiver<io::Result<Vec<f32>>>,
+    packets: mpsc::Sender<Result<Vec<u8>, Error>>,
+    timings: Option<Arc<StreamTimings>>,
+) {
+    // returns whether to keep going
+    let mut send = |frame: io::Result<Vec<f32>>| {
+        let packet = frame.map_err(Error::Io).and_then(|frame| {
+            let started = Instant::now();
+            let packet = coder
+                .encode_vec_float(&frame, MAX_PACKET_LEN)
+                .map_err(Error::Codec);
+
+            if let Some(timings) = &timings {
+                timings.record_encode(started.elapsed());
+            }
+
+            packet
+        });
+
+        let failed = packet.is_err();
+
+        packets.blocking_send(packet).is_ok() && !failed
+    };
+
+    let mut fade = None;
+
+    while let Some(frame) = frames.blocking_recv() {
+        if fade.is_none() {
+            fade = Some(fade_out.load(Ordering::Acquire))
+                .filter(|&frames| frames > 0)
+                .map(Fade::new);
+        }
+
+        let mut faded = false;
+        let frame = frame.map(|mut frame| {
+            if let Some(fade) = fade_in.as_mut() {
+                if !fade.apply(&mut frame) {
+                    fade_in = None;
+                }
+            }
+
+            mixer.mix(&mut frame);
+
+            if let Some(fade) = fade.as_mut() {
+                faded = !fade.apply(&mut frame);
+            }
+
+            frame
+        });
+
+        // a source that faded out is over, overlays and all
+        if !send(frame) || faded {
+            return;
+        }
+    }
+
+    // play out whatever overlay was cut off by the end of the source
+    while let Some(frame) = mixer.drain() {
+        if !send(Ok(frame)) {
+            return;
+        }
     }
 }
 
@@ -173,9 +1077,23 @@ pub enum Error {
     /// Io error.
     Io(std::io::Error),
     /// Codec error.
-    Codec(opus::Error),
+    Codec(CodecError),
     /// Error from `youtube-dl`.
     Ytdl(YtdlError),
+    /// The bitrate is outside of what Opus supports.
+    InvalidBitrate(i32),
+    /// The encoder complexity is above 10.
+    InvalidComplexity(u8),
+    /// The expected packet loss is above 100%.
+    InvalidPacketLoss(u8),
+    /// An `ffmpeg` filter was empty.
+    InvalidFilter,
+    /// A `ytdl` source was given no formats to try.
+    NoFormats,
+    /// The source stopped producing audio and was killed.
+    Stalled,
+    /// A remote audio node failed to play the source.
+    Remote(String),
 }
 
 impl Display for Error {
@@ -184,6 +1102,15 @@ impl Display for Error {
             Error::Io(err) => Display::fmt(err, f),
             Error::Codec(err) => Display::fmt(err, f),
             Error::Ytdl(err) => Display::fmt(err, f),
+            Error::InvalidBitrate(bits) => write!(f, "invalid bitrate {}", bits),
+            Error::InvalidComplexity(complexity) => {
+                write!(f, "invalid encoder complexity {}", complexity)
+            }
+            Error::InvalidPacketLoss(percent) => write!(f, "invalid packet loss {}%", percent),
+            Error::InvalidFilter => f.write_str("empty ffmpeg filter"),
+            Error::NoFormats => f.write_str("no ytdl formats to try"),
+            Error::Stalled => f.write_str("source stalled"),
+            Error::Remote(message) => write!(f, "audio node failed: {}", message),
         }
     }
 }
@@ -194,6 +1121,7 @@ impl std::error::Error for Error {
             Error::Io(err) => Some(err),
             Error::Codec(err) => Some(err),
             Error::Ytdl(err) => Some(err),
+            _ => None,
         }
     }
 }
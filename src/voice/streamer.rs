@@ -1,72 +1,281 @@
 //! Audio streamer.
-
-use super::constants::{SILENCE_FRAME, TIMESTEP_LENGTH, VOICE_PACKET_MAX};
+//!
+//! A [`PacketStreamer`] paces Opus frames from an [`AudioSource`] over a
+//! [`Socket`], and tells its caller when to start and stop speaking with
+//! [`Status`]. [`Player`](super::Player)s stream [`Source`]s with it, but any
+//! source of Opus frames works, like a generated tone or a buffer of
+//! text-to-speech. See `examples/tone.rs`.
+
+use super::constants::{
+    DEFAULT_SILENCE_FRAMES, GAPLESS_HOLD, SILENCE_FRAME, TIMESTEP_LENGTH, VOICE_PACKET_MAX,
+};
+use super::encoder::DTX_PACKET_LEN;
 use super::rtp::{Packet, Socket};
+use super::source::{self, Overlay};
+use super::timing::StreamTimings;
 use super::{Error, Source};
 
+use std::future::Future;
+
 use tracing::{debug_span, warn};
 
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use tokio::time::{sleep_until, timeout_at, Duration, Instant};
 
+/// Something that makes Opus frames for a [`PacketStreamer`].
+///
+/// Only [`AudioSource::read_opus`] has to be implemented.
+pub trait AudioSource: Send {
+    /// Reads the next Opus frame, of [`TIMESTEP_LENGTH`], into `buf`,
+    /// returning its length.
+    ///
+    /// A length of `0` ends the source. The streamer waits as long as it
+    /// takes for the first frame, but after that, a frame that takes too
+    /// long is a break in the audio.
+    fn read_opus(
+        &mut self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = Result<usize, source::Error>> + Send;
+
+    /// Where in the audio the source starts, which the streamer counts its
+    /// position from.
+    fn offset(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Fades the source out over `duration`, then ends it.
+    ///
+    /// Returns `false` if the source can't fade out, which it can't unless
+    /// this is implemented.
+    fn fade_out(&self, _duration: Duration) -> bool {
+        false
+    }
+
+    /// Cleans up the source after it ends or is taken out of the streamer.
+    fn close(&mut self) -> impl Future<Output = Result<(), source::Error>> + Send {
+        async { Ok(()) }
+    }
+}
+
+impl AudioSource for Source {
+    async fn read_opus(&mut self, buf: &mut [u8]) -> Result<usize, source::Error> {
+        self.read(buf).await
+    }
+
+    fn offset(&self) -> Duration {
+        Source::offset(self)
+    }
+
+    fn fade_out(&self, duration: Duration) -> bool {
+        Source::fade_out(self, duration)
+    }
+
+    async fn close(&mut self) -> Result<(), source::Error> {
+        Source::close(self).await
+    }
+}
+
 /// Audio packet streamer.
 ///
 /// Most of the time, we receive audio data faster than its playback speed. This
 /// is actually really awesome! Well, until you realize that sending packets at
 /// 4x the actual speed of the audio is going to cause some buffer issues and
 /// also make you tonight's biggest loser.
+///
+/// The streamer is driven with [`PacketStreamer::stream`], which is
+/// cancellable, so it can be polled alongside the voice connection.
 #[derive(Debug)]
-pub struct PacketStreamer {
+pub struct PacketStreamer<S = Source> {
     patience: Duration,
 
-    source: Option<Source>,
+    source: Option<S>,
+    /// Sources played over the source, like announcements, in order.
+    interjections: VecDeque<S>,
     waiting_for_source: bool,
+    /// Whether the source is paused. Interjections still play.
+    paused: bool,
 
     packet: Packet<[u8; VOICE_PACKET_MAX]>,
     next_packet: Instant,
     ready: bool,
+    /// Whether the ready packet is left out instead of sent.
+    skip: bool,
 
     silence_frames: usize,
+    /// How many frames of silence are sent before a break in the audio.
+    silence_padding: usize,
+    /// How much silence is sent before the first audio.
+    pre_roll: Duration,
+    /// Whether the first audio started, with or without a pre-roll.
+    pre_rolled: bool,
+    /// How many frames of the pre-roll are left to send.
+    pre_roll_frames: usize,
+    /// The first packet of audio, held back until the pre-roll is sent.
+    held_packet: Option<Vec<u8>>,
+    /// How many frames in a row the source left out with DTX.
+    dtx_frames: usize,
+
+    /// Whether sources follow each other without silence in between.
+    gapless: bool,
+    /// When to stop waiting for the next source and play silence, if a
+    /// source ended while gapless.
+    held_until: Option<Instant>,
+
+    /// Milliseconds of the source that have been read.
+    position: Arc<AtomicU64>,
+    /// How long reading, waking up for and sending packets take.
+    timings: Arc<StreamTimings>,
 }
 
-impl PacketStreamer {
+impl<S> PacketStreamer<S>
+where
+    S: AudioSource,
+{
     /// Create a new, empty `PacketStreamer`.
     ///
     /// `patience` determines how much extra time the packet streamer will wait
     /// for audio data before considering there to be a break in the stream, so
     /// it can do proper audio interpolation. 200ms is a good default.
-    pub fn new(patience: Duration) -> PacketStreamer {
+    ///
+    /// The streamer keeps `position` updated with how far into the current
+    /// source it is, in milliseconds.
+    pub fn new(patience: Duration, position: Arc<AtomicU64>) -> PacketStreamer<S> {
         PacketStreamer {
             patience,
             source: None,
+            interjections: VecDeque::new(),
             waiting_for_source: true,
+            paused: false,
             packet: Packet::default(),
             next_packet: Instant::now(),
             ready: false,
+            skip: false,
             silence_frames: 0,
+            silence_padding: DEFAULT_SILENCE_FRAMES,
+            pre_roll: Duration::ZERO,
+            pre_rolled: false,
+            pre_roll_frames: 0,
+            held_packet: None,
+            dtx_frames: 0,
+            gapless: false,
+            held_until: None,
+            position,
+            timings: Arc::default(),
         }
     }
 
+    /// Records how long streaming takes to `timings`.
+    pub fn set_timings(&mut self, timings: Arc<StreamTimings>) {
+        self.timings = timings;
+    }
+
     /// Gives the streamer a new source to play.
-    pub fn source(&mut self, source: Source) {
-        self.wait_for_source();
+    pub fn source(&mut self, source: S) {
+        if self.interjections.is_empty() {
+            self.wait_for_source();
+        }
+        self.held_until = None;
+        self.position
+            .store(source.offset().as_millis() as u64, Ordering::Release);
         self.source = Some(source);
     }
 
+    /// Sets whether sources follow each other without silence in between.
+    ///
+    /// When a source ends while gapless, the streamer waits a moment for the
+    /// next one instead of playing silence frames, so the break Discord
+    /// hears is only as long as the next source takes to start.
+    pub fn set_gapless(&mut self, gapless: bool) {
+        self.gapless = gapless;
+    }
+
+    /// Whether sources follow each other without silence in between.
+    pub fn gapless(&self) -> bool {
+        self.gapless
+    }
+
+    /// Sets how many frames of silence are sent before a break in the audio.
+    ///
+    /// At least one is always sent, since the last one ends the stream.
+    pub fn set_silence_frames(&mut self, frames: usize) {
+        self.silence_padding = frames.max(1);
+    }
+
+    /// Sets how much silence is sent before the first audio, rounded down to
+    /// whole frames.
+    pub fn set_pre_roll(&mut self, pre_roll: Duration) {
+        self.pre_roll = pre_roll;
+    }
+
+    /// Plays a source over the current one, pausing it until the
+    /// interjection is over.
+    ///
+    /// Interjections are played in the order they are given, and don't move
+    /// the position of the source.
+    pub fn interject(&mut self, source: S) {
+        self.interjections.push_back(source);
+    }
+
+    /// Pauses the source where it is.
+    ///
+    /// The source is held onto, so nothing is lost, and picks up where it
+    /// left off when it is [resumed](PacketStreamer::resume).
+    pub fn pause(&mut self) {
+        self.paused = true;
+
+        if self.interjections.is_empty() {
+            self.wait_for_source();
+        }
+    }
+
+    /// Fades the source out over `duration`, after which it stops like it
+    /// ran out of audio.
+    ///
+    /// Returns `false` if there is no source to fade out, because there is
+    /// none, it is paused or it is already fading out.
+    pub fn fade_out(&mut self, duration: Duration) -> bool {
+        !self.paused
+            && self
+                .source
+                .as_ref()
+                .is_some_and(|source| source.fade_out(duration))
+    }
+
+    /// Resumes a paused source.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Takes every interjection that hasn't finished yet.
+    pub fn take_interjections(&mut self) -> VecDeque<S> {
+        if self.source.is_none() || self.paused {
+            self.wait_for_source();
+        }
+
+        std::mem::take(&mut self.interjections)
+    }
+
     /// Checks if the streamer has a source.
-    #[allow(dead_code)]
     pub fn has_source(&self) -> bool {
         self.source.is_some()
     }
 
-    /// Takes the inner [`Source`].
-    pub fn take_source(&mut self) -> Option<Source> {
-        self.wait_for_source();
+    /// Takes the source, without [closing](AudioSource::close) it.
+    pub fn take_source(&mut self) -> Option<S> {
+        // interjections keep playing without the source
+        if self.interjections.is_empty() {
+            self.wait_for_source();
+        }
+
         self.source.take()
     }
 
     /// Checks if the streamer is still streaming packets
     pub fn is_streaming(&self) -> bool {
-        !self.waiting_for_source || self.silence_frames > 0
+        !self.waiting_for_source || self.silence_frames > 0 || self.held_until.is_some()
     }
 
     /// Streams the inner audio over the [`Socket`], pacing the packets so they
@@ -82,11 +291,23 @@ impl PacketStreamer {
 
                 sleep_until(self.next_packet).await;
 
-                // send packet
-                rtp.send(&mut self.packet).await?;
+                let woke = Instant::now();
+                self.timings
+                    .record_wake(woke.saturating_duration_since(self.next_packet));
+
+                // send packet, or leave it out while the source is silent
+                if self.skip {
+                    rtp.skip();
+                } else {
+                    rtp.send(&mut self.packet).await?;
+                    self.timings.record_send(woke.elapsed());
+                }
 
                 // setup for next packet
-                self.packet = Packet::default();
+                // the packet is reused: the header is rewritten by `send`
+                // and the payload and its length by whatever fills it next,
+                // so there is nothing to reset
+                //
                 // FIXED: For reasons far beyond my reasoning or comprehension,
                 // the normal timestep for sending packets of, y'know, 20ms,
                 // makes the Opus audio run fast, just fast enough to create
@@ -144,15 +365,22 @@ impl PacketStreamer {
     /// This will mark the `self.ready` flag so that the read packet can now
     /// be processed.
     async fn next(&mut self, ssrc: u32) -> Result<Option<Status>, Error> {
-        if self.silence_frames > 0 {
-            self.silence_frames -= 1;
-
-            // copy silence frame
-            self.packet.payload_mut()[..SILENCE_FRAME.len()].copy_from_slice(SILENCE_FRAME);
+        if self.pre_roll_frames > 0 {
+            self.pre_roll_frames -= 1;
+            self.silence();
 
-            self.packet.set_payload_len(SILENCE_FRAME.len());
+            Ok(None)
+        } else if let Some(held) = self.held_packet.take() {
+            // the pre-roll is over, so the audio can start
+            self.packet.payload_mut()[..held.len()].copy_from_slice(&held);
+            self.packet.set_payload_len(held.len());
             self.ready = true;
 
+            Ok(None)
+        } else if self.silence_frames > 0 {
+            self.silence_frames -= 1;
+            self.silence();
+
             // if there is no audio left to play
             if self.silence_frames == 0 && self.waiting_for_source {
                 // return new status
@@ -169,33 +397,57 @@ impl PacketStreamer {
         }
     }
 
-    /// Polls for the next packet from the source.
+    /// Polls for the next packet from the source, or the first interjection
+    /// if there is one.
     ///
     /// This will wait until the source is ready.
     async fn next_from_source(&mut self, ssrc: u32) -> Result<Option<Status>, Error> {
-        let Some(source) = self.source.as_mut() else {
-            // there is no source, wait
+        let interjecting = !self.interjections.is_empty();
+        let source = match self.interjections.front_mut() {
+            Some(interjection) => Some(interjection),
+            None if self.paused => None,
+            None => self.source.as_mut(),
+        };
+        let Some(source) = source else {
+            if let Some(until) = self.held_until {
+                // the next source never came, so end the stream properly
+                sleep_until(until).await;
+                self.held_until = None;
+                self.silence_frames += self.silence_padding;
+                return Ok(None);
+            }
+
+            // there is no source, or it is paused, wait
             std::future::pending().await
         };
 
         let (len, end_wait) = if self.waiting_for_source {
             // we don't actually need to satisfy a strict packet time schedule,
             // since Discord is no longer expecting packets
-            let len = source.read(self.packet.payload_mut()).await?;
+            let len = match source.read_opus(self.packet.payload_mut()).await {
+                Ok(len) => len,
+                Err(err) if interjecting => return self.interjection_failed(err).await,
+                Err(err) => return Err(err.into()),
+            };
 
             // resume normal playback when the audio source continues results
             (len, true)
         } else {
             // we have to timeout if the source takes too long so we can warn
             // RTP of the break in audio
+            let started = Instant::now();
             let res = timeout_at(
                 self.next_packet + self.patience,
-                source.read(self.packet.payload_mut()),
+                source.read_opus(self.packet.payload_mut()),
             )
             .await;
 
             match res {
-                Ok(Ok(len)) => (len, false),
+                Ok(Ok(len)) => {
+                    self.timings.record_read(started.elapsed());
+                    (len, false)
+                }
+                Ok(Err(err)) if interjecting => return self.interjection_failed(err).await,
                 Ok(Err(err)) => return Err(err.into()),
                 Err(_) => {
                     let now = Instant::now();
@@ -209,13 +461,48 @@ impl PacketStreamer {
             }
         };
 
+        let was_skipping = self.dtx_frames > self.silence_padding;
+
         if len > 0 {
-            self.packet.set_payload_len(len);
+            // Opus leaves out silence with DTX, so the break in the audio is
+            // ended like any other before packets stop
+            if len <= DTX_PACKET_LEN && !interjecting {
+                self.dtx_frames += 1;
+            } else {
+                self.dtx_frames = 0;
+            }
+
+            if self.dtx_frames == 0 {
+                self.packet.set_payload_len(len);
+            } else {
+                self.packet.payload_mut()[..SILENCE_FRAME.len()].copy_from_slice(SILENCE_FRAME);
+                self.packet.set_payload_len(SILENCE_FRAME.len());
+            }
+
+            self.skip = self.dtx_frames > self.silence_padding;
             self.ready = true;
+
+            if !interjecting {
+                self.position
+                    .fetch_add(TIMESTEP_LENGTH.as_millis() as u64, Ordering::AcqRel);
+            }
+        } else if interjecting {
+            // go back to the source, or the next interjection
+            self.end_interjection().await?;
+            return Ok(None);
         } else {
             // clean up
-            self.take_source().unwrap().close().await?;
-            self.wait_for_source();
+            self.source.take().unwrap().close().await?;
+
+            if self.gapless {
+                // wait for the next source without playing silence, so
+                // there is no gap before it
+                self.waiting_for_source = true;
+                self.held_until = Some(Instant::now() + GAPLESS_HOLD);
+            } else {
+                self.wait_for_source();
+            }
+
             return Ok(Some(Status::SourceStopped));
         }
 
@@ -225,20 +512,99 @@ impl PacketStreamer {
             self.next_packet = Instant::now() + TIMESTEP_LENGTH;
             self.waiting_for_source = false;
 
+            if !self.pre_rolled {
+                self.pre_rolled = true;
+                self.start_pre_roll();
+            }
+
+            Ok(Some(Status::Started(ssrc)))
+        } else if self.skip && !was_skipping {
+            // nothing is sent while the source is silent, so stop speaking
+            // until it isn't
+            Ok(Some(Status::Stopped(ssrc)))
+        } else if !self.skip && was_skipping {
             Ok(Some(Status::Started(ssrc)))
         } else {
             Ok(None)
         }
     }
 
+    /// Skips an interjection that failed, so it doesn't take the source
+    /// down with it.
+    async fn interjection_failed(
+        &mut self,
+        err: super::source::Error,
+    ) -> Result<Option<Status>, Error> {
+        warn!(%err, "interjection error");
+
+        self.end_interjection().await?;
+        Ok(None)
+    }
+
+    /// Closes the interjection that is playing.
+    async fn end_interjection(&mut self) -> Result<(), Error> {
+        if let Some(mut interjection) = self.interjections.pop_front() {
+            interjection.close().await?;
+        }
+
+        if self.interjections.is_empty() && (self.source.is_none() || self.paused) {
+            self.wait_for_source();
+        }
+
+        Ok(())
+    }
+
+    /// Holds the first packet of audio back, and sends silence before it.
+    fn start_pre_roll(&mut self) {
+        let frames = (self.pre_roll.as_millis() / TIMESTEP_LENGTH.as_millis()) as usize;
+
+        if frames == 0 {
+            return;
+        }
+
+        let len = self.packet.payload_len();
+        self.held_packet = Some(self.packet.payload()[..len].to_vec());
+        self.pre_roll_frames = frames - 1;
+        self.silence();
+    }
+
+    /// Readies a frame of silence.
+    fn silence(&mut self) {
+        self.packet.payload_mut()[..SILENCE_FRAME.len()].copy_from_slice(SILENCE_FRAME);
+        self.packet.set_payload_len(SILENCE_FRAME.len());
+        self.ready = true;
+        self.skip = false;
+    }
+
     fn wait_for_source(&mut self) {
         if !self.waiting_for_source {
             self.waiting_for_source = true;
-            self.silence_frames += 5;
+            self.silence_frames += self.silence_padding;
+            // the break is ended like any other, whatever left it silent
+            self.dtx_frames = 0;
         }
     }
 }
 
+impl PacketStreamer<Source> {
+    /// Mixes an overlay over the source, ducking it to `gain`.
+    ///
+    /// If there is no source to mix it over, the overlay is interjected
+    /// instead.
+    pub fn duck(&mut self, overlay: Overlay, gain: f32) -> Result<(), source::Error> {
+        let overlay = match &self.source {
+            Some(source) => match source.duck(overlay, gain) {
+                Ok(()) => return Ok(()),
+                Err(overlay) => *overlay,
+            },
+            None => overlay,
+        };
+
+        self.interject(overlay.into_source()?);
+        Ok(())
+    }
+}
+
 /// An event that is returned from [`PacketStreamer::stream`] that is
 /// informative on the status of the streamer.
 pub enum Status {
@@ -250,3 +616,52 @@ pub enum Status {
     /// The source that was playing has stopped.
     SourceStopped,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source of frames of the given lengths.
+    struct Frames(VecDeque<usize>);
+
+    impl AudioSource for Frames {
+        async fn read_opus(&mut self, buf: &mut [u8]) -> Result<usize, source::Error> {
+            let len = self.0.pop_front().unwrap_or(0);
+            buf[..len].fill(0xF8);
+            Ok(len)
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_speaking_while_silent() {
+        let mut streamer = PacketStreamer::new(Duration::from_millis(200), Arc::default());
+        streamer.set_silence_frames(1);
+
+        let audio = DTX_PACKET_LEN + 1;
+        let frames = [audio, 1, 1, 1, audio];
+        streamer.source(Frames(frames.into_iter().collect()));
+
+        assert!(matches!(
+            streamer.next(1).await,
+            Ok(Some(Status::Started(1)))
+        ));
+        // the first silent frame ends the audio like any other break
+        assert!(matches!(streamer.next(1).await, Ok(None)));
+        assert!(!streamer.skip);
+        assert!(matches!(
+            streamer.next(1).await,
+            Ok(Some(Status::Stopped(1)))
+        ));
+        assert!(streamer.skip);
+        assert!(matches!(streamer.next(1).await, Ok(None)));
+        assert!(matches!(
+            streamer.next(1).await,
+            Ok(Some(Status::Started(1)))
+        ));
+        assert!(!streamer.skip);
+        assert!(matches!(
+            streamer.next(1).await,
+            Ok(Some(Status::SourceStopped))
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+//! How long things take.
+//!
+//! Stutter can come from a lot of places: encoding can fall behind, the
+//! source can be slow to read its next packet, the runtime can wake the streamer up late, or sending the
+//! packet can take a while. A [`StreamTimings`] keeps a [`Histogram`] of
+//! each, so the worst of them stand out instead of averaging away.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+/// The upper bounds of the buckets of a [`Histogram`], in microseconds.
+///
+/// Anything slower goes in one last bucket.
+pub const BUCKETS: [u64; 12] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 20_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+
+/// A histogram of durations that can be recorded to from many tasks at
+/// once.
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    count: AtomicU64,
+    /// The sum of every duration, in microseconds.
+    sum: AtomicU64,
+    /// The longest duration, in microseconds.
+    max: AtomicU64,
+}
+
+impl Histogram {
+    /// Creates an empty `Histogram`.
+    pub const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len() + 1],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a duration.
+    pub fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = BUCKETS.partition_point(|&bound| bound < micros);
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// How many durations were recorded.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The sum of every duration recorded.
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// The longest duration recorded.
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max.load(Ordering::Relaxed))
+    }
+
+    /// The average duration, or zero if none were recorded.
+    pub fn mean(&self) -> Duration {
+        let count = self.count();
+
+        if count == 0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_micros(self.sum.load(Ordering::Relaxed) / count)
+    }
+
+    /// The upper bound of the bucket the `q`th quantile falls in, or `None`
+    /// if nothing was recorded.
+    ///
+    /// If it falls in the last bucket, this is the longest duration instead.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((count as f64 * q).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+
+            if seen >= rank {
+                return Some(match BUCKETS.get(i) {
+                    Some(&bound) => Duration::from_micros(bound).min(self.max()),
+                    None => self.max(),
+                });
+            }
+        }
+
+        Some(self.max())
+    }
+
+    /// The histogram as JSON, in milliseconds.
+    pub fn to_json(&self) -> Value {
+        let ms =
+            |duration: Option<Duration>| duration.map(|duration| duration.as_secs_f64() * 1000.);
+
+        let buckets = BUCKETS
+            .iter()
+            .map(|&bound| format!("le_{}ms", bound as f64 / 1000.))
+            .chain([String::from("le_inf")])
+            .zip(&self.buckets)
+            .map(|(bound, bucket)| (bound, Value::from(bucket.load(Ordering::Relaxed))))
+            .collect::<serde_json::Map<_, _>>();
+
+        json!({
+            "count": self.count(),
+            "mean_ms": ms(Some(self.mean())),
+            "p50_ms": ms(self.quantile(0.5)),
+            "p99_ms": ms(self.quantile(0.99)),
+            "max_ms": ms(Some(self.max())),
+            "buckets": buckets,
+        })
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+/// How long the steps of streaming packets took.
+///
+/// Timings can have a parent, like the totals across every player, which
+/// everything recorded to them is also recorded to.
+#[derive(Debug, Default)]
+pub struct StreamTimings {
+    /// How long encoding a packet took.
+    pub encode: Histogram,
+    /// How long reading a packet from the source took.
+    pub read: Histogram,
+    /// How late the streamer woke up to send a packet.
+    pub wake: Histogram,
+    /// How long sending a packet took.
+    pub send: Histogram,
+    parent: Option<Arc<StreamTimings>>,
+}
+
+impl StreamTimings {
+    /// Creates empty timings that also record to `parent`.
+    pub fn with_parent(parent: Arc<StreamTimings>) -> StreamTimings {
+        StreamTimings {
+            parent: Some(parent),
+            ..Default::default()
+        }
+    }
+
+    /// Records how long encoding a packet took.
+    pub fn record_encode(&self, duration: Duration) {
+        self.encode.record(duration);
+
+        if let Some(parent) = &self.parent {
+            parent.record_encode(duration);
+        }
+    }
+
+    /// Records how long reading a packet took.
+    pub fn record_read(&self, duration: Duration) {
+        self.read.record(duration);
+
+        if let Some(parent) = &self.parent {
+            parent.record_read(duration);
+        }
+    }
+
+    /// Records how late the streamer woke up.
+    pub fn record_wake(&self, duration: Duration) {
+        self.wake.record(duration);
+
+        if let Some(parent) = &self.parent {
+            parent.record_wake(duration);
+        }
+    }
+
+    /// Records how long sending a packet took.
+    pub fn record_send(&self, duration: Duration) {
+        self.send.record(duration);
+
+        if let Some(parent) = &self.parent {
+            parent.record_send(duration);
+        }
+    }
+
+    /// The timings as JSON.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "encode": self.encode.to_json(),
+            "read": self.read.to_json(),
+            "wake": self.wake.to_json(),
+            "send": self.send.to_json(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_durations() {
+        let histogram = Histogram::new();
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for _ in 0..98 {
+            histogram.record(Duration::from_micros(80));
+        }
+        histogram.record(Duration::from_millis(15));
+        histogram.record(Duration::from_secs(3));
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(100)));
+        assert_eq!(histogram.quantile(0.99), Some(Duration::from_millis(20)));
+        assert_eq!(histogram.quantile(1.), Some(Duration::from_secs(3)));
+        assert_eq!(histogram.max(), Duration::from_secs(3));
+
+        let parent = Arc::new(StreamTimings::default());
+        let timings = StreamTimings::with_parent(parent.clone());
+        timings.record_send(Duration::from_millis(1));
+        assert_eq!(parent.send.count(), 1);
+    }
+}
@@ -15,6 +15,8 @@ pub enum Error {
     Ws(WsError),
     Io(std::io::Error),
     IpDiscovery(IpDiscoveryError),
+    /// The server stopped acknowledging heartbeats.
+    HeartbeatTimeout,
 }
 
 impl Error {
@@ -30,6 +32,7 @@ impl Error {
     pub fn can_resume(&self) -> bool {
         match self {
             Error::Api(err) => matches!(err.code, Code::VoiceServerCrashed),
+            Error::HeartbeatTimeout => true,
             Error::Ws(WsError::Protocol(p)) => {
                 matches!(p, WsProtocolError::ResetWithoutClosingHandshake)
             }
@@ -47,6 +50,7 @@ impl Display for Error {
             Error::Closed(err) => Debug::fmt(err, f),
             Error::IpDiscovery(err) => Display::fmt(err, f),
             Error::Protocol(err) => Display::fmt(err, f),
+            Error::HeartbeatTimeout => f.write_str("heartbeats not acknowledged"),
         }
     }
 }
@@ -96,6 +100,16 @@ pub enum ProtocolError {
     UnsupportedEncryptionMode(super::payload::EncryptionMode),
     /// The server returned a payload without a valid opcode.
     MissingOpcode,
+    /// The connection closed before the server sent a Hello.
+    MissingHello,
+    /// The connection closed before the server sent a Ready.
+    MissingReady,
+    /// The connection closed before the server sent a Session Description.
+    MissingSessionDescription,
+    /// The server doesn't offer any encryption mode we support.
+    NoEncryptionMode,
+    /// The gateway didn't say which voice server to connect to.
+    MissingEndpoint,
 }
 
 impl Display for ProtocolError {
@@ -113,6 +127,13 @@ impl Display for ProtocolError {
             ProtocolError::MissingOpcode => {
                 write!(f, "payload missing opcode")
             }
+            ProtocolError::MissingHello => f.write_str("connection closed before hello"),
+            ProtocolError::MissingReady => f.write_str("connection closed before ready"),
+            ProtocolError::MissingSessionDescription => {
+                f.write_str("connection closed before session description")
+            }
+            ProtocolError::NoEncryptionMode => f.write_str("no supported encryption mode"),
+            ProtocolError::MissingEndpoint => f.write_str("voice server update missing endpoint"),
         }
     }
 }
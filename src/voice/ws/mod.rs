@@ -12,7 +12,6 @@ use payload::{
     Ready, Resume, SelectProtocol, SelectProtocolData, SessionDescription, Speaking,
 };
 
-use tokio::net::UdpSocket;
 use tokio::time::{sleep_until, Duration, Instant};
 
 use async_tungstenite::{
@@ -29,6 +28,7 @@ use twilight_model::id::{
 
 use std::borrow::Cow;
 use std::fmt::Debug;
+use std::ops::RangeInclusive;
 
 use tracing::{debug, debug_span, error, info, instrument, warn};
 
@@ -46,9 +46,13 @@ impl Connection {
     /// Establishes a connection to an endpoint.
     ///
     /// Returns the websocket connection and the UDP connection used to send
-    /// Opus frames.
+    /// Opus frames. The UDP socket binds to a port in `udp_ports`, or any port
+    /// if `None`.
     #[instrument]
-    pub async fn connect(session: Session) -> Result<(Connection, Socket), Error> {
+    pub async fn connect(
+        session: Session,
+        udp_ports: Option<RangeInclusive<u16>>,
+    ) -> Result<(Connection, Socket), Error> {
         let (wss, _response) = connect_async(format!("wss://{}/?v=4", session.endpoint)).await?;
 
         let mut conn = Connection {
@@ -56,7 +60,7 @@ impl Connection {
             wss,
             heartbeater: Default::default(),
         };
-        let rtp = conn.handshake().await?;
+        let rtp = conn.handshake(udp_ports).await?;
 
         Ok((conn, rtp))
     }
@@ -72,8 +76,9 @@ impl Connection {
                 ev = recv(&mut self.wss) => {
                     match ev {
                         Some(Ok(GatewayEvent::HeartbeatAck(ack))) => {
-                            if self.heartbeater.nonce() == ack.0 {
-                                debug!("voice heartbeat ACK");
+                            if let Some(latency) = self.heartbeater.ack(ack.0) {
+                                debug!(?latency, "voice heartbeat ACK");
+                                return Some(Ok(Event::HeartbeatAck(latency)));
                             } else {
                                 warn!(nonce = ack.0, "invalid ACK");
                             }
@@ -108,7 +113,22 @@ impl Connection {
                 // wait for heartbeats
                 heartbeat = self.heartbeater.next() => {
                     // send heartbeat
-                    send(&mut self.wss, &GatewayEvent::Heartbeat(heartbeat)).await.unwrap();
+                    let res = match heartbeat {
+                        Ok(heartbeat) => send(&mut self.wss, &GatewayEvent::Heartbeat(heartbeat)).await,
+                        Err(err) => Err(err),
+                    };
+
+                    match res {
+                        Ok(()) => (),
+                        Err(err) if err.can_resume() => {
+                            warn!(%err, "resuming connection");
+
+                            if let Err(err) = self.resume().await {
+                                return Some(Err(err));
+                            }
+                        }
+                        Err(err) => return Some(Err(err)),
+                    }
                 }
             }
         }
@@ -139,7 +159,7 @@ impl Connection {
     ///
     /// [1]: https://discord.com/developers/docs/topics/voice-connections#establishing-a-voice-websocket-connection
     #[instrument(name = "voice_handshake", skip(self))]
-    async fn handshake(&mut self) -> Result<Socket, Error> {
+    async fn handshake(&mut self, udp_ports: Option<RangeInclusive<u16>>) -> Result<Socket, Error> {
         debug!(?self.session, "setting up connection");
 
         send(
@@ -189,8 +209,8 @@ impl Connection {
             }
         }
 
-        let hello = hello.unwrap();
-        let ready = ready.unwrap();
+        let hello = hello.ok_or(Error::Protocol(ProtocolError::MissingHello))?;
+        let ready = ready.ok_or(Error::Protocol(ProtocolError::MissingReady))?;
 
         drop(_span);
 
@@ -198,8 +218,7 @@ impl Connection {
         self.heartbeater = Heartbeater::new(hello.heartbeat_interval);
 
         // establish udp connection and discover ip
-        let udp = UdpSocket::bind(("0.0.0.0", ready.port)).await?;
-        udp.connect((ready.ip, ready.port)).await?;
+        let udp = rtp::connect(&ready.ip, ready.port, udp_ports).await?;
 
         let ip = rtp::ip_discovery(&udp, ready.ssrc).await?;
 
@@ -207,20 +226,26 @@ impl Connection {
         let _span = span.enter();
 
         // choose encryption mode
-        // order: lite > suffix > normal
-        let mode = ready
-            .modes
-            .iter()
-            .find(|&m| *m == EncryptionMode::Lite)
-            .or_else(|| ready.modes.iter().find(|&m| *m == EncryptionMode::Suffix))
-            .or_else(|| ready.modes.iter().find(|&m| *m == EncryptionMode::Normal))
-            .cloned()
-            .unwrap();
+        // order: aes256 gcm > xchacha20 > lite > suffix > normal
+        let mode = [
+            EncryptionMode::Aes256GcmRtpSize,
+            EncryptionMode::XChaCha20Poly1305RtpSize,
+            EncryptionMode::Lite,
+            EncryptionMode::Suffix,
+            EncryptionMode::Normal,
+        ]
+        .into_iter()
+        .find(|mode| ready.modes.contains(mode))
+        .ok_or(Error::Protocol(ProtocolError::NoEncryptionMode))?;
 
         let encryptor_mode = match mode {
             EncryptionMode::Normal => rtp::EncryptionMode::Normal,
             EncryptionMode::Suffix => rtp::EncryptionMode::Suffix,
             EncryptionMode::Lite => rtp::EncryptionMode::Lite,
+            EncryptionMode::Aes256GcmRtpSize => rtp::EncryptionMode::Aes256GcmRtpSize,
+            EncryptionMode::XChaCha20Poly1305RtpSize => {
+                rtp::EncryptionMode::XChaCha20Poly1305RtpSize
+            }
             mode => {
                 return Err(Error::Protocol(ProtocolError::UnsupportedEncryptionMode(
                     mode,
@@ -271,7 +296,7 @@ impl Connection {
             }
         }
 
-        let desc = desc.unwrap();
+        let desc = desc.ok_or(Error::Protocol(ProtocolError::MissingSessionDescription))?;
 
         info!(endpoint = self.session.endpoint, "voice connected");
 
@@ -313,6 +338,9 @@ impl Connection {
                 Ok(GatewayEvent::Resumed) => {
                     break;
                 }
+                Ok(GatewayEvent::Hello(hello)) => {
+                    self.heartbeater = Heartbeater::new(hello.heartbeat_interval);
+                }
                 Ok(ev) => {
                     warn!(?ev, "unexpected event");
                 }
@@ -408,7 +436,7 @@ async fn send(
 }
 
 /// Session information of a websocket.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Session {
     /// The endpoint of the session.
     pub endpoint: String,
@@ -428,6 +456,9 @@ pub enum Event {
     Speaking(Speaking),
     ClientConnect(ClientConnect),
     ClientDisconnect(ClientDisconnect),
+    /// A heartbeat was acknowledged, with the round trip time of the
+    /// heartbeat.
+    HeartbeatAck(Duration),
 }
 
 /// Voice command.
@@ -441,11 +472,21 @@ impl Command for Speaking {
     }
 }
 
+/// How many heartbeats can go unacknowledged before the connection is
+/// considered dead.
+const MAX_MISSED_ACKS: u32 = 3;
+
 /// Manages heartbeat state.
+///
+/// Each heartbeat gets a random nonce, and the heartbeater keeps track of
+/// whether the server acknowledged it.
 #[derive(Debug)]
 struct Heartbeater {
     interval: f32,
     nonce: u64,
+    acked: bool,
+    missed: u32,
+    sent: Instant,
     next: Instant,
 }
 
@@ -455,24 +496,54 @@ impl Heartbeater {
         Heartbeater {
             interval,
             nonce: 0,
+            acked: true,
+            missed: 0,
+            sent: Instant::now(),
             next: Instant::now() + Duration::from_millis(interval as u64),
         }
     }
 
     /// Returns the next heartbeat after the alloted time has passed.
-    pub async fn next(&mut self) -> Heartbeat {
+    ///
+    /// Fails with [`Error::HeartbeatTimeout`] if too many heartbeats in a row
+    /// went unacknowledged.
+    pub async fn next(&mut self) -> Result<Heartbeat, Error> {
         sleep_until(self.next).await;
 
-        self.nonce += 1;
-        let heartbeat = Heartbeat(self.nonce);
         self.next = Instant::now() + Duration::from_millis(self.interval as u64);
 
-        heartbeat
+        if !self.acked {
+            self.missed += 1;
+            warn!(missed = self.missed, "voice heartbeat not acknowledged");
+
+            if self.missed >= MAX_MISSED_ACKS {
+                // start over for the next connection
+                self.acked = true;
+                self.missed = 0;
+                return Err(Error::HeartbeatTimeout);
+            }
+        }
+
+        // keep nonces small enough to survive a round trip through a js
+        // number
+        self.nonce = rand::random::<u32>() as u64;
+        self.acked = false;
+        self.sent = Instant::now();
+
+        Ok(Heartbeat(self.nonce))
     }
 
-    /// The current nonce of the heartbeater.
-    pub fn nonce(&self) -> u64 {
-        self.nonce
+    /// Acknowledges a heartbeat, returning the time it took to be
+    /// acknowledged, or `None` if `nonce` isn't the nonce of the last
+    /// heartbeat.
+    pub fn ack(&mut self, nonce: u64) -> Option<Duration> {
+        if self.nonce == nonce && !self.acked {
+            self.acked = true;
+            self.missed = 0;
+            Some(self.sent.elapsed())
+        } else {
+            None
+        }
     }
 }
 
@@ -1,5 +1,6 @@
 //! Websocket payloads.
 
+use bitflags::bitflags;
 use serde::{
     de::{
         self, value::U8Deserializer, DeserializeSeed, Deserializer, IgnoredAny, IntoDeserializer,
@@ -254,12 +255,47 @@ pub struct SessionDescription {
 /// The `SPEAKING` payload.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Speaking {
-    pub speaking: u8,
+    pub speaking: SpeakingFlags,
     #[serde(default)]
     pub delay: Option<u32>,
     pub ssrc: u32,
 }
 
+bitflags! {
+    /// How the client is speaking.
+    ///
+    /// See [discord docs][1] for more info.
+    ///
+    /// [1]: https://discord.com/developers/docs/topics/voice-connections#speaking
+    #[derive(Default)]
+    pub struct SpeakingFlags: u8 {
+        /// Normal transmission of voice audio.
+        const MICROPHONE = 1 << 0;
+        /// Transmission of context audio for video, no speaking indicator.
+        const SOUNDSHARE = 1 << 1;
+        /// Priority speaker, lowering audio of other speakers.
+        const PRIORITY = 1 << 2;
+    }
+}
+
+impl Serialize for SpeakingFlags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(self.bits())
+    }
+}
+
+impl<'de> Deserialize<'de> for SpeakingFlags {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        u8::deserialize(deserializer).map(SpeakingFlags::from_bits_truncate)
+    }
+}
+
 /// The `HEARTBEAT` payload.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Heartbeat(pub u64);
@@ -314,6 +350,16 @@ pub enum EncryptionMode {
     ///
     /// Nonce generated incrementally.
     Lite,
+    /// AES-256-GCM, with the RTP header as associated data.
+    ///
+    /// The nonce bytes are 4-bytes appended to the payload of the RTP packet,
+    /// generated incrementally.
+    Aes256GcmRtpSize,
+    /// XChaCha20-Poly1305, with the RTP header as associated data.
+    ///
+    /// The nonce bytes are 4-bytes appended to the payload of the RTP packet,
+    /// generated incrementally.
+    XChaCha20Poly1305RtpSize,
     /// Other encryption modes supported by discord, but not by this library.
     Other(String),
 }
@@ -322,6 +368,8 @@ impl EncryptionMode {
     const NORMAL_STR: &'static str = "xsalsa20_poly1305";
     const SUFFIX_STR: &'static str = "xsalsa20_poly1305_suffix";
     const LITE_STR: &'static str = "xsalsa20_poly1305_lite";
+    const AES256_GCM_RTPSIZE_STR: &'static str = "aead_aes256_gcm_rtpsize";
+    const XCHACHA20_POLY1305_RTPSIZE_STR: &'static str = "aead_xchacha20_poly1305_rtpsize";
 
     /// Returns the string representation of the mode.
     pub fn as_str(&self) -> &str {
@@ -329,6 +377,8 @@ impl EncryptionMode {
             Self::Normal => Self::NORMAL_STR,
             Self::Suffix => Self::SUFFIX_STR,
             Self::Lite => Self::LITE_STR,
+            Self::Aes256GcmRtpSize => Self::AES256_GCM_RTPSIZE_STR,
+            Self::XChaCha20Poly1305RtpSize => Self::XCHACHA20_POLY1305_RTPSIZE_STR,
             Self::Other(s) => s,
         }
     }
@@ -371,6 +421,10 @@ impl<'de> Deserialize<'de> for EncryptionMode {
                     EncryptionMode::NORMAL_STR => Ok(EncryptionMode::Normal),
                     EncryptionMode::SUFFIX_STR => Ok(EncryptionMode::Suffix),
                     EncryptionMode::LITE_STR => Ok(EncryptionMode::Lite),
+                    EncryptionMode::AES256_GCM_RTPSIZE_STR => Ok(EncryptionMode::Aes256GcmRtpSize),
+                    EncryptionMode::XCHACHA20_POLY1305_RTPSIZE_STR => {
+                        Ok(EncryptionMode::XChaCha20Poly1305RtpSize)
+                    }
                     v => Ok(EncryptionMode::Other(v.to_owned())),
                 }
             }
@@ -386,14 +440,27 @@ mod tests {
 
     #[test]
     fn test_opcode_resume() {
-        const PAYLOAD: &'static str = r#"{"op":9,"d":null}"#;
+        const PAYLOAD: &str = r#"{"op":9,"d":null}"#;
 
-        let event = GatewayEventDeserializer::from_json(&PAYLOAD).unwrap();
+        let event = GatewayEventDeserializer::from_json(PAYLOAD).unwrap();
 
-        let mut json = serde_json::Deserializer::from_str(&PAYLOAD);
+        let mut json = serde_json::Deserializer::from_str(PAYLOAD);
 
         let event = event.deserialize(&mut json).unwrap();
 
         assert!(matches!(event, GatewayEvent::Resumed));
     }
+
+    #[test]
+    fn test_speaking_flags() {
+        let speaking = Speaking {
+            speaking: SpeakingFlags::MICROPHONE | SpeakingFlags::PRIORITY,
+            delay: Some(0),
+            ssrc: 1,
+        };
+
+        let json = serde_json::to_string(&speaking).unwrap();
+
+        assert_eq!(json, r#"{"speaking":5,"delay":0,"ssrc":1}"#);
+    }
 }
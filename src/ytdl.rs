@@ -3,11 +3,17 @@
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
 use tokio::process::Command;
 
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
 use std::fmt::{self, Display, Formatter};
 use std::process::Stdio;
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use twilight_model::channel::message::embed::{Embed, EmbedAuthor, EmbedThumbnail};
+use twilight_model::channel::message::embed::{Embed, EmbedAuthor, EmbedImage, EmbedThumbnail};
+use twilight_model::id::{marker::UserMarker, Id};
 
 use serde::Deserialize;
 
@@ -15,20 +21,180 @@ use tracing::instrument;
 
 //use crate::voice::{Source, source::Error as SourceError};
 
-static YTDL_EXECUTABLE: OnceLock<String> = OnceLock::new();
+/// How `youtube-dl` is run.
+///
+/// This is a handle: clones share the executable, so swapping it with
+/// [`YtdlConfig::set_executable`] swaps it for every clone, and count
+/// [`YtdlConfig::extraction_failures`] together.
+#[derive(Clone, Debug)]
+pub struct YtdlConfig {
+    executable: Arc<RwLock<Arc<str>>>,
+    options: Arc<YtdlOptions>,
+    failures: Arc<AtomicU64>,
+}
+
+impl YtdlConfig {
+    /// Creates a config that runs `executable` with no extra options.
+    pub fn new(executable: impl Into<Arc<str>>) -> YtdlConfig {
+        YtdlConfig {
+            executable: Arc::new(RwLock::new(executable.into())),
+            options: Arc::default(),
+            failures: Arc::default(),
+        }
+    }
 
-/// The `youtube-dl` executable.
-pub fn ytdl_executable() -> &'static str {
-    YTDL_EXECUTABLE
-        .get()
-        .expect("ytdl executable initialized at startup")
+    /// Reads the config from the environment.
+    ///
+    /// `YTDL_EXECUTABLE` is the executable, `youtube-dl` if it isn't set, and
+    /// the options are read with [`YtdlOptions::from_env`].
+    pub fn from_env() -> YtdlConfig {
+        let executable = env::var("YTDL_EXECUTABLE").unwrap_or_else(|_| String::from("youtube-dl"));
+
+        YtdlConfig::new(executable).with_options(YtdlOptions::from_env())
+    }
+
+    /// Passes extra options to every invocation.
+    pub fn with_options(self, options: YtdlOptions) -> YtdlConfig {
+        YtdlConfig {
+            options: Arc::new(options),
+            ..self
+        }
+    }
+
+    /// The `youtube-dl` executable.
+    pub fn executable(&self) -> Arc<str> {
+        self.executable.read().unwrap().clone()
+    }
+
+    /// Swaps the `youtube-dl` executable, like after an update.
+    ///
+    /// Processes that are already running keep the executable they started
+    /// with.
+    pub fn set_executable(&self, executable: impl Into<Arc<str>>) {
+        *self.executable.write().unwrap() = executable.into();
+    }
+
+    /// The extra options passed to every invocation.
+    pub fn options(&self) -> &YtdlOptions {
+        &self.options
+    }
+
+    /// Starts a command for the executable, with the extra options.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&*self.executable());
+        command.args(self.options.args());
+        command
+    }
+
+    /// Gets the version of the executable.
+    pub async fn version(&self) -> Result<String, std::io::Error> {
+        executable_version(&*self.executable()).await
+    }
+
+    /// How many queries failed to extract with this config.
+    ///
+    /// Videos that can't be played, like private ones, don't count. A spike
+    /// usually means a site changed and `youtube-dl` needs an update.
+    pub fn extraction_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for YtdlConfig {
+    fn default() -> YtdlConfig {
+        YtdlConfig::new("youtube-dl")
+    }
+}
+
+/// Gets the version of a `youtube-dl` executable, which doesn't have to be
+/// the one in use.
+pub async fn executable_version(executable: impl AsRef<OsStr>) -> Result<String, std::io::Error> {
+    let out = Command::new(executable)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+
+    if !out.status.success() {
+        return Err(std::io::Error::other(format!(
+            "youtube-dl exited with {}",
+            out.status
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
 }
 
-pub fn init_ytdl_executable<F>(f: F) -> &'static str
-where
-    F: FnOnce() -> String,
-{
-    YTDL_EXECUTABLE.get_or_init(f)
+/// Extra options for `youtube-dl`.
+///
+/// These are passed to both queries and streams, so operators can work around
+/// age-restricted and region-locked videos.
+#[derive(Clone, Debug, Default)]
+pub struct YtdlOptions {
+    /// A Netscape-formatted cookies file to read cookies from.
+    pub cookies: Option<String>,
+    /// A proxy URL to route requests through.
+    pub proxy: Option<String>,
+    /// Whether to fake the `X-Forwarded-For` header to bypass geographic
+    /// restrictions.
+    pub geo_bypass: bool,
+    /// The maximum download rate, e.g. `50K` or `4.2M`.
+    pub rate_limit: Option<String>,
+    /// The client-side IP address to bind to.
+    pub source_address: Option<String>,
+}
+
+impl YtdlOptions {
+    /// Reads the options from the environment.
+    ///
+    /// | Variable              | Option               |
+    /// |-----------------------|----------------------|
+    /// | `YTDL_COOKIES`        | [`cookies`]          |
+    /// | `YTDL_PROXY`          | [`proxy`]            |
+    /// | `YTDL_GEO_BYPASS`     | [`geo_bypass`]       |
+    /// | `YTDL_RATE_LIMIT`     | [`rate_limit`]       |
+    /// | `YTDL_SOURCE_ADDRESS` | [`source_address`]   |
+    ///
+    /// [`cookies`]: YtdlOptions::cookies
+    /// [`proxy`]: YtdlOptions::proxy
+    /// [`geo_bypass`]: YtdlOptions::geo_bypass
+    /// [`rate_limit`]: YtdlOptions::rate_limit
+    /// [`source_address`]: YtdlOptions::source_address
+    pub fn from_env() -> YtdlOptions {
+        YtdlOptions {
+            cookies: env::var("YTDL_COOKIES").ok(),
+            proxy: env::var("YTDL_PROXY").ok(),
+            geo_bypass: env::var("YTDL_GEO_BYPASS")
+                .map(|v| matches!(&*v, "1" | "true" | "yes"))
+                .unwrap_or_default(),
+            rate_limit: env::var("YTDL_RATE_LIMIT").ok(),
+            source_address: env::var("YTDL_SOURCE_ADDRESS").ok(),
+        }
+    }
+
+    /// The command line arguments for these options.
+    pub fn args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+
+        if let Some(cookies) = &self.cookies {
+            args.extend(["--cookies", cookies]);
+        }
+        if let Some(proxy) = &self.proxy {
+            args.extend(["--proxy", proxy]);
+        }
+        if self.geo_bypass {
+            args.push("--geo-bypass");
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            args.extend(["--limit-rate", rate_limit]);
+        }
+        if let Some(source_address) = &self.source_address {
+            args.extend(["--source-address", source_address]);
+        }
+
+        args
+    }
 }
 
 /// The result of a `youtube-dl` query.
@@ -50,18 +216,20 @@ impl Query {
     /// slow operation, and has a tendency to time things out. Offload this
     /// work to a new async task and communicate the completion of the task
     /// through message passing.
-    #[instrument(name = "Query::query")]
-    pub async fn query(query: &str) -> Result<Query, QueryError> {
-        let mut ytdl = Command::new(ytdl_executable())
-            .args(["--yes-playlist", "--flat-playlist", "-J", query])
+    #[instrument(name = "Query::query", skip(ytdl))]
+    pub async fn query(ytdl: &YtdlConfig, query: &str) -> Result<Query, crate::Error> {
+        let mut child = ytdl
+            .command()
+            // `--` so a query starting with `-` isn't read as an option
+            .args(["--yes-playlist", "--flat-playlist", "-J", "--", query])
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
             .map_err(QueryError::Io)?;
 
-        let stdout = ytdl.stdout.take().unwrap();
-        let stderr = ytdl.stderr.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
 
         async fn read_to_end(mut stream: impl AsyncRead + Unpin) -> Result<String, std::io::Error> {
             let mut out = String::new();
@@ -70,20 +238,26 @@ impl Query {
 
         // wait for the query to finish
         let (_exit, out, err) = tokio::try_join!(
-            ytdl.wait(),
+            child.wait(),
             read_to_end(stdout),
             YtdlError::from_ytdl(BufReader::new(stderr)),
         )
         .map_err(QueryError::Io)?;
 
-        if let Some(err) = err {
-            Err(QueryError::Ytdl(err))
+        let res = if let Some(err) = err {
+            Err(QueryError::from(err))
         } else if output_is_playlist(&out) {
             Query::playlist_from_json(&out)
         } else {
             // not a playlist, or an error occured
             Query::track_from_json(&out)
+        };
+
+        if let Err(QueryError::Ytdl(_) | QueryError::Json(_)) = res {
+            ytdl.failures.fetch_add(1, Ordering::Relaxed);
         }
+
+        Ok(res?)
     }
 
     fn playlist_from_json(json: &str) -> Result<Query, QueryError> {
@@ -97,6 +271,8 @@ impl Query {
             webpage_url: String,
             #[serde(default)]
             thumbnail: Option<String>,
+            #[serde(default)]
+            thumbnails: Vec<YtdlThumbnail>,
             entries: Vec<YtdlQuery>,
         }
 
@@ -106,23 +282,63 @@ impl Query {
             uploader_url,
             webpage_url,
             thumbnail,
+            thumbnails,
             entries,
         } = serde_json::from_str(json).map_err(QueryError::Json)?;
 
+        // the playlists of channels come with the channel's avatar
+        let avatar_url = thumbnails
+            .into_iter()
+            .find(|t| t.id.as_deref() == Some(AVATAR_THUMBNAIL_ID))
+            .map(|t| t.url);
+
+        let album_title = entries.iter().find_map(|entry| entry.album.clone());
+        let artist = entries.iter().find_map(|entry| entry.artist.clone());
+        let year = entries.iter().find_map(|entry| entry.release_year);
+
+        let tracks = entries
+            .into_iter()
+            // skip privated videos (wtf)
+            .filter_map(|entry| {
+                let number = entry.track_number;
+                Track::try_from(entry).ok().map(|track| (track, number))
+            })
+            .map(|(mut track, number)| {
+                if track.author.name == uploader {
+                    track.author.avatar_url.clone_from(&avatar_url);
+                }
+
+                (track, number)
+            })
+            .collect::<Vec<_>>();
+
+        let album = is_album(&tracks).then(|| {
+            Arc::new(Album {
+                title: album_title.unwrap_or_else(|| title.clone()),
+                artist: artist.unwrap_or_else(|| tracks[0].0.author.name.clone()),
+                year,
+                tracks: tracks.len(),
+            })
+        });
+
         // create a playlist as the result
         let playlist = Playlist {
             url: webpage_url,
             title,
+            tracks: tracks
+                .into_iter()
+                .map(|(track, _)| Track {
+                    album: album.clone(),
+                    ..track
+                })
+                .collect(),
+            album,
             author: Author {
                 name: uploader,
                 url: uploader_url,
+                avatar_url,
             },
             thumbnail_url: thumbnail,
-            tracks: entries
-                .into_iter()
-                // skip privated videos (wtf)
-                .filter_map(|entry| entry.try_into().ok())
-                .collect(),
         };
 
         Ok(Query::Playlist(playlist))
@@ -148,10 +364,60 @@ struct YtdlQuery {
     thumbnail: Option<String>,
     #[serde(default)]
     thumbnails: Option<Vec<YtdlThumbnail>>,
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    is_live: Option<bool>,
+    #[serde(default)]
+    age_limit: Option<u8>,
+    #[serde(default)]
+    chapters: Option<Vec<YtdlChapter>>,
+    #[serde(default)]
+    track_number: Option<u32>,
+    #[serde(default)]
+    album: Option<String>,
+    #[serde(default)]
+    artist: Option<String>,
+    #[serde(default)]
+    release_year: Option<u32>,
+    #[serde(default)]
+    formats: Option<Vec<YtdlFormat>>,
+}
+
+#[derive(Deserialize)]
+struct YtdlFormat {
+    format_id: String,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    protocol: Option<String>,
+    #[serde(default)]
+    ext: Option<String>,
+    #[serde(default)]
+    acodec: Option<String>,
+    #[serde(default)]
+    vcodec: Option<String>,
+    #[serde(default)]
+    abr: Option<f64>,
+    #[serde(default)]
+    http_headers: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct YtdlChapter {
+    start_time: f64,
+    end_time: f64,
+    #[serde(default)]
+    title: String,
 }
 
+/// The id `youtube-dl` gives the avatar in the thumbnails of a channel.
+const AVATAR_THUMBNAIL_ID: &str = "avatar_uncropped";
+
 #[derive(Deserialize)]
 struct YtdlThumbnail {
+    #[serde(default)]
+    id: Option<String>,
     url: String,
     height: Option<u32>,
     width: Option<u32>,
@@ -173,7 +439,7 @@ fn output_is_playlist(out: &str) -> bool {
 /// A single `youtube-dl` track.
 ///
 /// Produced from the output of a `youtube-dl` query.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Track {
     /// A url which, when provided to `youtube-dl` should produce the same
     /// result.
@@ -184,11 +450,105 @@ pub struct Track {
     pub author: Author,
     /// The URL of the thumbnail of the track.
     pub thumbnail_url: Option<String>,
+    /// The user that requested the track, if it was requested by a user.
+    pub requester: Option<Id<UserMarker>>,
+    /// Where to start playing the track from.
+    pub start: Duration,
+    /// How long the track is, if known.
+    pub duration: Option<Duration>,
+    /// Text to say with text-to-speech instead of playing `url`, which is
+    /// empty for speech.
+    pub speech: Option<String>,
+    /// When `youtube-dl` last found the track, if it's known. Tracks that
+    /// were saved or imported don't know when they were found.
+    pub resolved_at: Option<Instant>,
+    /// Whether the requester picked the track to play next with `/mynext`.
+    pub picked: bool,
+    /// Whether the track is a livestream.
+    pub live: bool,
+    /// The age needed to watch the track, or zero if it isn't
+    /// age-restricted.
+    pub age_limit: u8,
+    /// The chapters of the track, in order, if the uploader split it up.
+    pub chapters: Vec<Chapter>,
+    /// The album the track was enqueued from, if it was.
+    pub album: Option<Arc<Album>>,
+    /// The best audio-only format `youtube-dl` found for the track, if it
+    /// listed any.
+    pub format: Option<Arc<AudioFormat>>,
+}
+
+/// An audio-only format of a track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioFormat {
+    /// The id `youtube-dl` selects the format with.
+    pub id: String,
+    /// The codec of the audio, like `opus`.
+    pub codec: String,
+    /// The container of the audio, like `webm`.
+    pub container: String,
+    /// The average bitrate of the audio, in kbps.
+    pub abr: Option<f64>,
+    /// A url the audio can be streamed from over plain HTTP, without
+    /// `youtube-dl`. These expire after a few hours.
+    pub url: Option<String>,
+    /// The headers to send with requests to `url`.
+    pub http_headers: Vec<(String, String)>,
+}
+
+/// Picks the best audio-only format out of the formats of a track.
+///
+/// Higher bitrates are better, and Opus is preferred when the bitrates are
+/// the same.
+fn best_audio_format(formats: Vec<YtdlFormat>) -> Option<AudioFormat> {
+    formats
+        .into_iter()
+        .filter(|format| {
+            format.vcodec.as_deref() == Some("none")
+                && format
+                    .acodec
+                    .as_deref()
+                    .is_some_and(|codec| codec != "none")
+        })
+        .map(|format| {
+            let direct = matches!(format.protocol.as_deref(), Some("http" | "https"));
+            let mut http_headers = format.http_headers.into_iter().collect::<Vec<_>>();
+            http_headers.sort();
+
+            AudioFormat {
+                id: format.format_id,
+                codec: format.acodec.unwrap_or_default(),
+                container: format.ext.unwrap_or_default(),
+                abr: format.abr.filter(|abr| abr.is_finite()),
+                url: format.url.filter(|_| direct),
+                http_headers,
+            }
+        })
+        .max_by(|a, b| {
+            let abr = |format: &AudioFormat| format.abr.unwrap_or_default();
+
+            abr(a)
+                .total_cmp(&abr(b))
+                .then_with(|| (a.codec == "opus").cmp(&(b.codec == "opus")))
+        })
+}
+
+/// A named part of a track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    /// Where the chapter starts in the track.
+    pub start: Duration,
+    /// Where the chapter ends in the track.
+    pub end: Duration,
 }
 
 impl Track {
     /// Converts a `Track` to a readable embed.
-    pub fn as_embed(&self) -> Embed {
+    ///
+    /// The thumbnail is shown as the embed's full-size image if
+    /// `large_thumbnail` is set, instead of in the corner.
+    pub fn as_embed(&self, large_thumbnail: bool) -> Embed {
         let Track {
             url,
             title,
@@ -197,31 +557,39 @@ impl Track {
             ..
         } = self.clone();
 
+        let (image, thumbnail) = match thumbnail_url {
+            Some(url) if large_thumbnail => (
+                Some(EmbedImage {
+                    url,
+                    height: None,
+                    width: None,
+                    proxy_url: None,
+                }),
+                None,
+            ),
+            url => (
+                None,
+                url.map(|url| EmbedThumbnail {
+                    url,
+                    height: None,
+                    width: None,
+                    proxy_url: None,
+                }),
+            ),
+        };
+
         Embed {
             author: Some(EmbedAuthor {
                 name: author.name,
                 url: author.url,
-                icon_url: None,
+                icon_url: author.avatar_url,
                 proxy_icon_url: None,
             }),
-            // TODO: color
-            color: Some(0xEE1428),
-            description: None,
-            fields: Vec::new(),
-            footer: None,
-            image: None,
-            kind: String::from("rich"),
-            provider: None,
+            image,
             title: Some(title),
-            timestamp: None,
-            thumbnail: thumbnail_url.map(|url| EmbedThumbnail {
-                url,
-                height: None,
-                width: None,
-                proxy_url: None,
-            }),
-            url: Some(url),
-            video: None,
+            thumbnail,
+            url: Some(url).filter(|url| !url.is_empty()),
+            ..crate::embed()
         }
     }
 }
@@ -238,6 +606,12 @@ impl TryFrom<YtdlQuery> for Track {
             uploader_url,
             thumbnail,
             thumbnails,
+            duration,
+            is_live,
+            age_limit,
+            chapters,
+            formats,
+            ..
         } = e;
 
         let url = match webpage_url {
@@ -260,15 +634,57 @@ impl TryFrom<YtdlQuery> for Track {
                 .map(|t| t.url)
         });
 
+        // a track without audio would only fail once it's played
+        let silent = formats.as_ref().is_some_and(|formats| {
+            !formats.is_empty()
+                && formats
+                    .iter()
+                    .all(|format| format.acodec.as_deref() == Some("none"))
+        });
+
+        if silent {
+            return Err(QueryError::NoAudio);
+        }
+
+        let chapters = chapters
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|chapter| {
+                chapter.start_time.is_finite()
+                    && chapter.end_time.is_finite()
+                    && 0.0 <= chapter.start_time
+                    && chapter.start_time <= chapter.end_time
+            })
+            .map(|chapter| Chapter {
+                title: chapter.title,
+                start: Duration::from_secs_f64(chapter.start_time),
+                end: Duration::from_secs_f64(chapter.end_time),
+            })
+            .collect();
+
         // create a track as the result
         Ok(Track {
             url,
             title,
             author: Author {
-                name: uploader.ok_or_else(|| QueryError::PrivateVideo)?,
+                name: uploader.ok_or(QueryError::PrivateVideo)?,
                 url: uploader_url,
+                avatar_url: None,
             },
             thumbnail_url: thumbnail,
+            requester: None,
+            start: Duration::ZERO,
+            duration: duration
+                .filter(|duration| duration.is_finite() && *duration >= 0.0)
+                .map(Duration::from_secs_f64),
+            speech: None,
+            resolved_at: Some(Instant::now()),
+            picked: false,
+            live: is_live.unwrap_or_default(),
+            age_limit: age_limit.unwrap_or_default(),
+            chapters,
+            album: None,
+            format: formats.and_then(best_audio_format).map(Arc::new),
         })
     }
 }
@@ -289,6 +705,44 @@ pub struct Playlist {
     pub thumbnail_url: Option<String>,
     /// The tracks of the playlist.
     pub tracks: Vec<Track>,
+    /// What the playlist is an album of, if it looks like one.
+    pub album: Option<Arc<Album>>,
+}
+
+/// An album, which plays without gaps between its tracks.
+#[derive(Debug)]
+pub struct Album {
+    pub title: String,
+    pub artist: String,
+    /// The year the album was released, if known.
+    pub year: Option<u32>,
+    /// How many tracks the album has.
+    pub tracks: usize,
+}
+
+impl Display for Album {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.year {
+            Some(year) => write!(f, "{} ({})", self.title, year),
+            None => f.write_str(&self.title),
+        }
+    }
+}
+
+/// Checks if the tracks of a playlist, with their track numbers, are an
+/// album.
+///
+/// An album has a single uploader, and its tracks are numbered in order
+/// from 1.
+fn is_album(tracks: &[(Track, Option<u32>)]) -> bool {
+    let Some((first, _)) = tracks.first() else {
+        return false;
+    };
+
+    tracks.len() > 1
+        && tracks.iter().enumerate().all(|(i, (track, number))| {
+            track.author.name == first.author.name && *number == Some(i as u32 + 1)
+        })
 }
 
 impl Playlist {
@@ -307,19 +761,10 @@ impl Playlist {
             author: Some(EmbedAuthor {
                 name: author.name,
                 url: author.url,
-                icon_url: None,
+                icon_url: author.avatar_url,
                 proxy_icon_url: None,
             }),
-            // TODO: color
-            color: Some(0xEE1428),
-            description: None,
-            fields: Vec::new(),
-            footer: None,
-            image: None,
-            kind: String::from("rich"),
-            provider: None,
             title: Some(title),
-            timestamp: None,
             thumbnail: thumbnail_url
                 .or_else(|| tracks.first().and_then(|t| t.thumbnail_url.clone()))
                 .map(|url| EmbedThumbnail {
@@ -328,19 +773,21 @@ impl Playlist {
                     width: None,
                     proxy_url: None,
                 }),
-            url: Some(url),
-            video: None,
+            url: Some(url).filter(|url| !url.is_empty()),
+            ..crate::embed()
         }
     }
 }
 
 /// An author of a track.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Author {
     /// The name of the author.
     pub name: String,
     /// A URL to the author's channel.
     pub url: Option<String>,
+    /// A URL to the author's avatar, if `youtube-dl` found it.
+    pub avatar_url: Option<String>,
 }
 
 /// An error that can occur querying `youtube-dl`.
@@ -356,6 +803,43 @@ pub enum QueryError {
     Ytdl(YtdlError),
     /// The video that was queried is private.
     PrivateVideo,
+    /// The video is age-restricted, and `youtube-dl` isn't signed in.
+    AgeRestricted(YtdlError),
+    /// The video isn't available where the bot is.
+    GeoBlocked(YtdlError),
+    /// The video was taken down for copyright.
+    Copyright(YtdlError),
+    /// None of the formats of the video have audio.
+    NoAudio,
+}
+
+impl From<YtdlError> for QueryError {
+    /// Sorts a `youtube-dl` error by its [`YtdlErrorKind`].
+    fn from(err: YtdlError) -> QueryError {
+        match err.kind() {
+            YtdlErrorKind::AgeRestricted => QueryError::AgeRestricted(err),
+            YtdlErrorKind::GeoBlocked => QueryError::GeoBlocked(err),
+            YtdlErrorKind::Copyright => QueryError::Copyright(err),
+            YtdlErrorKind::Private => QueryError::PrivateVideo,
+            YtdlErrorKind::Other => QueryError::Ytdl(err),
+        }
+    }
+}
+
+impl QueryError {
+    /// Whether the query failed because the video can't be played, rather
+    /// than because `youtube-dl` couldn't be run.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(
+            self,
+            QueryError::Ytdl(_)
+                | QueryError::PrivateVideo
+                | QueryError::AgeRestricted(_)
+                | QueryError::GeoBlocked(_)
+                | QueryError::Copyright(_)
+                | QueryError::NoAudio
+        )
+    }
 }
 
 impl Display for QueryError {
@@ -368,6 +852,16 @@ impl Display for QueryError {
             QueryError::PrivateVideo => {
                 f.write_str("query result is privated or otherwise not visible")
             }
+            QueryError::AgeRestricted(_) => f.write_str(
+                "this video is age-restricted; the bot needs cookies configured to play it",
+            ),
+            QueryError::GeoBlocked(_) => {
+                f.write_str("this video isn't available in the bot's country")
+            }
+            QueryError::Copyright(_) => {
+                f.write_str("this video was taken down over a copyright claim")
+            }
+            QueryError::NoAudio => f.write_str("this video has no audio to play"),
         }
     }
 }
@@ -378,16 +872,68 @@ impl std::error::Error for QueryError {
             QueryError::Io(err) => Some(err),
             QueryError::Utf8(err) => Some(err),
             QueryError::Json(err) => Some(err),
-            QueryError::Ytdl(err) => Some(err),
+            QueryError::Ytdl(err)
+            | QueryError::AgeRestricted(err)
+            | QueryError::GeoBlocked(err)
+            | QueryError::Copyright(err) => Some(err),
             _ => None,
         }
     }
 }
 
+/// What a [`YtdlError`] is about, as far as can be told from its message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YtdlErrorKind {
+    /// The video is age-restricted.
+    AgeRestricted,
+    /// The video is blocked in some countries.
+    GeoBlocked,
+    /// The video was taken down for copyright.
+    Copyright,
+    /// The video is private.
+    Private,
+    /// Anything else.
+    Other,
+}
+
+impl YtdlErrorKind {
+    /// Classifies an error message.
+    ///
+    /// `youtube-dl` doesn't have error codes, and every extractor words its
+    /// errors differently, so this looks for phrases the common ones use.
+    pub fn classify(message: &str) -> YtdlErrorKind {
+        const PATTERNS: &[(&str, YtdlErrorKind)] = &[
+            ("confirm your age", YtdlErrorKind::AgeRestricted),
+            ("age-restricted", YtdlErrorKind::AgeRestricted),
+            ("age restricted", YtdlErrorKind::AgeRestricted),
+            ("inappropriate for some users", YtdlErrorKind::AgeRestricted),
+            ("in your country", YtdlErrorKind::GeoBlocked),
+            ("geo restriction", YtdlErrorKind::GeoBlocked),
+            ("geo-restricted", YtdlErrorKind::GeoBlocked),
+            (
+                "not available from your location",
+                YtdlErrorKind::GeoBlocked,
+            ),
+            ("copyright", YtdlErrorKind::Copyright),
+            ("private video", YtdlErrorKind::Private),
+            ("video is private", YtdlErrorKind::Private),
+        ];
+
+        let message = message.to_lowercase();
+
+        PATTERNS
+            .iter()
+            .find(|(pattern, _)| message.contains(pattern))
+            .map(|(_, kind)| *kind)
+            .unwrap_or(YtdlErrorKind::Other)
+    }
+}
+
 /// An error from a `youtube-dl` command.
 #[derive(Debug)]
 pub struct YtdlError {
     message: String,
+    kind: YtdlErrorKind,
 }
 
 impl YtdlError {
@@ -410,8 +956,11 @@ impl YtdlError {
         let mut lines = stderr.lines();
         while let Some(line) = lines.next_line().await? {
             if let Some(stripped) = line.strip_prefix(ERROR_PREFIX) {
+                let message = stripped.trim().to_owned();
+
                 return Ok(Some(YtdlError {
-                    message: stripped.trim().to_owned(),
+                    kind: YtdlErrorKind::classify(&message),
+                    message,
                 }));
             }
         }
@@ -423,6 +972,11 @@ impl YtdlError {
     pub fn message(&self) -> &str {
         &self.message
     }
+
+    /// What the error is about.
+    pub fn kind(&self) -> YtdlErrorKind {
+        self.kind
+    }
 }
 
 impl Display for YtdlError {
@@ -432,3 +986,76 @@ impl Display for YtdlError {
 }
 
 impl std::error::Error for YtdlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let cases = [
+            (
+                "[youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.",
+                YtdlErrorKind::AgeRestricted,
+            ),
+            (
+                "[youtube] abc: The uploader has not made this video available in your country",
+                YtdlErrorKind::GeoBlocked,
+            ),
+            (
+                "[youtube] abc: Video unavailable. This video is no longer available due to a copyright claim by Someone",
+                YtdlErrorKind::Copyright,
+            ),
+            ("[youtube] abc: Private video. Sign in if you've been granted access", YtdlErrorKind::Private),
+            ("[youtube] abc: Video unavailable", YtdlErrorKind::Other),
+        ];
+
+        for (message, kind) in cases {
+            assert_eq!(YtdlErrorKind::classify(message), kind, "{}", message);
+        }
+    }
+
+    #[test]
+    fn detects_albums() {
+        let track = |author: &str, number: Option<u32>| {
+            let track = Track {
+                author: Author {
+                    name: author.to_owned(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            (track, number)
+        };
+
+        assert!(is_album(&[track("band", Some(1)), track("band", Some(2))]));
+        // a mixtape
+        assert!(!is_album(&[
+            track("band", Some(1)),
+            track("other", Some(2))
+        ]));
+        // missing a track
+        assert!(!is_album(&[track("band", Some(1)), track("band", Some(3))]));
+        assert!(!is_album(&[track("band", None), track("band", None)]));
+        assert!(!is_album(&[track("band", Some(1))]));
+    }
+
+    #[test]
+    fn picks_audio_format() {
+        let formats = serde_json::from_str::<Vec<YtdlFormat>>(
+            r#"[
+                {"format_id": "140", "url": "https://a/140", "protocol": "https", "ext": "m4a", "acodec": "mp4a.40.2", "vcodec": "none", "abr": 129.5},
+                {"format_id": "251", "url": "https://a/251", "protocol": "https", "ext": "webm", "acodec": "opus", "vcodec": "none", "abr": 129.5},
+                {"format_id": "18", "url": "https://a/18", "protocol": "https", "ext": "mp4", "acodec": "mp4a.40.2", "vcodec": "avc1", "abr": 96.0},
+                {"format_id": "233", "url": "https://a/233", "protocol": "m3u8_native", "ext": "mp4", "acodec": "mp4a.40.5", "vcodec": "none"}
+            ]"#,
+        )
+        .unwrap();
+
+        let format = best_audio_format(formats).unwrap();
+        assert_eq!(format.id, "251");
+        assert_eq!(format.codec, "opus");
+        assert_eq!(format.url.as_deref(), Some("https://a/251"));
+    }
+}